@@ -0,0 +1,73 @@
+// A line after each run saying how it went (`--banner`), where the log
+// lines are easy to lose among the run's output:
+//
+//   ── cargo test · exit code 101 · 4.2s · src/lib.rs, src/parse.rs and 3 more
+//
+// `--banner-format TEMPLATE` sets its layout, with {command}, {job},
+// {run_id}, {result} (succeeded or failed), {status} (exit code N or
+// signal NAME), {exit_code}, {signal}, {duration}, {duration_ms},
+// {files} (the first few paths that triggered the run) and {count} (how
+// many there were) filled in. On a terminal it is green or red.
+
+use crate::expand;
+use crate::process::{self, Run};
+use nix::sys::signal::Signal;
+use std::{env, os::unix::process::ExitStatusExt, path::PathBuf, process::ExitStatus};
+
+pub const DEFAULT_FORMAT: &str = "── {command} · {status} · {duration} · {files}";
+
+/// How many of the triggering paths {files} names.
+const FILES: usize = 3;
+
+/// The first [`FILES`] of `trigger`, relative to the working directory
+/// where they are below it.
+fn files(trigger: &[PathBuf]) -> String {
+  let cwd = env::current_dir().unwrap_or_default();
+  let named: Vec<String> = trigger
+    .iter()
+    .take(FILES)
+    .map(|p| p.strip_prefix(&cwd).unwrap_or(p).display().to_string())
+    .collect();
+  match trigger.len() {
+    0 => "no file changes".to_string(),
+    n if n > FILES => format!("{} and {} more", named.join(", "), n - FILES),
+    _ => named.join(", "),
+  }
+}
+
+/// Prints the banner for `run` of `job`, which ended with `status`.
+pub fn print(format: &str, color: bool, job: &str, run: &Run, status: ExitStatus) {
+  let duration = run.duration();
+  let signal = status
+    .signal()
+    .map(|sig| Signal::try_from(sig).map_or_else(|_| sig.to_string(), |s| s.to_string()));
+  let values = [
+    ("command", run.command.clone()),
+    ("job", job.to_string()),
+    ("run_id", run.id.to_string()),
+    (
+      "result",
+      match status.success() {
+        true => "succeeded",
+        false => "failed",
+      }
+      .to_string(),
+    ),
+    ("status", process::describe(status)),
+    (
+      "exit_code",
+      status.code().map(|c| c.to_string()).unwrap_or_default(),
+    ),
+    ("signal", signal.unwrap_or_default()),
+    ("duration", format!("{:.1}s", duration.as_secs_f64())),
+    ("duration_ms", duration.as_millis().to_string()),
+    ("files", files(&run.trigger)),
+    ("count", run.trigger.len().to_string()),
+  ];
+  let line = expand::placeholders(format, &values);
+  match (color, status.success()) {
+    (true, true) => println!("\x1b[32m{line}\x1b[0m"),
+    (true, false) => println!("\x1b[31m{line}\x1b[0m"),
+    (false, _) => println!("{line}"),
+  }
+}
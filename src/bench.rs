@@ -0,0 +1,231 @@
+// Measuring the watch pipeline on this machine (`run_on_file_change bench
+// DIR`), to pick a backend with numbers rather than guesses:
+//
+//   run_on_file_change bench /mnt/nfs/project --files 5000
+//
+// Generates a tree of files in a scratch directory under DIR, then, for
+// the native backend and for polling, times setting up the watches and
+// how long a write takes to show up as an event. It also times the
+// filters on the generated paths, and starting a run of `true` through
+// the shell. The scratch directory is removed afterwards.
+
+use crate::cli::Options;
+use crate::filter::Filter;
+use crate::output::OutputConfig;
+use crate::{jobs, process};
+use notify::{Config, Event, PollWatcher, RecommendedWatcher, RecursiveMode, Watcher};
+use std::{
+  fs, hint,
+  path::{Path, PathBuf},
+  sync::mpsc::{self, Receiver},
+  thread,
+  time::{Duration, Instant},
+};
+
+/// Writes timed per backend, and runs started.
+const SAMPLES: usize = 20;
+/// How long a write may take to show up before it counts as missed.
+const MISSED: Duration = Duration::from_secs(5);
+
+fn fail(msg: &str) -> ! {
+  eprintln!("Error: {msg}");
+  eprintln!("Usage: run_on_file_change bench <DIR> [--files <N>] [--poll-interval <MS>]");
+  std::process::exit(1);
+}
+
+/// "1.2ms median, 3.4ms p95, 5.6ms max".
+fn describe(mut samples: Vec<Duration>) -> String {
+  if samples.is_empty() {
+    return "no samples".to_string();
+  }
+  samples.sort();
+  let at = |q: f64| samples[((samples.len() - 1) as f64 * q).round() as usize];
+  let ms = |d: Duration| d.as_secs_f64() * 1000.0;
+  format!(
+    "{:.1}ms median, {:.1}ms p95, {:.1}ms max",
+    ms(at(0.5)),
+    ms(at(0.95)),
+    ms(at(1.0))
+  )
+}
+
+/// Creates `count` files spread over ten directories below `dir`.
+fn generate(dir: &Path, count: usize) -> std::io::Result<Vec<PathBuf>> {
+  let mut files = Vec::with_capacity(count);
+  for i in 0..count {
+    let sub = dir.join(format!("d{}", i % 10));
+    if i < 10 {
+      fs::create_dir_all(&sub)?;
+    }
+    let file = sub.join(format!("f{i}.rs"));
+    fs::write(&file, "fn main() {}\n")?;
+    files.push(file);
+  }
+  Ok(files)
+}
+
+/// Times `SAMPLES` writes to `files` until their event arrives, returning
+/// the delays and how many never came.
+fn latency(rx: &Receiver<notify::Result<Event>>, files: &[PathBuf]) -> (Vec<Duration>, usize) {
+  let mut delays = Vec::new();
+  let mut missed = 0;
+  for (n, file) in files.iter().step_by(files.len() / SAMPLES + 1).enumerate() {
+    while rx.try_recv().is_ok() {} // earlier writes' stragglers
+    let written = Instant::now();
+    if fs::write(file, format!("fn main() {{ {n} }}\n")).is_err() {
+      missed += 1;
+      continue;
+    }
+    loop {
+      let left = MISSED.saturating_sub(written.elapsed());
+      match rx.recv_timeout(left) {
+        Ok(Ok(event)) if event.paths.iter().any(|p| p.ends_with(file)) => {
+          delays.push(written.elapsed());
+          break;
+        }
+        Ok(_) => continue,
+        Err(_) => {
+          missed += 1;
+          break;
+        }
+      }
+    }
+  }
+  (delays, missed)
+}
+
+/// Times `watcher` on the files below `root`, once it has had `warmup` to
+/// take stock of them.
+fn backend(
+  name: &str,
+  watcher: notify::Result<impl Watcher>,
+  warmup: Duration,
+  root: &Path,
+  files: &[PathBuf],
+  rx: Receiver<notify::Result<Event>>,
+) {
+  let mut watcher = match watcher {
+    Ok(watcher) => watcher,
+    Err(e) => return println!("{name}: unavailable ({e})"),
+  };
+  let setup = Instant::now();
+  if let Err(e) = watcher.watch(root, RecursiveMode::Recursive) {
+    return println!("{name}: could not watch ({e})");
+  }
+  let setup = setup.elapsed();
+  thread::sleep(warmup);
+  let (delays, missed) = latency(&rx, files);
+  println!(
+    "{name}: watching took {:.1}ms; write to event {}{}",
+    setup.as_secs_f64() * 1000.0,
+    describe(delays),
+    match missed {
+      0 => String::new(),
+      n => format!(", {n} of {SAMPLES} missed"),
+    }
+  );
+}
+
+/// `run_on_file_change bench DIR [--files N] [--poll-interval MS]`.
+pub fn run(mut args: impl Iterator<Item = String>) -> ! {
+  let mut dir = None;
+  let mut count = 1000;
+  let mut interval = Duration::from_millis(100);
+  while let Some(arg) = args.next() {
+    let mut number = |name: &str| -> u64 {
+      args
+        .next()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or_else(|| fail(&format!("{name} takes a number")))
+    };
+    match arg.as_str() {
+      "--files" => count = number("--files").max(1) as usize,
+      "--poll-interval" => interval = Duration::from_millis(number("--poll-interval")),
+      _ if dir.is_none() && !arg.starts_with('-') => dir = Some(PathBuf::from(arg)),
+      _ => fail(&format!("unexpected argument {arg:?}")),
+    }
+  }
+  let dir = dir.unwrap_or_else(|| fail("no directory given"));
+  let scratch = dir.join(format!(".rof-bench-{}", std::process::id()));
+  let root = match fs::create_dir(&scratch).and_then(|_| scratch.canonicalize()) {
+    Ok(root) => root,
+    Err(e) => fail(&format!("could not create {}: {e}", scratch.display())),
+  };
+  let generated = Instant::now();
+  let files = generate(&root, count);
+  let files = match files {
+    Ok(files) => files,
+    Err(e) => {
+      let _ = fs::remove_dir_all(&root);
+      fail(&format!("could not create the files: {e}"));
+    }
+  };
+  println!(
+    "{count} files in {}, written in {:.1}ms",
+    root.display(),
+    generated.elapsed().as_secs_f64() * 1000.0
+  );
+
+  let (tx, rx) = mpsc::channel();
+  backend(
+    "native",
+    RecommendedWatcher::new(tx, Config::default()),
+    Duration::ZERO,
+    &root,
+    &files,
+    rx,
+  );
+  let (tx, rx) = mpsc::channel();
+  backend(
+    &format!("poll every {}ms", interval.as_millis()),
+    PollWatcher::new(tx, Config::default().with_poll_interval(interval)),
+    interval * 2,
+    &root,
+    &files,
+    rx,
+  );
+
+  let opts = Options {
+    extensions: vec!["rs".into(), "toml".into()],
+    ignore: vec!["target".into(), "node_modules".into(), "*.tmp".into()],
+    ..Options::default()
+  };
+  if let Ok(filter) = Filter::new(vec![root.clone()], &[], &opts) {
+    let started = Instant::now();
+    let mut checked = 0;
+    while started.elapsed() < Duration::from_millis(200) {
+      for file in &files {
+        hint::black_box(filter.matches(file));
+      }
+      checked += files.len();
+    }
+    println!(
+      "filters: {:.0} paths/s",
+      checked as f64 / started.elapsed().as_secs_f64()
+    );
+  }
+
+  let (tx, _rx) = mpsc::channel();
+  let mut spawns = Vec::new();
+  for id in 0..SAMPLES as u64 {
+    let started = Instant::now();
+    let run = process::spawn(
+      &opts,
+      "true",
+      id,
+      OutputConfig::default(),
+      &jobs::Output::Null,
+      &[],
+      &tx,
+    );
+    if let Ok(mut run) = run
+      && run.child.wait().is_ok()
+    {
+      spawns.push(started.elapsed());
+    }
+  }
+  println!("run of `true` until it exits: {}", describe(spawns));
+
+  let _ = fs::remove_dir_all(&root);
+  std::process::exit(0);
+}
@@ -0,0 +1,136 @@
+// Leaving things as the watcher found them when it goes. A panic, on any
+// thread, stops the runs' process groups, which would otherwise carry on
+// without a watcher, and puts the terminal back, since a run cut short can
+// leave it in raw mode, its cursor hidden or on the alternate screen; then
+// the watcher exits with status 101. The orderly shutdown (Ctrl-C,
+// SIGTERM, `quit`) restores the terminal too, once its runs have stopped,
+// if one of them left it changed.
+//
+// The watcher itself never changes the terminal's modes: what is restored
+// is how it was when the watcher started.
+
+use crate::exit;
+use nix::errno::Errno;
+use nix::libc;
+use nix::sys::signal::{Signal, kill};
+use nix::sys::wait::{WaitPidFlag, waitpid};
+use nix::unistd::Pid;
+use std::{
+  panic,
+  sync::{Mutex, OnceLock},
+  thread,
+  time::{Duration, Instant},
+};
+
+/// How long runs get to stop after a panic before they are killed.
+const GRACE: Duration = Duration::from_secs(1);
+
+/// Leaves the alternate screen, shows the cursor and resets colors.
+const RESET: &str = "\x1b[?1049l\x1b[?25h\x1b[0m";
+
+/// The process groups of the runs going on.
+static GROUPS: Mutex<Vec<i32>> = Mutex::new(Vec::new());
+
+/// The terminal's modes when the watcher started.
+static SAVED: OnceLock<libc::termios> = OnceLock::new();
+
+/// Whether the watcher's stdin is its controlling terminal, with the
+/// watcher in the foreground: changing the modes from the background would
+/// stop it with SIGTTOU.
+fn foreground() -> bool {
+  // SAFETY: plain queries of the stdin descriptor
+  unsafe {
+    libc::isatty(libc::STDIN_FILENO) == 1 && libc::tcgetpgrp(libc::STDIN_FILENO) == libc::getpgrp()
+  }
+}
+
+/// Remembers the terminal's modes and has panics stop the runs, stopping
+/// them with `signal` as a shutdown would. Must run before any thread that
+/// could panic is started.
+pub fn install(signal: Signal) {
+  if foreground() {
+    // SAFETY: termios is plain data, filled in by tcgetattr
+    let mut modes: libc::termios = unsafe { std::mem::zeroed() };
+    if unsafe { libc::tcgetattr(libc::STDIN_FILENO, &mut modes) } == 0 {
+      let _ = SAVED.set(modes);
+    }
+  }
+  let report = panic::take_hook();
+  panic::set_hook(Box::new(move |info| {
+    restore_terminal(true);
+    report(info);
+    stop_groups(signal);
+    std::process::exit(exit::PANIC);
+  }));
+}
+
+/// Notes a run's process group, for a panic to stop.
+pub fn track(pgid: i32) {
+  GROUPS.lock().unwrap_or_else(|e| e.into_inner()).push(pgid);
+}
+
+/// Forgets a run's process group once the run is done with.
+pub fn untrack(pgid: i32) {
+  GROUPS
+    .lock()
+    .unwrap_or_else(|e| e.into_inner())
+    .retain(|&g| g != pgid);
+}
+
+/// Signals the runs' groups, and kills those still there after [`GRACE`].
+fn stop_groups(signal: Signal) {
+  // Not waited for: the panic may have come with the lock held.
+  let groups = match GROUPS.try_lock() {
+    Ok(groups) => groups.clone(),
+    Err(_) => return,
+  };
+  for &pgid in &groups {
+    let _ = kill(Pid::from_raw(-pgid), signal);
+  }
+  let asked = Instant::now();
+  let mut left = groups;
+  while !left.is_empty() && asked.elapsed() < GRACE {
+    // Zombies count as members, so collect the exited ones first.
+    left.retain(|&pgid| {
+      while let Ok(status) = waitpid(Pid::from_raw(-pgid), Some(WaitPidFlag::WNOHANG)) {
+        if status.pid().is_none() {
+          break;
+        }
+      }
+      kill(Pid::from_raw(-pgid), None) != Err(Errno::ESRCH)
+    });
+    thread::sleep(Duration::from_millis(20));
+  }
+  for pgid in left {
+    let _ = kill(Pid::from_raw(-pgid), Signal::SIGKILL);
+  }
+}
+
+/// Puts the terminal's modes back as they were when the watcher started.
+/// Unless `always`, only if a run changed them, in which case whatever
+/// else it changed on the screen is reset as well.
+pub fn restore_terminal(always: bool) {
+  let Some(saved) = SAVED.get() else {
+    return;
+  };
+  if !foreground() {
+    return;
+  }
+  // SAFETY: termios is plain data, filled in by tcgetattr
+  let mut modes: libc::termios = unsafe { std::mem::zeroed() };
+  let changed = unsafe { libc::tcgetattr(libc::STDIN_FILENO, &mut modes) } != 0
+    || (modes.c_iflag, modes.c_oflag, modes.c_lflag)
+      != (saved.c_iflag, saved.c_oflag, saved.c_lflag);
+  if !always && !changed {
+    return;
+  }
+  // SAFETY: saved was filled in by tcgetattr on the same descriptor
+  unsafe { libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, saved) };
+  // Written around std's stdout, whose lock a panicking thread may hold.
+  // SAFETY: RESET outlives the call
+  unsafe {
+    if libc::isatty(libc::STDOUT_FILENO) == 1 {
+      libc::write(libc::STDOUT_FILENO, RESET.as_ptr().cast(), RESET.len());
+    }
+  }
+}
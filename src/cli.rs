@@ -0,0 +1,1628 @@
+// Command-line parsing.
+
+use crate::banner;
+use crate::bench;
+use crate::control;
+use crate::cron::{Schedule, Window};
+use crate::daemon;
+use crate::doctor;
+use crate::exit;
+use crate::expand::expand;
+use crate::filter::Priority;
+use crate::git::GitEvent;
+use crate::hooks::Hooks;
+use crate::jobs::{self, Job};
+use crate::k8s;
+use crate::livereload;
+use crate::logging::{self, LogTarget, Timestamps};
+use crate::mqtt;
+use crate::notification::{self, Sink};
+use crate::output::OutputConfig;
+use crate::pair;
+use crate::proxy;
+use crate::ready::Probe;
+use crate::rotate;
+use crate::serve;
+use crate::tmux;
+use crate::watch::{Backend, BackendOpts};
+use nix::sys::signal::Signal;
+use std::{
+  env,
+  io::{self, IsTerminal},
+  path::PathBuf,
+  str::FromStr,
+  time::Duration,
+};
+
+/// Debounce window for changes to the Cargo manifests with -x, which
+/// cargo rewrites itself on the runs they set off.
+const CARGO_MANIFEST_DEBOUNCE: Duration = Duration::from_secs(20);
+
+const USAGE: &str = "Usage:
+  run_on_file_change [options] \"<command>\" <path|glob> [path|glob] …
+  run_on_file_change [options] --config <FILE> [run <job> …]
+  run_on_file_change [options] -w <path> [-w <path> …] [--] <command words…>
+  run_on_file_change [options] -x <cargo args> [-x …] [path …]
+  run_on_file_change snapshot [options] [path …] [-o <FILE>]
+  run_on_file_change test-ignore [options] [-w <path|glob> …] <path> …
+  run_on_file_change ctl [--socket <PATH>] inject --path <PATH> [--kind <KIND>]
+  run_on_file_change ctl [--socket <PATH>] watch add|remove <DIR>
+  run_on_file_change ctl history --state-dir <DIR> [--last <N>]
+  run_on_file_change daemon [--socket <PATH>] [--log-dir <DIR>]
+  run_on_file_change ctl add <DIR> [options] … | remove <DIR> | list
+  run_on_file_change pair-serve <ADDR:PORT> \"<command>\"
+  run_on_file_change bench <DIR> [--files <N>] [--poll-interval <MS>]
+  run_on_file_change doctor [path|glob …]
+  run_on_file_change check-config <FILE>
+
+Paths are watched recursively, skipping dotfiles and dot-directories unless
+--hidden is given. Quoted globs such as 'src/**/*.c' watch the directories
+they cover and only trigger on matching files. A path inside another one
+given (`. src`) is watched only once, as part of the outer one.
+
+With -w or -x the arguments follow watchexec / cargo-watch: after -w the
+positional arguments are the command, after -x they are extra paths, and
+the current directory is watched if no path is given.
+
+Runs are numbered from 1. The command finds its number in $ROF_RUN_ID, and
+the log lines about a run start with it ([run 3] …), so the output of runs
+that overlap or were killed can be told apart.
+
+With --config, the commands come from a TOML file of [[job]] tables, each
+with a name, a command, the paths it watches (watch = [\"src\"]) and
+optionally a color for its output prefix. Jobs run concurrently, each
+restarted by changes to its own paths, except that a job with a group
+stops the running jobs of the same group before it starts (when a change
+starts several of them at once, the first listed wins). A job with
+needs = [\"build\"] runs build first and starts only once it has
+succeeded, or once it is ready if build sets ready_when to a check like
+--ready-when's (for a server to be up before the tests); a job with
+needs but no paths runs whenever a job it needs does. A new change
+restarts the whole chain. A job's output may be set to \"inherit\",
+\"null\", \"capture\" or a file to append to. With run and job
+names, only those jobs and the jobs they need are watched and run. The
+file may also set before_all, before_each, after_each and after_all to
+shell snippets run around the watcher and each run, with ROF_JOB,
+ROF_RUN_ID, ROF_TRIGGER, ROF_STATUS, ROF_EXIT_CODE and similar variables
+describing it. A [debounce] table of pattern = window pairs gives changes
+to matching paths their own debounce window; the shortest one wins. An
+[env] table, and a job's env, set variables for the runs, with {path},
+{paths}, {dir}, {file}, {ext}, {job} and {run_id} filled in per run.
+
+ctl inject hands a watcher started with --control a made-up change of KIND
+(create, modify, remove or rename; modify by default), which it handles
+like a real one. ctl watch add starts watching DIR as well, as if it had
+been given at startup, and ctl watch remove stops watching one of the
+paths, both without a restart. ctl history prints the newest runs (20 by
+default) kept in a --state-dir.
+
+daemon keeps the watchers of several projects in one background process.
+ctl add starts one in DIR with the options, command and paths that follow,
+as if run there, logging to a file per project in the --log-dir
+($XDG_RUNTIME_DIR/run_on_file_change-daemon by default); ctl remove stops
+it and ctl list shows them all. Stopping the daemon stops its watchers.
+
+pair-serve runs the command in place of the runs of watchers started
+elsewhere with --pair ADDR:PORT, streaming the output back to them, with
+the changed paths in $ROF_TRIGGER. Set ROF_PAIR_TOKEN to the same secret
+on both sides so that no one else can start runs (experimental).
+
+bench creates N files (1000) in a scratch directory under DIR and reports
+how long watching them takes and how soon a write shows up as an event,
+natively and by polling, plus how fast the filters and starting a run are,
+to choose a backend for DIR's filesystem.
+
+doctor looks for what may keep changes to the paths (the current
+directory by default) from triggering: a backend that doesn't start,
+missing or unreadable directories, more directories than the inotify
+limits allow, network filesystems, paths inside others and build or
+package directories worth ignoring. It says what to do about each, and
+exits with status 1 if any is a problem.
+
+test-ignore puts each path through the filters the options (or the
+config's jobs) set up for the paths given with -w (the current directory
+by default) and says whether a change to it would trigger, and if not,
+which rule turns it down, without watching anything. It exits with status
+1 if any of them would not trigger.
+
+check-config reads a --config file and reports everything wrong with it,
+in all of its profiles, each at its line and column: unknown keys, jobs
+defined twice, needs that don't exist or go round in circles, invalid
+durations, priorities, patterns and readiness checks, settings that
+contradict each other and paths to watch that don't exist. It exits with
+status 2 if there is anything to report. The same mistakes, bar the
+missing paths, keep the watcher from starting.
+
+snapshot walks the paths (or the config's jobs' paths) with the same ignore
+rules and writes the manifest of file times, sizes and hashes --catch-up
+keeps, to FILE or stdout, then exits.
+
+The exit status says why the watcher stopped: 0 when it was done, 1 when
+--expect-exit's run never came, 2 for invalid arguments or configuration,
+3 when the watches could not be set up, 4 when a run could not be started
+(--exit-on-spawn-error), 5 when something else failed to start, 101 if it
+crashed, having stopped its runs and restored the terminal, and 128+N
+after signal N (143 for SIGTERM).
+
+Options:
+  --config <FILE>      read jobs from FILE instead of the command line
+  --profile <NAME>     with --config, use the jobs and filters of the file's
+                       [profile.NAME]
+  -w, --watch <PATH>   watch PATH (repeatable)
+  -e, --exts <EXTS>    only trigger on these comma-separated file extensions
+  -i, --ignore <GLOB>  don't trigger on paths matching GLOB (repeatable); a
+                       relative GLOB matches at any depth, as does a directory
+                       name for everything below it
+  -c, --clear          clear the screen before each run
+  --banner             print a line after each run with its command, how it
+                       ended, how long it took and the files that triggered it
+  --banner-format <TEMPLATE>
+                       the same, laid out as TEMPLATE, with {command}, {job},
+                       {run_id}, {result}, {status}, {exit_code}, {signal},
+                       {duration}, {duration_ms}, {files} and {count} filled in
+  --tty, --no-tty      behave as if stdout were a terminal, or as if it were
+                       not, rather than seeing whether it is: when it isn't,
+                       the screen is never cleared, job names aren't colored
+                       and --strip-ansi applies; when forced, runs get
+                       CLICOLOR_FORCE=1 or NO_COLOR=1 to follow suit
+  -x, --exec <ARGS>    run `cargo ARGS`; several -x run one after the other,
+                       and changes to Cargo.toml or Cargo.lock run them with
+                       --locked, after a longer debounce window
+  --cargo-fetch        with -x, run `cargo fetch --locked` first when
+                       Cargo.toml or Cargo.lock change
+  --shell <SHELL>      run the command with SHELL -c (default /bin/sh), or
+                       split it into words and run it directly with `none`
+  --exit-on-spawn-error
+                       exit when the command can't be started at all (missing,
+                       not executable) instead of waiting for the next change
+  --cpuset <CPUS>      run the command on these CPUs only, e.g. 0-3 or 0,2,4-7
+  --detach             start runs in their own session and never kill them
+  --quiet              don't report progress while setting up watches, nor how
+                       many directories are watched once done
+  --quiet-success      hold output back and only show it when a run fails
+  --tail-on-failure <N>
+                       with --quiet-success, show only the last N lines
+  --diff-output        hold output back and print a unified diff against the
+                       previous run's output when the run ends (all of it the
+                       first time)
+  --strip-ansi         remove color codes from captured output written to files
+  --tee <FILE>         show output live and also append it to FILE
+  --output-fifo <PATH> create a named pipe at PATH that gets a copy of the output
+                       while something reads it (lines it can't take are dropped,
+                       and lines over 4 KiB cut short)
+  --log-per-run <DIR>  also write each run's output to its own numbered file in
+                       DIR (run-0001.log, …), headed by what triggered it
+  --run-uuid           give each run a random UUID besides its number, in
+                       $ROF_RUN_UUID, the log lines and the JSON events
+  --tmpdir-per-run     give each run a fresh, empty temporary directory, in
+                       $ROF_TMPDIR and $TMPDIR, deleted once the run is over
+  --keep-failed-tmpdirs
+                       with --tmpdir-per-run, keep the directories of failed
+                       runs to look into, saying where they are
+  --keep-logs <N>      keep only the N newest run logs, and N old copies of a
+                       rotated log file
+  --max-log-size <BYTES>
+                       rotate the log file and --tee file once they grow past
+                       this size, and prune run logs to this total (K/M/G
+                       suffixes allowed)
+  --timestamps-output  prefix each output line with the time since the run started
+  --max-output <BYTES> in capture modes, drop output beyond this size per run
+                       (K/M/G suffixes allowed)
+  --summary-file <PATH>
+                       write a JSON summary of all runs on exit
+  --status-file <PATH> keep a line like `state=running run=4 last_exit=0
+                       last_duration_ms=1520` in PATH, rewritten as it
+                       changes, for tmux or prompt status bars
+  --state-dir <DIR>    keep the history of runs in DIR across sessions, for
+                       ctl history and --stats
+  --on-exit <CMD>      run CMD once when the watcher shuts down
+  --on-exit-timeout <SECS>
+                       kill the exit hook if it runs longer (default 10s)
+  --self-reload        re-exec the watcher when its own binary is rebuilt
+  --seamless-reload    take SIGHUP to re-exec the watcher, re-reading --config,
+                       rather than to quit; on any reload, runs waiting to start
+                       start afterwards, and running commands are stopped and
+                       started again from scratch (they don't keep running)
+  --no-expand          don't expand $VAR / ${VAR} in the command and paths
+  --depth <N>          watch only N directory levels below each path, picking up
+                       new directories within that depth as they appear
+  --non-recursive      same as --depth 0
+  --path-backend <PATH>=<native|poll>
+                       watch PATH and below with the platform's notifications or
+                       by polling (for NFS and similar mounts), mixing both;
+                       the closest PATH wins
+  --poll-interval <MS> how often polled paths are looked at (default 1000)
+  --poll-backoff <DURATION>
+                       once nothing has changed for a minute, poll less and
+                       less often, up to every DURATION (e.g. 30s), until
+                       the next change
+  --backend-opt <KEY>=<VALUE>
+                       tune the backends: follow_symlinks=false stops following
+                       symlinked directories, compare_contents=true has polling
+                       compare file contents rather than times and sizes
+  --watch-threads <N>  set up the native watches of large trees from N threads,
+                       each with an inotify instance of its own (default 1)
+  --filter-threads <N> filter bursts of events (a branch switch, say) on N
+                       threads, the events waiting at once as one batch
+                       (default 1)
+  --allow-missing      skip paths that don't exist (yet) instead of failing, and
+                       watch them once they appear
+  --stdin              read the paths to watch from stdin, one per line
+                       (e.g. find . -name '*.py' | run_on_file_change --stdin pytest)
+  -d, --exit-on-new-file
+                       exit when a new file appears next to the watched files,
+                       so a surrounding loop can rebuild the list (as entr -d)
+  --ready-when <CHECK> report when a run is ready: tcp:PORT (accepts
+                       connections), http:HOST:PORT/PATH (answers 2xx/3xx) or
+                       log:REGEX (prints a matching line)
+  --livereload [PORT]  reload browser pages after each successful run (or once
+                       --ready-when passes), via a WebSocket on PORT (35729)
+  --serve <DIR[:PORT]> serve DIR over HTTP on PORT (8080), with the live reload
+                       script injected into HTML pages (implies --livereload)
+  --proxy <FRONT->BACK>
+                       proxy port FRONT to the command's port BACK, holding
+                       requests while it restarts until --ready-when passes
+                       (tcp:BACK unless given)
+  --git-tracked-only   only trigger on files tracked by git
+  --describe-changes   log what changed in tracked files since the last run
+                       (files, lines added and removed) when a change triggers,
+                       and say so in notifications
+  --on-git <EVENTS>    also run on repository events: a comma-separated list of
+                       commit, checkout and merge; paths become optional
+  --on-success <CMD>, --on-failure <CMD>
+                       once the command has exited, run CMD next depending on
+                       how it did (e.g. deploy a preview when tests pass); a
+                       new change stops it like the command itself
+  --on-checkout <CMD>  run CMD instead of the command when a branch switch is
+                       detected (e.g. a full rather than incremental build)
+  --command-for <GLOB>=<CMD>
+                       run CMD instead of the command for changes to paths
+                       matching GLOB (as for -i; repeatable, the first match
+                       wins)
+  --group-by dir[:<DEPTH>]
+                       run the command separately for each directory DEPTH
+                       levels (default 1) below the watched ones, with {dir}
+                       in it replaced by the directory (e.g. `make -C {dir}`):
+                       a change to services/a and one to services/b make two
+                       runs; other changes, and directories made later, are
+                       not watched until a restart
+  --stats              report watched directories, inotify usage and event counts
+                       after startup and on exit (send SIGUSR1 for them any time)
+  --max-file-size <BYTES>
+                       ignore changes to files larger than this (K/M/G allowed)
+  --text-only          ignore changes to files that look binary (contain NUL bytes)
+  --ignore-whitespace-only
+                       ignore changes that only touch whitespace: trailing spaces,
+                       blank lines, line endings, indentation where it doesn't matter
+  --ignore-comments    also ignore changes that only touch comments, in files of
+                       languages recognized by their extension (both wait for
+                       files to settle, 100ms unless --settle says otherwise)
+  --include-metadata   also trigger on changes to only permissions, ownership or
+                       other metadata (chmod -R, chown), ignored by default
+  --hidden             also trigger on dotfiles and in dot-directories below the
+                       watched directories (ignored by default)
+  --ignore-case        match globs, -i patterns and -e extensions regardless of
+                       case, as on case-insensitive filesystems
+  --wait-port <PORT>   after stopping a run, also wait until nothing listens on
+                       PORT before starting the next one (repeatable)
+  --expects-port <PORT>
+                       the command listens on PORT: before each run, wait until
+                       it is free, saying which process holds it, and skip the
+                       run if it still isn't after 5s (repeatable)
+  --kill-if-rss-exceeds <MB>
+                       kill a run once its processes together use more than MB
+                       megabytes of resident memory
+  --max-runs <N>       exit once N runs have finished
+  --expect-exit <CODE> exit successfully once a run exits with CODE, and with
+                       status 1 if none has by the end (for test harnesses)
+  --within <DURATION>  with --expect-exit, give up after DURATION (e.g. 120s)
+  --every <DURATION>   also run the command every DURATION (e.g. 15m), changes or
+                       not; a scheduled run is skipped while another is running,
+                       and paths become optional
+  --cron <SCHEDULE>    also run the command on a crontab schedule such as
+                       \"0 9 * * 1-5\" (minute hour day month weekday, local
+                       time), like --every
+  --quiet-hours <WINDOW>
+                       hold changes back during WINDOW of local time, such as
+                       22:00-07:00, mon-fri 12:00-13:00 or sat,sun (days as
+                       in crontab), and run for them when it ends; scheduled
+                       runs are skipped. May be given more than once
+  --quiet-hours-mute   send no --notify notifications during quiet hours
+  --min-debounce <DURATION>, --max-debounce <DURATION>
+                       adapt the debounce window to bursts of changes: it
+                       starts at the minimum (100ms) and doubles with every
+                       change inside it, up to the maximum (8s), counting from
+                       the latest change (a branch switch or npm install runs
+                       once, a single edit runs right away)
+  --settle <MS>        only trigger once a changed file's size and mtime have
+                       stayed the same for MS (e.g. files still being copied in)
+  --priority <GLOB>=<high|low>
+                       schedule changes to paths matching GLOB (as for -i)
+                       differently: high ones skip the debounce window and stop
+                       a run that is going at once, even with --cancel-window
+                       or --reload-signal; low ones let it finish and run
+                       after it (repeatable; the highest match wins)
+  --trigger-file <NAME>
+                       creating or touching NAME (in each watched directory if
+                       relative) forces a run, bypassing filters and debounce;
+                       the watcher deletes it once seen (repeatable)
+  --relative-to <DIR>  hand the changed paths to the command, hooks, scripts and
+                       plugins, and log them, relative to DIR (`.` for the
+                       current directory) with symlinks resolved
+  --catch-up           remember the watched files between sessions and, on
+                       start, run for what changed while the watcher was off
+  --control            accept ctl inject and ctl watch on a socket for this
+                       directory
+  --control-socket <PATH>
+                       the same, on a socket at PATH
+  --fifo <PATH>        create a named pipe at PATH; each line written to it forces
+                       a run, with the line in $ROF_TRIGGER_MESSAGE
+  --control-stdin      take commands from stdin, one per line: trigger, pause,
+                       resume, quit, set debounce MS, profile NAME (which
+                       restarts the watcher with --profile NAME) and command
+                       CMD, which runs CMD instead from then on; `command`
+                       alone lists those used so far, for `command !N` to go
+                       back to, and watch add|remove DIR; runs get no stdin,
+                       and its end quits
+  --simulate           watch nothing and read events as `KIND PATH…` lines
+                       from stdin instead, to try out filters and hooks
+  -vv, --debug-events  log every raw event (kind, paths, flags, backend) and
+                       what the filters made of it
+  --record <FILE>      write every event received to FILE, one JSON line each
+  --replay <FILE>      watch nothing and feed back the events recorded in FILE
+  --replay-speed <X>   replay X times as fast as recorded (0: no pauses)
+  --tmux-send <PANE>:<KEYS>
+                       instead of a command of its own, send KEYS to tmux pane
+                       PANE (e.g. 'dev:1.0:Up Enter' re-runs its last command);
+                       every argument is then a path
+  --k8s-rollout <KIND>/<NAME> [-n <NAMESPACE>]
+                       instead of a command of its own, restart a deployment,
+                       daemonset or statefulset with kubectl and follow the
+                       rollout; every argument is then a path
+  --pair <HOST:PORT>   instead of a command of its own, have the pair-serve at
+                       HOST:PORT run its command and stream back the output;
+                       every argument is then a path (experimental)
+  --coordinate <DIR>   share jobs with other watchers through lock files in DIR:
+                       one runs each job, the others stand by to take over
+  --script <FILE>      ask the Rhai script FILE about each change that passes
+                       the filters: its on_event(event) returns true to run,
+                       false to skip, or a command to run instead
+  --plugin <FILE>      load the WebAssembly plugin FILE, which can filter
+                       changes and hear about runs starting and ending
+                       (repeatable; see src/plugin.rs for the interface)
+  --paranoid           before each run, check that the config file, the script,
+                       the plugins and the files the command names belong to
+                       this user or root and that no one else can write to them
+                       or any directory above them, and refuse to run if not
+                       (for watchers running with extra privileges)
+  --run-on-resume      when resumed after SIGUSR2 paused the watcher, run once if
+                       changes came in meanwhile (they are ignored otherwise)
+  --command-writes <GLOB>
+                       the command writes files matching GLOB (as for -i): ignore
+                       changes to them while it runs and shortly after (repeatable)
+  --ignore-during-run  ignore all changes while a run is going and shortly after,
+                       so the command's own output can never retrigger it
+  --report-writes      after each run, log the watched files it wrote that the
+                       filters let through, with -i patterns that would ignore
+                       them, to track down a command triggering itself
+  --allow-cycles       with --config, let jobs whose writes trigger each other
+                       keep doing so (otherwise a cycle is reported, and broken
+                       once a job comes round for the third time)
+  --notify <TARGET>    announce finished runs: bell, desktop (notify-send) or
+                       webhook:http://HOST[:PORT]/PATH (JSON POST); repeatable
+  --notify-on <WHEN>   always (default), failure, or change: only when a run's
+                       outcome differs from the previous one's (a failing first
+                       run counts)
+  --dbus               emit RunStarted / RunFinished signals on the D-Bus session
+                       bus (see src/dbus.rs)
+  --mqtt <URL>         publish run events as JSON to the MQTT broker at
+                       mqtt://[USER:PASSWORD@]HOST[:PORT]; the finished ones
+                       are retained
+  --mqtt-topic <TOPIC> topic to publish on (default run_on_file_change)
+  --signal <SIG>       signal sent to the previous run on restart (default SIGTERM)
+  --reload-signal <SIG>
+                       on a change while a run is going, send SIG (e.g. SIGHUP)
+                       to its process group and leave it running, for servers
+                       that reload in place; a run that has ended is restarted
+  --drop-grace <DURATION>
+                       how long a run the watcher lets go of while it is still
+                       going (as a panic or reload can) gets to stop after the
+                       --signal before its process group is killed (default 2s)
+  --cancel-window <DURATION>
+                       on a change while a run is going, let it go on for this
+                       long (bare numbers are ms) before stopping it, so the
+                       rest of a save-all restarts it just once
+  --subreaper          adopt orphaned descendants so they can be reaped
+  --timestamp-format <FMT>
+                       strftime format for log lines (default \"%Y-%m-%d %H:%M:%S\")
+  --utc                log times in UTC instead of local time
+  --no-timestamps      log lines without a timestamp
+  --log-target <T>     stdout (default), stderr, syslog, journald, file:<path>,
+                       jsonl:<path> (JSON lines) or webhook:http://HOST[:PORT]/PATH
+                       (a JSON POST per line); repeatable, to log to several
+
+Log verbosity follows RUST_LOG (e.g. RUST_LOG=debug to trace every event).";
+
+pub struct Options {
+  pub command: String,
+  pub paths: Vec<PathBuf>,
+  pub detach: bool,
+  pub signal: Signal,
+  /// Signal that asks a run to reload rather than be restarted.
+  pub reload_signal: Option<Signal>,
+  /// How long a run may go on after a change before it is stopped.
+  pub cancel_window: Option<Duration>,
+  /// How long a run dropped while going gets before it is killed.
+  pub drop_grace: Duration,
+  pub subreaper: bool,
+  pub timestamps: Timestamps,
+  /// Where log lines go; stdout if nowhere.
+  pub log_targets: Vec<LogTarget>,
+  pub output: OutputConfig,
+  pub summary_file: Option<PathBuf>,
+  /// One-line status for status bars (`--status-file`).
+  pub status_file: Option<PathBuf>,
+  /// No progress or totals for setting up watches.
+  pub quiet: bool,
+  /// Where the run history is kept (`--state-dir`).
+  pub state_dir: Option<PathBuf>,
+  pub on_exit: Option<String>,
+  pub on_exit_timeout: Duration,
+  pub self_reload: bool,
+  /// SIGHUP reloads, and reloads carry the runs over (`--seamless-reload`).
+  pub seamless_reload: bool,
+  /// Expand environment variables in the command (per run) and paths.
+  pub expand_env: bool,
+  /// Depth limit for watching; `None` is fully recursive.
+  pub depth: Option<usize>,
+  /// `--allow-missing`: watch paths once they exist.
+  pub allow_missing: bool,
+  /// Debounce windows for changes to some paths, from the config's
+  /// `[debounce]` table.
+  pub debounce: Vec<(String, Duration)>,
+  /// `--priority`, and the config's `[priority]` table.
+  pub priorities: Vec<(String, Priority)>,
+  /// `--command-for`, and -x's commands for the Cargo manifests.
+  pub command_for: Vec<(String, String)>,
+  /// `--group-by dir:DEPTH`'s depth.
+  pub group_by: Option<usize>,
+  /// `--path-backend` overrides, in the order given.
+  pub path_backends: Vec<(PathBuf, Backend)>,
+  pub poll_interval: Duration,
+  /// Longest poll interval to back off to while nothing changes.
+  pub poll_backoff: Option<Duration>,
+  pub backend_opts: BackendOpts,
+  /// Threads registering native recursive watches (`--watch-threads`).
+  pub watch_threads: usize,
+  /// `--filter-threads`: threads bursts of events are filtered on.
+  pub filter_threads: usize,
+  pub stdin: bool,
+  pub exit_on_new_file: bool,
+  /// Exit when a run can't be started, rather than carry on watching.
+  pub exit_on_spawn_error: bool,
+  /// Extensions (without dot) a changed file must have; empty allows all.
+  pub extensions: Vec<String>,
+  pub ignore: Vec<String>,
+  pub clear: bool,
+  /// The line printed after each run (`--banner`, `--banner-format`).
+  pub banner: Option<String>,
+  /// `--tty` or `--no-tty`: behave as if stdout were a terminal, or not,
+  /// rather than seeing whether it is.
+  pub tty: Option<bool>,
+  /// Shell the command runs through; `None` executes it directly.
+  pub shell: Option<String>,
+  /// File that gets a copy of the output (`--tee`).
+  pub tee: Option<PathBuf>,
+  /// Named pipe that gets a copy of the output (`--output-fifo`).
+  pub output_fifo: Option<PathBuf>,
+  /// Directory for a log file per run (`--log-per-run`).
+  pub log_per_run: Option<PathBuf>,
+  /// Give each run a UUID as well as its number.
+  pub run_uuid: bool,
+  /// Give each run a scratch directory of its own (`--tmpdir-per-run`).
+  pub tmpdir_per_run: bool,
+  /// Leave failed runs' scratch directories in place.
+  pub keep_failed_tmpdirs: bool,
+  /// How many and how large log files may get.
+  pub retention: rotate::Retention,
+  /// CPUs the command may run on (`--cpuset`).
+  pub cpuset: Option<Vec<usize>>,
+  /// Network check for when a run is ready; log patterns live in `output`.
+  pub ready_when: Option<Probe>,
+  /// Port of the live reload server, if enabled.
+  pub livereload: Option<u16>,
+  /// Directory and port of the static file server, if enabled.
+  pub serve: Option<(PathBuf, u16)>,
+  /// Front and backend port of the hold-and-forward proxy, if enabled.
+  pub proxy: Option<(u16, u16)>,
+  pub git_tracked_only: bool,
+  pub describe_changes: bool,
+  /// Git events that trigger runs (`--on-git`).
+  pub on_git: Vec<GitEvent>,
+  /// Command for branch switches, instead of `command`.
+  pub on_checkout: Option<String>,
+  /// Commands run after the command, by how it exited.
+  pub on_success: Option<String>,
+  pub on_failure: Option<String>,
+  pub stats: bool,
+  pub max_file_size: Option<u64>,
+  pub text_only: bool,
+  /// `--ignore-whitespace-only` / `--ignore-comments`.
+  pub ignore_whitespace: bool,
+  pub ignore_comments: bool,
+  /// `--include-metadata`: metadata-only changes trigger too.
+  pub include_metadata: bool,
+  pub hidden: bool,
+  pub ignore_case: bool,
+  /// Ports a stopped run must have released before the next one starts.
+  pub wait_ports: Vec<u16>,
+  /// Ports that must be free before a run starts (`--expects-port`).
+  pub expect_ports: Vec<u16>,
+  /// Memory a run may use before it is killed, in KiB.
+  pub rss_limit_kb: Option<u64>,
+  /// Exit after this many finished runs (`--max-runs`).
+  pub max_runs: Option<usize>,
+  /// Exit code a run has to achieve (`--expect-exit`), and the time it
+  /// has to do so (`--within`).
+  pub expect_exit: Option<i32>,
+  pub within: Option<Duration>,
+  /// Also run the command at this interval, changes or not.
+  pub every: Option<Duration>,
+  /// Also run the command on this crontab schedule.
+  pub cron: Option<Schedule>,
+  /// Windows in which changes wait and scheduled runs are skipped.
+  pub quiet_hours: Vec<Window>,
+  /// No notifications in those windows either.
+  pub quiet_hours_mute: bool,
+  /// Bounds of the adaptive debounce window, both set when either is given.
+  pub min_debounce: Option<Duration>,
+  pub max_debounce: Option<Duration>,
+  /// Hold changes until the file has stayed the same for this long.
+  pub settle: Option<Duration>,
+  /// Files whose creation or touch forces a run (`--trigger-file`).
+  pub trigger_files: Vec<PathBuf>,
+  /// Base the changed paths are reported relative to (`--relative-to`).
+  pub relative_to: Option<PathBuf>,
+  /// `snapshot`: where to write the manifest (`-` for stdout).
+  pub snapshot: Option<PathBuf>,
+  /// `test-ignore`: the paths to put through the filters.
+  pub test_ignore: Option<Vec<PathBuf>>,
+  /// Run on start for changes made while the watcher was off.
+  pub catch_up: bool,
+  /// Socket for `ctl inject` (`--control`, `--control-socket`).
+  pub control: Option<PathBuf>,
+  /// Named pipe whose lines force runs (`--fifo`).
+  pub fifo: Option<PathBuf>,
+  /// Take commands from stdin (`--control-stdin`).
+  pub control_stdin: bool,
+  /// Take events from stdin rather than the filesystem.
+  pub simulate: bool,
+  /// Log raw events and the decisions taken on them.
+  pub debug_events: bool,
+  /// Where raw events are recorded (`--record`).
+  pub record: Option<PathBuf>,
+  /// Recording to play back instead of watching (`--replay`), and how
+  /// much faster.
+  pub replay: Option<PathBuf>,
+  pub replay_speed: f64,
+  /// Where watchers sharing jobs keep their locks (`--coordinate`).
+  pub coordinate: Option<PathBuf>,
+  /// Pane and keys for `--tmux-send`, which stands in for the command.
+  pub tmux_send: Option<(String, Vec<String>)>,
+  /// Workload and namespace for `--k8s-rollout`, likewise.
+  pub k8s_rollout: Option<(String, Option<String>)>,
+  /// Server that runs the command for this watcher (`--pair`), likewise.
+  pub pair: Option<String>,
+  /// Rhai script with the last say on each change (`--script`).
+  pub script: Option<PathBuf>,
+  /// WebAssembly plugins (`--plugin`).
+  pub plugins: Vec<PathBuf>,
+  /// Check who can change what runs before each run (`--paranoid`).
+  pub paranoid: bool,
+  /// Run once on resume if changes were let go while paused.
+  pub run_on_resume: bool,
+  /// Globs the command writes, ignored while it runs.
+  pub command_writes: Vec<String>,
+  /// Ignore every change made while a run is going.
+  pub ignore_during_run: bool,
+  /// `--report-writes`: log what each run wrote in the watched trees.
+  pub report_writes: bool,
+  /// Don't break chains of jobs triggering each other.
+  pub allow_cycles: bool,
+  /// Where to announce finished runs.
+  pub notify: Vec<Sink>,
+  pub notify_on: notification::When,
+  /// Emit run signals on the session bus.
+  pub dbus: bool,
+  pub mqtt: Option<mqtt::Broker>,
+  pub mqtt_topic: String,
+  /// Jobs from `--config`; empty when the command line gives the command.
+  pub jobs: Vec<Job>,
+  /// The config file's hooks.
+  pub hooks: Hooks,
+  pub config: Option<PathBuf>,
+  /// `--profile`, and the names of all the config's profiles.
+  pub profile: Option<String>,
+  pub profiles: Vec<String>,
+}
+
+impl Default for Options {
+  fn default() -> Self {
+    Options {
+      command: String::new(),
+      paths: Vec::new(),
+      detach: false,
+      signal: Signal::SIGTERM,
+      reload_signal: None,
+      cancel_window: None,
+      drop_grace: Duration::from_secs(2),
+      subreaper: false,
+      timestamps: Timestamps::default(),
+      log_targets: Vec::new(),
+      output: OutputConfig::default(),
+      summary_file: None,
+      status_file: None,
+      state_dir: None,
+      quiet: false,
+      on_exit: None,
+      on_exit_timeout: Duration::from_secs(10),
+      self_reload: false,
+      seamless_reload: false,
+      expand_env: true,
+      depth: None,
+      allow_missing: false,
+      debounce: Vec::new(),
+      priorities: Vec::new(),
+      command_for: Vec::new(),
+      group_by: None,
+      path_backends: Vec::new(),
+      poll_interval: Duration::from_secs(1),
+      poll_backoff: None,
+      backend_opts: BackendOpts::default(),
+      watch_threads: 1,
+      filter_threads: 1,
+      stdin: false,
+      exit_on_new_file: false,
+      exit_on_spawn_error: false,
+      extensions: Vec::new(),
+      ignore: Vec::new(),
+      clear: false,
+      banner: None,
+      tty: None,
+      shell: Some("/bin/sh".to_string()),
+      cpuset: None,
+      tee: None,
+      output_fifo: None,
+      log_per_run: None,
+      run_uuid: false,
+      tmpdir_per_run: false,
+      keep_failed_tmpdirs: false,
+      retention: rotate::Retention::default(),
+      ready_when: None,
+      livereload: None,
+      serve: None,
+      proxy: None,
+      git_tracked_only: false,
+      describe_changes: false,
+      on_git: Vec::new(),
+      on_checkout: None,
+      on_success: None,
+      on_failure: None,
+      stats: false,
+      max_file_size: None,
+      text_only: false,
+      ignore_whitespace: false,
+      include_metadata: false,
+      ignore_comments: false,
+      hidden: false,
+      ignore_case: false,
+      wait_ports: Vec::new(),
+      expect_ports: Vec::new(),
+      rss_limit_kb: None,
+      max_runs: None,
+      expect_exit: None,
+      within: None,
+      every: None,
+      cron: None,
+      quiet_hours: Vec::new(),
+      quiet_hours_mute: false,
+      min_debounce: None,
+      max_debounce: None,
+      settle: None,
+      trigger_files: Vec::new(),
+      relative_to: None,
+      snapshot: None,
+      test_ignore: None,
+      catch_up: false,
+      coordinate: None,
+      tmux_send: None,
+      k8s_rollout: None,
+      pair: None,
+      control: None,
+      fifo: None,
+      control_stdin: false,
+      simulate: false,
+      debug_events: false,
+      record: None,
+      replay: None,
+      replay_speed: 1.0,
+      script: None,
+      plugins: Vec::new(),
+      paranoid: false,
+      run_on_resume: false,
+      command_writes: Vec::new(),
+      ignore_during_run: false,
+      report_writes: false,
+      allow_cycles: false,
+      notify: Vec::new(),
+      notify_on: notification::When::default(),
+      dbus: false,
+      mqtt: None,
+      mqtt_topic: "run_on_file_change".into(),
+      jobs: Vec::new(),
+      hooks: Hooks::default(),
+      config: None,
+      profile: None,
+      profiles: Vec::new(),
+    }
+  }
+}
+
+impl Options {
+  /// Whether stdout is taken for a terminal, to clear and color.
+  pub fn interactive(&self) -> bool {
+    self.tty.unwrap_or_else(|| io::stdout().is_terminal())
+  }
+
+  /// Whether runs report readiness through `--ready-when`.
+  pub fn has_ready_check(&self) -> bool {
+    self.ready_when.is_some() || self.output.ready_log.is_some()
+  }
+}
+
+/// An -x command (`cargo ARGS`) with --locked after its subcommand, and
+/// before any `--` that hands the rest to the program.
+fn locked(command: &str) -> String {
+  let mut words: Vec<&str> = command.split_whitespace().collect();
+  if words.contains(&"--locked") {
+    return command.to_string();
+  }
+  // cargo, then maybe a +toolchain, then the subcommand
+  let at = match words.get(1) {
+    Some(word) if word.starts_with('+') => 3,
+    _ => 2,
+  };
+  words.insert(at.min(words.len()), "--locked");
+  words.join(" ")
+}
+
+/// `--group-by dir[:DEPTH]`'s depth.
+fn group_depth(v: &str) -> usize {
+  match v.split_once(':') {
+    None if v == "dir" => 1,
+    Some(("dir", depth)) => depth
+      .parse()
+      .ok()
+      .filter(|&d| d > 0)
+      .unwrap_or_else(|| usage_error(&format!("invalid --group-by depth {depth:?}"))),
+    _ => usage_error(&format!("expected dir or dir:DEPTH, got {v:?}")),
+  }
+}
+
+fn usage_error(msg: &str) -> ! {
+  eprintln!("Error: {msg}\n\n{USAGE}");
+  std::process::exit(exit::CONFIG);
+}
+
+/// "0-3,6" as [0, 1, 2, 3, 6].
+fn cpu_list(s: &str) -> Option<Vec<usize>> {
+  let mut cpus = Vec::new();
+  for part in s.split(',') {
+    let (first, last) = part.split_once('-').unwrap_or((part, part));
+    let (first, last): (usize, usize) = (first.trim().parse().ok()?, last.trim().parse().ok()?);
+    if first > last {
+      return None;
+    }
+    cpus.extend(first..=last);
+  }
+  Some(cpus)
+}
+
+fn value(args: &mut impl Iterator<Item = String>, name: &str) -> String {
+  args
+    .next()
+    .unwrap_or_else(|| usage_error(&format!("{name} requires a value")))
+}
+
+/// A duration like `500ms`, `10s`, `2m` or `1h`; a bare number is taken
+/// in `bare_unit`.
+pub fn parse_duration(s: &str, bare_unit: Duration) -> Option<Duration> {
+  let split = s
+    .find(|c: char| !c.is_ascii_digit() && c != '.')
+    .unwrap_or(s.len());
+  let (number, unit) = s.split_at(split);
+  let n: f64 = number.parse().ok()?;
+  let unit = match unit {
+    "" => bare_unit,
+    "ms" => Duration::from_millis(1),
+    "s" => Duration::from_secs(1),
+    "m" => Duration::from_secs(60),
+    "h" => Duration::from_secs(3600),
+    _ => return None,
+  };
+  Duration::try_from_secs_f64(unit.as_secs_f64() * n).ok()
+}
+
+fn duration_value(
+  args: &mut impl Iterator<Item = String>,
+  name: &str,
+  bare_unit: Duration,
+) -> Duration {
+  let v = value(args, name);
+  parse_duration(&v, bare_unit)
+    .unwrap_or_else(|| usage_error(&format!("invalid duration {v:?} for {name}")))
+}
+
+/// A byte count with an optional K/M/G suffix (powers of 1024).
+fn parse_size(s: &str) -> Option<u64> {
+  let (digits, unit) = match s.char_indices().find(|(_, c)| !c.is_ascii_digit()) {
+    Some((i, _)) => s.split_at(i),
+    None => (s, ""),
+  };
+  let factor = match unit.to_ascii_uppercase().as_str() {
+    "" | "B" => 1,
+    "K" | "KB" | "KIB" => 1 << 10,
+    "M" | "MB" | "MIB" => 1 << 20,
+    "G" | "GB" | "GIB" => 1 << 30,
+    _ => return None,
+  };
+  digits.parse::<u64>().ok()?.checked_mul(factor)
+}
+
+/// Accepts `SIGINT`, `INT`, `int` or a plain signal number.
+fn parse_signal(s: &str) -> Option<Signal> {
+  if let Ok(n) = s.parse::<i32>() {
+    return Signal::try_from(n).ok();
+  }
+  let upper = s.to_ascii_uppercase();
+  let name = if upper.starts_with("SIG") {
+    upper
+  } else {
+    format!("SIG{upper}")
+  };
+  Signal::from_str(&name).ok()
+}
+
+pub fn parse() -> Options {
+  let mut args = env::args().skip(1).peekable(); // skip program name
+  let mut opts = Options::default();
+  let mut positional = Vec::new();
+  let mut watched = Vec::new();
+  let mut cargo = Vec::new();
+  let mut cargo_fetch = false;
+  if args.next_if(|a| a == "ctl").is_some() {
+    control::client(args);
+  }
+  if args.next_if(|a| a == "daemon").is_some() {
+    daemon::run(args);
+  }
+  if args.next_if(|a| a == "pair-serve").is_some() {
+    pair::run(args);
+  }
+  if args.next_if(|a| a == "pair-run").is_some() {
+    pair::client(args);
+  }
+  if args.next_if(|a| a == "bench").is_some() {
+    bench::run(args);
+  }
+  if args.next_if(|a| a == "doctor").is_some() {
+    doctor::run(args);
+  }
+  if args.next_if(|a| a == "check-config").is_some() {
+    jobs::check_config(args);
+  }
+  let snapshot = args.next_if(|a| a == "snapshot").is_some();
+  let test_ignore = args.next_if(|a| a == "test-ignore").is_some();
+
+  while let Some(arg) = args.next() {
+    match arg.as_str() {
+      "-o" | "--output" if snapshot => {
+        opts.snapshot = Some(value(&mut args, "--output").into());
+      }
+      "--config" => opts.config = Some(value(&mut args, "--config").into()),
+      "--profile" => opts.profile = Some(value(&mut args, "--profile")),
+      "--detach" => opts.detach = true,
+      "--quiet" => opts.quiet = true,
+      "--quiet-success" => opts.output.quiet_success = true,
+      "--tail-on-failure" => {
+        let v = value(&mut args, "--tail-on-failure");
+        let n = v
+          .parse()
+          .unwrap_or_else(|_| usage_error(&format!("invalid line count {v:?}")));
+        opts.output.tail_on_failure = Some(n);
+        opts.output.quiet_success = true;
+      }
+      "--strip-ansi" => opts.output.strip_ansi = true,
+      "--tee" => opts.tee = Some(value(&mut args, "--tee").into()),
+      "--output-fifo" => opts.output_fifo = Some(value(&mut args, "--output-fifo").into()),
+      "--log-per-run" => opts.log_per_run = Some(value(&mut args, "--log-per-run").into()),
+      "--run-uuid" => opts.run_uuid = true,
+      "--tmpdir-per-run" => opts.tmpdir_per_run = true,
+      "--keep-failed-tmpdirs" => opts.keep_failed_tmpdirs = true,
+      "--keep-logs" => {
+        let v = value(&mut args, "--keep-logs");
+        let n = v
+          .parse()
+          .unwrap_or_else(|_| usage_error(&format!("invalid log count {v:?}")));
+        opts.retention.keep = Some(n);
+      }
+      "--max-log-size" => {
+        let v = value(&mut args, "--max-log-size");
+        let max = parse_size(&v).unwrap_or_else(|| usage_error(&format!("invalid size {v:?}")));
+        opts.retention.max_size = Some(max);
+      }
+      "--diff-output" => opts.output.diff_output = true,
+      "--timestamps-output" => opts.output.timestamps = true,
+      "--max-output" => {
+        let v = value(&mut args, "--max-output");
+        let max = parse_size(&v).unwrap_or_else(|| usage_error(&format!("invalid size {v:?}")));
+        opts.output.max_output = Some(max);
+      }
+      "--summary-file" => opts.summary_file = Some(value(&mut args, "--summary-file").into()),
+      "--status-file" => opts.status_file = Some(value(&mut args, "--status-file").into()),
+      "--state-dir" => opts.state_dir = Some(value(&mut args, "--state-dir").into()),
+      "--on-exit" => opts.on_exit = Some(value(&mut args, "--on-exit")),
+      "--on-exit-timeout" => {
+        opts.on_exit_timeout =
+          duration_value(&mut args, "--on-exit-timeout", Duration::from_secs(1))
+      }
+      "--self-reload" => opts.self_reload = true,
+      "--seamless-reload" => opts.seamless_reload = true,
+      "--no-expand" => opts.expand_env = false,
+      "--depth" => {
+        let v = value(&mut args, "--depth");
+        let depth = v
+          .parse()
+          .unwrap_or_else(|_| usage_error(&format!("invalid depth {v:?}")));
+        opts.depth = Some(depth);
+      }
+      "--non-recursive" => opts.depth = Some(0),
+      "--allow-missing" => opts.allow_missing = true,
+      "--priority" => {
+        let v = value(&mut args, "--priority");
+        let (pattern, priority) = v
+          .rsplit_once('=')
+          .unwrap_or_else(|| usage_error(&format!("expected GLOB=PRIORITY, got {v:?}")));
+        let priority = priority.parse().unwrap_or_else(|e: String| usage_error(&e));
+        opts.priorities.push((pattern.to_string(), priority));
+      }
+      "--path-backend" => {
+        let v = value(&mut args, "--path-backend");
+        let (path, backend) = v
+          .rsplit_once('=')
+          .unwrap_or_else(|| usage_error(&format!("expected PATH=BACKEND, got {v:?}")));
+        let backend = backend.parse().unwrap_or_else(|e: String| usage_error(&e));
+        opts.path_backends.push((path.into(), backend));
+      }
+      "--poll-interval" => {
+        opts.poll_interval = duration_value(&mut args, "--poll-interval", Duration::from_millis(1))
+      }
+      "--poll-backoff" => {
+        opts.poll_backoff = Some(duration_value(
+          &mut args,
+          "--poll-backoff",
+          Duration::from_millis(1),
+        ))
+      }
+      "--backend-opt" => {
+        let v = value(&mut args, "--backend-opt");
+        let (key, val) = v
+          .split_once('=')
+          .unwrap_or_else(|| usage_error(&format!("expected KEY=VALUE, got {v:?}")));
+        if let Err(e) = opts.backend_opts.set(key, val) {
+          usage_error(&format!("invalid --backend-opt: {e}"));
+        }
+      }
+      "--watch-threads" => {
+        let v = value(&mut args, "--watch-threads");
+        opts.watch_threads = v
+          .parse()
+          .ok()
+          .filter(|&n| n > 0)
+          .unwrap_or_else(|| usage_error(&format!("invalid thread count {v:?}")));
+      }
+      "--filter-threads" => {
+        let v = value(&mut args, "--filter-threads");
+        opts.filter_threads = v
+          .parse()
+          .ok()
+          .filter(|&n| n > 0)
+          .unwrap_or_else(|| usage_error(&format!("invalid thread count {v:?}")));
+      }
+      "--stdin" => opts.stdin = true,
+      "-d" | "--exit-on-new-file" => opts.exit_on_new_file = true,
+      "--exit-on-spawn-error" => opts.exit_on_spawn_error = true,
+      "-w" | "--watch" => watched.push(value(&mut args, "--watch")),
+      "-e" | "--exts" => {
+        let v = value(&mut args, "--exts");
+        opts.extensions.extend(
+          v.split(',')
+            .map(|e| e.trim().trim_start_matches('.'))
+            .filter(|e| !e.is_empty())
+            .map(str::to_string),
+        );
+      }
+      "-i" | "--ignore" => opts.ignore.push(value(&mut args, "--ignore")),
+      "-c" | "--clear" => opts.clear = true,
+      "--banner" => {
+        opts
+          .banner
+          .get_or_insert_with(|| banner::DEFAULT_FORMAT.to_string());
+      }
+      "--banner-format" => opts.banner = Some(value(&mut args, "--banner-format")),
+      "--tty" => opts.tty = Some(true),
+      "--no-tty" => opts.tty = Some(false),
+      "-x" | "--exec" => cargo.push(format!("cargo {}", value(&mut args, "--exec"))),
+      "--shell" => {
+        let v = value(&mut args, "--shell");
+        opts.shell = (v != "none").then_some(v);
+      }
+      "--cpuset" => {
+        let v = value(&mut args, "--cpuset");
+        let cpus = cpu_list(&v).unwrap_or_else(|| usage_error(&format!("invalid CPU list {v:?}")));
+        opts.cpuset = Some(cpus);
+      }
+      "--ready-when" => match Probe::parse(&value(&mut args, "--ready-when")) {
+        Ok(Probe::Log(pattern)) => opts.output.ready_log = Some(pattern),
+        Ok(probe) => opts.ready_when = Some(probe),
+        Err(e) => usage_error(&e),
+      },
+      "--livereload" => {
+        let port = args.next_if(|a| a.parse::<u16>().is_ok());
+        opts.livereload = Some(port.map_or(livereload::DEFAULT_PORT, |p| p.parse().unwrap()));
+      }
+      "--serve" => {
+        let v = value(&mut args, "--serve");
+        opts.serve = Some(
+          serve::parse(&v).unwrap_or_else(|| usage_error(&format!("invalid --serve value {v:?}"))),
+        );
+      }
+      "--proxy" => {
+        let v = value(&mut args, "--proxy");
+        opts.proxy = Some(
+          proxy::parse(&v).unwrap_or_else(|| usage_error(&format!("invalid --proxy value {v:?}"))),
+        );
+      }
+      "--git-tracked-only" => opts.git_tracked_only = true,
+      "--describe-changes" => opts.describe_changes = true,
+      "--on-git" => {
+        let v = value(&mut args, "--on-git");
+        opts.on_git = GitEvent::parse_list(&v)
+          .unwrap_or_else(|| usage_error(&format!("unknown git event in {v:?}")));
+      }
+      "--on-checkout" => opts.on_checkout = Some(value(&mut args, "--on-checkout")),
+      "--command-for" => {
+        let v = value(&mut args, "--command-for");
+        let (pattern, command) = v
+          .split_once('=')
+          .filter(|(p, c)| !p.is_empty() && !c.is_empty())
+          .unwrap_or_else(|| usage_error(&format!("expected GLOB=CMD, got {v:?}")));
+        opts
+          .command_for
+          .push((pattern.to_string(), command.to_string()));
+      }
+      "--group-by" => opts.group_by = Some(group_depth(&value(&mut args, "--group-by"))),
+      "--cargo-fetch" => cargo_fetch = true,
+      "--on-success" => opts.on_success = Some(value(&mut args, "--on-success")),
+      "--on-failure" => opts.on_failure = Some(value(&mut args, "--on-failure")),
+      "--stats" => opts.stats = true,
+      "--max-file-size" => {
+        let v = value(&mut args, "--max-file-size");
+        opts.max_file_size =
+          Some(parse_size(&v).unwrap_or_else(|| usage_error(&format!("invalid size {v:?}"))));
+      }
+      "--text-only" => opts.text_only = true,
+      "--ignore-whitespace-only" => opts.ignore_whitespace = true,
+      "--include-metadata" => opts.include_metadata = true,
+      "--ignore-comments" => opts.ignore_comments = true,
+      "--hidden" => opts.hidden = true,
+      "--ignore-case" => opts.ignore_case = true,
+      "--wait-port" => {
+        let v = value(&mut args, "--wait-port");
+        let port = v
+          .parse()
+          .unwrap_or_else(|_| usage_error(&format!("invalid port {v:?}")));
+        opts.wait_ports.push(port);
+      }
+      "--expects-port" => {
+        let v = value(&mut args, "--expects-port");
+        let port = v
+          .parse()
+          .unwrap_or_else(|_| usage_error(&format!("invalid port {v:?}")));
+        opts.expect_ports.push(port);
+      }
+      "--kill-if-rss-exceeds" => {
+        let v = value(&mut args, "--kill-if-rss-exceeds");
+        let mb: u64 = v
+          .parse()
+          .ok()
+          .filter(|&mb| mb > 0)
+          .unwrap_or_else(|| usage_error(&format!("invalid memory limit {v:?}")));
+        opts.rss_limit_kb = Some(mb * 1024);
+      }
+      "--max-runs" => {
+        let v = value(&mut args, "--max-runs");
+        let n = v
+          .parse()
+          .ok()
+          .filter(|&n| n > 0)
+          .unwrap_or_else(|| usage_error(&format!("invalid run count {v:?}")));
+        opts.max_runs = Some(n);
+      }
+      "--expect-exit" => {
+        let v = value(&mut args, "--expect-exit");
+        let code = v
+          .parse()
+          .unwrap_or_else(|_| usage_error(&format!("invalid exit code {v:?}")));
+        opts.expect_exit = Some(code);
+      }
+      "--within" => {
+        opts.within = Some(duration_value(
+          &mut args,
+          "--within",
+          Duration::from_secs(1),
+        ))
+      }
+      "--every" => {
+        let every = duration_value(&mut args, "--every", Duration::from_secs(1));
+        if every.is_zero() {
+          usage_error("--every must be longer than zero");
+        }
+        opts.every = Some(every);
+      }
+      "--cron" => {
+        let v = value(&mut args, "--cron");
+        let schedule = Schedule::parse(&v)
+          .unwrap_or_else(|e| usage_error(&format!("invalid --cron schedule {v:?}: {e}")));
+        if schedule.next_after(chrono::Local::now()).is_none() {
+          usage_error(&format!("--cron schedule {v:?} never matches"));
+        }
+        opts.cron = Some(schedule);
+      }
+      "--quiet-hours" => {
+        let v = value(&mut args, "--quiet-hours");
+        let window = Window::parse(&v)
+          .unwrap_or_else(|e| usage_error(&format!("invalid --quiet-hours {v:?}: {e}")));
+        opts.quiet_hours.push(window);
+      }
+      "--quiet-hours-mute" => opts.quiet_hours_mute = true,
+      "--min-debounce" => {
+        opts.min_debounce = Some(duration_value(
+          &mut args,
+          "--min-debounce",
+          Duration::from_millis(1),
+        ))
+      }
+      "--max-debounce" => {
+        opts.max_debounce = Some(duration_value(
+          &mut args,
+          "--max-debounce",
+          Duration::from_millis(1),
+        ))
+      }
+      "--settle" => {
+        opts.settle = Some(duration_value(
+          &mut args,
+          "--settle",
+          Duration::from_millis(1),
+        ))
+      }
+      "--trigger-file" => opts
+        .trigger_files
+        .push(value(&mut args, "--trigger-file").into()),
+      "--relative-to" => opts.relative_to = Some(value(&mut args, "--relative-to").into()),
+      "--catch-up" => opts.catch_up = true,
+      "--control" => opts.control = Some(control::default_socket()),
+      "--control-socket" => opts.control = Some(value(&mut args, "--control-socket").into()),
+      "--fifo" => opts.fifo = Some(value(&mut args, "--fifo").into()),
+      "--control-stdin" => opts.control_stdin = true,
+      "--simulate" => opts.simulate = true,
+      "-vv" | "--debug-events" => opts.debug_events = true,
+      "--record" => opts.record = Some(value(&mut args, "--record").into()),
+      "--replay" => opts.replay = Some(value(&mut args, "--replay").into()),
+      "--replay-speed" => {
+        let v = value(&mut args, "--replay-speed");
+        opts.replay_speed = v
+          .parse()
+          .ok()
+          .filter(|x: &f64| x.is_finite() && *x >= 0.0)
+          .unwrap_or_else(|| usage_error(&format!("invalid replay speed {v:?}")));
+      }
+      "--coordinate" => opts.coordinate = Some(value(&mut args, "--coordinate").into()),
+      "--tmux-send" => {
+        let v = value(&mut args, "--tmux-send");
+        opts.tmux_send = Some(tmux::parse(&v).unwrap_or_else(|e| usage_error(&e)));
+      }
+      "--k8s-rollout" => {
+        let v = value(&mut args, "--k8s-rollout");
+        let target = k8s::parse(&v).unwrap_or_else(|e| usage_error(&e));
+        let namespace = args
+          .next_if(|a| a == "-n" || a == "--namespace")
+          .map(|flag| value(&mut args, &flag));
+        opts.k8s_rollout = Some((target, namespace));
+      }
+      "--pair" => opts.pair = Some(value(&mut args, "--pair")),
+      "--script" => opts.script = Some(value(&mut args, "--script").into()),
+      "--plugin" => opts.plugins.push(value(&mut args, "--plugin").into()),
+      "--paranoid" => opts.paranoid = true,
+      "--run-on-resume" => opts.run_on_resume = true,
+      "--command-writes" => opts
+        .command_writes
+        .push(value(&mut args, "--command-writes")),
+      "--ignore-during-run" => opts.ignore_during_run = true,
+      "--report-writes" => opts.report_writes = true,
+      "--allow-cycles" => opts.allow_cycles = true,
+      "--notify" => {
+        let sink = Sink::parse(&value(&mut args, "--notify")).unwrap_or_else(|e| usage_error(&e));
+        opts.notify.push(sink);
+      }
+      "--dbus" => opts.dbus = true,
+      "--mqtt" => {
+        let url = value(&mut args, "--mqtt");
+        opts.mqtt = Some(mqtt::Broker::parse(&url).unwrap_or_else(|e| usage_error(&e)));
+      }
+      "--mqtt-topic" => opts.mqtt_topic = value(&mut args, "--mqtt-topic"),
+      "--notify-on" => {
+        let v = value(&mut args, "--notify-on");
+        opts.notify_on = notification::When::parse(&v)
+          .unwrap_or_else(|| usage_error(&format!("invalid --notify-on value {v:?}")));
+      }
+      "--signal" => {
+        let v = value(&mut args, "--signal");
+        opts.signal =
+          parse_signal(&v).unwrap_or_else(|| usage_error(&format!("unknown signal {v:?}")));
+      }
+      "--reload-signal" => {
+        let v = value(&mut args, "--reload-signal");
+        opts.reload_signal =
+          Some(parse_signal(&v).unwrap_or_else(|| usage_error(&format!("unknown signal {v:?}"))));
+      }
+      "--drop-grace" => {
+        opts.drop_grace = duration_value(&mut args, "--drop-grace", Duration::from_secs(1))
+      }
+      "--cancel-window" => {
+        opts.cancel_window = Some(duration_value(
+          &mut args,
+          "--cancel-window",
+          Duration::from_millis(1),
+        ))
+      }
+      "--subreaper" => opts.subreaper = true,
+      "--timestamp-format" => {
+        let v = value(&mut args, "--timestamp-format");
+        let format = logging::parse_format(&v);
+        if format.is_none() {
+          usage_error(&format!("invalid timestamp format {v:?}"));
+        }
+        opts.timestamps.format = format;
+      }
+      "--utc" => opts.timestamps.utc = true,
+      "--no-timestamps" => opts.timestamps.format = None,
+      "--log-target" => {
+        let v = value(&mut args, "--log-target");
+        let target =
+          LogTarget::parse(&v).unwrap_or_else(|| usage_error(&format!("unknown log target {v:?}")));
+        opts.log_targets.push(target);
+      }
+      "-h" | "--help" => {
+        println!("{USAGE}");
+        std::process::exit(0);
+      }
+      "--" => {
+        positional.extend(args.by_ref());
+        break;
+      }
+      s if s.starts_with("--") => usage_error(&format!("unknown option {s}")),
+      _ => positional.push(arg),
+    }
+  }
+
+  if opts.detach && (opts.output.captures() || opts.output_fifo.is_some()) {
+    usage_error("--detach cannot be combined with output capture");
+  }
+  if opts.serve.is_some() && opts.livereload.is_none() {
+    opts.livereload = Some(livereload::DEFAULT_PORT);
+  }
+  if let Some((_, backend)) = opts.proxy
+    && !opts.has_ready_check()
+  {
+    opts.ready_when = Some(Probe::Tcp(backend));
+  }
+  if opts.on_checkout.is_some() && !opts.on_git.contains(&GitEvent::Checkout) {
+    opts.on_git.push(GitEvent::Checkout);
+  }
+  if opts.detach && opts.ready_when.is_some() {
+    usage_error("--detach cannot be combined with --ready-when");
+  }
+  if opts.detach && opts.group_by.is_some() {
+    usage_error("--detach cannot be combined with --group-by");
+  }
+  if opts.detach && (opts.max_runs.is_some() || opts.expect_exit.is_some()) {
+    usage_error("--detach cannot be combined with --max-runs or --expect-exit");
+  }
+  if [opts.simulate, opts.stdin, opts.control_stdin]
+    .iter()
+    .filter(|&&b| b)
+    .count()
+    > 1
+  {
+    usage_error("only one of --simulate, --stdin and --control-stdin can read stdin");
+  }
+  if opts.simulate && opts.replay.is_some() {
+    usage_error("--simulate cannot be combined with --replay");
+  }
+  if opts
+    .poll_backoff
+    .is_some_and(|max| max < opts.poll_interval)
+  {
+    usage_error("--poll-backoff must not be shorter than --poll-interval");
+  }
+  if opts.keep_failed_tmpdirs && !opts.tmpdir_per_run {
+    usage_error("--keep-failed-tmpdirs needs --tmpdir-per-run");
+  }
+  if opts.within.is_some() && opts.expect_exit.is_none() {
+    usage_error("--within needs --expect-exit");
+  }
+  let stand_ins = [
+    opts.tmux_send.is_some(),
+    opts.k8s_rollout.is_some(),
+    opts.pair.is_some(),
+  ];
+  if stand_ins.iter().filter(|&&set| set).count() > 1 {
+    usage_error("only one of --tmux-send, --k8s-rollout and --pair can be given");
+  }
+  if stand_ins.contains(&true) && (opts.config.is_some() || !cargo.is_empty()) {
+    usage_error("--tmux-send, --k8s-rollout and --pair cannot be combined with --config or -x");
+  }
+  opts.output.tty = opts.tty;
+  if cargo_fetch && cargo.is_empty() {
+    usage_error("--cargo-fetch needs -x");
+  }
+  if opts.min_debounce.is_some() || opts.max_debounce.is_some() {
+    let min = *opts.min_debounce.get_or_insert(Duration::from_millis(100));
+    let max = *opts.max_debounce.get_or_insert(Duration::from_secs(8));
+    if min > max {
+      usage_error("--min-debounce must not exceed --max-debounce");
+    }
+  }
+  // A file saved in place is empty for a moment, which is a change that
+  // means something; its content is only judged once the write is done.
+  if opts.ignore_whitespace || opts.ignore_comments {
+    opts.settle.get_or_insert(Duration::from_millis(100));
+  }
+
+  if test_ignore {
+    if positional.is_empty() {
+      usage_error("test-ignore needs the paths to test");
+    }
+    let paths = std::mem::take(&mut positional);
+    opts.test_ignore = Some(paths.into_iter().map(PathBuf::from).collect());
+    if opts.config.is_none() {
+      if watched.is_empty() {
+        watched.push(".".to_string());
+      }
+      opts.paths = watched
+        .into_iter()
+        .map(|p| match opts.expand_env {
+          true => expand(&p, false).into(),
+          false => p.into(),
+        })
+        .collect();
+      return opts;
+    }
+  }
+
+  if snapshot {
+    opts.snapshot.get_or_insert_with(|| "-".into());
+    if opts.config.is_none() {
+      if positional.is_empty() {
+        positional.push(".".to_string());
+      }
+      opts.paths = positional
+        .into_iter()
+        .map(|p| match opts.expand_env {
+          true => expand(&p, false).into(),
+          false => p.into(),
+        })
+        .collect();
+      return opts;
+    }
+  }
+
+  if let Some(path) = opts.config.clone() {
+    let selected = match positional.split_first() {
+      Some((run, names)) if run == "run" && !names.is_empty() => names,
+      None => &[][..],
+      Some(_) => usage_error("--config replaces the command and paths on the command line"),
+    };
+    if !watched.is_empty() || !cargo.is_empty() {
+      usage_error("--config replaces the command and paths on the command line");
+    }
+    if opts.stdin
+      || opts.on_checkout.is_some()
+      || !opts.command_for.is_empty()
+      || opts.group_by.is_some()
+    {
+      usage_error(
+        "--stdin, --on-checkout, --command-for and --group-by cannot be combined with --config",
+      );
+    }
+    if opts.on_success.is_some() || opts.on_failure.is_some() {
+      usage_error("--on-success and --on-failure cannot be combined with --config (use needs)");
+    }
+    let config = jobs::load(&path, opts.profile.as_deref()).unwrap_or_else(|e| {
+      eprintln!("Error: {e}");
+      std::process::exit(exit::CONFIG);
+    });
+    opts.hooks = config.hooks;
+    opts.debounce = config.debounce;
+    opts.priorities.extend(config.priorities);
+    opts.ignore.extend(config.ignore);
+    opts.extensions.extend(
+      config
+        .exts
+        .iter()
+        .map(|e| e.trim_start_matches('.').to_string()),
+    );
+    opts.profiles = config.profiles;
+    opts.jobs = match selected {
+      [] => config.jobs,
+      names => jobs::select(config.jobs, names).unwrap_or_else(|e| {
+        eprintln!("Error: {e}");
+        std::process::exit(exit::CONFIG);
+      }),
+    };
+    if opts.expand_env {
+      for job in &mut opts.jobs {
+        for path in &mut job.watch {
+          *path = expand(&path.to_string_lossy(), false).into();
+        }
+      }
+    }
+    if opts.detach && opts.jobs.len() > 1 {
+      usage_error("--detach cannot be combined with several jobs");
+    }
+    return opts;
+  }
+  if opts.profile.is_some() {
+    usage_error("--profile needs --config");
+  }
+
+  let compat = !watched.is_empty() || !cargo.is_empty();
+  let mut positional = positional.into_iter();
+  let action = match (&opts.tmux_send, &opts.k8s_rollout, &opts.pair) {
+    (Some((pane, keys)), _, _) => Some(tmux::command(pane, keys)),
+    (_, Some((target, namespace)), _) => Some(k8s::command(target, namespace.as_deref())),
+    (_, _, Some(addr)) => Some(pair::command(addr).unwrap_or_else(|e| usage_error(&e))),
+    _ => None,
+  };
+  let (command, mut paths): (String, Vec<String>) = if let Some(command) = action {
+    // The command is made up; every argument is a path.
+    (command, watched.into_iter().chain(positional).collect())
+  } else if !cargo.is_empty() {
+    // cargo-watch: -x supplies the command, the rest are paths. A change
+    // to the dependencies reruns it all against the lock file, after a
+    // wait for the edits to them (and cargo's own) to settle.
+    let mut locked: Vec<String> = cargo.iter().map(|c| locked(c)).collect();
+    if cargo_fetch {
+      locked.insert(0, "cargo fetch --locked".to_string());
+    }
+    for manifest in ["Cargo.toml", "Cargo.lock"] {
+      opts
+        .command_for
+        .push((manifest.to_string(), locked.join(" && ")));
+      opts
+        .debounce
+        .push((manifest.to_string(), CARGO_MANIFEST_DEBOUNCE));
+    }
+    (
+      cargo.join(" && "),
+      watched.into_iter().chain(positional).collect(),
+    )
+  } else if compat {
+    // watchexec: the positional arguments are the command's words.
+    (positional.collect::<Vec<_>>().join(" "), watched)
+  } else {
+    match positional.next() {
+      Some(command) => (command, positional.collect()),
+      None => (String::new(), Vec::new()),
+    }
+  };
+  if command.is_empty() {
+    eprintln!("{USAGE}");
+    std::process::exit(exit::CONFIG);
+  }
+  opts.command = command;
+  if compat && paths.is_empty() && !opts.stdin {
+    paths.push(".".to_string());
+  }
+  opts.paths = paths
+    .into_iter()
+    .map(|p| {
+      if opts.expand_env {
+        expand(&p, false)
+      } else {
+        p
+      }
+    })
+    .map(PathBuf::from)
+    .collect();
+  if opts.paths.is_empty()
+    && !opts.stdin
+    && opts.on_git.is_empty()
+    && opts.every.is_none()
+    && opts.cron.is_none()
+  {
+    eprintln!("Error: at least one path must be given.");
+    std::process::exit(exit::CONFIG);
+  }
+  opts
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn signals() {
+    assert_eq!(parse_signal("SIGINT"), Some(Signal::SIGINT));
+    assert_eq!(parse_signal("term"), Some(Signal::SIGTERM));
+    assert_eq!(parse_signal("Hup"), Some(Signal::SIGHUP));
+    assert_eq!(parse_signal("9"), Some(Signal::SIGKILL));
+    assert_eq!(parse_signal("0"), None);
+    assert_eq!(parse_signal("SIGNOPE"), None);
+    assert_eq!(parse_signal(""), None);
+  }
+
+  #[test]
+  fn durations() {
+    let secs = Duration::from_secs(1);
+    assert_eq!(
+      parse_duration("500ms", secs),
+      Some(Duration::from_millis(500))
+    );
+    assert_eq!(parse_duration("10s", secs), Some(Duration::from_secs(10)));
+    assert_eq!(parse_duration("1.5m", secs), Some(Duration::from_secs(90)));
+    assert_eq!(parse_duration("2h", secs), Some(Duration::from_secs(7200)));
+    assert_eq!(parse_duration("3", secs), Some(Duration::from_secs(3)));
+    assert_eq!(
+      parse_duration("3", Duration::from_millis(1)),
+      Some(Duration::from_millis(3))
+    );
+    for bad in ["", "s", "10x", "-1s", "1.2.3s", "1e400"] {
+      assert_eq!(parse_duration(bad, secs), None, "{bad:?}");
+    }
+  }
+
+  #[test]
+  fn cpu_lists() {
+    assert_eq!(cpu_list("3"), Some(vec![3]));
+    assert_eq!(cpu_list("0-2,5"), Some(vec![0, 1, 2, 5]));
+    assert_eq!(cpu_list(" 1 - 2 "), Some(vec![1, 2]));
+    for bad in ["", "2-1", "a", "1,", "-3"] {
+      assert_eq!(cpu_list(bad), None, "{bad:?}");
+    }
+  }
+
+  #[test]
+  fn sizes() {
+    assert_eq!(parse_size("0"), Some(0));
+    assert_eq!(parse_size("512b"), Some(512));
+    assert_eq!(parse_size("10K"), Some(10 << 10));
+    assert_eq!(parse_size("5mb"), Some(5 << 20));
+    assert_eq!(parse_size("2GiB"), Some(2 << 30));
+    for bad in ["", "K", "1.5M", "10T", "-1", "99999999999G"] {
+      assert_eq!(parse_size(bad), None, "{bad:?}");
+    }
+  }
+}
@@ -0,0 +1,553 @@
+// Feeding synthetic events to the watcher, to try out ignore rules, hooks
+// and scripts without touching files:
+//
+//   run_on_file_change --control … cmd src   # listen on a control socket
+//   run_on_file_change ctl inject --path src/main.rs --kind modify
+//
+// or with `--simulate`, which watches nothing and reads `KIND PATH…` lines
+// from stdin instead. Either way the events go through the same renames,
+// filters and debounce as real ones. Kinds are create, modify, remove and
+// rename (with the old and the new path).
+//
+// The socket defaults to one per working directory under $XDG_RUNTIME_DIR
+// (or /tmp), so `ctl` run next to the watcher finds it. Requests and
+// replies are single lines of JSON.
+//
+// The socket also changes what is watched, without a restart:
+//
+//   run_on_file_change ctl watch add ../shared
+//   run_on_file_change ctl watch remove ../shared
+//
+// An added directory is watched as the ones given at startup are, with
+// the same depth, backend choice and filters; only those, or earlier
+// added ones, can be removed.
+//
+// `--control-stdin` instead takes commands for the watcher itself, one per
+// line on stdin, for wrappers and editor plugins holding a pipe to it:
+// `trigger` (run now), `pause`, `resume`, `quit`, `set debounce MS`,
+// `profile NAME` (restart with the config's other profile) and `command
+// CMD` (run CMD from now on, keeping the watches; `command` lists the
+// earlier ones and `command !N` goes back to one), `watch add DIR` and
+// `watch remove DIR`.
+//
+// `--fifo PATH` creates a named pipe that forces a run for every line
+// written to it, the line going to the run as $ROF_TRIGGER_MESSAGE:
+//
+//   echo "schema changed" > /tmp/rof.fifo
+
+use crate::Msg;
+use crate::daemon;
+use crate::history;
+use nix::sys::stat::Mode;
+use notify::event::{CreateKind, DataChange, ModifyKind, RemoveKind, RenameMode};
+use notify::{Event, EventKind};
+use run_on_file_change::source::{EventSource, Events};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::{
+  env,
+  fs::{self, File},
+  io::{self, BufRead, BufReader, Write},
+  os::unix::{
+    fs::FileTypeExt,
+    net::{UnixListener, UnixStream},
+  },
+  path::{Path, PathBuf},
+  sync::mpsc::{self, Sender},
+  thread,
+  time::Duration,
+};
+use tracing::{debug, info, warn};
+
+#[derive(Serialize, Deserialize)]
+struct Inject {
+  kind: String,
+  paths: Vec<PathBuf>,
+}
+
+/// `{"watch": "add"|"remove", "path"}`, on the socket.
+#[derive(Serialize, Deserialize)]
+struct Rewatch {
+  watch: String,
+  path: PathBuf,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum Request {
+  Rewatch(Rewatch),
+  Inject(Inject),
+}
+
+/// Where sockets go: $XDG_RUNTIME_DIR, or /tmp.
+pub fn runtime_dir() -> PathBuf {
+  env::var_os("XDG_RUNTIME_DIR")
+    .filter(|v| !v.is_empty())
+    .map_or_else(env::temp_dir, PathBuf::from)
+}
+
+/// The socket for watchers started in the current directory.
+pub fn default_socket() -> PathBuf {
+  let cwd = env::current_dir().unwrap_or_default();
+  let cwd = cwd.canonicalize().unwrap_or(cwd);
+  let base = runtime_dir();
+  let key = sha1_smol::Sha1::from(cwd.to_string_lossy().as_bytes()).digest();
+  base.join(format!("run_on_file_change-{key}.sock"))
+}
+
+/// The event an injected `kind` of change to `paths` stands for.
+fn event(kind: &str, paths: Vec<PathBuf>) -> Result<Event, String> {
+  let kind = match kind {
+    "create" => EventKind::Create(CreateKind::File),
+    "modify" => EventKind::Modify(ModifyKind::Data(DataChange::Content)),
+    "remove" => EventKind::Remove(RemoveKind::File),
+    "rename" if paths.len() == 2 => EventKind::Modify(ModifyKind::Name(RenameMode::Both)),
+    "rename" => return Err("a rename takes the old and the new path".into()),
+    _ => return Err(format!("unknown event kind {kind:?}")),
+  };
+  if paths.is_empty() {
+    return Err("no path given".into());
+  }
+  Ok(paths.into_iter().fold(Event::new(kind), Event::add_path))
+}
+
+/// Relative paths are taken from the current directory, like real events'.
+fn absolute(path: PathBuf) -> PathBuf {
+  match path.is_absolute() {
+    true => path,
+    false => env::current_dir().unwrap_or_default().join(path),
+  }
+}
+
+/// Listens on the control socket until dropped, which removes it.
+pub struct Control {
+  path: PathBuf,
+}
+
+impl Drop for Control {
+  fn drop(&mut self) {
+    let _ = fs::remove_file(&self.path);
+  }
+}
+
+pub fn listen(path: &Path, tx: Sender<Msg>) -> io::Result<Control> {
+  if UnixStream::connect(path).is_ok() {
+    return Err(io::Error::new(
+      io::ErrorKind::AddrInUse,
+      "another watcher is listening there",
+    ));
+  }
+  let _ = fs::remove_file(path); // left behind by a watcher that crashed
+  let listener = UnixListener::bind(path)?;
+  thread::spawn(move || {
+    for stream in listener.incoming().flatten() {
+      let tx = tx.clone();
+      thread::spawn(move || serve(stream, tx));
+    }
+  });
+  Ok(Control {
+    path: path.to_path_buf(),
+  })
+}
+
+fn serve(stream: UnixStream, tx: Sender<Msg>) {
+  let mut reply = match stream.try_clone() {
+    Ok(reply) => reply,
+    Err(_) => return,
+  };
+  for line in BufReader::new(stream).lines().map_while(Result::ok) {
+    let inject = match serde_json::from_str::<Request>(&line) {
+      Ok(Request::Rewatch(rewatch)) => {
+        let response = match rewatch_command(&rewatch.watch, rewatch.path) {
+          Ok(mut change) => {
+            let (done, result) = mpsc::channel();
+            change.reply = Some(done);
+            let _ = tx.send(Msg::Control(Command::Watch(change)));
+            match result.recv() {
+              Ok(Ok(message)) => json!({ "ok": true, "message": message }),
+              Ok(Err(e)) => json!({ "ok": false, "error": e }),
+              Err(_) => json!({ "ok": false, "error": "the watcher is shutting down" }),
+            }
+          }
+          Err(e) => json!({ "ok": false, "error": e }),
+        };
+        if writeln!(reply, "{response}").is_err() {
+          return;
+        }
+        continue;
+      }
+      Ok(Request::Inject(inject)) => Ok(inject),
+      Err(e) => Err(e.to_string()),
+    };
+    let result = inject.and_then(|inject| event(&inject.kind, inject.paths));
+    let response = match result {
+      Ok(event) => {
+        debug!(kind = ?event.kind, paths = ?event.paths, "injected");
+        let _ = tx.send(Msg::Fs(Ok(event)));
+        json!({ "ok": true })
+      }
+      Err(e) => json!({ "ok": false, "error": e }),
+    };
+    if writeln!(reply, "{response}").is_err() {
+      return;
+    }
+  }
+}
+
+/// `--simulate`: reads `KIND PATH…` lines from stdin as events.
+pub struct Simulate;
+
+impl EventSource for Simulate {
+  fn name(&self) -> &'static str {
+    "simulated"
+  }
+
+  fn start(self: Box<Self>, events: Events) -> Result<(), String> {
+    info!("Simulating: reading events from stdin");
+    thread::spawn(move || {
+      for line in io::stdin().lock().lines().map_while(Result::ok) {
+        let mut words = line.split_whitespace();
+        let Some(kind) = words.next() else {
+          continue;
+        };
+        let paths = words.map(|w| absolute(w.into())).collect();
+        match event(kind, paths) {
+          Ok(event) => {
+            if !events.send(event) {
+              return;
+            }
+          }
+          Err(e) => warn!("Ignoring simulated event {line:?}: {e}"),
+        }
+      }
+    });
+    Ok(())
+  }
+}
+
+/// The `--fifo` pipe, removed when dropped.
+pub struct Fifo {
+  path: PathBuf,
+}
+
+impl Drop for Fifo {
+  fn drop(&mut self) {
+    let _ = fs::remove_file(&self.path);
+  }
+}
+
+/// Creates the named pipe at `path`, replacing one left behind, and sends
+/// each line written to it as [`Msg::Fifo`].
+pub fn fifo(path: &Path, tx: Sender<Msg>) -> io::Result<Fifo> {
+  if fs::symlink_metadata(path).is_ok_and(|m| m.file_type().is_fifo()) {
+    fs::remove_file(path)?;
+  }
+  nix::unistd::mkfifo(path, Mode::from_bits_truncate(0o600))?;
+  let reading = path.to_path_buf();
+  thread::spawn(move || {
+    // Opening blocks until a writer comes, and reading ends once the last
+    // one has gone; then we wait for the next.
+    while let Ok(file) = File::open(&reading) {
+      for line in BufReader::new(file).lines().map_while(Result::ok) {
+        if tx.send(Msg::Fifo(line)).is_err() {
+          return;
+        }
+      }
+    }
+  });
+  Ok(Fifo {
+    path: path.to_path_buf(),
+  })
+}
+
+/// A `--control-stdin` command.
+pub enum Command {
+  Trigger,
+  Pause,
+  Resume,
+  Quit,
+  SetDebounce(Duration),
+  Profile(String),
+  /// Run a new command from now on (`command CMD`), or one used before
+  /// (`command !N`).
+  Replace(String),
+  /// List the commands used so far (`command`).
+  Commands,
+  /// Watch another directory, or stop watching one (`watch add|remove DIR`).
+  Watch(WatchChange),
+}
+
+pub struct WatchChange {
+  pub add: bool,
+  pub path: PathBuf,
+  /// Where the socket waits to hear how it went.
+  pub reply: Option<Sender<Result<String, String>>>,
+}
+
+impl WatchChange {
+  /// Tells the socket how it went, or logs it for stdin.
+  pub fn answer(self, result: Result<String, String>) {
+    match self.reply {
+      Some(reply) => {
+        let _ = reply.send(result);
+      }
+      None => match result {
+        Ok(message) => info!("{message}"),
+        Err(e) => warn!("Could not {} {:?}: {e}", self.verb(), self.path),
+      },
+    }
+  }
+
+  fn verb(&self) -> &'static str {
+    match self.add {
+      true => "watch",
+      false => "stop watching",
+    }
+  }
+}
+
+fn rewatch_command(op: &str, path: PathBuf) -> Result<WatchChange, String> {
+  let add = match op {
+    "add" => true,
+    "remove" => false,
+    _ => return Err(format!("expected watch add or watch remove, not {op:?}")),
+  };
+  Ok(WatchChange {
+    add,
+    path: absolute(path),
+    reply: None,
+  })
+}
+
+fn command(line: &str) -> Result<Command, String> {
+  let line = line.trim();
+  if let Some(rest) = line.strip_prefix("command")
+    && (rest.is_empty() || rest.starts_with(char::is_whitespace))
+  {
+    return Ok(match rest.trim() {
+      "" => Command::Commands,
+      command => Command::Replace(command.to_string()),
+    });
+  }
+  // Like `command`, the path is the rest of the line, spaces and all.
+  if let Some(rest) = line.strip_prefix("watch")
+    && rest.starts_with(char::is_whitespace)
+  {
+    let (op, path) = rest
+      .trim_start()
+      .split_once(char::is_whitespace)
+      .unwrap_or((rest.trim(), ""));
+    return match path.trim() {
+      "" => Err(format!("expected a directory after watch {op}")),
+      path => Ok(Command::Watch(rewatch_command(op, path.into())?)),
+    };
+  }
+  let words: Vec<&str> = line.split_whitespace().collect();
+  Ok(match words.as_slice() {
+    ["trigger"] => Command::Trigger,
+    ["pause"] => Command::Pause,
+    ["resume"] => Command::Resume,
+    ["quit"] => Command::Quit,
+    ["set", "debounce", ms] => Command::SetDebounce(Duration::from_millis(
+      ms.parse()
+        .map_err(|_| format!("invalid debounce {ms:?} (milliseconds)"))?,
+    )),
+    ["profile", name] => Command::Profile(name.to_string()),
+    _ => {
+      return Err(
+        "expected trigger, pause, resume, quit, set debounce MS, profile NAME, command [CMD] \
+         or watch add|remove DIR"
+          .into(),
+      );
+    }
+  })
+}
+
+/// `--control-stdin`: reads commands from stdin; its end is a `quit`.
+pub fn commands(tx: Sender<Msg>) {
+  thread::spawn(move || {
+    for line in io::stdin().lock().lines().map_while(Result::ok) {
+      if line.trim().is_empty() {
+        continue;
+      }
+      match command(&line) {
+        Ok(command) => {
+          if tx.send(Msg::Control(command)).is_err() {
+            return;
+          }
+        }
+        Err(e) => warn!("Ignoring command {line:?}: {e}"),
+      }
+    }
+    let _ = tx.send(Msg::Control(Command::Quit));
+  });
+}
+
+/// `run_on_file_change ctl [--socket PATH] inject --path PATH… [--kind KIND]`,
+/// `ctl [--socket PATH] watch add|remove DIR`, or `ctl history --state-dir
+/// DIR [--last N]`, or `ctl add|remove|list` for the daemon.
+pub fn client(mut args: impl Iterator<Item = String>) -> ! {
+  let fail = |msg: &str| -> ! {
+    eprintln!("Error: {msg}");
+    eprintln!(
+      "Usage: run_on_file_change ctl [--socket <PATH>] inject --path <PATH> [--path …] [--kind create|modify|remove|rename]"
+    );
+    eprintln!("       run_on_file_change ctl [--socket <PATH>] watch add|remove <DIR>");
+    eprintln!("       run_on_file_change ctl history --state-dir <DIR> [--last <N>]");
+    eprintln!(
+      "       run_on_file_change ctl [--socket <PATH>] add <DIR> [options] <command> <path> …"
+    );
+    eprintln!("       run_on_file_change ctl [--socket <PATH>] remove <DIR> | list");
+    std::process::exit(1);
+  };
+  let mut socket = None;
+  let mut state_dir = None;
+  let mut project = None;
+  let mut last = 20;
+  let mut command = None;
+  let mut rewatch = None;
+  let mut inject = Inject {
+    kind: "modify".into(),
+    paths: Vec::new(),
+  };
+  while let Some(arg) = args.next() {
+    let mut value = |name: &str| {
+      args
+        .next()
+        .unwrap_or_else(|| fail(&format!("{name} requires a value")))
+    };
+    match arg.as_str() {
+      "--socket" => socket = Some(PathBuf::from(value("--socket"))),
+      "--path" => inject.paths.push(absolute(value("--path").into())),
+      "--kind" => inject.kind = value("--kind"),
+      "--state-dir" => state_dir = Some(PathBuf::from(value("--state-dir"))),
+      "--last" => {
+        last = value("--last")
+          .parse()
+          .unwrap_or_else(|_| fail("--last takes a number of runs"))
+      }
+      "add" if command.is_none() => {
+        let dir = absolute(value("add").into());
+        let args = args.collect();
+        daemon::client(socket, daemon::Request::Add { dir, args });
+      }
+      "watch" if command.is_none() => {
+        let (op, path) = (value("watch"), value("watch add|remove"));
+        if let Err(e) = rewatch_command(&op, path.clone().into()) {
+          fail(&e);
+        }
+        rewatch = Some(Rewatch {
+          watch: op,
+          path: absolute(path.into()),
+        });
+        command = Some(arg);
+      }
+      "remove" if command.is_none() => {
+        project = Some(absolute(value("remove").into()));
+        command = Some(arg);
+      }
+      "inject" | "history" | "list" if command.is_none() => command = Some(arg),
+      _ => fail(&format!("unexpected argument {arg:?}")),
+    }
+  }
+  match command.as_deref() {
+    None => fail("no command given"),
+    Some("history") => {
+      let dir = state_dir.unwrap_or_else(|| fail("history needs --state-dir"));
+      match history::print(&dir, last) {
+        Ok(()) => std::process::exit(0),
+        Err(e) => fail(&format!(
+          "could not read the history in {}: {e}",
+          dir.display()
+        )),
+      }
+    }
+    Some("remove") => {
+      let dir = project.expect("set with remove");
+      daemon::client(socket, daemon::Request::Remove { dir });
+    }
+    Some("list") => daemon::client(socket, daemon::Request::List),
+    Some(_) => {}
+  }
+  let request = match rewatch {
+    Some(rewatch) => serde_json::to_string(&rewatch),
+    None => {
+      if let Err(e) = event(&inject.kind, inject.paths.clone()) {
+        fail(&e);
+      }
+      serde_json::to_string(&inject)
+    }
+  }
+  .expect("serializable");
+  let socket = socket.unwrap_or_else(default_socket);
+  let reply = UnixStream::connect(&socket).and_then(|mut stream| {
+    writeln!(stream, "{request}")?;
+    let mut reply = String::new();
+    BufReader::new(stream).read_line(&mut reply)?;
+    Ok(reply)
+  });
+  let reply = match reply {
+    Ok(reply) => reply,
+    Err(e) => fail(&format!(
+      "could not reach a watcher on {} (started with --control?): {e}",
+      socket.display()
+    )),
+  };
+  match serde_json::from_str::<serde_json::Value>(&reply) {
+    Ok(v) if v["ok"] == true => {
+      if let Some(message) = v["message"].as_str() {
+        println!("{message}");
+      }
+      std::process::exit(0)
+    }
+    Ok(v) => fail(
+      v["error"]
+        .as_str()
+        .unwrap_or("the watcher refused the event"),
+    ),
+    Err(_) => fail("unexpected reply from the watcher"),
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn parses_commands() {
+    assert!(matches!(command("trigger"), Ok(Command::Trigger)));
+    assert!(matches!(command(" quit "), Ok(Command::Quit)));
+    assert!(matches!(
+      command("set debounce 250"),
+      Ok(Command::SetDebounce(d)) if d == Duration::from_millis(250)
+    ));
+    assert!(command("set debounce x").is_err());
+    assert!(command("set debounce").is_err());
+    assert!(command("trigger now").is_err());
+  }
+
+  #[test]
+  fn command_takes_the_rest_of_the_line() {
+    assert!(matches!(command("command"), Ok(Command::Commands)));
+    assert!(matches!(
+      command("command  make -j2 test "),
+      Ok(Command::Replace(c)) if c == "make -j2 test"
+    ));
+    assert!(matches!(command("command !2"), Ok(Command::Replace(c)) if c == "!2"));
+    assert!(command("commandfoo").is_err());
+  }
+
+  #[test]
+  fn watch_takes_the_rest_of_the_line() {
+    let Ok(Command::Watch(change)) = command("watch add /srv/my project ") else {
+      panic!("watch add was refused");
+    };
+    assert!(change.add);
+    assert_eq!(change.path, PathBuf::from("/srv/my project"));
+    assert!(matches!(command("watch remove /srv"), Ok(Command::Watch(c)) if !c.add));
+    assert!(command("watch add").is_err());
+    assert!(command("watch add   ").is_err());
+    assert!(command("watch move /srv").is_err());
+    assert!(command("watchadd /srv").is_err());
+  }
+}
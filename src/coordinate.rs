@@ -0,0 +1,100 @@
+// Sharing jobs between watchers (`--coordinate <DIR>`), e.g. two panes or
+// two developers on one NFS project: each job has a lock file in DIR, and
+// only the watcher holding it runs the job. The others keep watching and
+// take the lock over on their next change once the holder goes away.
+//
+// Jobs are told apart by name, and the command-line job by its command.
+
+use std::{
+  collections::BTreeMap,
+  fs::{self, File, TryLockError},
+  io,
+  path::{Path, PathBuf},
+};
+use tracing::{info, warn};
+
+enum Role {
+  /// Holding the lock, released when the file closes.
+  Leader {
+    _lock: File,
+  },
+  Standby,
+}
+
+pub struct Coordinator {
+  dir: PathBuf,
+  roles: BTreeMap<String, Role>,
+}
+
+/// The lock file for the job known as `key`.
+fn lock_path(dir: &Path, key: &str) -> PathBuf {
+  dir.join(format!(
+    ".run_on_file_change-{}.lock",
+    sha1_smol::Sha1::from(key).digest()
+  ))
+}
+
+impl Coordinator {
+  /// Creates the lock files for `keys` up front, so they don't show up as
+  /// changes later.
+  pub fn new(dir: PathBuf, keys: &[String]) -> io::Result<Coordinator> {
+    fs::create_dir_all(&dir)?;
+    for key in keys {
+      open(&lock_path(&dir, key))?;
+    }
+    Ok(Coordinator {
+      dir,
+      roles: BTreeMap::new(),
+    })
+  }
+
+  /// Whether this watcher should run the job known as `key`, taking its
+  /// lock if nobody holds it.
+  pub fn leads(&mut self, key: &str) -> bool {
+    if let Some(Role::Leader { .. }) = self.roles.get(key) {
+      return true;
+    }
+    let path = lock_path(&self.dir, key);
+    let taken = open(&path).and_then(|file| match file.try_lock() {
+      Ok(()) => Ok(Some(file)),
+      Err(TryLockError::WouldBlock) => Ok(None),
+      Err(TryLockError::Error(e)) => Err(e),
+    });
+    let file = match taken {
+      Ok(file) => file,
+      Err(e) => {
+        // Better to run twice than not at all.
+        warn!("Could not lock {}, running anyway: {e}", path.display());
+        return true;
+      }
+    };
+    let was = self.roles.insert(
+      key.to_string(),
+      match file {
+        Some(file) => Role::Leader { _lock: file },
+        None => Role::Standby,
+      },
+    );
+    match (&self.roles[key], was) {
+      (Role::Leader { .. }, Some(Role::Standby)) => {
+        info!("Taking over {key} from the watcher that stopped");
+        true
+      }
+      (Role::Leader { .. }, _) => true,
+      (Role::Standby, Some(Role::Standby)) => false,
+      (Role::Standby, _) => {
+        info!("Standing by: another watcher runs {key}");
+        false
+      }
+    }
+  }
+}
+
+fn open(path: &Path) -> io::Result<File> {
+  File::options()
+    .read(true)
+    .write(true)
+    .create(true)
+    .truncate(false)
+    .open(path)
+}
@@ -0,0 +1,330 @@
+// `--cron`: five-field crontab schedules, and `--quiet-hours`' windows.
+
+use chrono::{DateTime, Datelike, Duration, Local, NaiveDateTime, TimeZone, Timelike};
+
+/// When a crontab line would run: minute, hour, day of month, month and
+/// day of week, each as a bit set of the allowed values.
+#[derive(Clone, Debug)]
+pub struct Schedule {
+  minutes: u64,
+  hours: u64,
+  days: u64,
+  months: u64,
+  weekdays: u64,
+  /// Day of month and day of week were both restricted, in which case
+  /// either may match (as cron does).
+  either_day: bool,
+}
+
+const MONTHS: [&str; 12] = [
+  "jan", "feb", "mar", "apr", "may", "jun", "jul", "aug", "sep", "oct", "nov", "dec",
+];
+const WEEKDAYS: [&str; 7] = ["sun", "mon", "tue", "wed", "thu", "fri", "sat"];
+
+/// A value of a field, by number or (for months and weekdays) by name.
+fn value(s: &str, min: u32, names: &[&str]) -> Option<u32> {
+  if let Some(i) = names.iter().position(|n| s.eq_ignore_ascii_case(n)) {
+    return Some(i as u32 + min);
+  }
+  s.parse().ok()
+}
+
+/// Parses one field: `*`, `N`, `A-B`, any of those with `/STEP`, or a
+/// comma-separated list of them.
+fn field(s: &str, min: u32, max: u32, names: &[&str]) -> Result<u64, String> {
+  let mut bits = 0;
+  for part in s.split(',') {
+    let (range, step) = match part.split_once('/') {
+      Some((range, step)) => {
+        let step: u32 = step
+          .parse()
+          .ok()
+          .filter(|&n| n > 0)
+          .ok_or_else(|| format!("invalid step in {part:?}"))?;
+        (range, step)
+      }
+      None => (part, 1),
+    };
+    let (start, end) = match range.split_once('-') {
+      _ if range == "*" => (min, max),
+      Some((a, b)) => (
+        value(a, min, names).ok_or_else(|| format!("invalid value {a:?}"))?,
+        value(b, min, names).ok_or_else(|| format!("invalid value {b:?}"))?,
+      ),
+      None => {
+        let n = value(range, min, names).ok_or_else(|| format!("invalid value {range:?}"))?;
+        (n, if step > 1 { max } else { n })
+      }
+    };
+    if start < min || end > max || start > end {
+      return Err(format!("{part:?} is outside {min}-{max}"));
+    }
+    for n in (start..=end).step_by(step as usize) {
+      bits |= 1 << n;
+    }
+  }
+  Ok(bits)
+}
+
+impl Schedule {
+  pub fn parse(s: &str) -> Result<Schedule, String> {
+    let fields: Vec<&str> = s.split_whitespace().collect();
+    let [minute, hour, day, month, weekday] = fields[..] else {
+      return Err(format!("expected 5 fields, got {}", fields.len()));
+    };
+    let mut weekdays = field(weekday, 0, 7, &WEEKDAYS)?;
+    if weekdays & 1 << 7 != 0 {
+      weekdays |= 1; // 7 is Sunday too
+    }
+    Ok(Schedule {
+      minutes: field(minute, 0, 59, &[])?,
+      hours: field(hour, 0, 23, &[])?,
+      days: field(day, 1, 31, &[])?,
+      months: field(month, 1, 12, &MONTHS)?,
+      weekdays,
+      either_day: day != "*" && weekday != "*",
+    })
+  }
+
+  fn day_matches(&self, t: &NaiveDateTime) -> bool {
+    let day = self.days & 1 << t.day() != 0;
+    let weekday = self.weekdays & 1 << t.weekday().num_days_from_sunday() != 0;
+    if self.either_day {
+      day || weekday
+    } else {
+      day && weekday
+    }
+  }
+
+  /// The first matching minute after `after`, or `None` if there is none
+  /// within the next few years (say, February 30th).
+  pub fn next_after(&self, after: DateTime<Local>) -> Option<DateTime<Local>> {
+    let start = after.naive_local().with_second(0)?.with_nanosecond(0)? + Duration::minutes(1);
+    let limit = start + Duration::days(366 * 5);
+    let mut t = start;
+    while t < limit {
+      if self.months & 1 << t.month() == 0 || !self.day_matches(&t) {
+        t = (t.date() + Duration::days(1)).and_hms_opt(0, 0, 0)?;
+      } else if self.hours & 1 << t.hour() == 0 {
+        t = t.with_minute(0)? + Duration::hours(1);
+      } else if self.minutes & 1 << t.minute() == 0 {
+        t += Duration::minutes(1);
+      } else if let Some(at) = Local.from_local_datetime(&t).earliest() {
+        return Some(at);
+      } else {
+        t += Duration::minutes(1); // skipped by a DST change
+      }
+    }
+    None
+  }
+}
+
+/// `--quiet-hours`: a daily window of local time, such as `22:00-07:00`
+/// (running past midnight) or `mon-fri 12:00-13:00`, on the given days of
+/// the week (a crontab weekday field) or every day. Days alone, as in
+/// `sat,sun`, are quiet all day. A window past midnight belongs to the day
+/// it starts on.
+#[derive(Clone, Debug)]
+pub struct Window {
+  weekdays: u64,
+  /// Minutes after midnight.
+  start: u32,
+  end: u32,
+}
+
+/// Minutes after midnight of `HH:MM`, with `24:00` for the end of the day.
+fn minutes(s: &str) -> Result<u32, String> {
+  let (h, m) = s
+    .split_once(':')
+    .ok_or_else(|| format!("expected HH:MM, got {s:?}"))?;
+  match (h.parse::<u32>(), m.parse::<u32>()) {
+    (Ok(h), Ok(m)) if m < 60 && (h < 24 || (h, m) == (24, 0)) => Ok(h * 60 + m),
+    _ => Err(format!("invalid time {s:?}")),
+  }
+}
+
+impl Window {
+  pub fn parse(s: &str) -> Result<Window, String> {
+    let words: Vec<&str> = s.split_whitespace().collect();
+    let (days, times) = match words.as_slice() {
+      [times] if times.contains(':') => (None, Some(*times)),
+      [days] => (Some(*days), None),
+      [days, times] => (Some(*days), Some(*times)),
+      _ => return Err("expected [DAYS] HH:MM-HH:MM".into()),
+    };
+    let weekdays = match days {
+      Some(days) => {
+        let weekdays = field(days, 0, 7, &WEEKDAYS)?;
+        match weekdays & 1 << 7 {
+          0 => weekdays,
+          _ => weekdays | 1, // 7 is Sunday too
+        }
+      }
+      None => 0x7f,
+    };
+    let (start, end) = match times {
+      Some(times) => {
+        let (start, end) = times
+          .split_once('-')
+          .ok_or_else(|| format!("expected HH:MM-HH:MM, got {times:?}"))?;
+        (minutes(start)?, minutes(end)?)
+      }
+      None => (0, 24 * 60),
+    };
+    if start == end {
+      return Err(format!("{s:?} is an empty window"));
+    }
+    Ok(Window {
+      weekdays,
+      start,
+      end,
+    })
+  }
+
+  fn contains(&self, t: &NaiveDateTime) -> bool {
+    let day = |d: u32| self.weekdays & 1 << (d % 7) != 0;
+    let weekday = t.weekday().num_days_from_sunday();
+    let m = t.hour() * 60 + t.minute();
+    match self.start < self.end {
+      true => day(weekday) && (self.start..self.end).contains(&m),
+      false => (day(weekday) && m >= self.start) || (day(weekday + 6) && m < self.end),
+    }
+  }
+}
+
+/// Whether `now` falls in any of the `windows`.
+pub fn quiet(windows: &[Window], now: DateTime<Local>) -> bool {
+  let t = now.naive_local();
+  windows.iter().any(|w| w.contains(&t))
+}
+
+/// How long until `now` moves into or out of the `windows`.
+pub fn until_change(windows: &[Window], now: DateTime<Local>) -> std::time::Duration {
+  let t = now.naive_local();
+  let inside = quiet(windows, now);
+  let Some(mut next) = t.with_second(0).and_then(|t| t.with_nanosecond(0)) else {
+    return std::time::Duration::from_secs(60);
+  };
+  // A week on, every window has come round.
+  for _ in 0..8 * 24 * 60 {
+    next += Duration::minutes(1);
+    if windows.iter().any(|w| w.contains(&next)) != inside {
+      break;
+    }
+  }
+  (next - t).to_std().unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn at(y: i32, mo: u32, d: u32, h: u32, mi: u32) -> DateTime<Local> {
+    Local.with_ymd_and_hms(y, mo, d, h, mi, 0).unwrap()
+  }
+
+  fn next(schedule: &str, after: DateTime<Local>) -> Option<DateTime<Local>> {
+    Schedule::parse(schedule).unwrap().next_after(after)
+  }
+
+  #[test]
+  fn parses_fields() {
+    let s = Schedule::parse("*/15 9-17 * jan,JUL mon-fri").unwrap();
+    assert_eq!(s.minutes, 1 | 1 << 15 | 1 << 30 | 1 << 45);
+    assert_eq!(s.hours, (9..=17).map(|h| 1 << h).sum::<u64>());
+    assert_eq!(s.months, 1 << 1 | 1 << 7);
+    assert_eq!(s.weekdays, 0b0111110);
+    assert!(!s.either_day);
+    assert_eq!(Schedule::parse("0 0 * * 7").unwrap().weekdays & 1, 1);
+    assert!(Schedule::parse("0 0 1 * 1").unwrap().either_day);
+  }
+
+  #[test]
+  fn refuses_bad_fields() {
+    for bad in [
+      "* * * *",
+      "60 * * * *",
+      "* 24 * * *",
+      "* * 0 * *",
+      "*/0 * * * *",
+      "5-1 * * * *",
+    ] {
+      assert!(Schedule::parse(bad).is_err(), "{bad:?}");
+    }
+  }
+
+  #[test]
+  fn next_fire() {
+    let after = at(2025, 1, 15, 10, 7); // a Wednesday
+    assert_eq!(next("* * * * *", after), Some(at(2025, 1, 15, 10, 8)));
+    assert_eq!(next("*/15 * * * *", after), Some(at(2025, 1, 15, 10, 15)));
+    assert_eq!(next("30 9 * * *", after), Some(at(2025, 1, 16, 9, 30)));
+    assert_eq!(next("0 0 1 * *", after), Some(at(2025, 2, 1, 0, 0)));
+    assert_eq!(next("0 12 * * sat", after), Some(at(2025, 1, 18, 12, 0)));
+    // Day of month or day of week, when both are given.
+    assert_eq!(next("0 0 20 * fri", after), Some(at(2025, 1, 17, 0, 0)));
+    assert_eq!(next("0 0 29 2 *", after), Some(at(2028, 2, 29, 0, 0)));
+    assert_eq!(next("0 0 30 2 *", after), None);
+  }
+
+  fn quiet_at(window: &str, at: DateTime<Local>) -> bool {
+    quiet(&[Window::parse(window).unwrap()], at)
+  }
+
+  #[test]
+  fn parses_windows() {
+    for ok in [
+      "22:00-07:00",
+      "mon-fri 12:00-13:00",
+      "sat,sun",
+      "18:00-24:00",
+      "0 00:00-01:00",
+    ] {
+      assert!(Window::parse(ok).is_ok(), "{ok:?}");
+    }
+    for bad in [
+      "10:00-10:00",
+      "25:00-26:00",
+      "12:60-13:00",
+      "24:01-01:00",
+      "mon 1-2",
+      "a b c",
+      "",
+    ] {
+      assert!(Window::parse(bad).is_err(), "{bad:?}");
+    }
+  }
+
+  #[test]
+  fn windows() {
+    // 2025-01-17 is a Friday.
+    assert!(quiet_at("22:00-07:00", at(2025, 1, 17, 23, 0)));
+    assert!(quiet_at("22:00-07:00", at(2025, 1, 17, 6, 59)));
+    assert!(!quiet_at("22:00-07:00", at(2025, 1, 17, 7, 0)));
+    // Past midnight, a window belongs to the day it starts on.
+    assert!(quiet_at("fri 22:00-07:00", at(2025, 1, 18, 3, 0)));
+    assert!(!quiet_at("fri 22:00-07:00", at(2025, 1, 17, 3, 0)));
+    assert!(!quiet_at("fri 22:00-07:00", at(2025, 1, 18, 23, 0)));
+    assert!(quiet_at("sun 22:00-07:00", at(2025, 1, 20, 3, 0)));
+    assert!(quiet_at("18:00-24:00", at(2025, 1, 17, 23, 59)));
+    assert!(!quiet_at("18:00-24:00", at(2025, 1, 18, 0, 0)));
+    assert!(quiet_at("sat,sun", at(2025, 1, 19, 12, 0)));
+    assert!(!quiet_at("sat,sun", at(2025, 1, 20, 0, 0)));
+  }
+
+  #[test]
+  fn until_window_changes() {
+    let windows = [Window::parse("22:00-07:00").unwrap()];
+    let minutes = |m: u64| std::time::Duration::from_secs(m * 60);
+    assert_eq!(until_change(&windows, at(2025, 1, 17, 21, 30)), minutes(30));
+    assert_eq!(
+      until_change(&windows, at(2025, 1, 17, 23, 0)),
+      minutes(8 * 60)
+    );
+    let weekend = [Window::parse("sat,sun").unwrap()];
+    assert_eq!(
+      until_change(&weekend, at(2025, 1, 18, 12, 0)),
+      minutes(36 * 60)
+    );
+  }
+}
@@ -0,0 +1,349 @@
+// One background process for the watchers of several projects
+// (`run_on_file_change daemon`), managed through ctl:
+//
+//   run_on_file_change daemon &
+//   run_on_file_change ctl add ~/src/api --config rof.toml
+//   run_on_file_change ctl add ~/src/web -e ts "npm test" src
+//   run_on_file_change ctl list
+//   run_on_file_change ctl remove ~/src/api
+//
+// `ctl add DIR ARGS…` starts a watcher in DIR with ARGS, as if run there
+// by hand, and `ctl remove DIR` stops it the way SIGTERM would. Each
+// watcher is a process of its own, in its own process group, with its
+// output appended to a log file per project in the daemon's log directory;
+// `ctl list` shows them with their pids and logs. Stopping the daemon
+// (SIGINT or SIGTERM) stops its watchers first.
+//
+// The socket is $XDG_RUNTIME_DIR/run_on_file_change-daemon.sock (or in
+// /tmp), unless --socket says otherwise, for both sides. Requests and
+// replies are single lines of JSON, as on the control socket.
+
+use crate::{control, exit, signals};
+use nix::sys::signal::{self, SigSet, Signal};
+use nix::unistd::Pid;
+use serde::{Deserialize, Serialize};
+use serde_json::{Value, json};
+use std::{
+  collections::BTreeMap,
+  fs::{self, File},
+  io::{BufRead, BufReader, Write},
+  os::unix::{
+    net::{UnixListener, UnixStream},
+    process::{CommandExt, ExitStatusExt},
+  },
+  path::{Path, PathBuf},
+  process::{Child, Command, Stdio},
+  sync::{
+    Arc, Mutex,
+    atomic::{AtomicBool, Ordering},
+  },
+  thread,
+  time::{Duration, Instant},
+};
+
+/// How long a watcher gets to stop before it is killed.
+const STOP_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// A watcher that exits this soon after starting is reported as failed.
+const STARTUP: Duration = Duration::from_millis(300);
+
+#[derive(Serialize, Deserialize)]
+#[serde(tag = "op", rename_all = "lowercase")]
+pub enum Request {
+  Add { dir: PathBuf, args: Vec<String> },
+  Remove { dir: PathBuf },
+  List,
+}
+
+struct Project {
+  args: Vec<String>,
+  child: Child,
+  log: PathBuf,
+}
+
+type Projects = Arc<Mutex<BTreeMap<PathBuf, Project>>>;
+
+/// Set once the daemon is stopping, when no more watchers are started.
+static CLOSING: AtomicBool = AtomicBool::new(false);
+
+pub fn default_socket() -> PathBuf {
+  control::runtime_dir().join("run_on_file_change-daemon.sock")
+}
+
+fn usage(msg: &str) -> ! {
+  eprintln!("Error: {msg}");
+  eprintln!("Usage: run_on_file_change daemon [--socket <PATH>] [--log-dir <DIR>]");
+  std::process::exit(exit::CONFIG);
+}
+
+/// The log file for the watcher of `dir`: its name, and a hash of the
+/// whole path to tell directories of the same name apart.
+fn log_path(logs: &Path, dir: &Path) -> PathBuf {
+  let name = dir.file_name().unwrap_or_default().to_string_lossy();
+  let key = sha1_smol::Sha1::from(dir.to_string_lossy().as_bytes()).digest();
+  logs.join(format!("{name}-{}.log", &key.to_string()[..8]))
+}
+
+/// Starts the watcher for `dir`, failing if it exits straight away.
+fn start(dir: &Path, args: &[String], logs: &Path) -> Result<Project, String> {
+  let exe = std::env::current_exe().map_err(|e| format!("cannot find the watcher: {e}"))?;
+  let log = log_path(logs, dir);
+  let file = File::options()
+    .create(true)
+    .append(true)
+    .open(&log)
+    .map_err(|e| format!("cannot open {}: {e}", log.display()))?;
+  let stderr = file
+    .try_clone()
+    .map_err(|e| format!("cannot open {}: {e}", log.display()))?;
+  let mut command = Command::new(exe);
+  command
+    .args(args)
+    .current_dir(dir)
+    .stdin(Stdio::null())
+    .stdout(file)
+    .stderr(stderr)
+    .process_group(0);
+  // SAFETY: only resets the signal mask, which is async-signal-safe
+  unsafe { command.pre_exec(signals::clear_mask) };
+  let mut child = command
+    .spawn()
+    .map_err(|e| format!("cannot start the watcher: {e}"))?;
+  thread::sleep(STARTUP);
+  if let Ok(Some(status)) = child.try_wait() {
+    return Err(format!(
+      "the watcher exited at once ({status}); see {}",
+      log.display()
+    ));
+  }
+  Ok(Project {
+    args: args.to_vec(),
+    child,
+    log,
+  })
+}
+
+/// Stops a watcher as SIGTERM does (a first SIGINT would only go to its
+/// runs), killing it if it takes too long.
+fn stop(project: &mut Project) {
+  let pid = Pid::from_raw(project.child.id() as i32);
+  let _ = signal::kill(pid, Signal::SIGTERM);
+  let asked = Instant::now();
+  while matches!(project.child.try_wait(), Ok(None)) {
+    if asked.elapsed() > STOP_TIMEOUT {
+      let _ = project.child.kill();
+    }
+    thread::sleep(Duration::from_millis(50));
+  }
+}
+
+fn handle(request: Request, projects: &Projects, logs: &Path) -> Value {
+  let mut projects = projects.lock().expect("not poisoned");
+  match request {
+    Request::Add { .. } if CLOSING.load(Ordering::SeqCst) => {
+      json!({ "ok": false, "error": "the daemon is stopping" })
+    }
+    Request::Add { dir, args } => {
+      let dir = match dir.canonicalize() {
+        Ok(dir) if dir.is_dir() => dir,
+        _ => {
+          return json!({ "ok": false, "error": format!("{} is not a directory", dir.display()) });
+        }
+      };
+      if let Some(project) = projects.get_mut(&dir)
+        && matches!(project.child.try_wait(), Ok(None))
+      {
+        return json!({
+          "ok": false,
+          "error": format!("{} is watched already; remove it first", dir.display()),
+        });
+      }
+      match start(&dir, &args, logs) {
+        Ok(project) => {
+          let message = format!(
+            "Watching {} (pid {}), logging to {}",
+            dir.display(),
+            project.child.id(),
+            project.log.display()
+          );
+          projects.insert(dir, project);
+          json!({ "ok": true, "message": message })
+        }
+        Err(e) => json!({ "ok": false, "error": e }),
+      }
+    }
+    Request::Remove { dir } => {
+      let dir = dir.canonicalize().unwrap_or(dir);
+      let removed = projects.remove(&dir);
+      drop(projects); // the others stay manageable while it stops
+      match removed {
+        Some(mut project) => {
+          stop(&mut project);
+          json!({ "ok": true, "message": format!("Stopped watching {}", dir.display()) })
+        }
+        None => json!({ "ok": false, "error": format!("{} is not watched", dir.display()) }),
+      }
+    }
+    Request::List => {
+      let list: Vec<Value> = projects
+        .iter_mut()
+        .map(|(dir, project)| {
+          let state = match project.child.try_wait() {
+            Ok(None) => "running".to_string(),
+            Ok(Some(status)) => match (status.code(), status.signal()) {
+              (Some(code), _) => format!("exited with status {code}"),
+              (None, Some(sig)) => format!("killed by signal {sig}"),
+              (None, None) => "exited".to_string(),
+            },
+            Err(e) => format!("unknown ({e})"),
+          };
+          json!({
+            "dir": dir,
+            "pid": project.child.id(),
+            "state": state,
+            "args": project.args,
+            "log": project.log,
+          })
+        })
+        .collect();
+      json!({ "ok": true, "projects": list })
+    }
+  }
+}
+
+fn serve(stream: UnixStream, projects: Projects, logs: PathBuf) {
+  let Ok(mut reply) = stream.try_clone() else {
+    return;
+  };
+  for line in BufReader::new(stream).lines().map_while(Result::ok) {
+    let response = match serde_json::from_str::<Request>(&line) {
+      Ok(request) => handle(request, &projects, &logs),
+      Err(e) => json!({ "ok": false, "error": e.to_string() }),
+    };
+    if writeln!(reply, "{response}").is_err() {
+      return;
+    }
+  }
+}
+
+/// `run_on_file_change daemon [--socket PATH] [--log-dir DIR]`.
+pub fn run(mut args: impl Iterator<Item = String>) -> ! {
+  let mut socket = default_socket();
+  let mut logs = control::runtime_dir().join("run_on_file_change-daemon");
+  while let Some(arg) = args.next() {
+    let mut value = |name: &str| {
+      args
+        .next()
+        .unwrap_or_else(|| usage(&format!("{name} requires a value")))
+    };
+    match arg.as_str() {
+      "--socket" => socket = PathBuf::from(value("--socket")),
+      "--log-dir" => logs = PathBuf::from(value("--log-dir")),
+      _ => usage(&format!("unexpected argument {arg:?}")),
+    }
+  }
+  // Blocked before any thread starts, so that they all leave the
+  // shutdown signals to the main thread.
+  let mut shutdown = SigSet::empty();
+  shutdown.add(Signal::SIGINT);
+  shutdown.add(Signal::SIGTERM);
+  if let Err(e) = shutdown.thread_block() {
+    eprintln!("Error: could not block signals: {e}");
+    std::process::exit(exit::STARTUP);
+  }
+  if let Err(e) = fs::create_dir_all(&logs) {
+    eprintln!("Error: could not create {}: {e}", logs.display());
+    std::process::exit(exit::STARTUP);
+  }
+  if UnixStream::connect(&socket).is_ok() {
+    eprintln!("Error: a daemon is running on {} already", socket.display());
+    std::process::exit(exit::STARTUP);
+  }
+  let _ = fs::remove_file(&socket); // left behind by a daemon that crashed
+  let listener = match UnixListener::bind(&socket) {
+    Ok(listener) => listener,
+    Err(e) => {
+      eprintln!("Error: could not listen on {}: {e}", socket.display());
+      std::process::exit(exit::STARTUP);
+    }
+  };
+  println!("Daemon listening on {}", socket.display());
+
+  let projects = Projects::default();
+  let serving = Arc::clone(&projects);
+  thread::spawn(move || {
+    for stream in listener.incoming().flatten() {
+      let (projects, logs) = (Arc::clone(&serving), logs.clone());
+      thread::spawn(move || serve(stream, projects, logs));
+    }
+  });
+
+  let signal = shutdown.wait().unwrap_or(Signal::SIGTERM);
+  println!("Received {signal}, stopping the watchers");
+  let stopping = {
+    let mut projects = projects.lock().expect("not poisoned");
+    CLOSING.store(true, Ordering::SeqCst);
+    std::mem::take(&mut *projects)
+  };
+  thread::scope(|scope| {
+    for mut project in stopping.into_values() {
+      scope.spawn(move || stop(&mut project));
+    }
+  });
+  let _ = fs::remove_file(&socket);
+  std::process::exit(exit::signaled(signal));
+}
+
+/// `ctl add|remove|list`: sends `request` to the daemon on `socket` and
+/// prints what it says.
+pub fn client(socket: Option<PathBuf>, request: Request) -> ! {
+  let socket = socket.unwrap_or_else(default_socket);
+  let line = serde_json::to_string(&request).expect("serializable");
+  let reply = UnixStream::connect(&socket).and_then(|mut stream| {
+    writeln!(stream, "{line}")?;
+    let mut reply = String::new();
+    BufReader::new(stream).read_line(&mut reply)?;
+    Ok(reply)
+  });
+  let reply: Value = match reply.map(|r| serde_json::from_str(&r)) {
+    Ok(Ok(reply)) => reply,
+    Ok(Err(_)) => {
+      eprintln!("Error: unexpected reply from the daemon");
+      std::process::exit(1);
+    }
+    Err(e) => {
+      eprintln!(
+        "Error: could not reach a daemon on {} (started with `run_on_file_change daemon`?): {e}",
+        socket.display()
+      );
+      std::process::exit(1);
+    }
+  };
+  if reply["ok"] != true {
+    eprintln!("Error: {}", reply["error"].as_str().unwrap_or("refused"));
+    std::process::exit(1);
+  }
+  if let Some(message) = reply["message"].as_str() {
+    println!("{message}");
+  }
+  if reply["projects"].as_array().is_some_and(|p| p.is_empty()) {
+    println!("No projects are watched");
+  }
+  for project in reply["projects"].as_array().into_iter().flatten() {
+    let args: Vec<&str> = project["args"]
+      .as_array()
+      .into_iter()
+      .flatten()
+      .filter_map(Value::as_str)
+      .collect();
+    println!(
+      "{}  pid {}  {}\n  args: {}\n  log:  {}",
+      project["dir"].as_str().unwrap_or("?"),
+      project["pid"],
+      project["state"].as_str().unwrap_or("?"),
+      args.join(" "),
+      project["log"].as_str().unwrap_or("?")
+    );
+  }
+  std::process::exit(0);
+}
@@ -0,0 +1,81 @@
+// Session-bus signals about runs (`--dbus`), for panels and desktop
+// extensions that want to show the watcher's state without polling.
+//
+// Sent with `dbus-send` from object /org/runonfilechange/Watcher on
+// interface org.runonfilechange.Watcher:
+//
+//   RunStarted(job: s, command: s, run_id: t)
+//   RunFinished(job: s, command: s, run_id: t, success: b, exit_code: i,
+//               duration_ms: t)
+//
+// `job` is empty for the command-line job and `exit_code` is -1 when a
+// signal ended the run.
+
+use crate::signals;
+use std::{
+  os::unix::process::CommandExt,
+  process::{Command, Stdio},
+  sync::Once,
+  thread,
+};
+use tracing::warn;
+
+const PATH: &str = "/org/runonfilechange/Watcher";
+const INTERFACE: &str = "org.runonfilechange.Watcher";
+
+pub fn run_started(job: &str, command: &str, run_id: u64) {
+  emit(
+    "RunStarted",
+    &[
+      format!("string:{job}"),
+      format!("string:{command}"),
+      format!("uint64:{run_id}"),
+    ],
+  );
+}
+
+pub fn run_finished(
+  job: &str,
+  command: &str,
+  run_id: u64,
+  success: bool,
+  exit_code: Option<i32>,
+  duration_ms: u64,
+) {
+  emit(
+    "RunFinished",
+    &[
+      format!("string:{job}"),
+      format!("string:{command}"),
+      format!("uint64:{run_id}"),
+      format!("boolean:{success}"),
+      format!("int32:{}", exit_code.unwrap_or(-1)),
+      format!("uint64:{duration_ms}"),
+    ],
+  );
+}
+
+/// Sends the signal in the background.
+fn emit(member: &str, args: &[String]) {
+  let mut command = Command::new("dbus-send");
+  command
+    .arg("--session")
+    .arg("--type=signal")
+    .arg(PATH)
+    .arg(format!("{INTERFACE}.{member}"))
+    .args(args)
+    .stdin(Stdio::null())
+    .stdout(Stdio::null())
+    .stderr(Stdio::null());
+  // SAFETY: sigprocmask is async-signal-safe
+  unsafe { command.pre_exec(signals::clear_mask) };
+  match command.spawn() {
+    Ok(mut child) => {
+      thread::spawn(move || child.wait());
+    }
+    Err(e) => {
+      static WARNED: Once = Once::new();
+      WARNED.call_once(|| warn!("Could not run dbus-send: {e}"));
+    }
+  }
+}
@@ -0,0 +1,206 @@
+// Line diffs between the output of consecutive runs (`--diff-output`).
+
+use std::io::{self, Write};
+
+/// Lines of context around each change.
+const CONTEXT: usize = 3;
+/// Beyond this many differing lines the outputs are treated as unrelated
+/// instead of spending memory on the edit script.
+const MAX_EDITS: usize = 2_000;
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Op {
+  Same,
+  Delete,
+  Insert,
+}
+
+/// Myers' algorithm: the shortest edit script turning `a` into `b`, as
+/// one op per line, or `None` if it would exceed [`MAX_EDITS`].
+fn edits<T: PartialEq>(a: &[T], b: &[T]) -> Option<Vec<Op>> {
+  let (n, m) = (a.len() as isize, b.len() as isize);
+  let max = (n + m) as usize;
+  let off = max as isize + 1;
+  let mut v = vec![0isize; 2 * max + 3];
+  let mut trace = Vec::new();
+  let at = |k: isize| (off + k) as usize;
+
+  'search: for d in 0..=max.min(MAX_EDITS) as isize {
+    trace.push(v.clone());
+    for k in (-d..=d).step_by(2) {
+      let mut x = if k == -d || (k != d && v[at(k - 1)] < v[at(k + 1)]) {
+        v[at(k + 1)]
+      } else {
+        v[at(k - 1)] + 1
+      };
+      let mut y = x - k;
+      while x < n && y < m && a[x as usize] == b[y as usize] {
+        x += 1;
+        y += 1;
+      }
+      v[at(k)] = x;
+      if x >= n && y >= m {
+        break 'search;
+      }
+    }
+    if d == max.min(MAX_EDITS) as isize {
+      return None;
+    }
+  }
+
+  // Walk back through the saved frontiers to recover the path.
+  let mut ops = Vec::new();
+  let (mut x, mut y) = (n, m);
+  for (d, v) in trace.iter().enumerate().rev() {
+    let d = d as isize;
+    let k = x - y;
+    let prev_k = if k == -d || (k != d && v[at(k - 1)] < v[at(k + 1)]) {
+      k + 1
+    } else {
+      k - 1
+    };
+    let prev_x = v[at(prev_k)];
+    let prev_y = prev_x - prev_k;
+    while x > prev_x && y > prev_y {
+      ops.push(Op::Same);
+      x -= 1;
+      y -= 1;
+    }
+    if d > 0 {
+      ops.push(if x == prev_x { Op::Insert } else { Op::Delete });
+    }
+    (x, y) = (prev_x, prev_y);
+  }
+  ops.reverse();
+  Some(ops)
+}
+
+/// Writes a unified diff of `old` against `new` (whole lines, newlines
+/// included) to `out`. Returns `false` without writing anything if they
+/// are the same, or if they differ too much to diff, in which case the
+/// caller should show `new` in full.
+pub fn unified(
+  old: &[&[u8]],
+  new: &[&[u8]],
+  labels: (&str, &str),
+  out: &mut impl Write,
+) -> io::Result<bool> {
+  let Some(ops) = edits(old, new) else {
+    return Ok(false);
+  };
+  if ops.iter().all(|&op| op == Op::Same) {
+    return Ok(false);
+  }
+  // Position in `old` and `new` before each op.
+  let mut positions = Vec::with_capacity(ops.len() + 1);
+  let (mut i, mut j) = (0, 0);
+  for &op in &ops {
+    positions.push((i, j));
+    match op {
+      Op::Same => (i, j) = (i + 1, j + 1),
+      Op::Delete => i += 1,
+      Op::Insert => j += 1,
+    }
+  }
+  positions.push((i, j));
+
+  writeln!(out, "--- {}\n+++ {}", labels.0, labels.1)?;
+  let changed: Vec<usize> = (0..ops.len()).filter(|&i| ops[i] != Op::Same).collect();
+  let mut next = 0;
+  while next < changed.len() {
+    // Grow the hunk while the next change is within reach of its context.
+    let start = changed[next].saturating_sub(CONTEXT);
+    let mut last = changed[next];
+    next += 1;
+    while next < changed.len() && changed[next] <= last + 2 * CONTEXT + 1 {
+      last = changed[next];
+      next += 1;
+    }
+    let end = (last + CONTEXT + 1).min(ops.len());
+    let (from, to) = (positions[start], positions[end]);
+    // An empty range names the line before it, as diff(1) does.
+    let range = |from: usize, to: usize| match to - from {
+      0 => format!("{from},0"),
+      len => format!("{},{len}", from + 1),
+    };
+    writeln!(
+      out,
+      "@@ -{} +{} @@",
+      range(from.0, to.0),
+      range(from.1, to.1)
+    )?;
+    for (op, &(i, j)) in ops[start..end].iter().zip(&positions[start..end]) {
+      let (marker, line) = match op {
+        Op::Same => (b' ', new[j]),
+        Op::Delete => (b'-', old[i]),
+        Op::Insert => (b'+', new[j]),
+      };
+      out.write_all(&[marker])?;
+      out.write_all(line)?;
+      if !line.ends_with(b"\n") {
+        out.write_all(b"\n\\ No newline at end of file\n")?;
+      }
+    }
+  }
+  Ok(true)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn lines(s: &str) -> Vec<&[u8]> {
+    s.as_bytes().split_inclusive(|&b| b == b'\n').collect()
+  }
+
+  fn diff(old: &str, new: &str) -> Option<String> {
+    let (old, new) = (lines(old), lines(new));
+    let mut out = Vec::new();
+    match unified(&old, &new, ("a", "b"), &mut out).unwrap() {
+      true => Some(String::from_utf8(out).unwrap()),
+      false => None,
+    }
+  }
+
+  #[test]
+  fn same_is_no_diff() {
+    assert_eq!(diff("a\nb\n", "a\nb\n"), None);
+    assert_eq!(diff("", ""), None);
+  }
+
+  #[test]
+  fn one_hunk() {
+    assert_eq!(
+      diff("1\n2\n3\n4\n5\n", "1\n2\nthree\n4\n5\n").unwrap(),
+      "--- a\n+++ b\n@@ -1,5 +1,5 @@\n 1\n 2\n-3\n+three\n 4\n 5\n"
+    );
+    assert_eq!(
+      diff("", "new\n").unwrap(),
+      "--- a\n+++ b\n@@ -0,0 +1,1 @@\n+new\n"
+    );
+  }
+
+  #[test]
+  fn distant_changes_get_their_own_hunks() {
+    let old: String = (1..=20).map(|n| format!("{n}\n")).collect();
+    let new: String = (1..=20)
+      .map(|n| match n {
+        2 => "two\n".to_string(),
+        19 => "nineteen\n".to_string(),
+        n => format!("{n}\n"),
+      })
+      .collect();
+    let out = diff(&old, &new).unwrap();
+    assert_eq!(out.matches("@@ -").count(), 2, "{out}");
+    assert!(out.contains("@@ -1,5 +1,5 @@\n 1\n-2\n+two\n 3\n 4\n 5\n"));
+    assert!(out.contains("@@ -16,5 +16,5 @@\n 16\n 17\n 18\n-19\n+nineteen\n 20\n"));
+  }
+
+  #[test]
+  fn missing_newline() {
+    assert_eq!(
+      diff("a\n", "a\nb").unwrap(),
+      "--- a\n+++ b\n@@ -1,1 +1,2 @@\n a\n+b\n\\ No newline at end of file\n"
+    );
+  }
+}
@@ -0,0 +1,240 @@
+// Looking for what keeps changes from triggering (`run_on_file_change
+// doctor PATH…`), before digging through debug logs:
+//
+//   run_on_file_change doctor . ../shared
+//
+// Checks that the native backend starts, that each path exists and can be
+// read throughout, how many directories watching them takes against the
+// inotify limits, whether a path is on a network filesystem, whether one
+// lies inside another, and which directories below them builds and
+// package managers churn through. Each finding says what to do about it.
+// The exit status is 1 if any finding is a problem rather than a warning.
+
+use crate::{exit, stats, watch};
+use notify::{Config, RecommendedWatcher, Watcher};
+use std::{
+  fs, io,
+  path::{Path, PathBuf},
+};
+
+/// Directories that tools write to in bulk, which are better ignored.
+const CHURNING: &[&str] = &[
+  "target",
+  "node_modules",
+  "build",
+  "dist",
+  "__pycache__",
+  ".venv",
+  ".git",
+];
+
+/// Unreadable directories named before the rest are only counted.
+const SHOWN: usize = 3;
+
+/// Share of the watch limit past which other programs may run short.
+const CROWDED: f64 = 0.5;
+
+#[derive(Default)]
+struct Findings {
+  problems: usize,
+  warnings: usize,
+}
+
+impl Findings {
+  fn ok(&self, msg: &str) {
+    println!("ok       {msg}");
+  }
+
+  fn warning(&mut self, msg: &str) {
+    self.warnings += 1;
+    println!("warning  {msg}");
+  }
+
+  fn problem(&mut self, msg: &str) {
+    self.problems += 1;
+    println!("problem  {msg}");
+  }
+}
+
+/// "1 directory", "2 directories".
+fn directories(n: usize) -> String {
+  format!("{n} director{}", if n == 1 { "y" } else { "ies" })
+}
+
+/// What a walk of a watched tree came across.
+#[derive(Default)]
+struct Tree {
+  dirs: usize,
+  unreadable: Vec<PathBuf>,
+  /// Churning directories, with how many directories each holds.
+  churning: Vec<(PathBuf, usize)>,
+}
+
+/// Walks the directories below `root`, as the native backend would watch
+/// them (symlinks not followed).
+fn walk(root: &Path) -> Tree {
+  let mut tree = Tree::default();
+  let mut pending = vec![(root.to_path_buf(), None::<usize>)];
+  while let Some((dir, churn)) = pending.pop() {
+    tree.dirs += 1;
+    if let Some(k) = churn {
+      tree.churning[k].1 += 1;
+    }
+    let entries = match fs::read_dir(&dir) {
+      Ok(entries) => entries,
+      Err(e) if e.kind() == io::ErrorKind::PermissionDenied => {
+        tree.unreadable.push(dir);
+        continue;
+      }
+      Err(_) => continue,
+    };
+    for entry in entries.flatten() {
+      if !entry.file_type().is_ok_and(|t| t.is_dir()) {
+        continue;
+      }
+      let path = entry.path();
+      let churn = churn.or_else(|| {
+        let name = entry.file_name();
+        CHURNING.contains(&name.to_str()?).then(|| {
+          tree.churning.push((path.clone(), 0));
+          tree.churning.len() - 1
+        })
+      });
+      pending.push((path, churn));
+    }
+  }
+  tree
+}
+
+/// `run_on_file_change doctor [PATH…]`, the current directory if none.
+pub fn run(args: impl Iterator<Item = String>) -> ! {
+  let mut args: Vec<PathBuf> = args.map(PathBuf::from).collect();
+  if let Some(arg) = args.iter().find(|a| a.to_string_lossy().starts_with('-')) {
+    eprintln!("Error: unexpected option {arg:?}");
+    eprintln!("Usage: run_on_file_change doctor [path|glob …]");
+    std::process::exit(exit::CONFIG);
+  }
+  if args.is_empty() {
+    args.push(PathBuf::from("."));
+  }
+  let mut findings = Findings::default();
+
+  match RecommendedWatcher::new(|_| {}, Config::default()) {
+    Ok(_) => findings.ok(&format!(
+      "the native backend ({:?}) starts",
+      <RecommendedWatcher as Watcher>::kind()
+    )),
+    Err(e) => findings.problem(&format!(
+      "the native backend does not start ({e}); watch with --path-backend PATH=poll"
+    )),
+  }
+
+  let mut roots = Vec::new();
+  for arg in &args {
+    let root = match watch::interpret(arg, None) {
+      Ok(spec) => spec.root.path,
+      Err(e) => {
+        findings.problem(&format!(
+          "{}; create it first, or pass --allow-missing to watch for it",
+          e.trim_end_matches('.')
+        ));
+        continue;
+      }
+    };
+    let canonical = root.canonicalize().unwrap_or_else(|_| root.clone());
+    roots.push((arg, canonical));
+  }
+
+  let mut nested = Vec::new();
+  for (i, (arg, root)) in roots.iter().enumerate() {
+    if let Some((outer, _)) = roots
+      .iter()
+      .enumerate()
+      .find(|&(j, (_, other))| j != i && root.starts_with(other) && (root != other || j < i))
+      .map(|(_, r)| r)
+    {
+      findings.warning(&format!(
+        "{arg:?} is inside {outer:?}, which already covers it; it is watched once"
+      ));
+      nested.push(i);
+    }
+  }
+
+  let mut total = 0;
+  for (arg, root) in roots
+    .iter()
+    .enumerate()
+    .filter(|(i, _)| !nested.contains(i))
+    .map(|(_, r)| r)
+  {
+    if let Some(fs) = watch::network_filesystem(root) {
+      findings.warning(&format!(
+        "{arg:?} is on {fs}, where changes made from other machines are not reported; \
+         add --path-backend {}=poll",
+        arg.display()
+      ));
+    }
+    let tree = walk(root);
+    total += tree.dirs;
+    findings.ok(&format!("{arg:?}: {} to watch", directories(tree.dirs)));
+    if !tree.unreadable.is_empty() {
+      let mut shown: Vec<String> = tree
+        .unreadable
+        .iter()
+        .take(SHOWN)
+        .map(|d| format!("{d:?}"))
+        .collect();
+      if tree.unreadable.len() > SHOWN {
+        shown.push(format!("{} more", tree.unreadable.len() - SHOWN));
+      }
+      findings.problem(&format!(
+        "cannot read {}; changes in there go unseen, fix the permissions or -i them",
+        shown.join(", ")
+      ));
+    }
+    for (dir, dirs) in &tree.churning {
+      let name = dir.file_name().unwrap_or_default().to_string_lossy();
+      let hint = match name.starts_with('.') {
+        true => "changes there are ignored but still cost watches".to_string(),
+        false => format!("tools rewrite it; -i {name} keeps that from triggering runs"),
+      };
+      findings.warning(&format!("{dir:?} holds {}: {hint}", directories(*dirs)));
+    }
+  }
+
+  match stats::sysctl("max_user_watches") {
+    Some(limit) if total as u64 > limit => findings.problem(&format!(
+      "{} to watch, over the limit of {limit} inotify watches per user; \
+       raise it with sysctl fs.inotify.max_user_watches={}",
+      directories(total),
+      (total as u64 * 2).next_power_of_two()
+    )),
+    Some(limit) if total as f64 > limit as f64 * CROWDED => findings.warning(&format!(
+      "{} to watch take most of the {limit} inotify watches per user, \
+       which other programs share; consider raising fs.inotify.max_user_watches",
+      directories(total)
+    )),
+    Some(limit) => findings.ok(&format!(
+      "{} to watch, within the {limit} inotify watches per user",
+      directories(total)
+    )),
+    None => {}
+  }
+  if let Some(limit) = stats::sysctl("max_user_instances") {
+    findings.ok(&format!(
+      "{limit} inotify instances per user (each watcher takes one, --watch-threads N more)"
+    ));
+  }
+
+  println!(
+    "{} problem{}, {} warning{}",
+    findings.problems,
+    if findings.problems == 1 { "" } else { "s" },
+    findings.warnings,
+    if findings.warnings == 1 { "" } else { "s" }
+  );
+  std::process::exit(match findings.problems {
+    0 => 0,
+    _ => 1,
+  });
+}
@@ -0,0 +1,65 @@
+// Collapsing editor save patterns into plain modifications.
+//
+// Editors rarely write a file in place. Atomic saves write a temporary file
+// and rename it over the target; Vim moves the original to a `~` backup and
+// writes a fresh file; all of them create swap, lock and probe files. Left
+// alone these show up as bursts of events on paths nobody cares about.
+
+use notify::Event;
+use notify::event::{DataChange, EventKind, ModifyKind, RenameMode};
+use std::path::Path;
+
+/// The first name Vim probes directory writability with; later attempts
+/// add 123 each time.
+const VIM_PROBE: u32 = 4913;
+
+/// Whether `path` is a temporary, backup, swap or lock file an editor made.
+pub fn is_temp(path: &Path) -> bool {
+  let Some(name) = path.file_name().and_then(|n| n.to_str()) else {
+    return false;
+  };
+  name.ends_with('~')
+    || [
+      ".swp",
+      ".swo",
+      ".swx",
+      ".tmp",
+      ".kate-swp",
+      "___jb_tmp___",
+      "___jb_old___",
+    ]
+    .iter()
+    .any(|suffix| name.ends_with(suffix))
+    || name.starts_with(".#")
+    || name.starts_with(".goutputstream-")
+    || (name.starts_with('#') && name.ends_with('#'))
+    || name.contains(".tmp.")
+    || name
+      .parse::<u32>()
+      .is_ok_and(|n| n >= VIM_PROBE && (n - VIM_PROBE).is_multiple_of(123))
+}
+
+/// Rewrites an event from an editor save: a temp file renamed over the
+/// target becomes a data change of the target, and events that only touch
+/// temp files are dropped.
+pub fn normalize(event: Event) -> Option<Event> {
+  if matches!(
+    event.kind,
+    EventKind::Modify(ModifyKind::Name(RenameMode::Both))
+  ) && let [from, to] = event.paths.as_slice()
+  {
+    return match (is_temp(from), is_temp(to)) {
+      (true, false) => {
+        let mut modified = Event::new(EventKind::Modify(ModifyKind::Data(DataChange::Any)));
+        modified.paths.push(to.clone());
+        Some(modified)
+      }
+      (_, true) => None, // moved aside as a backup, the new file follows
+      (false, false) => Some(event),
+    };
+  }
+  if !event.paths.is_empty() && event.paths.iter().all(|p| is_temp(p)) {
+    return None;
+  }
+  Some(event)
+}
@@ -0,0 +1,62 @@
+// Why the watcher stopped, as its exit status, for scripts wrapping it:
+//
+//   0        it was done: --max-runs, --expect-exit met, quit, -d, …
+//   1        --expect-exit's run never came
+//   2        invalid arguments or configuration
+//   3        the watches could not be set up
+//   4        a run could not be started (--exit-on-spawn-error)
+//   5        something else it needs failed to start: the log target, a
+//            socket, a hook, a server…
+//   101      it crashed (a bug worth reporting), after stopping its runs
+//   128 + N  shut down by signal N (130 for SIGINT, 143 for SIGTERM)
+
+use nix::sys::signal::Signal;
+use std::fmt;
+
+pub const EXPECTATION_FAILED: i32 = 1;
+pub const CONFIG: i32 = 2;
+pub const WATCH: i32 = 3;
+pub const SPAWN: i32 = 4;
+pub const STARTUP: i32 = 5;
+pub const PANIC: i32 = 101;
+
+/// The status after a shutdown by `signal`, as shells report it.
+pub fn signaled(signal: Signal) -> i32 {
+  128 + signal as i32
+}
+
+/// What stopped the watcher before its time.
+#[derive(Debug)]
+pub enum Error {
+  Config(String),
+  Watch(String),
+  Spawn(String),
+  Startup(String),
+}
+
+impl Error {
+  pub fn code(&self) -> i32 {
+    match self {
+      Error::Config(_) => CONFIG,
+      Error::Watch(_) => WATCH,
+      Error::Spawn(_) => SPAWN,
+      Error::Startup(_) => STARTUP,
+    }
+  }
+}
+
+impl fmt::Display for Error {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      Error::Config(msg) | Error::Watch(msg) | Error::Spawn(msg) | Error::Startup(msg) => {
+        f.write_str(msg)
+      }
+    }
+  }
+}
+
+impl From<notify::Error> for Error {
+  fn from(e: notify::Error) -> Self {
+    Error::Watch(format!("could not set up the watches: {e}"))
+  }
+}
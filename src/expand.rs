@@ -0,0 +1,98 @@
+// `$VAR` / `${VAR}` expansion from the watcher's environment, and the
+// `{name}` placeholders the config's env templates fill in per run.
+
+use std::env;
+
+/// Expands `$VAR`, `${VAR}` and `${VAR:-default}`. References to unset
+/// variables without a default are kept verbatim, so anything meant for
+/// the shell (loop variables, `$1`, `$$`) survives. With `shell_quotes`,
+/// text inside single quotes is left alone, as `/bin/sh` would.
+pub fn expand(input: &str, shell_quotes: bool) -> String {
+  expand_with(input, shell_quotes, |name| env::var(name).ok())
+}
+
+/// [`expand`], with the values of variables from `var`.
+pub fn expand_with(
+  input: &str,
+  shell_quotes: bool,
+  var: impl Fn(&str) -> Option<String>,
+) -> String {
+  let mut out = String::with_capacity(input.len());
+  let mut rest = input;
+  let mut in_quotes = false;
+
+  while let Some(c) = rest.chars().next() {
+    if c == '\'' && shell_quotes {
+      in_quotes = !in_quotes;
+    }
+    if c != '$' || in_quotes {
+      out.push(c);
+      rest = &rest[c.len_utf8()..];
+      continue;
+    }
+
+    let after = &rest[1..];
+    if let Some(braced) = after.strip_prefix('{')
+      && let Some(end) = braced.find('}')
+    {
+      let inner = &braced[..end];
+      let (name, default) = match inner.split_once(":-") {
+        Some((name, default)) => (name, Some(default)),
+        None => (inner, None),
+      };
+      match (var(name), default) {
+        (Some(value), _) if !value.is_empty() || default.is_none() => out.push_str(&value),
+        (_, Some(default)) => out.push_str(default),
+        _ => out.push_str(&rest[..end + 3]),
+      }
+      rest = &braced[end + 1..];
+      continue;
+    }
+
+    let len = after
+      .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+      .unwrap_or(after.len());
+    let name = &after[..len];
+    match var(name) {
+      Some(value) if !name.is_empty() && !name.starts_with(|c: char| c.is_ascii_digit()) => {
+        out.push_str(&value)
+      }
+      _ => out.push_str(&rest[..len + 1]),
+    }
+    rest = &after[len..];
+  }
+  out
+}
+
+/// Fills in `{name}` from `values`. Unknown names are kept verbatim, and
+/// `{{` / `}}` stand for literal braces.
+pub fn placeholders(template: &str, values: &[(&str, String)]) -> String {
+  let mut out = String::with_capacity(template.len());
+  let mut rest = template;
+  while let Some(i) = rest.find(['{', '}']) {
+    out.push_str(&rest[..i]);
+    let at = &rest[i..];
+    if at.starts_with("{{") || at.starts_with("}}") {
+      out.push_str(&at[..1]);
+      rest = &at[2..];
+      continue;
+    }
+    let value = at.strip_prefix('{').and_then(|inner| {
+      let (name, _) = inner.split_once('}')?;
+      let (_, value) = values.iter().find(|(n, _)| *n == name)?;
+      Some((value, name.len() + 2))
+    });
+    match value {
+      Some((value, len)) => {
+        out.push_str(value);
+        rest = &at[len..];
+      }
+      None => {
+        out.push_str(&at[..1]);
+        rest = &at[1..];
+      }
+    }
+  }
+  out.push_str(rest);
+  out
+}
@@ -0,0 +1,490 @@
+// Deciding whether a changed path should trigger a run.
+//
+// Paths and patterns are compared in Unicode NFC: macOS reports names in
+// decomposed form, so a pattern typed as `café` would otherwise never match
+// the `cafe\u{301}` that comes back. The disk is still accessed under the
+// name the event carried.
+
+use crate::cli::Options;
+use globset::{GlobBuilder, GlobSet, GlobSetBuilder};
+use std::{
+  borrow::Cow,
+  fs::{self, File},
+  io::Read,
+  path::{Component, Path, PathBuf},
+  time::Duration,
+};
+use unicode_normalization::{UnicodeNormalization, is_nfc};
+
+/// How much of a file `--text-only` looks at.
+const SAMPLE: u64 = 8 * 1024;
+
+pub struct Filter {
+  /// Directories given as plain paths: any change below them counts.
+  open_roots: Vec<PathBuf>,
+  /// Patterns from glob and file arguments.
+  patterns: GlobSet,
+  /// `-e`: extensions a changed file must have.
+  extensions: Vec<String>,
+  ignore_case: bool,
+  /// `-i`: paths that never trigger, and the patterns they come from.
+  ignore: GlobSet,
+  ignored: Vec<String>,
+  /// Files larger than this never trigger.
+  max_file_size: Option<u64>,
+  /// Files that look binary never trigger.
+  text_only: bool,
+  /// Include dotfiles and dot-directories below plain directory arguments.
+  hidden: bool,
+  /// `--command-writes`: paths the command itself writes.
+  command_writes: GlobSet,
+}
+
+/// `path` in NFC, untouched if it already is (or isn't valid UTF-8).
+fn nfc(path: &Path) -> Cow<'_, Path> {
+  match path.to_str() {
+    Some(s) if !is_nfc(s) => Cow::Owned(PathBuf::from(s.nfc().collect::<String>())),
+    _ => Cow::Borrowed(path),
+  }
+}
+
+fn glob(pattern: &str, ignore_case: bool) -> Result<globset::Glob, globset::Error> {
+  let pattern: String = pattern.nfc().collect();
+  // `*` stays within one path component, as in the shell.
+  GlobBuilder::new(&pattern)
+    .literal_separator(true)
+    .case_insensitive(ignore_case)
+    .build()
+}
+
+/// Builds `-i`-style patterns: event paths are absolute, so relative ones
+/// may match anywhere, and a matching directory takes everything below it
+/// along.
+fn anywhere(patterns: &[String], ignore_case: bool) -> Result<GlobSet, globset::Error> {
+  let mut set = GlobSetBuilder::new();
+  for pattern in patterns {
+    let pattern = pattern.trim_end_matches('/');
+    let anchored = if pattern.starts_with('/') {
+      pattern.to_string()
+    } else {
+      format!("**/{pattern}")
+    };
+    set.add(glob(&anchored, ignore_case)?);
+    set.add(glob(&format!("{anchored}/**"), ignore_case)?);
+  }
+  set.build()
+}
+
+/// Whether `pattern` works as an `-i`-style pattern.
+pub fn check_pattern(pattern: &str) -> Result<(), globset::Error> {
+  anywhere(&[pattern.to_string()], false).map(|_| ())
+}
+
+/// Debounce windows for the paths patterns match (the config's
+/// `[debounce]` table).
+pub struct Windows {
+  /// Two globs per pattern, as [`anywhere`] builds them.
+  set: GlobSet,
+  windows: Vec<Duration>,
+}
+
+impl Windows {
+  pub fn new(windows: &[(String, Duration)], ignore_case: bool) -> Result<Self, globset::Error> {
+    let patterns: Vec<String> = windows.iter().map(|(p, _)| p.clone()).collect();
+    Ok(Windows {
+      set: anywhere(&patterns, ignore_case)?,
+      windows: windows.iter().map(|(_, w)| *w).collect(),
+    })
+  }
+
+  /// The window for a change to `paths`: the shortest of those the
+  /// patterns matching them have, or `default` if none does.
+  pub fn window(&self, paths: &[PathBuf], default: Duration) -> Duration {
+    paths
+      .iter()
+      .flat_map(|p| self.set.matches(&*nfc(p)))
+      .map(|i| self.windows[i / 2])
+      .min()
+      .unwrap_or(default)
+  }
+}
+
+/// How a change to matching paths is scheduled (`--priority`).
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Priority {
+  /// Waits for a run that is going to end rather than stopping it.
+  Low,
+  Normal,
+  /// Skips the debounce window and stops a run that is going right away.
+  High,
+}
+
+impl std::str::FromStr for Priority {
+  type Err = String;
+
+  fn from_str(s: &str) -> Result<Self, String> {
+    match s {
+      "low" => Ok(Priority::Low),
+      "normal" => Ok(Priority::Normal),
+      "high" => Ok(Priority::High),
+      _ => Err(format!("unknown priority {s:?} (high, normal or low)")),
+    }
+  }
+}
+
+/// Priorities for the paths patterns match (`--priority`, and the
+/// config's `[priority]` table).
+pub struct Priorities {
+  /// Two globs per pattern, as [`anywhere`] builds them.
+  set: GlobSet,
+  priorities: Vec<Priority>,
+}
+
+impl Priorities {
+  pub fn new(priorities: &[(String, Priority)], ignore_case: bool) -> Result<Self, globset::Error> {
+    let patterns: Vec<String> = priorities.iter().map(|(p, _)| p.clone()).collect();
+    Ok(Priorities {
+      set: anywhere(&patterns, ignore_case)?,
+      priorities: priorities.iter().map(|(_, p)| *p).collect(),
+    })
+  }
+
+  /// The priority of a change to `paths`: the highest of those the
+  /// patterns matching them have, or normal if none does.
+  pub fn of(&self, paths: &[PathBuf]) -> Priority {
+    paths
+      .iter()
+      .flat_map(|p| self.set.matches(&*nfc(p)))
+      .map(|i| self.priorities[i / 2])
+      .max()
+      .unwrap_or(Priority::Normal)
+  }
+}
+
+/// Commands for changes to some paths, instead of the usual one
+/// (`--command-for`).
+pub struct Commands {
+  /// Two globs per pattern, as [`anywhere`] builds them.
+  set: GlobSet,
+  commands: Vec<String>,
+}
+
+impl Commands {
+  pub fn new(commands: &[(String, String)], ignore_case: bool) -> Result<Self, globset::Error> {
+    let patterns: Vec<String> = commands.iter().map(|(p, _)| p.clone()).collect();
+    Ok(Commands {
+      set: anywhere(&patterns, ignore_case)?,
+      commands: commands.iter().map(|(_, c)| c.clone()).collect(),
+    })
+  }
+
+  /// The command for a change to `paths`: that of the first pattern
+  /// matching one of them, if any does.
+  pub fn of(&self, paths: &[PathBuf]) -> Option<&str> {
+    paths
+      .iter()
+      .flat_map(|p| self.set.matches(&*nfc(p)))
+      .min()
+      .map(|i| self.commands[i / 2].as_str())
+  }
+}
+
+/// Why a change to a path does or doesn't trigger a run.
+pub enum Verdict {
+  /// It is below this watched directory (the closest one).
+  Below(PathBuf),
+  /// It matches a glob or file argument.
+  Pattern,
+  /// Nothing selects paths (only repository events), so all pass.
+  Anything,
+  /// It matches this `-i` pattern.
+  Ignored(String),
+  /// It doesn't have one of the `-e` extensions.
+  Extension,
+  /// A dotfile or in a dot-directory, without `--hidden`.
+  Hidden,
+  /// Neither below a watched directory nor matching a pattern.
+  Unwatched,
+  /// Over `--max-file-size`.
+  TooLarge,
+  /// Binary, with `--text-only`.
+  Binary,
+}
+
+impl Verdict {
+  pub fn triggers(&self) -> bool {
+    matches!(
+      self,
+      Verdict::Below(_) | Verdict::Pattern | Verdict::Anything
+    )
+  }
+}
+
+impl std::fmt::Display for Verdict {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    match self {
+      Verdict::Below(root) => write!(f, "triggers: below watched {}", root.display()),
+      Verdict::Pattern => f.write_str("triggers: matches a watched glob or file"),
+      Verdict::Anything => f.write_str("triggers: nothing narrows the paths down"),
+      Verdict::Ignored(pattern) => write!(f, "ignored: matches -i {pattern}"),
+      Verdict::Extension => f.write_str("ignored: not one of the -e extensions"),
+      Verdict::Hidden => f.write_str("ignored: hidden (a dotfile or below one), see --hidden"),
+      Verdict::Unwatched => f.write_str("ignored: not below any watched path or glob"),
+      Verdict::TooLarge => f.write_str("ignored: larger than --max-file-size"),
+      Verdict::Binary => f.write_str("ignored: looks binary, with --text-only"),
+    }
+  }
+}
+
+impl Filter {
+  /// `patterns` come from the watch arguments, everything else from the
+  /// filtering options.
+  pub fn new(
+    open_roots: Vec<PathBuf>,
+    patterns: &[String],
+    opts: &Options,
+  ) -> Result<Self, globset::Error> {
+    let mut set = GlobSetBuilder::new();
+    for pattern in patterns {
+      set.add(glob(pattern, opts.ignore_case)?);
+    }
+    Ok(Filter {
+      open_roots: open_roots.iter().flat_map(|r| spellings(r)).collect(),
+      patterns: set.build()?,
+      extensions: opts.extensions.clone(),
+      ignore_case: opts.ignore_case,
+      ignore: anywhere(&opts.ignore, opts.ignore_case)?,
+      ignored: opts.ignore.clone(),
+      max_file_size: opts.max_file_size,
+      text_only: opts.text_only,
+      hidden: opts.hidden,
+      command_writes: anywhere(&opts.command_writes, opts.ignore_case)?,
+    })
+  }
+
+  /// Lets changes below `root` through from now on (`ctl watch add`).
+  pub fn add_root(&mut self, root: &Path) {
+    self.open_roots.extend(spellings(root));
+  }
+
+  /// Turns changes below `root` down again (`ctl watch remove`).
+  pub fn remove_root(&mut self, root: &Path) {
+    let gone = spellings(root);
+    self.open_roots.retain(|r| !gone.contains(r));
+  }
+
+  /// Whether `path` is one the command was declared to write.
+  pub fn written_by_command(&self, path: &Path) -> bool {
+    self.command_writes.is_match(&*nfc(path))
+  }
+
+  /// Whether nothing below directory `dir` can match, so a walk of the
+  /// tree may skip it.
+  pub fn prunes(&self, dir: &Path) -> bool {
+    let dir = &*nfc(dir);
+    if self.ignore.is_match(dir) {
+      return true;
+    }
+    let root = self
+      .open_roots
+      .iter()
+      .filter(|r| dir.starts_with(r))
+      .max_by_key(|r| r.as_os_str().len());
+    root.is_some_and(|root| !self.hidden && hidden_below(dir, root))
+  }
+
+  /// Whether a change to `path` should trigger a run.
+  pub fn matches(&self, path: &Path) -> bool {
+    self.verdict(path).triggers()
+  }
+
+  /// What the filters make of a change to `path`, and why.
+  pub fn verdict(&self, original: &Path) -> Verdict {
+    let path = &*nfc(original);
+    if self.ignore.is_match(path) {
+      let pattern = self.ignore.matches(path).first().map_or(0, |i| i / 2);
+      return Verdict::Ignored(self.ignored[pattern].clone());
+    }
+    if !self.extensions.is_empty()
+      && !path.extension().and_then(|e| e.to_str()).is_some_and(|e| {
+        self
+          .extensions
+          .iter()
+          .any(|x| e == x || (self.ignore_case && e.eq_ignore_ascii_case(x)))
+      })
+    {
+      return Verdict::Extension;
+    }
+    let root = self
+      .open_roots
+      .iter()
+      .filter(|r| path.starts_with(r))
+      .max_by_key(|r| r.as_os_str().len());
+    let verdict = match root {
+      Some(root) if !self.hidden && hidden_below(path, root) => Verdict::Hidden,
+      Some(root) => Verdict::Below(root.clone()),
+      // With nothing to select by (only repository events), all passes.
+      None if self.patterns.is_empty() && self.open_roots.is_empty() => Verdict::Anything,
+      None if self.patterns.is_match(path) => Verdict::Pattern,
+      None => Verdict::Unwatched,
+    };
+    if !verdict.triggers() {
+      verdict
+    } else if self.too_large(original) {
+      Verdict::TooLarge
+    } else if self.text_only && looks_binary(original) {
+      Verdict::Binary
+    } else {
+      verdict
+    }
+  }
+
+  /// Looked at last, as they are the checks that touch the disk. A path
+  /// that is gone or isn't a regular file passes.
+  fn too_large(&self, path: &Path) -> bool {
+    self
+      .max_file_size
+      .is_some_and(|max| fs::metadata(path).is_ok_and(|m| m.is_file() && m.len() > max))
+  }
+}
+
+/// How event paths below `root` may start. They are absolute, and so must
+/// the roots be to hold them. Both spellings of a root through a symlink
+/// are kept, for the native backend reports paths as watched and polling
+/// canonical ones.
+fn spellings(root: &Path) -> Vec<PathBuf> {
+  let absolute = std::path::absolute(root).unwrap_or_else(|_| root.to_path_buf());
+  let canonical = root.canonicalize().unwrap_or_else(|_| absolute.clone());
+  let spelled = (absolute != canonical).then_some(absolute);
+  [Some(canonical), spelled]
+    .into_iter()
+    .flatten()
+    .map(|r| nfc(&r).into_owned())
+    .collect()
+}
+
+/// Whether a component of `path` below `root` starts with a dot. The root
+/// itself may be hidden: watching `~/.config` explicitly still works.
+fn hidden_below(path: &Path, root: &Path) -> bool {
+  path.strip_prefix(root).is_ok_and(|rest| {
+    rest
+      .components()
+      .any(|c| matches!(c, Component::Normal(name) if name.as_encoded_bytes().starts_with(b".")))
+  })
+}
+
+/// Git's heuristic: a NUL byte in the first few kilobytes means binary.
+fn looks_binary(path: &Path) -> bool {
+  let Ok(file) = File::open(path) else {
+    return false;
+  };
+  if !file.metadata().is_ok_and(|m| m.is_file()) {
+    return false;
+  }
+  let mut sample = Vec::new();
+  file.take(SAMPLE).read_to_end(&mut sample).is_ok() && sample.contains(&0)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn filter(roots: &[&str], patterns: &[&str], opts: Options) -> Filter {
+    let roots = roots.iter().map(PathBuf::from).collect();
+    let patterns: Vec<String> = patterns.iter().map(|p| p.to_string()).collect();
+    Filter::new(roots, &patterns, &opts).unwrap()
+  }
+
+  fn passes(filter: &Filter, path: &str) -> bool {
+    filter.matches(Path::new(path))
+  }
+
+  #[test]
+  fn hidden_paths() {
+    let f = filter(&["/w/proj", "/w/.config"], &[], Options::default());
+    assert!(passes(&f, "/w/proj/src/main.rs"));
+    assert!(!passes(&f, "/w/proj/.git/index"));
+    assert!(!passes(&f, "/w/proj/src/.main.rs.swp"));
+    // A dot-directory watched explicitly isn't hidden below itself.
+    assert!(passes(&f, "/w/.config/app.toml"));
+    assert!(!passes(&f, "/w/.config/.cache/x"));
+    let opts = Options {
+      hidden: true,
+      ..Options::default()
+    };
+    assert!(passes(&filter(&["/w/proj"], &[], opts), "/w/proj/.env"));
+  }
+
+  #[test]
+  fn ignore_patterns() {
+    let opts = Options {
+      ignore: vec!["target/".into(), "*.log".into(), "/w/proj/tmp".into()],
+      ..Options::default()
+    };
+    let f = filter(&["/w/proj"], &[], opts);
+    // A directory takes everything below it along.
+    assert!(!passes(&f, "/w/proj/target"));
+    assert!(!passes(&f, "/w/proj/target/debug/app"));
+    // Relative patterns match at any depth, absolute ones only there.
+    assert!(!passes(&f, "/w/proj/a/b/c.log"));
+    assert!(!passes(&f, "/w/proj/tmp/x"));
+    assert!(passes(&f, "/w/proj/a/tmp/x"));
+    assert!(passes(&f, "/w/proj/src/target.rs"));
+  }
+
+  #[test]
+  fn ignore_case() {
+    let extensions = || vec!["rs".to_string(), "md".to_string()];
+    let opts = Options {
+      extensions: extensions(),
+      ..Options::default()
+    };
+    let f = filter(&["/w"], &[], opts);
+    assert!(passes(&f, "/w/a.rs"));
+    assert!(!passes(&f, "/w/A.RS"));
+    let opts = Options {
+      extensions: extensions(),
+      ignore: vec!["BUILD".into()],
+      ignore_case: true,
+      ..Options::default()
+    };
+    let f = filter(&["/w"], &["/g/*.MD"], opts);
+    assert!(passes(&f, "/w/A.RS"));
+    assert!(!passes(&f, "/w/build/a.rs"));
+    assert!(passes(&f, "/g/readme.md"));
+    assert!(!passes(&f, "/g/readme.txt"));
+  }
+
+  #[test]
+  fn unicode_normalization() {
+    let opts = Options {
+      ignore: vec!["naïve".into()],
+      ..Options::default()
+    };
+    let f = filter(&["/w/re\u{301}sume\u{301}"], &["/g/café.txt"], opts);
+    // Decomposed names, as macOS reports them, match composed patterns.
+    assert!(passes(&f, "/g/cafe\u{301}.txt"));
+    assert!(passes(&f, "/g/café.txt"));
+    assert!(!passes(&f, "/g/cafe.txt"));
+    assert!(passes(&f, "/w/résumé/a"));
+    assert!(!passes(&f, "/w/résumé/nai\u{308}ve/a"));
+  }
+
+  #[test]
+  fn verdicts() {
+    let opts = Options {
+      ignore: vec!["*.log".into()],
+      ..Options::default()
+    };
+    let f = filter(&["/w", "/w/proj"], &["/g/*.md"], opts);
+    let verdict = |path: &str| f.verdict(Path::new(path));
+    assert!(matches!(verdict("/w/proj/a"), Verdict::Below(root) if root == Path::new("/w/proj")));
+    assert!(matches!(verdict("/w/a"), Verdict::Below(root) if root == Path::new("/w")));
+    assert!(matches!(verdict("/g/a.md"), Verdict::Pattern));
+    assert!(matches!(verdict("/w/a.log"), Verdict::Ignored(p) if p == "*.log"));
+    assert!(matches!(verdict("/w/.git/x"), Verdict::Hidden));
+    assert!(matches!(verdict("/elsewhere/a"), Verdict::Unwatched));
+    let f = filter(&[], &[], Options::default());
+    assert!(matches!(f.verdict(Path::new("/any")), Verdict::Anything));
+  }
+}
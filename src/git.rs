@@ -0,0 +1,323 @@
+// Git integration, by shelling out to `git`.
+
+use std::{
+  collections::HashSet,
+  fs,
+  path::{Path, PathBuf},
+  process::Command,
+  time::SystemTime,
+};
+use tracing::debug;
+
+/// Runs `git -C dir args…` and returns its stdout if it succeeded.
+fn git(dir: &Path, args: &[&str]) -> Option<Vec<u8>> {
+  let output = Command::new("git")
+    .arg("-C")
+    .arg(dir)
+    .args(args)
+    .output()
+    .ok()?;
+  output.status.success().then_some(output.stdout)
+}
+
+/// Resolves a path the way git reports them, even if it no longer exists:
+/// canonical parent directory plus file name.
+fn absolute(path: &Path) -> Option<PathBuf> {
+  if let Ok(path) = path.canonicalize() {
+    return Some(path);
+  }
+  Some(path.parent()?.canonicalize().ok()?.join(path.file_name()?))
+}
+
+struct Repo {
+  top: PathBuf,
+  git_dir: PathBuf,
+  /// Modification time of the index the file list was read from.
+  stamp: Option<SystemTime>,
+  files: HashSet<PathBuf>,
+}
+
+impl Repo {
+  fn open(dir: &Path) -> Option<Repo> {
+    let out = git(dir, &["rev-parse", "--show-toplevel", "--absolute-git-dir"])?;
+    let out = String::from_utf8(out).ok()?;
+    let mut lines = out.lines();
+    Some(Repo {
+      top: lines.next()?.into(),
+      git_dir: lines.next()?.into(),
+      stamp: None,
+      files: HashSet::new(),
+    })
+  }
+
+  /// Re-reads the tracked files if the index changed since the last time.
+  fn refresh(&mut self) {
+    let stamp = fs::metadata(self.git_dir.join("index"))
+      .and_then(|m| m.modified())
+      .ok();
+    if stamp.is_some() && stamp == self.stamp {
+      return;
+    }
+    self.stamp = stamp;
+    let Some(out) = git(&self.top, &["ls-files", "-z", "--full-name"]) else {
+      return;
+    };
+    self.files = out
+      .split(|&b| b == 0)
+      .filter(|name| !name.is_empty())
+      .map(|name| self.top.join(String::from_utf8_lossy(name).as_ref()))
+      .collect();
+    debug!(repo = ?self.top, files = self.files.len(), "tracked files loaded");
+  }
+}
+
+/// `--git-tracked-only`: the files in the index of the repositories the
+/// watched paths belong to.
+pub struct Tracked {
+  repos: Vec<Repo>,
+}
+
+impl Tracked {
+  /// Finds the repository of every watched root; errors for a root that
+  /// isn't inside one.
+  pub fn new(roots: &[PathBuf]) -> Result<Tracked, String> {
+    let mut repos: Vec<Repo> = Vec::new();
+    for root in roots {
+      let repo =
+        Repo::open(root).ok_or_else(|| format!("{:?} is not inside a git repository.", root))?;
+      if !repos.iter().any(|r| r.top == repo.top) {
+        repos.push(repo);
+      }
+    }
+    Ok(Tracked { repos })
+  }
+
+  /// Whether `path` is a file git tracks.
+  pub fn contains(&mut self, path: &Path) -> bool {
+    let Some(path) = absolute(path) else {
+      return false;
+    };
+    self
+      .repos
+      .iter_mut()
+      .filter(|r| path.starts_with(&r.top))
+      .any(|repo| {
+        repo.refresh();
+        repo.files.contains(&path)
+      })
+  }
+}
+
+/// `--describe-changes`: what changed below the watched roots of a
+/// repository from one trigger to the next, as `git diff --numstat` sees
+/// it. The working tree at each trigger is kept as an unreferenced commit
+/// (`git stash create`, which touches neither the tree nor any ref), or
+/// HEAD if nothing was modified. Untracked files don't show.
+pub struct Changes {
+  top: PathBuf,
+  roots: Vec<PathBuf>,
+  /// The working tree as of the last trigger.
+  base: String,
+}
+
+/// Lines added and removed per file (`None` for binary files), with paths
+/// relative to the top of the repository.
+pub struct DiffStat {
+  pub files: Vec<(String, Option<(u64, u64)>)>,
+}
+
+impl Changes {
+  /// For the roots in the repository the current directory is in.
+  pub fn new(roots: &[PathBuf]) -> Result<Changes, String> {
+    let repo =
+      Repo::open(Path::new(".")).ok_or("the current directory is not inside a git repository")?;
+    let roots: Vec<PathBuf> = roots
+      .iter()
+      .filter_map(|r| absolute(r))
+      .filter(|r| r.starts_with(&repo.top))
+      .collect();
+    let mut changes = Changes {
+      top: repo.top,
+      roots,
+      base: String::new(),
+    };
+    changes.base = changes
+      .snapshot()
+      .ok_or("could not read the working tree (no commit yet?)")?;
+    Ok(changes)
+  }
+
+  fn snapshot(&self) -> Option<String> {
+    let stash = git(&self.top, &["stash", "create"])?;
+    let stash = String::from_utf8(stash).ok()?.trim().to_string();
+    if !stash.is_empty() {
+      return Some(stash);
+    }
+    let head = git(&self.top, &["rev-parse", "HEAD"])?;
+    Some(String::from_utf8(head).ok()?.trim().to_string())
+  }
+
+  /// The changes since the last call (or since the watcher started), or
+  /// `None` if no tracked file below the roots changed.
+  pub fn since_last(&mut self) -> Option<DiffStat> {
+    let now = self.snapshot()?;
+    let base = std::mem::replace(&mut self.base, now.clone());
+    let mut args = vec!["diff", "--numstat", "-z", "--no-renames", &base, &now, "--"];
+    let roots: Vec<String> = self
+      .roots
+      .iter()
+      .map(|r| r.to_string_lossy().into_owned())
+      .collect();
+    args.extend(roots.iter().map(String::as_str));
+    let out = git(&self.top, &args)?;
+    let files: Vec<(String, Option<(u64, u64)>)> = out
+      .split(|&b| b == 0)
+      .filter(|entry| !entry.is_empty())
+      .filter_map(|entry| {
+        let entry = String::from_utf8_lossy(entry);
+        let mut fields = entry.splitn(3, '\t');
+        let added = fields.next()?.parse().ok();
+        let removed = fields.next()?.parse().ok();
+        Some((fields.next()?.to_string(), added.zip(removed)))
+      })
+      .collect();
+    (!files.is_empty()).then_some(DiffStat { files })
+  }
+}
+
+impl DiffStat {
+  /// Like the last line of `git diff --stat`: `2 files changed, +12 -4`.
+  pub fn summary(&self) -> String {
+    let (added, removed) = self
+      .files
+      .iter()
+      .filter_map(|(_, lines)| *lines)
+      .fold((0, 0), |(a, r), (fa, fr)| (a + fa, r + fr));
+    match self.files.len() {
+      1 => format!("1 file changed, +{added} -{removed}"),
+      n => format!("{n} files changed, +{added} -{removed}"),
+    }
+  }
+
+  /// The files, with their own counts, up to `max` of them.
+  pub fn details(&self, max: usize) -> String {
+    let mut parts: Vec<String> = self
+      .files
+      .iter()
+      .take(max)
+      .map(|(path, lines)| match lines {
+        Some((added, removed)) => format!("{path} +{added} -{removed}"),
+        None => format!("{path} (binary)"),
+      })
+      .collect();
+    if self.files.len() > max {
+      parts.push(format!("and {} more", self.files.len() - max));
+    }
+    parts.join(", ")
+  }
+}
+
+/// Repository-level events, as recorded in the HEAD reflog.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum GitEvent {
+  Commit,
+  Checkout,
+  Merge,
+}
+
+impl std::fmt::Display for GitEvent {
+  fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+    f.write_str(match self {
+      GitEvent::Commit => "commit",
+      GitEvent::Checkout => "checkout",
+      GitEvent::Merge => "merge",
+    })
+  }
+}
+
+impl GitEvent {
+  /// A comma-separated list like `commit,checkout`.
+  pub fn parse_list(s: &str) -> Option<Vec<GitEvent>> {
+    s.split(',')
+      .map(|name| match name.trim() {
+        "commit" => Some(GitEvent::Commit),
+        "checkout" => Some(GitEvent::Checkout),
+        "merge" => Some(GitEvent::Merge),
+        _ => None,
+      })
+      .collect()
+  }
+
+  /// Classifies a reflog message ("commit (amend): …", "checkout: moving
+  /// from a to b", "merge topic: Fast-forward", "pull: …").
+  fn of(message: &str) -> Option<GitEvent> {
+    let action = message.split(':').next()?;
+    match action {
+      "commit (merge)" => Some(GitEvent::Merge),
+      a if a.starts_with("commit") => Some(GitEvent::Commit),
+      a if a.starts_with("checkout") => Some(GitEvent::Checkout),
+      a if a.starts_with("merge") || a.starts_with("pull") => Some(GitEvent::Merge),
+      _ => None,
+    }
+  }
+}
+
+/// `--on-git`: follows the HEAD reflog for the selected events.
+pub struct Reflog {
+  path: PathBuf,
+  /// Bytes of the reflog already seen.
+  offset: u64,
+  events: Vec<GitEvent>,
+}
+
+impl Reflog {
+  pub fn open(dir: &Path, events: Vec<GitEvent>) -> Result<Reflog, String> {
+    let repo =
+      Repo::open(dir).ok_or_else(|| format!("{:?} is not inside a git repository.", dir))?;
+    let logs = repo.git_dir.join("logs");
+    // git creates it with the first commit; the watch needs it now.
+    fs::create_dir_all(&logs).map_err(|e| format!("{:?}: {e}", logs))?;
+    let path = logs.join("HEAD");
+    let offset = fs::metadata(&path).map_or(0, |m| m.len());
+    Ok(Reflog {
+      path,
+      offset,
+      events,
+    })
+  }
+
+  /// The directory to watch (non-recursively) for reflog updates.
+  pub fn dir(&self) -> &Path {
+    self.path.parent().unwrap_or(&self.path)
+  }
+
+  pub fn is_reflog(&self, path: &Path) -> bool {
+    path == self.path
+  }
+
+  /// Entries appended since the last call that are among the selected
+  /// events, with their messages.
+  pub fn read_new(&mut self) -> Vec<(GitEvent, String)> {
+    let Ok(content) = fs::read(&self.path) else {
+      return Vec::new();
+    };
+    if (content.len() as u64) < self.offset {
+      self.offset = 0; // rewritten, e.g. by `git reflog expire`
+    }
+    let new = &content[self.offset as usize..];
+    // Only complete lines; a partial one is picked up next time.
+    let complete = new.iter().rposition(|&b| b == b'\n').map_or(0, |i| i + 1);
+    self.offset += complete as u64;
+    String::from_utf8_lossy(&new[..complete])
+      .lines()
+      .filter_map(|line| {
+        let message = line.split_once('\t')?.1;
+        let event = GitEvent::of(message)?;
+        self
+          .events
+          .contains(&event)
+          .then(|| (event, message.to_string()))
+      })
+      .collect()
+  }
+}
@@ -0,0 +1,131 @@
+// Run history kept across sessions (`--state-dir DIR`), for how the
+// feedback loop does over days rather than one sitting.
+//
+// Every run that ends or is stopped is appended to DIR/history.jsonl as a
+// JSON object per line: the summary's record of it, plus the command, the
+// watcher it ran under (its command or `--config` file) and when that
+// watcher started. `ctl history --state-dir DIR` prints the newest runs,
+// and `--stats` adds up the earlier sessions of the same watcher.
+
+use crate::summary::RunRecord;
+use chrono::DateTime;
+use serde::{Deserialize, Serialize};
+use std::{
+  collections::BTreeSet,
+  fs::{self, File},
+  io::{self, BufRead, BufReader, Write},
+  path::{Path, PathBuf},
+};
+use tracing::warn;
+
+#[derive(Serialize, Deserialize)]
+struct Entry {
+  watcher: String,
+  session_started_at: String,
+  command: String,
+  #[serde(flatten)]
+  run: RunRecord,
+}
+
+/// What the history holds about earlier sessions of a watcher.
+#[derive(Default)]
+pub struct Earlier {
+  pub sessions: usize,
+  pub runs: usize,
+  pub succeeded: usize,
+  pub failed: usize,
+  pub total_duration_ms: u64,
+}
+
+pub struct History {
+  file: File,
+  watcher: String,
+  session_started_at: String,
+  pub earlier: Earlier,
+}
+
+fn path(dir: &Path) -> PathBuf {
+  dir.join("history.jsonl")
+}
+
+/// The entries in `dir`'s history, skipping lines that don't parse.
+fn entries(dir: &Path) -> io::Result<Vec<Entry>> {
+  let file = match File::open(path(dir)) {
+    Ok(file) => file,
+    Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+    Err(e) => return Err(e),
+  };
+  let mut entries = Vec::new();
+  for line in BufReader::new(file).lines() {
+    match serde_json::from_str(&line?) {
+      Ok(entry) => entries.push(entry),
+      Err(e) => warn!("Skipping a history entry: {e}"),
+    }
+  }
+  Ok(entries)
+}
+
+impl History {
+  /// Opens the history in `dir`, creating both if need be, for a session
+  /// of `watcher` started at `started_at`.
+  pub fn open(dir: &Path, watcher: &str, started_at: String) -> io::Result<History> {
+    fs::create_dir_all(dir)?;
+    let mut earlier = Earlier::default();
+    let mut sessions = BTreeSet::new();
+    for entry in entries(dir)?.iter().filter(|e| e.watcher == watcher) {
+      sessions.insert(entry.session_started_at.clone());
+      earlier.runs += 1;
+      earlier.total_duration_ms += entry.run.duration_ms;
+      match (entry.run.stopped, entry.run.exit_code) {
+        (true, _) => {}
+        (false, Some(0)) => earlier.succeeded += 1,
+        (false, _) => earlier.failed += 1,
+      }
+    }
+    earlier.sessions = sessions.len();
+    Ok(History {
+      file: File::options().create(true).append(true).open(path(dir))?,
+      watcher: watcher.to_string(),
+      session_started_at: started_at,
+      earlier,
+    })
+  }
+
+  pub fn append(&mut self, command: &str, run: &RunRecord) {
+    let entry = Entry {
+      watcher: self.watcher.clone(),
+      session_started_at: self.session_started_at.clone(),
+      command: command.to_string(),
+      run: run.clone(),
+    };
+    let line = serde_json::to_string(&entry).expect("serializable");
+    if let Err(e) = writeln!(self.file, "{line}") {
+      warn!("Could not add the run to the history: {e}");
+    }
+  }
+}
+
+/// `ctl history`: prints the `last` newest runs in `dir`'s history.
+pub fn print(dir: &Path, last: usize) -> io::Result<()> {
+  let entries = entries(dir)?;
+  for entry in &entries[entries.len().saturating_sub(last)..] {
+    let run = &entry.run;
+    let outcome = match (run.stopped, run.exit_code, run.signal) {
+      (true, _, _) => "stopped".to_string(),
+      (false, Some(0), _) => "ok".to_string(),
+      (false, Some(code), _) => format!("exit {code}"),
+      (false, None, Some(sig)) => format!("signal {sig}"),
+      (false, None, None) => "?".to_string(),
+    };
+    let started = DateTime::parse_from_rfc3339(&run.started_at).map_or_else(
+      |_| run.started_at.clone(),
+      |t| t.format("%F %T").to_string(),
+    );
+    println!(
+      "{started}  {:>7.1}s  {outcome:<9}  {}",
+      run.duration_ms as f64 / 1000.0,
+      entry.command
+    );
+  }
+  Ok(())
+}
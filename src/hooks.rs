@@ -0,0 +1,124 @@
+// Shell snippets from the config file run around the watcher and its runs.
+//
+//   before_all = "docker compose up -d db"  # once, before watching starts
+//   before_each = "rm -rf target/report"    # before each run; if it fails
+//                                           # the run is skipped
+//   after_each = "notify.sh"                # after each run that ran to
+//                                           # its end, not one stopped
+//   after_all = "docker compose down"       # once, when the watcher exits
+//
+// Each runs with `/bin/sh -c` and is killed after five minutes. before_all
+// and after_all hold up the watcher; before_each and after_each run
+// alongside it, so changes, signals and commands are still handled while
+// they do, though a run waits for its before_each to succeed. What they see
+// in the environment:
+//
+//   ROF_HOOK         the hook's name, e.g. before_each
+//   ROF_JOB          the job's name                      (before/after_each)
+//   ROF_COMMAND      the command run                     (before/after_each)
+//   ROF_RUN_ID       the run's number, from 1            (before/after_each)
+//   ROF_TRIGGER      the changed paths, one per line     (before/after_each)
+//   ROF_STATUS       success or failure                  (after_each)
+//   ROF_EXIT_CODE    exit code, empty if a signal ended the run (after_each)
+//   ROF_DURATION_MS  how long the run took               (after_each)
+//   ROF_RUNS         how many runs there were            (after_all)
+//   ROF_FAILURES     how many of them failed             (after_all)
+
+use crate::process::{self, Hook};
+use std::{io, path::PathBuf, sync::Mutex, thread, time::Duration};
+use tracing::warn;
+
+const LIMIT: Duration = Duration::from_secs(300);
+/// How often the after_each hooks still going are looked at.
+const POLL: Duration = Duration::from_millis(50);
+
+/// after_each hooks started and not yet collected.
+static RUNNING: Mutex<Vec<Hook>> = Mutex::new(Vec::new());
+
+#[derive(Clone, Default)]
+pub struct Hooks {
+  pub before_all: Option<String>,
+  pub before_each: Option<String>,
+  pub after_each: Option<String>,
+  pub after_all: Option<String>,
+}
+
+/// The variables describing a run, for `before_each` and `after_each`.
+pub fn run_env(
+  job: &str,
+  command: &str,
+  id: u64,
+  trigger: &[PathBuf],
+) -> Vec<(&'static str, String)> {
+  let trigger: Vec<String> = trigger.iter().map(|p| p.display().to_string()).collect();
+  vec![
+    ("ROF_JOB", job.to_string()),
+    ("ROF_COMMAND", command.to_string()),
+    ("ROF_RUN_ID", id.to_string()),
+    ("ROF_TRIGGER", trigger.join("\n")),
+  ]
+}
+
+/// The command of hook `name`, with the variables it sees.
+fn command<'a>(
+  name: &str,
+  hook: &'a Option<String>,
+  env: &mut Vec<(&'static str, String)>,
+) -> Option<&'a str> {
+  env.push(("ROF_HOOK", name.to_string()));
+  hook.as_deref()
+}
+
+/// Runs hook `name` to completion if it is set. Returns whether it
+/// succeeded, or `true` without one.
+pub fn run(
+  name: &'static str,
+  hook: &Option<String>,
+  mut env: Vec<(&'static str, String)>,
+) -> bool {
+  match command(name, hook, &mut env) {
+    Some(command) => process::run_hook(name, command, &env, LIMIT),
+    None => true,
+  }
+}
+
+/// Starts hook `name` if it is set, for the caller to poll.
+pub fn start(
+  name: &'static str,
+  hook: &Option<String>,
+  mut env: Vec<(&'static str, String)>,
+) -> io::Result<Option<Hook>> {
+  match command(name, hook, &mut env) {
+    Some(command) => Hook::start(name, command, &env, LIMIT).map(Some),
+    None => Ok(None),
+  }
+}
+
+/// Starts hook `name` if it is set, leaving it to [`finish`].
+pub fn spawn(name: &'static str, hook: &Option<String>, env: Vec<(&'static str, String)>) {
+  match start(name, hook, env) {
+    Ok(Some(hook)) => RUNNING.lock().unwrap().push(hook),
+    Ok(None) => {}
+    Err(e) => warn!("Could not start {name} hook: {e}"),
+  }
+}
+
+/// Collects the spawned hooks that are over, and says when to look again
+/// if some are still going.
+pub fn finish() -> Option<Duration> {
+  let mut running = RUNNING.lock().unwrap();
+  running.retain_mut(|hook| hook.poll().is_none());
+  (!running.is_empty()).then_some(POLL)
+}
+
+/// Whether spawned hooks are still going.
+pub fn running() -> bool {
+  !RUNNING.lock().unwrap().is_empty()
+}
+
+/// Waits for the spawned hooks to be over.
+pub fn wait() {
+  while finish().is_some() {
+    thread::sleep(POLL);
+  }
+}
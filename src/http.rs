@@ -0,0 +1,118 @@
+// Just enough HTTP/1.1 for the built-in servers.
+
+use std::{
+  io::{self, BufRead, BufReader, Write},
+  net::{TcpStream, ToSocketAddrs},
+  time::Duration,
+};
+
+/// Splits `[//]HOST[:PORT][/PATH]` (what follows `http:`) into its parts;
+/// the host defaults to localhost, the port to 80 and the path to `/`.
+pub fn parse_url(s: &str) -> Option<(String, u16, String)> {
+  let rest = s.trim_start_matches('/');
+  let (authority, path) = match rest.find('/') {
+    Some(i) => rest.split_at(i),
+    None => (rest, "/"),
+  };
+  let (host, port) = match authority.rsplit_once(':') {
+    Some((host, port)) => (host, port.parse().ok()?),
+    None => (authority, 80),
+  };
+  let host = if host.is_empty() { "localhost" } else { host };
+  Some((host.to_string(), port, path.to_string()))
+}
+
+/// How long a webhook may take to answer.
+const WEBHOOK_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Sends a POST and waits for the status line, which is returned.
+fn post(
+  host: &str,
+  port: u16,
+  path: &str,
+  content_type: &str,
+  body: &[u8],
+  timeout: Duration,
+) -> io::Result<String> {
+  let addr = (host, port)
+    .to_socket_addrs()?
+    .next()
+    .ok_or_else(|| io::Error::other("host has no address"))?;
+  let mut stream = TcpStream::connect_timeout(&addr, timeout)?;
+  stream.set_read_timeout(Some(timeout))?;
+  write!(
+    stream,
+    "POST {path} HTTP/1.1\r\nHost: {host}\r\nContent-Type: {content_type}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+    body.len()
+  )?;
+  stream.write_all(body)?;
+  let mut status = String::new();
+  BufReader::new(stream).read_line(&mut status)?;
+  Ok(status.trim_end().to_string())
+}
+
+/// Posts `body` as JSON to a webhook, which must answer with a 2xx status.
+pub fn post_ok(host: &str, port: u16, path: &str, body: &[u8]) -> Result<(), String> {
+  match post(host, port, path, "application/json", body, WEBHOOK_TIMEOUT) {
+    Ok(status) if status.split(' ').nth(1).is_some_and(|c| c.starts_with('2')) => Ok(()),
+    Ok(status) => Err(format!("answered {status:?}")),
+    Err(e) => Err(format!("could not be reached: {e}")),
+  }
+}
+
+/// The request line and headers, read up to the blank line.
+pub struct Head {
+  pub method: String,
+  pub path: String,
+  /// Header names are lower-cased.
+  pub headers: Vec<(String, String)>,
+}
+
+impl Head {
+  pub fn header(&self, name: &str) -> Option<&str> {
+    self
+      .headers
+      .iter()
+      .find(|(n, _)| n == name)
+      .map(|(_, v)| v.as_str())
+  }
+}
+
+pub fn read_head(stream: &TcpStream) -> Option<Head> {
+  let mut reader = BufReader::new(stream);
+  let mut line = String::new();
+  reader.read_line(&mut line).ok()?;
+  let mut words = line.split_whitespace();
+  let method = words.next()?.to_string();
+  let path = words.next()?.to_string();
+  let mut headers = Vec::new();
+  loop {
+    line.clear();
+    if reader.read_line(&mut line).ok()? == 0 || line.trim().is_empty() {
+      break;
+    }
+    if let Some((name, value)) = line.split_once(':') {
+      headers.push((name.trim().to_ascii_lowercase(), value.trim().to_string()));
+    }
+  }
+  Some(Head {
+    method,
+    path,
+    headers,
+  })
+}
+
+/// Writes a complete response and lets the connection close.
+pub fn respond(
+  stream: &mut TcpStream,
+  status: &str,
+  content_type: &str,
+  body: &[u8],
+) -> io::Result<()> {
+  write!(
+    stream,
+    "HTTP/1.1 {status}\r\nContent-Type: {content_type}\r\nContent-Length: {}\r\nCache-Control: no-cache\r\nConnection: close\r\n\r\n",
+    body.len()
+  )?;
+  stream.write_all(body)
+}
@@ -0,0 +1,899 @@
+// Several commands in one watcher, from a config file (`--config`).
+//
+//   [[job]]
+//   name = "build"
+//   command = "cargo build"
+//   watch = ["src", "Cargo.toml"]
+//   color = "cyan"
+//   group = "tests"
+//
+//   [[job]]
+//   name = "server"
+//   command = "cargo run"
+//   needs = ["build"]
+//   ready_when = "http:localhost:3000/health"
+//
+//   [[job]]
+//   name = "test"
+//   command = "cargo test"
+//   watch = ["tests"]
+//   needs = ["server"]
+//   output = "test.log"
+//   env.CHANGED_DIR = "{dir}"
+//
+// Hooks (see hooks.rs) go at the top level, before the jobs, and so does
+// a table of debounce windows for the paths that need their own:
+//
+//   [debounce]
+//   "*.rs" = "200ms"
+//   "migrations/*.sql" = "3s"
+//   "assets/" = "1s"
+//
+// Patterns are matched like -i's. A change to paths several patterns
+// match gets the shortest window, and one no pattern matches the default.
+// A [priority] table of the same kind marks paths "high" or "low", as
+// --priority does:
+//
+//   [priority]
+//   "Cargo.toml" = "high"
+//   "docs/" = "low"
+//
+// An [env] table there sets variables for every job's runs, and a job's
+// own env adds to it. Values are filled in per run: {path} is the first
+// changed path, {paths} all of them (space-separated), {dir}, {file} and
+// {ext} its directory, file name and extension, {job} the job's name and
+// {run_id} the run's number.
+//
+// Profiles are alternative sets of jobs and filters in the same file,
+// one of which `--profile NAME` picks (and `profile NAME` on
+// --control-stdin switches to, by restarting the watcher):
+//
+//   [profile.quick]
+//   exts = ["rs"]
+//   [[profile.quick.job]]
+//   name = "check"
+//   command = "cargo check"
+//   watch = ["src"]
+//
+// A profile's jobs replace the top-level ones, which it keeps if it has
+// none; its ignore and exts add to the command line's -i and -e.
+//
+// Each job has its own paths, debounce and run, and jobs run concurrently,
+// except that jobs sharing a group preempt each other, and that a job
+// waits for the jobs it needs: until they have exited successfully, or
+// for one with a ready_when check (as --ready-when's), until that passes.
+// A job without paths of its own runs whenever one of its needs does. A
+// job's output is captured or inherited as the options say, unless it
+// sets output to "inherit", "null", "capture" (always piped through the
+// watcher) or a file to append to.
+// Without a config file the command line makes up a single unnamed job,
+// or with `--group-by dir[:DEPTH]` one per directory that many levels
+// below the watched ones, named after it. Each job's command has `{dir}`
+// replaced by its directory, so a burst of changes across packages runs
+// each package's build once:
+//
+//   run_on_file_change --group-by dir:2 "make -C {dir}" .
+//
+// Mistakes in the file are reported at their line and column, the first
+// one on startup and all of them, in every profile, by `run_on_file_change
+// check-config FILE`, which also looks for paths to watch that are missing.
+
+use crate::cli;
+use crate::exit;
+use crate::expand;
+use crate::filter::{self, Priority};
+use crate::hooks::Hooks;
+use crate::process;
+use crate::ready;
+use crate::watch;
+use serde::Deserialize;
+use std::{collections::BTreeMap, fs, path::Path, path::PathBuf, time::Duration};
+use toml::de::{DeTable, DeValue};
+
+#[derive(Clone, Copy, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Color {
+  Red,
+  Green,
+  Yellow,
+  Blue,
+  Magenta,
+  Cyan,
+}
+
+impl Color {
+  fn code(self) -> u8 {
+    match self {
+      Color::Red => 31,
+      Color::Green => 32,
+      Color::Yellow => 33,
+      Color::Blue => 34,
+      Color::Magenta => 35,
+      Color::Cyan => 36,
+    }
+  }
+}
+
+/// Colors handed out in order to jobs that don't pick one.
+const PALETTE: [Color; 6] = [
+  Color::Cyan,
+  Color::Yellow,
+  Color::Green,
+  Color::Magenta,
+  Color::Blue,
+  Color::Red,
+];
+
+/// Where a job's output goes.
+#[derive(Clone, Default, Deserialize)]
+#[serde(from = "String")]
+pub enum Output {
+  /// Captured if the options or an output prefix call for it.
+  #[default]
+  Auto,
+  Inherit,
+  Null,
+  Capture,
+  /// Appended to this file.
+  File(PathBuf),
+}
+
+impl From<String> for Output {
+  fn from(s: String) -> Output {
+    match s.as_str() {
+      "inherit" => Output::Inherit,
+      "null" => Output::Null,
+      "capture" => Output::Capture,
+      _ => Output::File(s.into()),
+    }
+  }
+}
+
+#[derive(Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Job {
+  /// Empty for the command-line job.
+  pub name: String,
+  pub command: String,
+  /// Paths and globs, as on the command line.
+  #[serde(default)]
+  pub watch: Vec<PathBuf>,
+  /// Color of the output prefix.
+  pub color: Option<Color>,
+  /// Concurrency group: starting a job stops the others in its group.
+  pub group: Option<String>,
+  /// Jobs that run first whenever this one does, and must succeed.
+  #[serde(default)]
+  pub needs: Vec<String>,
+  /// Readiness check (as `--ready-when`'s) that jobs needing this one wait
+  /// for, instead of its exit.
+  pub ready_when: Option<String>,
+  #[serde(default)]
+  pub output: Output,
+  /// Variables for its runs, as templates (see [`Job::env`]).
+  #[serde(default)]
+  pub env: BTreeMap<String, String>,
+}
+
+impl Job {
+  /// The job's variables for a run `id` started by changes to `trigger`.
+  pub fn env(&self, id: u64, trigger: &[PathBuf]) -> Vec<(&str, String)> {
+    let first = trigger
+      .first()
+      .map(PathBuf::as_path)
+      .unwrap_or(Path::new(""));
+    let show = |p: Option<&std::ffi::OsStr>| p.map(|p| p.to_string_lossy().into_owned());
+    let values = [
+      ("path", first.display().to_string()),
+      (
+        "paths",
+        trigger
+          .iter()
+          .map(|p| p.display().to_string())
+          .collect::<Vec<_>>()
+          .join(" "),
+      ),
+      (
+        "dir",
+        show(first.parent().map(Path::as_os_str)).unwrap_or_default(),
+      ),
+      ("file", show(first.file_name()).unwrap_or_default()),
+      ("ext", show(first.extension()).unwrap_or_default()),
+      ("job", self.name.clone()),
+      ("run_id", id.to_string()),
+    ];
+    self
+      .env
+      .iter()
+      .map(|(name, template)| (name.as_str(), expand::placeholders(template, &values)))
+      .collect()
+  }
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Profile {
+  #[serde(rename = "job", default)]
+  jobs: Vec<Job>,
+  #[serde(default)]
+  ignore: Vec<String>,
+  #[serde(default)]
+  exts: Vec<String>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ConfigFile {
+  #[serde(rename = "job", default)]
+  jobs: Vec<Job>,
+  before_all: Option<String>,
+  before_each: Option<String>,
+  after_each: Option<String>,
+  after_all: Option<String>,
+  #[serde(default)]
+  debounce: BTreeMap<String, String>,
+  #[serde(default)]
+  priority: BTreeMap<String, String>,
+  #[serde(default)]
+  env: BTreeMap<String, String>,
+  #[serde(default)]
+  profile: BTreeMap<String, Profile>,
+}
+
+pub struct Config {
+  pub jobs: Vec<Job>,
+  pub hooks: Hooks,
+  pub debounce: Vec<(String, Duration)>,
+  pub priorities: Vec<(String, Priority)>,
+  /// The chosen profile's filters.
+  pub ignore: Vec<String>,
+  pub exts: Vec<String>,
+  /// Names of all the profiles.
+  pub profiles: Vec<String>,
+}
+
+/// A step on the way to a value in the config file: a key, or an index
+/// into an array (of tables).
+#[derive(Clone)]
+enum Step {
+  Key(String),
+  At(usize),
+}
+
+fn key(name: &str) -> Step {
+  Step::Key(name.to_string())
+}
+
+/// Something wrong with the config, and the value it is about.
+struct Problem {
+  at: Vec<Step>,
+  message: String,
+}
+
+impl Problem {
+  fn new(at: &[Step], message: String) -> Self {
+    Problem {
+      at: at.to_vec(),
+      message,
+    }
+  }
+
+  /// "rof.toml:12:14: message", pointing at the value the problem is
+  /// about, or the closest one on the way there.
+  fn describe(&self, path: &Path, text: &str) -> String {
+    match position(text, &self.at) {
+      Some((line, column)) => format!("{}:{line}:{column}: {}", path.display(), self.message),
+      None => format!("{}: {}", path.display(), self.message),
+    }
+  }
+}
+
+/// Where the value at `at` starts in `text`, as a line and a column
+/// counted from 1.
+fn position(text: &str, at: &[Step]) -> Option<(usize, usize)> {
+  let root = toml::Spanned::new(
+    0..0,
+    DeValue::Table(DeTable::parse(text).ok()?.into_inner()),
+  );
+  let mut value = &root;
+  for step in at {
+    let next = match step {
+      Step::Key(name) => value.get_ref().get(name.as_str()),
+      Step::At(i) => value.get_ref().get(*i),
+    };
+    let Some(next) = next else {
+      break;
+    };
+    value = next;
+  }
+  let before = &text[..value.span().start];
+  let line_start = before.rfind('\n').map_or(0, |nl| nl + 1);
+  Some((
+    before.matches('\n').count() + 1,
+    before[line_start..].chars().count() + 1,
+  ))
+}
+
+fn parse(path: &Path, text: &str) -> Result<ConfigFile, String> {
+  toml::from_str(text).map_err(|e| format!("{}: {e}", path.display()))
+}
+
+/// What is wrong with `jobs`, found at `at` in the file.
+fn check_jobs(jobs: &[Job], at: &[Step]) -> Vec<Problem> {
+  let mut problems = Vec::new();
+  let within = |steps: &[Step]| [at, steps].concat();
+  for (i, job) in jobs.iter().enumerate() {
+    let field = |name: &str| within(&[Step::At(i), key(name)]);
+    if job.name.is_empty() {
+      problems.push(Problem::new(
+        &within(&[Step::At(i)]),
+        format!("job {} has no name", i + 1),
+      ));
+    } else if jobs[..i].iter().any(|j| j.name == job.name) {
+      problems.push(Problem::new(
+        &field("name"),
+        format!("job {:?} defined twice", job.name),
+      ));
+    }
+    if let Some(check) = &job.ready_when {
+      match ready::Probe::parse(check) {
+        Err(e) => problems.push(Problem::new(
+          &field("ready_when"),
+          format!("job {:?}: invalid ready_when: {e}", job.name),
+        )),
+        Ok(ready::Probe::Log(_))
+          if matches!(job.output, Output::Inherit | Output::Null | Output::File(_)) =>
+        {
+          problems.push(Problem::new(
+            &field("output"),
+            format!(
+              "job {:?}: ready_when = {check:?} looks for a line of the output its output \
+               setting keeps from the watcher; use \"capture\" or leave it out",
+              job.name
+            ),
+          ))
+        }
+        Ok(_) => {}
+      }
+    }
+    if job.watch.is_empty() && job.needs.is_empty() {
+      problems.push(Problem::new(
+        &within(&[Step::At(i)]),
+        format!("job {:?} watches nothing", job.name),
+      ));
+    }
+    for (k, name) in job.needs.iter().enumerate() {
+      if !jobs.iter().any(|j| &j.name == name) {
+        problems.push(Problem::new(
+          &within(&[Step::At(i), key("needs"), Step::At(k)]),
+          format!("job {:?} needs unknown job {name:?}", job.name),
+        ));
+      }
+    }
+  }
+  let Ok(needs) = needs(jobs) else {
+    return problems; // unknown jobs, reported above
+  };
+  // Depth-first, looking for a job reachable from itself.
+  fn cycle(needs: &[Vec<usize>], i: usize, stack: &mut Vec<usize>) -> bool {
+    if stack.contains(&i) {
+      return true;
+    }
+    stack.push(i);
+    let found = needs[i].iter().any(|&j| cycle(needs, j, stack));
+    stack.pop();
+    found
+  }
+  if let Some(i) = (0..needs.len()).find(|&i| cycle(&needs, i, &mut Vec::new())) {
+    problems.push(Problem::new(
+      &within(&[Step::At(i), key("needs")]),
+      format!("job {:?} ends up needing itself", jobs[i].name),
+    ));
+  }
+  problems
+}
+
+/// What is wrong with the -i-style `patterns` at `at`.
+fn check_patterns<'a>(patterns: impl Iterator<Item = &'a String>, at: &[Step]) -> Vec<Problem> {
+  patterns
+    .enumerate()
+    .filter_map(|(k, pattern)| {
+      let e = filter::check_pattern(pattern).err()?;
+      Some(Problem::new(
+        &[at, &[Step::At(k)]].concat(),
+        format!("invalid pattern {pattern:?}: {e}"),
+      ))
+    })
+    .collect()
+}
+
+/// What is wrong with the [debounce] and [priority] tables.
+fn check_tables(config: &ConfigFile) -> Vec<Problem> {
+  let mut problems = Vec::new();
+  for (pattern, window) in &config.debounce {
+    let at = [key("debounce"), key(pattern)];
+    if cli::parse_duration(window, Duration::from_millis(1)).is_none() {
+      problems.push(Problem::new(
+        &at,
+        format!("invalid debounce {window:?} for {pattern:?}"),
+      ));
+    }
+    if let Err(e) = filter::check_pattern(pattern) {
+      problems.push(Problem::new(
+        &at,
+        format!("invalid pattern {pattern:?}: {e}"),
+      ));
+    }
+  }
+  for (pattern, priority) in &config.priority {
+    let at = [key("priority"), key(pattern)];
+    if let Err(e) = priority.parse::<Priority>() {
+      problems.push(Problem::new(&at, format!("{e} for {pattern:?}")));
+    }
+    if let Err(e) = filter::check_pattern(pattern) {
+      problems.push(Problem::new(
+        &at,
+        format!("invalid pattern {pattern:?}: {e}"),
+      ));
+    }
+  }
+  problems
+}
+
+/// Loads the config in `path`, with `profile`'s jobs and filters if one
+/// is named.
+pub fn load(path: &Path, profile: Option<&str>) -> Result<Config, String> {
+  let text = fs::read_to_string(path).map_err(|e| format!("{}: {e}", path.display()))?;
+  let mut config = parse(path, &text)?;
+  let profiles: Vec<String> = config.profile.keys().cloned().collect();
+  let (mut ignore, mut exts) = (Vec::new(), Vec::new());
+  let mut problems = check_tables(&config);
+  let mut jobs_at = vec![key("job")];
+  if let Some(name) = profile {
+    let chosen = config
+      .profile
+      .remove(name)
+      .ok_or_else(|| match profiles.as_slice() {
+        [] => format!("{}: no profiles defined", path.display()),
+        names => format!(
+          "{}: no profile {name:?} (there are {})",
+          path.display(),
+          names.join(", ")
+        ),
+      })?;
+    let at = [key("profile"), key(name)];
+    if !chosen.jobs.is_empty() {
+      config.jobs = chosen.jobs;
+      jobs_at = [&at[..], &[key("job")]].concat();
+    }
+    problems.extend(check_patterns(
+      chosen.ignore.iter(),
+      &[&at[..], &[key("ignore")]].concat(),
+    ));
+    (ignore, exts) = (chosen.ignore, chosen.exts);
+  }
+  if config.jobs.is_empty() {
+    return Err(match (profile, profiles.as_slice()) {
+      (None, [_, ..]) => format!(
+        "{}: no [[job]] defined outside the profiles; pick one with --profile ({})",
+        path.display(),
+        profiles.join(", ")
+      ),
+      _ => format!("{}: no [[job]] defined", path.display()),
+    });
+  }
+  problems.splice(0..0, check_jobs(&config.jobs, &jobs_at));
+  if let Some(problem) = problems.first() {
+    return Err(problem.describe(path, &text));
+  }
+  let debounce = config
+    .debounce
+    .iter()
+    .map(|(pattern, window)| {
+      let window = cli::parse_duration(window, Duration::from_millis(1));
+      (pattern.clone(), window.expect("checked"))
+    })
+    .collect();
+  let priorities = config
+    .priority
+    .iter()
+    .map(|(pattern, priority)| (pattern.clone(), priority.parse().expect("checked")))
+    .collect();
+  for job in &mut config.jobs {
+    for (name, template) in &config.env {
+      job
+        .env
+        .entry(name.clone())
+        .or_insert_with(|| template.clone());
+    }
+  }
+  Ok(Config {
+    jobs: config.jobs,
+    hooks: Hooks {
+      before_all: config.before_all,
+      before_each: config.before_each,
+      after_each: config.after_each,
+      after_all: config.after_all,
+    },
+    debounce,
+    priorities,
+    ignore,
+    exts,
+    profiles,
+  })
+}
+
+/// Every problem with the config in `path`, in all its profiles, as
+/// `check-config` reports them: what [`load`] turns down, and paths to
+/// watch that don't exist.
+fn check(path: &Path) -> Result<Vec<String>, String> {
+  let text = fs::read_to_string(path).map_err(|e| format!("{}: {e}", path.display()))?;
+  let config = parse(path, &text)?;
+  let mut problems = check_tables(&config);
+  let mut sets = vec![(vec![key("job")], &config.jobs)];
+  for (name, profile) in &config.profile {
+    let at = [key("profile"), key(name)];
+    problems.extend(check_patterns(
+      profile.ignore.iter(),
+      &[&at[..], &[key("ignore")]].concat(),
+    ));
+    if !profile.jobs.is_empty() {
+      sets.push(([&at[..], &[key("job")]].concat(), &profile.jobs));
+    }
+  }
+  if config.jobs.is_empty() && config.profile.values().all(|p| p.jobs.is_empty()) {
+    problems.push(Problem::new(&[], "no [[job]] defined".to_string()));
+  }
+  for (at, jobs) in sets {
+    problems.extend(check_jobs(jobs, &at));
+    for (i, job) in jobs.iter().enumerate() {
+      for (k, watched) in job.watch.iter().enumerate() {
+        let watched = PathBuf::from(expand::expand(&watched.to_string_lossy(), false));
+        if let Err(e) = watch::interpret(&watched, None) {
+          problems.push(Problem::new(
+            &[&at[..], &[Step::At(i), key("watch"), Step::At(k)]].concat(),
+            format!("job {:?}: {}", job.name, e.trim_end_matches('.')),
+          ));
+        }
+      }
+    }
+  }
+  problems.sort_by_cached_key(|p| position(&text, &p.at));
+  Ok(problems.iter().map(|p| p.describe(path, &text)).collect())
+}
+
+/// `run_on_file_change check-config FILE`.
+pub fn check_config(mut args: impl Iterator<Item = String>) -> ! {
+  let (Some(path), None) = (args.next(), args.next()) else {
+    eprintln!("Usage: run_on_file_change check-config <FILE>");
+    std::process::exit(exit::CONFIG);
+  };
+  let path = PathBuf::from(path);
+  match check(&path) {
+    Ok(problems) if problems.is_empty() => {
+      println!("{}: ok", path.display());
+      std::process::exit(0);
+    }
+    Ok(problems) => {
+      for problem in &problems {
+        eprintln!("{problem}");
+      }
+      std::process::exit(exit::CONFIG);
+    }
+    Err(e) => {
+      eprintln!("{e}");
+      std::process::exit(exit::CONFIG);
+    }
+  }
+}
+
+/// Keeps the jobs named in `names` and all they need, in config order.
+pub fn select(jobs: Vec<Job>, names: &[String]) -> Result<Vec<Job>, String> {
+  let needs = needs(&jobs)?;
+  let mut keep = vec![false; jobs.len()];
+  let mut todo = Vec::new();
+  for name in names {
+    match jobs.iter().position(|j| &j.name == name) {
+      Some(i) => todo.push(i),
+      None => return Err(format!("no job named {name:?}")),
+    }
+  }
+  while let Some(i) = todo.pop() {
+    if !keep[i] {
+      keep[i] = true;
+      todo.extend(&needs[i]);
+    }
+  }
+  Ok(
+    jobs
+      .into_iter()
+      .zip(keep)
+      .filter_map(|(job, keep)| keep.then_some(job))
+      .collect(),
+  )
+}
+
+/// The directories `depth` levels below `dir`, leaving out hidden ones.
+fn dirs_below(dir: &Path, depth: usize) -> Vec<PathBuf> {
+  let mut level = vec![dir.to_path_buf()];
+  for _ in 0..depth {
+    let mut next = Vec::new();
+    for dir in &level {
+      let Ok(entries) = fs::read_dir(dir) else {
+        continue;
+      };
+      let mut found: Vec<PathBuf> = entries
+        .flatten()
+        .filter(|e| e.file_type().is_ok_and(|t| t.is_dir()))
+        .filter(|e| !e.file_name().to_string_lossy().starts_with('.'))
+        .map(|e| match dir.as_os_str() == "." {
+          true => PathBuf::from(e.file_name()),
+          false => e.path(),
+        })
+        .collect();
+      found.sort();
+      next.extend(found);
+    }
+    level = next;
+  }
+  level
+}
+
+/// The command-line `job` as one job per directory `depth` levels below
+/// the directories it watches (`--group-by dir:DEPTH`), named after the
+/// directory and watching only it, with `{dir}` in the command replaced by
+/// the directory.
+pub fn group_by_dir(job: Job, depth: usize) -> Result<Vec<Job>, String> {
+  let mut grouped = Vec::new();
+  for path in &job.watch {
+    if !path.is_dir() {
+      return Err(format!(
+        "--group-by needs directories to watch, not {}",
+        path.display()
+      ));
+    }
+    for dir in dirs_below(path, depth) {
+      let shown = dir.display().to_string();
+      grouped.push(Job {
+        name: shown.clone(),
+        command: job.command.replace("{dir}", &process::quote(&shown)),
+        watch: vec![dir],
+        ..job.clone()
+      });
+    }
+  }
+  if grouped.is_empty() {
+    return Err(format!(
+      "--group-by found no directories {depth} level{} below the watched ones",
+      if depth == 1 { "" } else { "s" }
+    ));
+  }
+  Ok(grouped)
+}
+
+/// Each job's needs, as indices into `jobs`.
+pub fn needs(jobs: &[Job]) -> Result<Vec<Vec<usize>>, String> {
+  jobs
+    .iter()
+    .map(|job| {
+      job
+        .needs
+        .iter()
+        .map(|name| {
+          jobs
+            .iter()
+            .position(|j| &j.name == name)
+            .ok_or_else(|| format!("job {:?} needs unknown job {name:?}", job.name))
+        })
+        .collect()
+    })
+    .collect()
+}
+
+/// The jobs a trigger of `triggered` runs, dependencies before the jobs
+/// needing them: those jobs, all they need, and the `pathless` jobs (with
+/// no paths of their own) that need any of them.
+pub fn wave(needs: &[Vec<usize>], pathless: &[bool], triggered: &[usize]) -> Vec<usize> {
+  let mut member = vec![false; needs.len()];
+  for &i in triggered {
+    member[i] = true;
+  }
+  loop {
+    let mut grew = false;
+    for i in 0..needs.len() {
+      if member[i] {
+        continue;
+      }
+      let joins = (0..needs.len()).any(|j| member[j] && needs[j].contains(&i))
+        || (pathless[i] && needs[i].iter().any(|&j| member[j]));
+      if joins {
+        member[i] = true;
+        grew = true;
+      }
+    }
+    if !grew {
+      break;
+    }
+  }
+  fn visit(i: usize, needs: &[Vec<usize>], member: &[bool], order: &mut Vec<usize>) {
+    if order.contains(&i) {
+      return;
+    }
+    for &j in &needs[i] {
+      if member[j] {
+        visit(j, needs, member, order);
+      }
+    }
+    order.push(i);
+  }
+  let mut order = Vec::new();
+  for i in (0..needs.len()).filter(|&i| member[i]) {
+    visit(i, needs, &member, &mut order);
+  }
+  order
+}
+
+/// The prefix put before each output line of each job, like docker
+/// compose: names padded to the same width, each in its own color when
+/// `color` is set. A single job gets none.
+pub fn prefixes(jobs: &[Job], color: bool) -> Vec<Option<String>> {
+  if jobs.len() < 2 {
+    return vec![None; jobs.len()];
+  }
+  let width = jobs
+    .iter()
+    .map(|j| j.name.chars().count())
+    .max()
+    .unwrap_or(0);
+  jobs
+    .iter()
+    .enumerate()
+    .map(|(i, job)| {
+      let label = format!("{:width$} |", job.name);
+      Some(match color {
+        true => {
+          let code = job.color.unwrap_or(PALETTE[i % PALETTE.len()]).code();
+          format!("\x1b[{code}m{label}\x1b[0m ")
+        }
+        false => format!("{label} "),
+      })
+    })
+    .collect()
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn jobs(text: &str) -> Vec<Job> {
+    parse(Path::new("rof.toml"), text).unwrap().jobs
+  }
+
+  const CHAIN: &str = r#"
+    [[job]]
+    name = "gen"
+    command = "gen"
+    watch = ["schema"]
+    [[job]]
+    name = "build"
+    command = "build"
+    watch = ["src"]
+    needs = ["gen"]
+    [[job]]
+    name = "test"
+    command = "test"
+    needs = ["build"]
+    [[job]]
+    name = "docs"
+    command = "docs"
+    watch = ["docs"]
+  "#;
+
+  #[test]
+  fn needs_by_index() {
+    assert_eq!(
+      needs(&jobs(CHAIN)).unwrap(),
+      [vec![], vec![0], vec![1], vec![]]
+    );
+    let unknown = jobs("[[job]]\nname = \"a\"\ncommand = \"a\"\nneeds = [\"b\"]");
+    assert!(needs(&unknown).unwrap_err().contains("unknown job \"b\""));
+  }
+
+  #[test]
+  fn waves() {
+    let jobs = jobs(CHAIN);
+    let needs = needs(&jobs).unwrap();
+    let pathless: Vec<bool> = jobs.iter().map(|j| j.watch.is_empty()).collect();
+    // What a job needs runs first; pathless jobs needing it run after.
+    assert_eq!(wave(&needs, &pathless, &[1]), [0, 1, 2]);
+    // A job with paths of its own only runs for them.
+    assert_eq!(wave(&needs, &pathless, &[0]), [0]);
+    assert_eq!(wave(&needs, &pathless, &[3]), [3]);
+    assert_eq!(wave(&needs, &pathless, &[3, 1]), [0, 1, 2, 3]);
+  }
+
+  #[test]
+  fn finds_cycles() {
+    let cyclic = jobs(
+      r#"
+      [[job]]
+      name = "a"
+      command = "a"
+      needs = ["b"]
+      [[job]]
+      name = "b"
+      command = "b"
+      needs = ["a"]
+      "#,
+    );
+    let problems = check_jobs(&cyclic, &[key("job")]);
+    assert_eq!(problems.len(), 1);
+    assert_eq!(problems[0].message, "job \"a\" ends up needing itself");
+    assert!(check_jobs(&jobs(CHAIN), &[key("job")]).is_empty());
+  }
+
+  #[test]
+  fn positions() {
+    let text = concat!(
+      "before_all = \"x\"\n",
+      "\n",
+      "[[job]]\n",
+      "name = \"a\"\n",
+      "[[job]]\n",
+      "name = \"b\" # ü\n",
+      "  env = { \"ü\" = \"1\", b = \"2\" }\n",
+    );
+    assert_eq!(position(text, &[key("before_all")]), Some((1, 14)));
+    assert_eq!(
+      position(text, &[key("job"), Step::At(1), key("name")]),
+      Some((6, 8))
+    );
+    // Columns count characters, not bytes.
+    assert_eq!(
+      position(text, &[key("job"), Step::At(1), key("env"), key("b")]),
+      Some((7, 26))
+    );
+    // A missing value points at the closest one on the way.
+    assert_eq!(
+      position(text, &[key("job"), Step::At(0), key("command")]),
+      Some((3, 1))
+    );
+    assert_eq!(position("name = ", &[key("name")]), None);
+  }
+
+  #[test]
+  fn groups_by_dir() {
+    let root = std::env::temp_dir().join(format!("rof-group-by-{}", std::process::id()));
+    for dir in ["a/x", "a/y", "b's", ".git/objects"] {
+      fs::create_dir_all(root.join(dir)).unwrap();
+    }
+    fs::write(root.join("a/file"), "").unwrap();
+    let job = |watch: &Path| Job {
+      watch: vec![watch.to_path_buf()],
+      ..jobs("[[job]]\nname = \"\"\ncommand = \"make -C {dir}\"").remove(0)
+    };
+
+    let grouped = group_by_dir(job(&root), 1).unwrap();
+    let names: Vec<String> = grouped.iter().map(|j| j.name.clone()).collect();
+    let (a, b) = (
+      root.join("a").display().to_string(),
+      root.join("b's").display().to_string(),
+    );
+    assert_eq!(names, [a.clone(), b.clone()]);
+    assert_eq!(grouped[0].watch, [root.join("a")]);
+    assert_eq!(grouped[0].command, format!("make -C '{a}'"));
+    assert_eq!(
+      grouped[1].command,
+      format!("make -C '{}'", b.replace('\'', r"'\''"))
+    );
+
+    let deeper = group_by_dir(job(&root), 2).unwrap();
+    assert_eq!(
+      deeper.iter().map(|j| &j.watch[0]).collect::<Vec<_>>(),
+      [&root.join("a/x"), &root.join("a/y")]
+    );
+    assert!(group_by_dir(job(&root), 3).is_err());
+    assert!(group_by_dir(job(&root.join("a/file")), 1).is_err());
+    fs::remove_dir_all(&root).unwrap();
+  }
+}
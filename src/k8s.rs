@@ -0,0 +1,61 @@
+// Bouncing a Kubernetes workload instead of running a command of our own
+// (`--k8s-rollout deployment/<name> [-n <namespace>]`), for the loop of
+// editing manifests or config locally and restarting what uses them:
+//
+//   run_on_file_change --k8s-rollout deployment/api -n staging config
+//
+// Each run is `kubectl rollout restart` followed by `kubectl rollout
+// status`, whose progress is the run's output; the run fails, and says so
+// in notifications, if the rollout does. kubectl's current context picks
+// the cluster.
+
+use crate::process;
+use std::process::{Command, Stdio};
+
+const KINDS: &[&str] = &["deployment", "daemonset", "statefulset"];
+
+/// Checks a `KIND/NAME` target, accepting kubectl's short kind names.
+pub fn parse(target: &str) -> Result<String, String> {
+  let (kind, name) = target
+    .split_once('/')
+    .filter(|(_, name)| !name.is_empty())
+    .ok_or_else(|| format!("expected KIND/NAME, got {target:?}"))?;
+  let kind = match kind {
+    "deploy" | "deployments" => "deployment",
+    "ds" | "daemonsets" => "daemonset",
+    "sts" | "statefulsets" => "statefulset",
+    kind => kind,
+  };
+  if !KINDS.contains(&kind) {
+    return Err(format!(
+      "cannot restart a {kind:?} (one of {})",
+      KINDS.join(", ")
+    ));
+  }
+  Ok(format!("{kind}/{name}"))
+}
+
+/// The command that restarts `target` and waits for the rollout.
+pub fn command(target: &str, namespace: Option<&str>) -> String {
+  let mut args = process::quote(target);
+  if let Some(namespace) = namespace {
+    args.push_str(" -n ");
+    args.push_str(&process::quote(namespace));
+  }
+  format!("kubectl rollout restart {args} && kubectl rollout status {args}")
+}
+
+/// Makes sure kubectl can be run at all.
+pub fn check() -> Result<(), String> {
+  Command::new("kubectl")
+    .args(["version", "--client"])
+    .stdin(Stdio::null())
+    .stdout(Stdio::null())
+    .stderr(Stdio::null())
+    .status()
+    .map_err(|e| format!("could not run kubectl: {e}"))
+    .and_then(|status| match status.success() {
+      true => Ok(()),
+      false => Err(format!("kubectl version --client failed ({status})")),
+    })
+}
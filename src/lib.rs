@@ -0,0 +1,4 @@
+// The parts of the watcher other programs can build on: for now, the
+// trait for sources of events in place of the filesystem backends.
+
+pub mod source;
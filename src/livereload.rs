@@ -0,0 +1,127 @@
+// Browser live reload (`--livereload`): a minimal WebSocket broadcaster.
+//
+// Pages load `/livereload.js` from this server; the script opens a
+// WebSocket back to it and reloads the page whenever a message arrives.
+
+use crate::http;
+use std::{
+  io::{self, Write},
+  net::{Ipv4Addr, TcpListener, TcpStream},
+  sync::{Arc, Mutex},
+  thread,
+};
+use tracing::{debug, info};
+
+pub const DEFAULT_PORT: u16 = 35729;
+
+const WEBSOCKET_GUID: &str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+
+/// Connected browsers.
+#[derive(Clone)]
+pub struct LiveReload {
+  port: u16,
+  clients: Arc<Mutex<Vec<TcpStream>>>,
+}
+
+/// Reloads the page on any message and reconnects once the watcher is
+/// back after a restart of its own.
+fn script(port: u16) -> String {
+  format!(
+    "(function connect() {{\n  var ws = new WebSocket('ws://' + (location.hostname || 'localhost') + ':{port}/');\n  ws.onmessage = function () {{ location.reload(); }};\n  ws.onclose = function () {{ setTimeout(connect, 1000); }};\n}})();\n"
+  )
+}
+
+/// The tag to put into pages (or inject into them) to enable reloading.
+pub fn snippet(port: u16) -> String {
+  format!("<script src=\"http://localhost:{port}/livereload.js\"></script>")
+}
+
+fn base64(bytes: &[u8]) -> String {
+  const ALPHABET: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+  let mut out = String::new();
+  for chunk in bytes.chunks(3) {
+    let n = chunk
+      .iter()
+      .enumerate()
+      .fold(0u32, |n, (i, &b)| n | (b as u32) << (16 - 8 * i));
+    for i in 0..4 {
+      if i <= chunk.len() {
+        out.push(ALPHABET[(n >> (18 - 6 * i)) as usize & 63] as char);
+      } else {
+        out.push('=');
+      }
+    }
+  }
+  out
+}
+
+impl LiveReload {
+  pub fn start(port: u16) -> io::Result<LiveReload> {
+    let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, port))?;
+    let server = LiveReload {
+      port,
+      clients: Arc::new(Mutex::new(Vec::new())),
+    };
+    let accepting = server.clone();
+    thread::spawn(move || {
+      for stream in listener.incoming().flatten() {
+        let server = accepting.clone();
+        thread::spawn(move || server.handle(stream));
+      }
+    });
+    info!(
+      "Live reload on port {port}; add {} to your pages",
+      snippet(port)
+    );
+    Ok(server)
+  }
+
+  fn handle(&self, mut stream: TcpStream) {
+    let Some(head) = http::read_head(&stream) else {
+      return;
+    };
+    let Some(key) = head.header("sec-websocket-key") else {
+      let _ = if head.path.starts_with("/livereload.js") {
+        http::respond(
+          &mut stream,
+          "200 OK",
+          "application/javascript",
+          script(self.port).as_bytes(),
+        )
+      } else {
+        http::respond(&mut stream, "404 Not Found", "text/plain", b"")
+      };
+      return;
+    };
+    let accept = base64(
+      &sha1_smol::Sha1::from(format!("{key}{WEBSOCKET_GUID}"))
+        .digest()
+        .bytes(),
+    );
+    let handshake = format!(
+      "HTTP/1.1 101 Switching Protocols\r\nUpgrade: websocket\r\nConnection: Upgrade\r\nSec-WebSocket-Accept: {accept}\r\n\r\n"
+    );
+    if stream.write_all(handshake.as_bytes()).is_err() {
+      return;
+    }
+    if let Ok(clone) = stream.try_clone() {
+      self.clients.lock().unwrap().push(clone);
+      debug!("live reload client connected");
+    }
+    // Nothing the browser sends matters; a write error on the next
+    // broadcast drops the connection once it is gone.
+    let _ = io::copy(&mut stream, &mut io::sink());
+  }
+
+  /// Tells every connected page to reload.
+  pub fn reload(&self) {
+    const MESSAGE: &[u8] = b"reload";
+    let mut frame = vec![0x81, MESSAGE.len() as u8]; // final text frame, unmasked
+    frame.extend_from_slice(MESSAGE);
+    let mut clients = self.clients.lock().unwrap();
+    clients.retain_mut(|c| c.write_all(&frame).is_ok());
+    if !clients.is_empty() {
+      info!("Reloading {} browser page(s)", clients.len());
+    }
+  }
+}
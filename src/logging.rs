@@ -0,0 +1,384 @@
+// The watcher's own log lines: a `tracing` layer that writes events to the
+// configured targets, filtered through `RUST_LOG` (default `info`).
+//
+// `--log-target` may be given several times, to have the same lines on the
+// terminal, in a file and elsewhere at once, each target in its own form:
+// text lines on stdout, stderr and in files, syslog and journald messages,
+// and JSON objects, one per line in a `jsonl:` file or one per POST to a
+// `webhook:`, with the run's identifiers and the other fields as keys:
+//
+//   {"level":"INFO","message":"Executing: make","run_id":"3",
+//    "time":"2025-06-01T12:00:00.123+02:00"}
+//
+// Webhooks get their lines in order from a thread of their own, which the
+// watcher gives a few seconds to catch up before it exits. One that can't
+// be reached is reported on stderr, not to the targets.
+
+use crate::http;
+use crate::rotate::{Retention, RotatingFile};
+use chrono::format::{Item, StrftimeItems};
+use chrono::{Local, SecondsFormat, Utc};
+use std::{
+  fmt::{self, Write as _},
+  io::{self, Write},
+  os::unix::net::UnixDatagram,
+  path::PathBuf,
+  sync::{
+    Mutex,
+    atomic::{AtomicUsize, Ordering},
+    mpsc::{Sender, channel},
+  },
+  thread,
+  time::{Duration, Instant},
+};
+use tracing::field::{Field, Visit};
+use tracing::span::{Attributes, Id};
+use tracing::{Event, Level, Subscriber};
+use tracing_subscriber::EnvFilter;
+use tracing_subscriber::layer::{Context, Layer, SubscriberExt};
+use tracing_subscriber::registry::LookupSpan;
+use tracing_subscriber::util::SubscriberInitExt;
+
+pub const DEFAULT_TIMESTAMP_FORMAT: &str = "%Y-%m-%d %H:%M:%S";
+const IDENTIFIER: &str = "run_on_file_change";
+const SYSLOG_SOCKETS: [&str; 3] = ["/dev/log", "/var/run/syslog", "/var/run/log"];
+const JOURNALD_SOCKET: &str = "/run/systemd/journal/socket";
+
+/// Lines sent to the webhook threads and not posted yet.
+static UNPOSTED: AtomicUsize = AtomicUsize::new(0);
+
+/// How log lines are stamped; `format: None` disables timestamps.
+pub struct Timestamps {
+  pub format: Option<Vec<Item<'static>>>,
+  pub utc: bool,
+}
+
+impl Default for Timestamps {
+  fn default() -> Self {
+    Timestamps {
+      format: parse_format(DEFAULT_TIMESTAMP_FORMAT),
+      utc: false,
+    }
+  }
+}
+
+/// Where log lines go, as given by `--log-target`.
+pub enum LogTarget {
+  Stdout,
+  Stderr,
+  File(PathBuf),
+  Syslog,
+  Journald,
+  /// JSON lines appended to a file.
+  Jsonl(PathBuf),
+  /// Each line POSTed as JSON to a plain-HTTP endpoint.
+  Webhook {
+    host: String,
+    port: u16,
+    path: String,
+  },
+}
+
+impl LogTarget {
+  pub fn parse(s: &str) -> Option<LogTarget> {
+    Some(match s {
+      "stdout" => LogTarget::Stdout,
+      "stderr" => LogTarget::Stderr,
+      "syslog" => LogTarget::Syslog,
+      "journald" => LogTarget::Journald,
+      _ if s.starts_with("jsonl:") => LogTarget::Jsonl(PathBuf::from(&s["jsonl:".len()..])),
+      _ if s.starts_with("webhook:") => {
+        let (host, port, path) = http::parse_url(s.strip_prefix("webhook:http:")?)?;
+        LogTarget::Webhook { host, port, path }
+      }
+      _ => LogTarget::File(PathBuf::from(s.strip_prefix("file:")?)),
+    })
+  }
+}
+
+enum Sink {
+  Stdout,
+  Stderr,
+  File(Mutex<RotatingFile>),
+  Syslog(UnixDatagram),
+  Journald(UnixDatagram),
+  Jsonl(Mutex<RotatingFile>),
+  /// The thread posting to the webhook.
+  Webhook(Sender<String>),
+}
+
+struct SinkLayer {
+  timestamps: Timestamps,
+  sinks: Vec<Sink>,
+}
+
+/// Parses a strftime format up front so a typo is reported at startup
+/// rather than panicking on the first log line.
+pub fn parse_format(fmt: &str) -> Option<Vec<Item<'static>>> {
+  StrftimeItems::new(fmt).parse_to_owned().ok()
+}
+
+fn connect(paths: &[&str]) -> io::Result<UnixDatagram> {
+  let socket = UnixDatagram::unbound()?;
+  let mut last_err = io::Error::from(io::ErrorKind::NotFound);
+  for path in paths {
+    match socket.connect(path) {
+      Ok(()) => return Ok(socket),
+      Err(e) => last_err = e,
+    }
+  }
+  Err(last_err)
+}
+
+/// Posts the lines sent to it to the webhook, one at a time.
+fn webhook(host: String, port: u16, path: String) -> Sender<String> {
+  let (tx, rx) = channel::<String>();
+  thread::spawn(move || {
+    let mut failing = false;
+    for line in rx {
+      // Not logged, which would post it in turn; said once per outage.
+      match http::post_ok(&host, port, &path, line.as_bytes()) {
+        Ok(()) => failing = false,
+        Err(e) if !failing => {
+          failing = true;
+          eprintln!("Log webhook {host}:{port}{path} {e}");
+        }
+        Err(_) => {}
+      }
+      UNPOSTED.fetch_sub(1, Ordering::SeqCst);
+    }
+  });
+  tx
+}
+
+/// Waits up to `timeout` for the webhooks to have been sent every line.
+pub fn flush(timeout: Duration) {
+  let start = Instant::now();
+  while UNPOSTED.load(Ordering::SeqCst) > 0 && start.elapsed() < timeout {
+    thread::sleep(Duration::from_millis(10));
+  }
+}
+
+/// Installs the global subscriber, writing to each of `targets` (stdout
+/// if none). Log files are rotated as `retention` says; `debug_events`
+/// shows the `events` target's debug lines.
+pub fn init(
+  timestamps: Timestamps,
+  targets: &[LogTarget],
+  retention: Retention,
+  debug_events: bool,
+) -> io::Result<()> {
+  let mut sinks = Vec::new();
+  for target in targets {
+    sinks.push(match target {
+      LogTarget::Stdout => Sink::Stdout,
+      LogTarget::Stderr => Sink::Stderr,
+      LogTarget::File(path) => Sink::File(Mutex::new(RotatingFile::open(path, retention)?)),
+      LogTarget::Syslog => Sink::Syslog(connect(&SYSLOG_SOCKETS)?),
+      LogTarget::Journald => Sink::Journald(connect(&[JOURNALD_SOCKET])?),
+      LogTarget::Jsonl(path) => Sink::Jsonl(Mutex::new(RotatingFile::open(path, retention)?)),
+      LogTarget::Webhook { host, port, path } => {
+        Sink::Webhook(webhook(host.clone(), *port, path.clone()))
+      }
+    });
+  }
+  if sinks.is_empty() {
+    sinks.push(Sink::Stdout);
+  }
+  let mut filter = EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info"));
+  if debug_events {
+    filter = filter.add_directive("events=debug".parse().expect("valid directive"));
+  }
+  tracing_subscriber::registry()
+    .with(filter)
+    .with(SinkLayer { timestamps, sinks })
+    .init();
+  Ok(())
+}
+
+/// Splits an event into its message and the remaining fields.
+#[derive(Default)]
+struct Fields {
+  message: String,
+  rest: Vec<(&'static str, String)>,
+}
+
+impl Visit for Fields {
+  fn record_str(&mut self, field: &Field, value: &str) {
+    if field.name() == "message" {
+      self.message = value.to_string();
+    } else {
+      self.rest.push((field.name(), value.to_string()));
+    }
+  }
+
+  fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+    if field.name() == "message" {
+      self.message = format!("{value:?}");
+    } else {
+      self.rest.push((field.name(), format!("{value:?}")));
+    }
+  }
+}
+
+impl SinkLayer {
+  fn timestamp(&self) -> Option<String> {
+    let items = self.timestamps.format.as_ref()?.iter();
+    Some(if self.timestamps.utc {
+      Utc::now().format_with_items(items).to_string()
+    } else {
+      Local::now().format_with_items(items).to_string()
+    })
+  }
+
+  /// Info and above print just the message, as they always have; debug and
+  /// trace output also shows level, span scope and fields.
+  fn line(&self, level: Level, scope: &str, fields: &Fields) -> String {
+    let mut line = String::new();
+    if let Some(now) = self.timestamp() {
+      let _ = write!(line, "{now}: ");
+    }
+    if level > Level::INFO {
+      let _ = write!(line, "{level} {scope}");
+    }
+    line.push_str(&fields.message);
+    if level > Level::INFO {
+      for (key, value) in &fields.rest {
+        let _ = write!(line, " {key}={value}");
+      }
+    }
+    line.push('\n');
+    line
+  }
+
+  /// The event as a JSON object, with `message` free of the run prefix.
+  fn json(&self, level: Level, message: &str, fields: &Fields, run: Option<&RunFields>) -> String {
+    let mut object = serde_json::Map::new();
+    let now = match self.timestamps.utc {
+      true => Utc::now().to_rfc3339_opts(SecondsFormat::Millis, true),
+      false => Local::now().to_rfc3339_opts(SecondsFormat::Millis, false),
+    };
+    object.insert("time".to_string(), now.into());
+    object.insert("level".to_string(), level.as_str().into());
+    object.insert("message".to_string(), message.into());
+    for (key, value) in run.into_iter().flat_map(|r| &r.0).chain(&fields.rest) {
+      object.insert(key.to_string(), value.as_str().into());
+    }
+    serde_json::Value::Object(object).to_string()
+  }
+}
+
+/// The fields of a `run` span, kept in its extensions.
+#[derive(Clone)]
+struct RunFields(Vec<(&'static str, String)>);
+
+impl<S> Layer<S> for SinkLayer
+where
+  S: Subscriber + for<'a> LookupSpan<'a>,
+{
+  fn on_new_span(&self, attrs: &Attributes<'_>, id: &Id, ctx: Context<'_, S>) {
+    if attrs.metadata().name() != "run" {
+      return;
+    }
+    let mut fields = Fields::default();
+    attrs.record(&mut fields);
+    if let Some(span) = ctx.span(id) {
+      span.extensions_mut().insert(RunFields(fields.rest));
+    }
+  }
+
+  fn on_event(&self, event: &Event<'_>, ctx: Context<'_, S>) {
+    let mut fields = Fields::default();
+    event.record(&mut fields);
+    let level = *event.metadata().level();
+    // Lines about a run start with its number, and journald gets its
+    // identifiers as fields.
+    let run = ctx.event_scope(event).and_then(|spans| {
+      spans
+        .into_iter()
+        .find_map(|span| span.extensions().get::<RunFields>().cloned())
+    });
+    let message = fields.message.clone();
+    if let Some(run) = &run
+      && let Some((_, id)) = run.0.iter().find(|(key, _)| *key == "run_id")
+    {
+      fields.message = format!("[run {id}] {}", fields.message);
+    }
+
+    for sink in &self.sinks {
+      match sink {
+        Sink::Stdout | Sink::Stderr | Sink::File(_) => {
+          let mut scope = String::new();
+          if let Some(spans) = ctx.event_scope(event) {
+            for span in spans.from_root() {
+              let _ = write!(scope, "{}: ", span.name());
+            }
+          }
+          let line = self.line(level, &scope, &fields);
+          match sink {
+            Sink::Stdout => print!("{line}"),
+            Sink::Stderr => eprint!("{line}"),
+            Sink::File(f) => {
+              let _ = f.lock().unwrap().write_all(line.as_bytes());
+            }
+            _ => unreachable!(),
+          }
+        }
+        Sink::Syslog(s) => {
+          // RFC 3164 without a header timestamp; syslogd adds its own.
+          // Facility user (1 << 3) plus severity.
+          let pri = 8 + severity(level);
+          let pid = std::process::id();
+          let _ = s.send(format!("<{pri}>{IDENTIFIER}[{pid}]: {}", fields.message).as_bytes());
+        }
+        Sink::Journald(s) => {
+          let mut datagram = Vec::new();
+          journal_field(&mut datagram, "MESSAGE", &fields.message);
+          journal_field(&mut datagram, "PRIORITY", &severity(level).to_string());
+          journal_field(&mut datagram, "SYSLOG_IDENTIFIER", IDENTIFIER);
+          for (key, value) in fields.rest.iter().chain(run.iter().flat_map(|r| &r.0)) {
+            journal_field(&mut datagram, &key.to_ascii_uppercase(), value);
+          }
+          let _ = s.send(&datagram);
+        }
+        Sink::Jsonl(f) => {
+          let line = self.json(level, &message, &fields, run.as_ref());
+          let _ = writeln!(f.lock().unwrap(), "{line}");
+        }
+        Sink::Webhook(tx) => {
+          UNPOSTED.fetch_add(1, Ordering::SeqCst);
+          if tx
+            .send(self.json(level, &message, &fields, run.as_ref()))
+            .is_err()
+          {
+            UNPOSTED.fetch_sub(1, Ordering::SeqCst);
+          }
+        }
+      }
+    }
+  }
+}
+
+/// Syslog severity of a tracing level.
+fn severity(level: Level) -> u8 {
+  match level {
+    Level::ERROR => 3,
+    Level::WARN => 4,
+    Level::INFO => 6,
+    _ => 7,
+  }
+}
+
+/// Appends one field in journald's native protocol, using the length-prefixed
+/// form for values that contain newlines.
+fn journal_field(buf: &mut Vec<u8>, key: &str, value: &str) {
+  buf.extend_from_slice(key.as_bytes());
+  if value.contains('\n') {
+    buf.push(b'\n');
+    buf.extend_from_slice(&(value.len() as u64).to_le_bytes());
+  } else {
+    buf.push(b'=');
+  }
+  buf.extend_from_slice(value.as_bytes());
+  buf.push(b'\n');
+}
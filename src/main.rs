@@ -2,106 +2,2556 @@
 // Usage example:
 //   ./run_on_file_change "cargo run --release" ./src ./tests
 
-use chrono::Local;
-use nix::libc;
-use nix::sys::signal::{Signal::SIGTERM, kill};
-use nix::unistd::Pid;
-use notify::{Config, Event, EventKind, RecommendedWatcher, RecursiveMode, Watcher};
-use std::os::unix::process::CommandExt; // for .pre_exec
+mod banner;
+mod bench;
+mod cleanup;
+mod cli;
+mod control;
+mod coordinate;
+mod cron;
+mod daemon;
+mod dbus;
+mod diff;
+mod doctor;
+mod editor;
+mod exit;
+mod expand;
+mod filter;
+mod git;
+mod history;
+mod hooks;
+mod http;
+mod jobs;
+mod k8s;
+mod livereload;
+mod logging;
+mod manifest;
+mod mqtt;
+mod notification;
+mod output;
+mod pair;
+mod paranoid;
+mod pipeline;
+mod plugin;
+mod process;
+mod proxy;
+mod ready;
+mod record;
+mod rename;
+mod rotate;
+mod runlog;
+mod script;
+mod self_reload;
+mod serve;
+mod settle;
+mod signals;
+mod significance;
+mod stats;
+mod status;
+mod summary;
+mod timers;
+mod tmux;
+mod watch;
+mod watchdog;
+mod writes;
+
+use exit::Error::{Config, Startup};
+use filter::{Filter, Priority};
+use livereload::LiveReload;
+use nix::sys::signal::Signal;
+use notify::event::{MetadataKind, ModifyKind, RenameMode};
+use notify::{Event, EventKind, PollWatcher, RecommendedWatcher, Watcher};
+use output::OutputConfig;
+use process::Run;
+use run_on_file_change::source::{EventSource, Events};
 use std::{
-  env,
+  collections::{BTreeMap, VecDeque},
+  io,
+  os::unix::fs::MetadataExt,
   path::PathBuf,
-  process::{Child, Command, Stdio},
-  sync::mpsc::channel,
-  time::{Duration, Instant},
+  process::ExitStatus,
+  sync::{
+    Arc, Mutex,
+    mpsc::{RecvTimeoutError, Sender, channel},
+  },
+  time::{Duration, Instant, SystemTime},
 };
+use summary::Summary;
+use timers::Timers;
+use tracing::{debug, error, info, info_span, warn};
+use watch::WatchSet;
+
+/// A second Ctrl-C within this window stops the watcher itself.
+const QUIT_WINDOW: Duration = Duration::from_millis(1_500);
+
+/// How often to check whether a stopped run's group is gone (and its
+/// ports are free) before starting the next run.
+const RESTART_POLL: Duration = Duration::from_millis(20);
+/// Start the next run anyway once the old one took this long to clear.
+const RESTART_LIMIT: Duration = Duration::from_secs(5);
+/// How often to check on a before_each hook, besides when a child exits.
+const HOOK_POLL: Duration = Duration::from_millis(100);
+
+/// Changes this soon after a run ended still count as the run's own writes.
+const WRITE_WINDOW: Duration = Duration::from_millis(500);
+
+/// How often a job may come round again in a chain of jobs whose writes
+/// trigger each other before the chain is broken (`--allow-cycles`).
+const MAX_LAPS: usize = 3;
+
+/// How often `--allow-missing` looks for paths that didn't exist.
+const RECHECK: Duration = Duration::from_secs(5);
 
-fn log(msg: &str) {
-  let now = Local::now().format("%Y-%m-%d %H:%M:%S");
-  println!("{now}: {msg}");
+/// For this long after the watches are set up (or two poll intervals, if
+/// longer), changes to files last written before then are taken for
+/// stale reports of old changes, which some backends deliver on startup.
+const STALE_WINDOW: Duration = Duration::from_secs(2);
+/// The same change to the same paths within this long (plus a poll
+/// interval, with polling) is taken for one delivered by two watches.
+const DUPLICATE_WINDOW: Duration = Duration::from_millis(50);
+
+/// How long `--log-target webhook:` gets to catch up on exit.
+const LOG_FLUSH: Duration = Duration::from_secs(3);
+
+/// Deadlines the event loop sets for itself.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Timer {
+  /// Start the job's pending run once the previous one is gone.
+  Spawn(usize),
+  /// `--cancel-window` is over: stop the job's run for the pending one.
+  Cancel(usize),
+  /// The rebuilt binary has settled: re-exec it.
+  Reload,
+  /// A rename half has waited long enough for its partner.
+  Rename,
+  /// `--every` has come round again.
+  Every,
+  /// The `--cron` schedule is due.
+  Cron,
+  /// Time to see whether held files have stopped changing.
+  Settle,
+  /// `--within` has run out.
+  Deadline,
+  /// Time to watch roots the backend failed on again.
+  Rewatch,
+  /// Time to look for roots skipped as missing.
+  Recheck,
+  /// The job's run is over and done writing: report what it wrote.
+  Writes(usize),
+  /// `--quiet-hours` begin or end.
+  Quiet,
+  /// Time to poll, with `--poll-backoff`.
+  Poll,
+  /// Time to see whether the groups of dropped runs are gone.
+  Dropped,
+  /// Time to see whether after_each hooks are over.
+  Hooks,
 }
 
-fn main() -> notify::Result<()> {
-  // ----------- Parse CLI --------------------------------------------------
-  let mut args = env::args().skip(1); // skip program name
-  let cmd_string = args.next().unwrap_or_else(|| {
-    eprintln!("Usage:\n  run_on_file_change \"<command>\" <dir1> [dir2] …");
-    std::process::exit(1);
+/// Log target of the raw events and what became of them
+/// (`--debug-events`, or `RUST_LOG=events=debug`).
+const EVENTS: &str = "events";
+
+/// Everything the event loop reacts to arrives through one channel, except
+/// for timers, which it produces itself once they are due, ahead of
+/// anything still waiting.
+pub enum Msg {
+  Fs(notify::Result<Event>),
+  Signal(Signal),
+  /// All captured output of the run with this id has been read.
+  OutputClosed(u64),
+  Timer(Timer),
+  /// The run with this id passed its `--ready-when` check.
+  Ready(u64),
+  /// The run with this id uses this much memory (KiB), over the limit.
+  MemoryExceeded(u64, u64),
+  /// A `--control-stdin` command.
+  Control(control::Command),
+  /// A line written to the `--fifo`.
+  Fifo(String),
+}
+
+/// The current run, and the one queued to replace it.
+#[derive(Default)]
+struct Runs {
+  child: Option<Run>,
+  /// A replaced run on its way out.
+  stopping: Option<process::Stopping>,
+  /// Command and trigger waiting for `stopping` to be gone.
+  pending: Option<(String, Vec<PathBuf>)>,
+  /// Whether `pending` follows up on the run before it.
+  follow_up: bool,
+  /// The `--fifo` line that asked for `pending` (or the run going, once
+  /// it has started).
+  message: Option<String>,
+}
+
+impl Runs {
+  /// Queues a run of `command` for job `job`, stopping the current one
+  /// first, or once `cancel_window` has passed if one is given; it starts
+  /// on [`Timer::Spawn`]. A later request replaces a queued one.
+  fn request(
+    &mut self,
+    job: usize,
+    command: String,
+    trigger: Vec<PathBuf>,
+    signal: Signal,
+    cancel_window: Option<Duration>,
+    timers: &mut Timers<Timer>,
+  ) {
+    self.pending = Some((command, trigger));
+    self.follow_up = false;
+    self.message = None;
+    if let Some(window) = cancel_window
+      && self.child.as_mut().is_some_and(process::is_running)
+    {
+      if !timers.is_set(Timer::Cancel(job)) {
+        timers.set(Timer::Cancel(job), window);
+      }
+      return;
+    }
+    self.cancel(job, signal, timers);
+  }
+
+  /// Queues a run of `command` for job `job` to start once the current one
+  /// has ended by itself, or right away if there is none.
+  fn queue(
+    &mut self,
+    job: usize,
+    command: String,
+    trigger: Vec<PathBuf>,
+    signal: Signal,
+    timers: &mut Timers<Timer>,
+  ) {
+    self.pending = Some((command, trigger));
+    self.follow_up = false;
+    self.message = None;
+    if !self.child.as_mut().is_some_and(process::is_running) {
+      self.cancel(job, signal, timers);
+    }
+  }
+
+  /// Stops the current run for the queued one, or starts that right away
+  /// if nothing is in its way.
+  fn cancel(&mut self, job: usize, signal: Signal, timers: &mut Timers<Timer>) {
+    timers.cancel(Timer::Cancel(job));
+    if let Some(c) = self.child.take() {
+      let mut s = process::begin_stop(c, signal);
+      if s.poll() {
+        timers.set(Timer::Spawn(job), Duration::ZERO);
+      }
+      self.stopping = Some(s);
+      return;
+    }
+    if self.stopping.is_some() || timers.is_set(Timer::Spawn(job)) {
+      return; // already waiting, the latest trigger wins
+    }
+    timers.set(Timer::Spawn(job), Duration::ZERO);
+  }
+
+  /// Whether the command may still be writing files: it is running, being
+  /// stopped, or ended less than [`WRITE_WINDOW`] ago.
+  fn writing(&mut self) -> bool {
+    if self.stopping.is_some() {
+      return true;
+    }
+    let Some(run) = &mut self.child else {
+      return false;
+    };
+    process::is_running(run) || run.ended().is_none_or(|t| t.elapsed() < WRITE_WINDOW)
+  }
+
+  /// Stops the current run for good, if it is still going, and drops a
+  /// queued one: another job of the group takes over.
+  fn preempt(&mut self, job: usize, signal: Signal, timers: &mut Timers<Timer>) {
+    self.pending = None;
+    self.follow_up = false;
+    self.message = None;
+    let Some(c) = self.child.take_if(process::is_running) else {
+      return;
+    };
+    let mut s = process::begin_stop(c, signal);
+    if s.poll() {
+      timers.set(Timer::Spawn(job), Duration::ZERO);
+    }
+    self.stopping = Some(s);
+  }
+
+  /// Whether a run of job `job` is going or about to start.
+  fn busy(&mut self, job: usize, timers: &Timers<Timer>) -> bool {
+    self.child.as_mut().is_some_and(process::is_running)
+      || self.stopping.is_some()
+      || timers.is_set(Timer::Spawn(job))
+  }
+}
+
+/// A job and what the event loop keeps for it.
+struct Slot {
+  job: jobs::Job,
+  filter: Filter,
+  /// Output prefix, when several jobs share the terminal.
+  prefix: Option<String>,
+  runs: Runs,
+  last_event: Option<Instant>,
+  /// The latest change, and how many in a row fell inside the window
+  /// before it (`--min-debounce`/`--max-debounce`).
+  last_seen: Option<Instant>,
+  burst: u32,
+  /// `--diff-output`'s base.
+  previous_output: Option<Vec<(output::Stream, Vec<u8>)>>,
+  /// Indices of the jobs this one needs.
+  needs: Vec<usize>,
+  /// Needs that have yet to succeed before the queued run may start.
+  blocked: Vec<usize>,
+  /// What changed for the queued run (`--describe-changes`).
+  changes: Option<String>,
+  /// The jobs whose writes, one after the other, led to the current run.
+  lineage: Vec<usize>,
+  /// Since when the queued run has waited for `--expects-port`.
+  port_wait: Option<Instant>,
+  /// The queued run's before_each hook, while it runs, and the run id it
+  /// was given.
+  hook: Option<(u64, process::Hook)>,
+  /// What the latest run wrote (`--report-writes`).
+  writes: Option<writes::Writes>,
+  /// The job's own readiness check, which the jobs needing it wait for.
+  ready: Option<ready::Probe>,
+}
+
+impl Slot {
+  /// Whether a change now falls outside the job's debounce window, which
+  /// counts from its latest run. With a `max`imum the window adapts
+  /// instead: it doubles from `window` with each change inside it, up to
+  /// `max`, and counts from the latest change, though a run is never held
+  /// back for longer than `max`.
+  fn outside_window(&mut self, window: Duration, max: Option<Duration>) -> bool {
+    let Some(max) = max else {
+      return self.last_event.is_none_or(|t| t.elapsed() >= window);
+    };
+    let window = window.saturating_mul(1 << self.burst.min(16)).min(max);
+    let outside = self.last_seen.is_none_or(|t| t.elapsed() >= window)
+      || self.last_event.is_none_or(|t| t.elapsed() >= max);
+    self.last_seen = Some(Instant::now());
+    self.burst = if outside { 0 } else { self.burst + 1 };
+    outside
+  }
+
+  /// What a trigger runs: the job's command, `--on-checkout`'s for a
+  /// branch switch, or the `overridden` one `--command-for` picked.
+  fn command(&self, opts: &cli::Options, checkout: bool, overridden: Option<&str>) -> String {
+    match (&opts.on_checkout, overridden) {
+      (Some(command), _) if checkout => command.clone(),
+      (_, Some(command)) => command.to_string(),
+      _ => self.job.command.clone(),
+    }
+  }
+
+  /// What tells the job apart across watchers: its name, or the
+  /// command-line job's command.
+  fn key(&self) -> &str {
+    match self.job.name.as_str() {
+      "" => &self.job.command,
+      name => name,
+    }
+  }
+}
+
+/// Queues runs for jobs (index, command, trigger), along with the jobs
+/// they need, which run first. Of several jobs in one concurrency group
+/// only the first runs, and the group's other jobs are stopped before it
+/// starts. With `--reload-signal`, a job still running is signalled to
+/// reload instead. A high `priority` stops runs at once regardless, and a
+/// low one queues behind them.
+fn request(
+  slots: &mut [Slot],
+  due: Vec<(usize, String, Vec<PathBuf>)>,
+  opts: &cli::Options,
+  priority: Priority,
+  timers: &mut Timers<Timer>,
+) {
+  let signal = opts.signal;
+  let triggered: Vec<usize> = due.iter().map(|(i, _, _)| *i).collect();
+  let needs: Vec<Vec<usize>> = slots.iter().map(|s| s.needs.clone()).collect();
+  let pathless: Vec<bool> = slots.iter().map(|s| s.job.watch.is_empty()).collect();
+  let wave = jobs::wave(&needs, &pathless, &triggered);
+  let trigger = due.first().map(|(_, _, t)| t.clone()).unwrap_or_default();
+  let mut groups = Vec::new();
+  for i in wave {
+    let (command, trigger) = match due.iter().find(|(j, _, _)| *j == i) {
+      Some((_, command, trigger)) => (command.clone(), trigger.clone()),
+      None => (slots[i].job.command.clone(), trigger.clone()),
+    };
+    // The wave holds everything a job needs, to be run again.
+    slots[i].blocked = slots[i].needs.clone();
+    if let Some(group) = slots[i].job.group.clone() {
+      if groups.contains(&group) {
+        debug!(job = %slots[i].job.name, "another job of its group runs instead");
+        continue;
+      }
+      for j in 0..slots.len() {
+        if j != i && slots[j].job.group.as_ref() == Some(&group) {
+          if slots[j]
+            .runs
+            .child
+            .as_mut()
+            .is_some_and(process::is_running)
+          {
+            info!("Stopping {} for {}", slots[j].job.name, slots[i].job.name);
+          }
+          slots[j].runs.preempt(j, signal, timers);
+        }
+      }
+      groups.push(group);
+    }
+    if priority == Priority::Low {
+      slots[i].runs.queue(i, command, trigger, signal, timers);
+      continue;
+    }
+    if let Some(reload) = opts.reload_signal
+      && priority != Priority::High
+      && let Some(run) = &mut slots[i].runs.child
+      && process::is_running(run)
+    {
+      let _span = run.span().entered();
+      info!("Sending {reload} to reload the running command");
+      process::signal_group(run, reload);
+      continue;
+    }
+    let cancel_window = opts.cancel_window.filter(|_| priority != Priority::High);
+    slots[i]
+      .runs
+      .request(i, command, trigger, signal, cancel_window, timers);
+  }
+}
+
+/// While paused by SIGUSR2: how many changes were let go, and per job the
+/// run the last of them would have started.
+#[derive(Default)]
+struct Paused {
+  changes: u64,
+  missed: BTreeMap<usize, (String, Vec<PathBuf>)>,
+}
+
+/// Arms [`Timer::Cron`] for the schedule's next match.
+fn arm_cron(schedule: &cron::Schedule, timers: &mut Timers<Timer>) {
+  let now = chrono::Local::now();
+  if let Some(next) = schedule.next_after(now) {
+    debug!(%next, "next scheduled run");
+    timers.set(Timer::Cron, (next - now).to_std().unwrap_or_default());
+  }
+}
+
+/// What hears about runs starting and ending, besides the log.
+struct Listeners {
+  plugins: Vec<plugin::Plugin>,
+  dbus: bool,
+  mqtt: Option<mqtt::Publisher>,
+}
+
+impl Listeners {
+  fn message(event: &str, job: &str, run: &Run) -> serde_json::Value {
+    serde_json::json!({
+      "event": event,
+      "job": job,
+      "command": run.command,
+      "run_id": run.id,
+      "run_uuid": run.uuid,
+      "trigger": run.trigger,
+    })
+  }
+
+  fn started(&mut self, job: &str, run: &Run) {
+    if self.dbus {
+      dbus::run_started(job, &run.command, run.id);
+    }
+    let message = Listeners::message("started", job, run);
+    for p in &mut self.plugins {
+      p.started(&message);
+    }
+    if let Some(mqtt) = &self.mqtt {
+      mqtt.publish(message, false);
+    }
+  }
+
+  fn finished(&mut self, job: &str, run: &Run, status: ExitStatus) {
+    let duration_ms = run.duration().as_millis() as u64;
+    if self.dbus {
+      dbus::run_finished(
+        job,
+        &run.command,
+        run.id,
+        status.success(),
+        status.code(),
+        duration_ms,
+      );
+    }
+    let mut message = Listeners::message("finished", job, run);
+    message["success"] = status.success().into();
+    message["exit_code"] = status.code().into();
+    message["duration_ms"] = duration_ms.into();
+    for p in &mut self.plugins {
+      p.finished(&message);
+    }
+    if let Some(mqtt) = &self.mqtt {
+      mqtt.publish(message, true);
+    }
+  }
+}
+
+/// Handles the end of a run of `job` once it has exited and its output is
+/// drained. Returns whether it succeeded, if it has just ended.
+fn complete(
+  job: &str,
+  run: &mut Run,
+  opts: &cli::Options,
+  summary: &mut Summary,
+  livereload: Option<&LiveReload>,
+  previous_output: &mut Option<Vec<(output::Stream, Vec<u8>)>>,
+  listeners: &mut Listeners,
+) -> Option<bool> {
+  let status = run.take_completion()?;
+  let _span = run.span().entered();
+  run.ready = None;
+  if let Some(path) = &run.log {
+    runlog::finish(path, status, run.duration());
+  }
+  if let Some(usage) = &run.usage {
+    info!(
+      job = %run.job,
+      duration_ms = run.duration().as_millis() as u64,
+      user_ms = usage.user.as_millis() as u64,
+      system_ms = usage.system.as_millis() as u64,
+      max_rss_kb = usage.max_rss_kb,
+      "{} took {:.1}s ({})",
+      match job {
+        "" => "Run",
+        name => name,
+      },
+      run.duration().as_secs_f64(),
+      process::describe_usage(usage)
+    );
+  }
+  if opts.keep_failed_tmpdirs
+    && !status.success()
+    && let Some(dir) = run.tmpdir.take()
+  {
+    info!(
+      "Keeping the failed run's temporary directory {}",
+      dir.display()
+    );
+  }
+  let previous = summary.last_success();
+  summary.record(run, status, false);
+  let outcome = if status.success() {
+    "success"
+  } else {
+    "failure"
+  };
+  let mut env = hooks::run_env(job, &run.command, run.id, &run.trigger);
+  env.extend([
+    ("ROF_STATUS", outcome.to_string()),
+    (
+      "ROF_EXIT_CODE",
+      status.code().map(|c| c.to_string()).unwrap_or_default(),
+    ),
+    ("ROF_DURATION_MS", run.duration().as_millis().to_string()),
+  ]);
+  hooks::spawn("after_each", &opts.hooks.after_each, env);
+  listeners.finished(job, run, status);
+  if !opts.notify.is_empty() && opts.notify_on.applies(status.success(), previous) {
+    let mut message = match status.success() {
+      true => format!("{} succeeded", run.command),
+      false => format!("{} failed ({})", run.command, process::describe(status)),
+    };
+    if let Some(changes) = &run.changes {
+      message.push_str(&format!(" after {changes}"));
+    }
+    notification::send(&opts.notify, status.success(), &message);
+  }
+  // With a readiness check, pages reload once the run is ready instead.
+  if status.success()
+    && !opts.has_ready_check()
+    && let Some(livereload) = livereload
+  {
+    livereload.reload();
+  }
+  if let Some(captured) = &run.captured {
+    if opts.output.diff_output {
+      output::report_diff(captured, run.id, previous_output);
+    } else {
+      if opts.output.quiet_success && !status.success() {
+        info!(
+          "Run failed ({}), captured output follows",
+          process::describe(status)
+        );
+      }
+      output::report(captured, status.success(), &opts.output);
+    }
+  }
+  if let Some(format) = &opts.banner {
+    banner::print(format, opts.interactive(), job, run, status);
+  }
+  Some(status.success())
+}
+
+/// Drops the queued runs waiting on `skipped`, which won't run because
+/// `failed` failed.
+fn skip_dependents(slots: &mut [Slot], skipped: usize, failed: usize) {
+  for i in 0..slots.len() {
+    if slots[i].blocked.contains(&skipped) && slots[i].runs.pending.take().is_some() {
+      info!(
+        "Skipping {}: {} failed",
+        slots[i].job.name, slots[failed].job.name
+      );
+      slots[i].blocked.clear();
+      skip_dependents(slots, i, failed);
+    }
+  }
+}
+
+/// Whether the leader of some run, going, being stopped or detached, is
+/// yet to be collected: reaping orphans now could take its exit status.
+fn leaders_pending(slots: &mut [Slot], detached: &mut [Run]) -> bool {
+  slots.iter_mut().any(|s| {
+    s.runs.child.as_mut().is_some_and(process::is_running)
+      || s.runs.stopping.as_mut().is_some_and(|s| !s.poll())
+      || s.hook.is_some()
+  }) || detached.iter_mut().any(process::is_running)
+    || hooks::running()
+}
+
+/// Job `done` has finished a run: jobs waiting on it start once nothing
+/// else blocks them, or are dropped, along with what waits on them, if it
+/// failed.
+fn resolve_needs(slots: &mut [Slot], done: usize, success: bool, timers: &mut Timers<Timer>) {
+  if !success {
+    skip_dependents(slots, done, done);
+    return;
+  }
+  for (i, slot) in slots.iter_mut().enumerate() {
+    if slot.blocked.contains(&done) {
+      slot.blocked.retain(|&j| j != done);
+      if slot.blocked.is_empty() && slot.runs.pending.is_some() {
+        timers.set(Timer::Spawn(i), Duration::ZERO);
+      }
+    }
+  }
+}
+
+/// Moves on from job `i`'s run, which has ended with `success`: queues
+/// `--on-success`'s or `--on-failure`'s command next in the same job,
+/// unless the run was one of those itself, and lets the jobs that need it
+/// go by how it did, unless they went once it was ready.
+fn follow_up(
+  slots: &mut [Slot],
+  i: usize,
+  success: bool,
+  opts: &cli::Options,
+  timers: &mut Timers<Timer>,
+) {
+  // A run queued behind this one goes now.
+  if slots[i].runs.pending.is_some() && !timers.is_set(Timer::Spawn(i)) {
+    timers.set(Timer::Spawn(i), Duration::ZERO);
+  }
+  // Those waiting for it to be ready have gone ahead already.
+  let resolved = slots[i].ready.is_some()
+    && slots[i]
+      .runs
+      .child
+      .as_ref()
+      .is_some_and(|r| r.ready_after.is_some());
+  let runs = &mut slots[i].runs;
+  let Some(run) = runs.child.as_ref().filter(|r| !r.follow_up) else {
+    return;
+  };
+  let next = match success {
+    true => &opts.on_success,
+    false => &opts.on_failure,
+  };
+  if let Some(command) = next {
+    runs.pending = Some((command.clone(), run.trigger.clone()));
+    runs.follow_up = true;
+    timers.set(Timer::Spawn(i), Duration::ZERO);
+  }
+  if !resolved {
+    resolve_needs(slots, i, success, timers);
+  }
+}
+
+/// Whether a finished run has exited with `--expect-exit`'s code.
+fn expectation_met(opts: &cli::Options, summary: &Summary) -> bool {
+  opts.expect_exit.is_some_and(|code| {
+    summary
+      .runs
+      .iter()
+      .any(|r| !r.stopped && r.exit_code == Some(code))
+  })
+}
+
+/// Whether `--expect-exit` or `--max-runs` says the watcher is done,
+/// saying why if so.
+fn done(opts: &cli::Options, summary: &Summary) -> bool {
+  let finished = summary.runs.iter().filter(|r| !r.stopped).count();
+  if expectation_met(opts, summary) {
+    info!(
+      "A run exited with code {} as expected",
+      opts.expect_exit.unwrap_or(0)
+    );
+    return true;
+  }
+  if opts.max_runs.is_some_and(|n| finished >= n) {
+    info!("Finished {finished} run(s), exiting");
+    return true;
+  }
+  false
+}
+
+/// Whether an event of `kind` only concerns files unchanged since
+/// `since`, going by their modification times.
+fn stale(kind: &EventKind, paths: &[PathBuf], since: SystemTime) -> bool {
+  !matches!(kind, EventKind::Remove(_))
+    && !paths.is_empty()
+    && paths.iter().all(|path| {
+      std::fs::symlink_metadata(path)
+        .and_then(|m| m.modified())
+        .is_ok_and(|t| t < since)
+    })
+}
+
+/// Whether an event only changed metadata: permissions, ownership, access
+/// times. Inotify doesn't say which metadata changed, so there a file
+/// counts as written when its mtime moved along with its ctime, as `touch`
+/// and writes do and `chmod` doesn't.
+fn metadata_only(kind: &EventKind, paths: &[PathBuf]) -> bool {
+  match kind {
+    EventKind::Modify(ModifyKind::Metadata(MetadataKind::WriteTime)) => false,
+    EventKind::Modify(ModifyKind::Metadata(MetadataKind::Any)) => paths.iter().all(|path| {
+      std::fs::symlink_metadata(path)
+        .is_ok_and(|m| (m.mtime(), m.mtime_nsec()) < (m.ctime(), m.ctime_nsec()))
+    }),
+    EventKind::Modify(ModifyKind::Metadata(_)) => true,
+    _ => false,
+  }
+}
+
+/// `--seamless-reload`: the runs of `slots` waiting to start, or about to
+/// be cut short by a reload, for the new process to start again.
+fn carry(slots: &mut [Slot]) -> Vec<self_reload::Carried> {
+  let mut carried = Vec::new();
+  for slot in slots {
+    let going = slot.runs.child.as_ref().filter(|r| r.ended().is_none());
+    let trigger = match (slot.runs.pending.take(), going) {
+      (Some((_, trigger)), _) => trigger,
+      (None, Some(run)) => run.trigger.clone(),
+      (None, None) => continue,
+    };
+    carried.push(self_reload::Carried {
+      job: slot.key().to_string(),
+      trigger,
+    });
+  }
+  if !carried.is_empty() {
+    info!(
+      "Handing {} run(s) over to the reloaded watcher",
+      carried.len()
+    );
+  }
+  carried
+}
+
+/// `watch add|remove DIR`: changes what is watched, and what the filter
+/// lets through, to match.
+fn rewatch(
+  change: &control::WatchChange,
+  filter: &mut Filter,
+  watch_set: &mut WatchSet,
+  watchers: &mut watch::Watchers,
+  root_paths: &mut Vec<PathBuf>,
+  opts: &cli::Options,
+) -> Result<String, String> {
+  let path = &change.path;
+  let spellings =
+    |path: &std::path::Path| [std::path::absolute(path).ok(), path.canonicalize().ok()];
+  if !change.add {
+    let root = watch_set.remove(watchers, path)?;
+    filter.remove_root(&root.path);
+    let gone: Vec<PathBuf> = spellings(&root.path).into_iter().flatten().collect();
+    root_paths.retain(|p| !gone.contains(p));
+    return Ok(format!("Stopped watching {:?}", root.path));
+  }
+  let spec = watch::interpret(path, opts.depth)?;
+  if spec.pattern.is_some() {
+    return Err("only directories can be added".into());
+  }
+  let mut root = spec.root;
+  root.backend = watch::backend_for(&root.path, &opts.path_backends);
+  if root.backend == watch::Backend::Poll {
+    if watchers.poll.is_none() {
+      return Err("it would be polled, and nothing else is; restart to watch it".into());
+    }
+    if let Ok(canonical) = root.path.canonicalize() {
+      root.path = canonical;
+    }
+  }
+  watch_set.add(watchers, root.clone())?;
+  filter.add_root(&root.path);
+  root_paths.extend(spellings(&root.path).into_iter().flatten());
+  Ok(format!("Watching {:?} too", root.path))
+}
+
+/// `--report-writes`: logs what the job's latest run wrote that its filters
+/// let through, and the patterns that would ignore it.
+fn report_writes(slot: &mut Slot, roots: &[PathBuf]) {
+  let Some(writes) = slot.writes.take().filter(|w| w.len() > 0) else {
+    return;
+  };
+  let _span = process::run_span(writes.run_id, None).entered();
+  let patterns: Vec<String> = writes
+    .suggestions(roots)
+    .iter()
+    .map(|p| format!("-i '{p}'"))
+    .collect();
+  info!(
+    "{} wrote {} watched path{}; to keep such writes from triggering runs: {}",
+    writes.command,
+    writes.len(),
+    if writes.len() == 1 { "" } else { "s" },
+    patterns.join(" ")
+  );
+}
+
+/// `test-ignore`: prints what each job's filters make of `paths`, and
+/// returns 1 if any of them triggers no job.
+fn test_ignore(paths: &[PathBuf], slots: &[Slot], mut tracked: Option<&mut git::Tracked>) -> i32 {
+  let mut status = 0;
+  for path in paths {
+    let absolute = std::path::absolute(path).unwrap_or_else(|_| path.clone());
+    let mut triggers = false;
+    for slot in slots {
+      let job = match slot.job.name.as_str() {
+        "" => String::new(),
+        name => format!("{name}: "),
+      };
+      let verdict = slot.filter.verdict(&absolute);
+      let untracked = tracked.as_mut().is_some_and(|t| !t.contains(&absolute));
+      match verdict.triggers() && untracked {
+        true => println!(
+          "{}: {job}ignored: not tracked by git, with --git-tracked-only",
+          path.display()
+        ),
+        false => println!("{}: {job}{verdict}", path.display()),
+      }
+      triggers |= verdict.triggers() && !untracked;
+    }
+    if !triggers {
+      status = 1;
+    }
+  }
+  status
+}
+
+/// Records a stopped run if stopping it cut it short.
+fn record_stopped(stopping: &process::Stopping, summary: &mut Summary) {
+  if let Some(status) = stopping.interrupted() {
+    summary.record(&stopping.run, status, true);
+    if let Some(path) = &stopping.run.log {
+      runlog::finish(path, status, stopping.run.duration());
+    }
+  }
+}
+
+/// Stops the jobs' runs, and waits for those already being stopped, as
+/// the watcher shuts down or reloads.
+fn stop_all(slots: &mut [Slot], opts: &cli::Options, summary: &mut Summary) {
+  let runs = slots
+    .iter_mut()
+    .filter_map(|s| s.runs.child.take())
+    .collect();
+  let stopping = slots
+    .iter_mut()
+    .filter_map(|s| s.runs.stopping.take())
+    .collect();
+  for s in process::stop_all(runs, stopping, opts.signal) {
+    record_stopped(&s, summary);
+  }
+}
+
+/// Starts the next run of `command` for a change to `trigger`.
+fn start(
+  opts: &cli::Options,
+  id: u64,
+  slot: &Slot,
+  command: &str,
+  trigger: Vec<PathBuf>,
+  run_logs: Option<&runlog::RunLogs>,
+  tx: &Sender<Msg>,
+) -> io::Result<Run> {
+  if opts.clear && opts.interactive() {
+    print!("\x1b[2J\x1b[3J\x1b[H");
+    let _ = io::Write::flush(&mut io::stdout());
+  }
+  let tmpdir = match opts.tmpdir_per_run {
+    true => Some(process::create_tmpdir(id).map_err(|e| {
+      io::Error::new(
+        e.kind(),
+        format!("could not create its temporary directory: {e}"),
+      )
+    })?),
+    false => None,
+  };
+  // $TMPDIR is the run's own, also where the watcher expands it.
+  let command = if opts.expand_env {
+    expand::expand_with(command, true, |name| match (name, &tmpdir) {
+      ("TMPDIR", Some(dir)) => Some(dir.display().to_string()),
+      _ => std::env::var(name).ok(),
+    })
+  } else {
+    command.to_string()
+  };
+  let uuid = match opts.run_uuid {
+    true => Some(process::uuid()?),
+    false => None,
+  };
+  let _span = process::run_span(id, uuid.as_deref()).entered();
+  match slot.job.name.as_str() {
+    "" => info!("Executing: {command}"),
+    name => info!("Executing {name}: {command}"),
+  }
+  let log = run_logs.and_then(|logs| {
+    logs
+      .create(id, &slot.job.name, &command, &trigger)
+      .inspect_err(|e| warn!("Could not create the log for run {id}: {e}"))
+      .ok()
   });
-  let paths: Vec<PathBuf> = args.map(PathBuf::from).collect();
-  if paths.is_empty() {
-    eprintln!("Error: at least one directory must be given.");
-    std::process::exit(1);
+  let (log_path, log_file) = log.unzip();
+  let mut output = OutputConfig {
+    prefix: slot.prefix.clone(),
+    ..opts.output.clone()
+  };
+  if let Some(file) = log_file {
+    output.copies.push(Arc::new(Mutex::new(file)));
+  }
+  if let Some(ready::Probe::Log(pattern)) = &slot.ready {
+    output.ready_log = Some(pattern.clone());
+  }
+  let mut env = slot.job.env(id, &trigger);
+  env.push(("ROF_RUN_ID", id.to_string()));
+  match opts.tty {
+    Some(true) => env.push(("CLICOLOR_FORCE", "1".to_string())),
+    Some(false) => env.push(("NO_COLOR", "1".to_string())),
+    None => {}
+  }
+  env.extend(uuid.iter().map(|u| ("ROF_RUN_UUID", u.clone())));
+  if let Some(dir) = &tmpdir {
+    env.push(("ROF_TMPDIR", dir.display().to_string()));
+    env.push(("TMPDIR", dir.display().to_string()));
+  }
+  if opts.pair.is_some() {
+    let paths: Vec<String> = trigger.iter().map(|p| p.display().to_string()).collect();
+    env.push(("ROF_TRIGGER", paths.join("\n")));
+  }
+  env.extend(
+    slot
+      .runs
+      .message
+      .iter()
+      .map(|m| ("ROF_TRIGGER_MESSAGE", m.clone())),
+  );
+  let mut run = process::spawn(opts, &command, id, output, &slot.job.output, &env, tx)
+    .inspect_err(|_| {
+      if let Some(dir) = &tmpdir {
+        let _ = std::fs::remove_dir_all(dir);
+      }
+    })?;
+  run.uuid = uuid;
+  run.job = slot.job.name.clone();
+  run.trigger = trigger;
+  run.log = log_path;
+  run.tmpdir = tmpdir;
+  if let Some(probe) = slot
+    .ready
+    .as_ref()
+    .filter(|p| !matches!(p, ready::Probe::Log(_)))
+  {
+    run.ready = Some(ready::start(probe, id, tx.clone()));
+  }
+  Ok(run)
+}
+
+fn main() {
+  let carried = self_reload::take_carried();
+  let code = match run(carried) {
+    Ok(code) => code,
+    Err(e) => {
+      eprintln!("Error: {e}");
+      e.code()
+    }
+  };
+  logging::flush(LOG_FLUSH);
+  std::process::exit(code);
+}
+
+/// The watcher, returning its exit status; see [`exit`] for what they mean.
+fn run(carried: Vec<self_reload::Carried>) -> Result<i32, exit::Error> {
+  // ----------- Parse CLI --------------------------------------------------
+  let mut opts = cli::parse();
+  if let Err(e) = logging::init(
+    std::mem::take(&mut opts.timestamps),
+    &opts.log_targets,
+    opts.retention,
+    opts.debug_events,
+  ) {
+    return Err(Startup(format!("could not open log target: {e}")));
+  }
+  cleanup::install(opts.signal);
+  if let Some(cpus) = &opts.cpuset
+    && let Err(e) = process::cpu_set(cpus)
+  {
+    return Err(Config(format!("invalid --cpuset: {e}")));
+  }
+  if let Some((pane, _)) = &opts.tmux_send
+    && let Err(e) = tmux::check(pane)
+  {
+    return Err(Config(format!("no tmux pane {pane:?}: {e}")));
+  }
+  if opts.k8s_rollout.is_some()
+    && let Err(e) = k8s::check()
+  {
+    return Err(Startup(e));
+  }
+  if let Some(path) = &opts.tee {
+    let file = rotate::RotatingFile::open(path, opts.retention)
+      .map_err(|e| Startup(format!("could not open {}: {e}", path.display())))?;
+    opts.output.copies.push(Arc::new(Mutex::new(file)));
   }
+  if let Some(path) = &opts.output_fifo {
+    let fifo = output::OutputFifo::create(path)
+      .map_err(|e| Startup(format!("could not create FIFO {}: {e}", path.display())))?;
+    opts.output.copies.push(Arc::new(Mutex::new(fifo)));
+  }
+  let run_logs = opts
+    .log_per_run
+    .clone()
+    .map(|dir| {
+      runlog::RunLogs::new(dir.clone(), opts.retention)
+        .map_err(|e| Startup(format!("could not use {} for run logs: {e}", dir.display())))
+    })
+    .transpose()?;
+  let mut next_run_id = 0;
+  let mut paths = opts.paths.clone();
+  if opts.stdin {
+    let listed = watch::read_list(std::io::stdin().lock());
+    if listed.is_empty() {
+      return Err(Config("no paths were given on stdin.".into()));
+    }
+    paths.extend(listed);
+  }
+  let jobs = match opts.jobs.is_empty() {
+    true => vec![jobs::Job {
+      name: String::new(),
+      command: opts.command.clone(),
+      watch: paths,
+      color: None,
+      group: None,
+      needs: Vec::new(),
+      output: jobs::Output::Auto,
+      env: BTreeMap::new(),
+      ready_when: None,
+    }],
+    false => opts.jobs.clone(),
+  };
+  let jobs = match opts.group_by {
+    Some(depth) => {
+      let job = jobs.into_iter().next().expect("the command-line job");
+      jobs::group_by_dir(job, depth).map_err(Config)?
+    }
+    None => jobs,
+  };
+  let all_paths: Vec<PathBuf> = jobs.iter().flat_map(|j| j.watch.clone()).collect();
+  let new_files = opts
+    .exit_on_new_file
+    .then(|| watch::NewFileTracker::new(&all_paths));
 
   // ----------- Validate paths ---------------------------------------------
-  for p in &paths {
-    if !p.is_dir() {
-      eprintln!("Error: {:?} is not a directory.", p);
-      std::process::exit(1);
+  let mut roots = Vec::new();
+  let mut slots = Vec::new();
+  let prefixes = jobs::prefixes(&jobs, opts.interactive());
+  let needs = jobs::needs(&jobs).expect("checked when loading");
+  for ((job, prefix), needs) in jobs.into_iter().zip(prefixes).zip(needs) {
+    let mut open_roots = Vec::new();
+    let mut patterns = Vec::new();
+    for p in &job.watch {
+      match watch::interpret(p, opts.depth) {
+        Ok(spec) => {
+          match spec.pattern {
+            Some(pattern) => patterns.push(pattern),
+            None => open_roots.push(spec.root.path.clone()),
+          }
+          roots.push(spec.root);
+        }
+        Err(_) if opts.allow_missing => {
+          let spec = watch::missing(p, opts.depth);
+          match spec.pattern {
+            Some(pattern) => patterns.push(pattern),
+            None => open_roots.push(spec.root.path.clone()),
+          }
+          roots.push(spec.root);
+        }
+        Err(e) => return Err(exit::Error::Watch(e)),
+      }
     }
+    let filter = Filter::new(open_roots, &patterns, &opts)
+      .map_err(|e| Config(format!("invalid pattern: {e}")))?;
+    let ready = job
+      .ready_when
+      .as_deref()
+      .map(|check| ready::Probe::parse(check).expect("checked when loading"));
+    slots.push(Slot {
+      job,
+      filter,
+      prefix,
+      runs: Runs::default(),
+      last_event: None,
+      last_seen: None,
+      burst: 0,
+      previous_output: None,
+      needs,
+      blocked: Vec::new(),
+      changes: None,
+      lineage: Vec::new(),
+      port_wait: None,
+      hook: None,
+      writes: None,
+      ready,
+    });
   }
+  // -d also needs to see creations next to the listed files.
+  for dir in new_files.iter().flat_map(|t| t.dirs()) {
+    if !roots.iter().any(|r| &r.path == dir) {
+      roots.push(watch::Root {
+        path: dir.clone(),
+        depth: Some(0),
+        backend: watch::Backend::Native,
+      });
+    }
+  }
+  // Trigger files outside the watched trees are watched on their own.
+  let script = opts
+    .script
+    .as_ref()
+    .map(|path| script::Script::load(path).map_err(Config))
+    .transpose()?;
 
-  // ----------- File‑watcher setup -----------------------------------------
+  let trigger_files = watch::TriggerFiles::new(&opts.trigger_files, &roots);
+  let relative = opts
+    .relative_to
+    .as_deref()
+    .map(|dir| {
+      watch::Relative::new(dir).map_err(|e| Config(format!("--relative-to {}: {e}", dir.display())))
+    })
+    .transpose()?;
+  for dir in trigger_files.dirs() {
+    let covered = roots
+      .iter()
+      .filter_map(|r| r.path.canonicalize().ok())
+      .any(|root| dir.starts_with(root));
+    if !covered {
+      roots.push(watch::Root {
+        path: dir.to_path_buf(),
+        depth: Some(0),
+        backend: watch::Backend::Native,
+      });
+    }
+  }
+
+  if let Some(output) = &opts.snapshot {
+    let filters: Vec<&Filter> = slots.iter().map(|s| &s.filter).collect();
+    let snapshot = manifest::Manifest::scan(&roots, &filters, &Default::default());
+    let written = match output.as_os_str() == "-" {
+      true => snapshot.write(io::stdout().lock()),
+      false => snapshot.save(output),
+    };
+    if let Err(e) = written {
+      return Err(Startup(format!(
+        "could not write {}: {e}",
+        output.display()
+      )));
+    }
+    return Ok(0);
+  }
+
+  let mut coordinator = opts
+    .coordinate
+    .clone()
+    .map(|dir| {
+      let keys: Vec<String> = slots.iter().map(|s| s.key().to_string()).collect();
+      coordinate::Coordinator::new(dir.clone(), &keys).map_err(|e| {
+        Startup(format!(
+          "could not set up coordination in {}: {e}",
+          dir.display()
+        ))
+      })
+    })
+    .transpose()?;
+
+  let mut reflog = (!opts.on_git.is_empty())
+    .then(|| {
+      let dir = roots.first().map_or(std::path::Path::new("."), |r| &r.path);
+      git::Reflog::open(dir, opts.on_git.clone()).map_err(Startup)
+    })
+    .transpose()?;
+  let mut changes = opts
+    .describe_changes
+    .then(|| {
+      let dirs: Vec<_> = roots.iter().map(|r| r.path.clone()).collect();
+      git::Changes::new(&dirs).map_err(|e| Startup(format!("--describe-changes: {e}")))
+    })
+    .transpose()?;
+  let mut tracked = opts
+    .git_tracked_only
+    .then(|| {
+      let dirs: Vec<_> = roots.iter().map(|r| r.path.clone()).collect();
+      git::Tracked::new(&dirs).map_err(Startup)
+    })
+    .transpose()?;
+
+  if let Some(paths) = &opts.test_ignore {
+    return Ok(test_ignore(paths, &slots, tracked.as_mut()));
+  }
+
+  if opts.subreaper
+    && let Err(e) = process::become_subreaper()
+  {
+    warn!("Could not become child subreaper: {e}");
+  }
+
+  // ----------- Signal handling --------------------------------------------
   let (tx, rx) = channel();
-  let mut watcher = RecommendedWatcher::new(tx, Config::default())?;
-  for p in &paths {
-    watcher.watch(p, RecursiveMode::Recursive)?;
-    log(&format!("Watching {:?}", p));
+  if let Err(e) = signals::spawn_handler(tx.clone()) {
+    return Err(Startup(format!("could not set up signal handling: {e}")));
+  }
+
+  // Spawned threads must inherit the mask the signal thread relies on.
+  let mut listeners = Listeners {
+    plugins: opts
+      .plugins
+      .iter()
+      .map(|path| {
+        plugin::Plugin::load(path).map_err(|e| Config(format!("could not load plugin {e}")))
+      })
+      .collect::<Result<_, _>>()?,
+    dbus: opts.dbus,
+    mqtt: opts
+      .mqtt
+      .clone()
+      .map(|broker| mqtt::Publisher::start(broker, opts.mqtt_topic.clone())),
+  };
+
+  let mut recorder = opts
+    .record
+    .as_ref()
+    .map(|path| {
+      record::Recorder::create(path)
+        .map_err(|e| Startup(format!("could not record to {}: {e}", path.display())))
+    })
+    .transpose()?;
+  let _control = opts
+    .control
+    .as_ref()
+    .map(|path| {
+      control::listen(path, tx.clone())
+        .map_err(|e| Startup(format!("could not listen on {}: {e}", path.display())))
+    })
+    .transpose()?;
+  let _fifo = opts
+    .fifo
+    .as_ref()
+    .map(|path| {
+      control::fifo(path, tx.clone())
+        .map_err(|e| Startup(format!("could not create FIFO {}: {e}", path.display())))
+    })
+    .transpose()?;
+  if opts.control_stdin {
+    control::commands(tx.clone());
+  }
+
+  if !hooks::run("before_all", &opts.hooks.before_all, Vec::new()) {
+    return Err(Startup("the before_all hook failed.".into()));
+  }
+
+  // ----------- File‑watcher setup -----------------------------------------
+  let handler = |tx: Sender<Msg>| {
+    move |res| {
+      let _ = tx.send(Msg::Fs(res));
+    }
+  };
+  roots.extend(watch::override_roots(&roots, &opts.path_backends));
+  for root in &mut roots {
+    root.backend = watch::backend_for(&root.path, &opts.path_backends);
+    // Polling reports paths as given, and filters need them absolute.
+    if root.backend == watch::Backend::Poll
+      && let Ok(path) = root.path.canonicalize()
+    {
+      root.path = path;
+    }
+  }
+  let polls = roots.iter().any(|r| r.backend == watch::Backend::Poll);
+  let mut watchers = watch::Watchers {
+    native: RecommendedWatcher::new(handler(tx.clone()), opts.backend_opts.config())?,
+    poll: match polls {
+      true => Some(PollWatcher::new(
+        handler(tx.clone()),
+        match opts.poll_backoff {
+          Some(_) => opts.backend_opts.config().with_manual_polling(),
+          None => opts
+            .backend_opts
+            .config()
+            .with_poll_interval(opts.poll_interval),
+        },
+      )?),
+      false => None,
+    },
+    shards: Vec::new(),
+  };
+  if opts.watch_threads > 1 {
+    for _ in 0..opts.watch_threads {
+      let shard = RecommendedWatcher::new(handler(tx.clone()), opts.backend_opts.config())?;
+      watchers.shards.push(shard);
+    }
+  }
+  let self_exe = opts.self_reload.then(self_reload::current_exe).flatten();
+  let session = match &opts.config {
+    Some(config) => format!("--config {}", config.display()),
+    None => opts.command.clone(),
+  };
+  // Events come from the backend, rather than stdin or a recording.
+  let source: Option<Box<dyn EventSource>> = match (opts.simulate, &opts.replay) {
+    (true, _) => Some(Box::new(control::Simulate)),
+    (_, Some(path)) => Some(Box::new(record::Replay {
+      path: path.clone(),
+      speed: opts.replay_speed,
+    })),
+    _ => None,
+  };
+  let backend_live = source.is_none();
+  let backend = match &source {
+    Some(source) => source.name().to_string(),
+    _ if polls => format!(
+      "{:?}+{:?}",
+      <RecommendedWatcher as Watcher>::kind(),
+      <PollWatcher as Watcher>::kind()
+    ),
+    _ => format!("{:?}", <RecommendedWatcher as Watcher>::kind()),
+  };
+  let catch_up_roots = opts.catch_up.then(|| roots.clone());
+  let mut significance = (opts.ignore_whitespace || opts.ignore_comments).then(|| {
+    let filters: Vec<&Filter> = slots.iter().map(|s| &s.filter).collect();
+    significance::Significance::new(opts.ignore_comments, &roots, &filters)
+  });
+  // Both spellings of the roots, for events through symlinks.
+  let mut root_paths: Vec<PathBuf> = roots
+    .iter()
+    .flat_map(|r| {
+      [
+        std::path::absolute(&r.path).ok(),
+        r.path.canonicalize().ok(),
+      ]
+    })
+    .flatten()
+    .collect();
+  let mut watch_set = WatchSet::new(roots);
+  {
+    let _span = info_span!("watch_setup").entered();
+    if let Some(source) = source {
+      source
+        .start(Events::new({
+          let tx = tx.clone();
+          move |event| tx.send(Msg::Fs(Ok(event))).is_ok()
+        }))
+        .map_err(Startup)?;
+    } else {
+      let progress = (!opts.quiet).then(watch::Progress::start);
+      watch_set.setup(&mut watchers, opts.allow_missing)?;
+      if let Some(progress) = progress {
+        progress.finish();
+      }
+    }
+    if let Some(exe) = &self_exe {
+      self_reload::watch(&mut watchers.native, exe)?;
+    }
+    if let Some(reflog) = &reflog {
+      watchers
+        .native
+        .watch(reflog.dir(), notify::RecursiveMode::NonRecursive)?;
+    }
+  }
+  let watching = SystemTime::now();
+  // Polling reports a change up to an interval after the native watcher.
+  let mut duplicates = (backend_live && watch_set.overlaps(&watchers)).then(|| {
+    watch::Duplicates::new(match polls {
+      true => opts.poll_interval + DUPLICATE_WINDOW,
+      false => DUPLICATE_WINDOW,
+    })
+  });
+  let stale_window = match polls {
+    true => STALE_WINDOW.max(opts.poll_interval * 2),
+    false => STALE_WINDOW,
+  };
+
+  let livereload = opts
+    .livereload
+    .map(|port| {
+      LiveReload::start(port).map_err(|e| {
+        Startup(format!(
+          "could not start live reload server on port {port}: {e}"
+        ))
+      })
+    })
+    .transpose()?;
+
+  if let Some((dir, port)) = &opts.serve
+    && let Err(e) = serve::start(dir.clone(), *port, opts.livereload)
+  {
+    return Err(Startup(format!(
+      "could not serve {:?} on port {port}: {e}",
+      dir
+    )));
   }
 
+  let proxy = opts
+    .proxy
+    .map(|(front, backend)| {
+      proxy::Proxy::start(front, backend)
+        .map_err(|e| Startup(format!("could not start proxy on port {front}: {e}")))
+    })
+    .transpose()?;
+
   // ----------- Event loop --------------------------------------------------
-  let mut last_event: Option<Instant> = None;
-  let debounce = Duration::from_millis(8_000);
-  let mut child: Option<Child> = None;
-
-  while let Ok(event) = rx.recv() {
-    match event {
-      Ok(Event { kind, .. }) => {
-        if !matches!(
-          kind,
-          EventKind::Create(_) | EventKind::Modify(_) | EventKind::Remove(_)
-        ) {
-          continue; // ignore other kinds
-        }
-
-        // debounce
-        if let Some(t) = last_event {
-          if t.elapsed() < debounce {
+  let mut debounce = opts.min_debounce.unwrap_or(Duration::from_millis(8_000));
+  let windows = filter::Windows::new(&opts.debounce, opts.ignore_case)
+    .map_err(|e| Config(format!("invalid debounce pattern: {e}")))?;
+  let priorities = filter::Priorities::new(&opts.priorities, opts.ignore_case)
+    .map_err(|e| Config(format!("invalid priority pattern: {e}")))?;
+  let command_for = filter::Commands::new(&opts.command_for, opts.ignore_case)
+    .map_err(|e| Config(format!("invalid --command-for pattern: {e}")))?;
+  let mut timers = Timers::new();
+  // Why the loop ended early, if something did: the exit status says.
+  let mut failure = None;
+  let mut stopped_by = None;
+  if opts.allow_missing && backend_live {
+    timers.set(Timer::Recheck, RECHECK);
+  }
+  if let Some(every) = opts.every {
+    timers.set(Timer::Every, every);
+  }
+  if let Some(schedule) = &opts.cron {
+    arm_cron(schedule, &mut timers);
+  }
+  if let Some(within) = opts.within {
+    timers.set(Timer::Deadline, within);
+  }
+  if !opts.quiet_hours.is_empty() {
+    timers.set(Timer::Quiet, Duration::ZERO);
+  }
+  let mut poll_backoff = match (&watchers.poll, opts.poll_backoff) {
+    (Some(_), Some(max)) if backend_live => {
+      timers.set(Timer::Poll, opts.poll_interval);
+      Some(watch::PollBackoff::new(opts.poll_interval, max))
+    }
+    _ => None,
+  };
+  let mut renames = rename::Renames::default();
+  // Messages taken off the channel for a batch, and what was made of it.
+  let mut backlog = VecDeque::new();
+  let mut prefiltered = pipeline::Prefiltered::default();
+  let mut settling = settle::Settling::default();
+  let mut counters = stats::Counters::default();
+  let mut summary = Summary::new();
+  if let Some(dir) = &opts.state_dir {
+    match history::History::open(dir, &session, summary.started_at()) {
+      Ok(history) => summary.history = Some(history),
+      Err(e) => warn!("Could not open the run history in {}: {e}", dir.display()),
+    }
+  }
+  if opts.stats {
+    stats::report(&watch_set, &counters, &summary);
+  }
+  let mut detached: Vec<Run> = Vec::new(); // never killed, only reaped
+  let mut last_interrupt: Option<Instant> = None;
+  let mut reload = false;
+  // SIGHUP with --seamless-reload.
+  let mut hangup = false;
+  // The profile to restart with (`profile NAME` on --control-stdin).
+  let mut profile = None;
+  // The commands run so far, the latest last (`command` on --control-stdin).
+  let mut commands = vec![opts.command.clone()];
+  let mut paused: Option<Paused> = None;
+  // What `--quiet-hours` are holding back, while they last.
+  let mut quiet: Option<Paused> = None;
+  let mut status_file = opts.status_file.clone().map(status::StatusFile::new);
+
+  // Compare against the last session's manifest, now that changes from
+  // here on reach the watcher anyway.
+  let mut catch_up = catch_up_roots.and_then(|roots| {
+    let key = roots.iter().fold(session.clone(), |key, root| {
+      format!(
+        "{key}\n{}",
+        root
+          .path
+          .canonicalize()
+          .unwrap_or_else(|_| root.path.clone())
+          .display()
+      )
+    });
+    let Some(path) = manifest::state_path(&key) else {
+      warn!("No state directory (HOME is unset), --catch-up is off");
+      return None;
+    };
+    let previous = match manifest::Manifest::load(&path) {
+      Ok(previous) => Some(previous),
+      Err(e) if e.kind() == io::ErrorKind::NotFound => None,
+      Err(e) => {
+        warn!("Could not read {}: {e}", path.display());
+        None
+      }
+    };
+    let filters: Vec<&Filter> = slots.iter().map(|s| &s.filter).collect();
+    let current = manifest::Manifest::scan(
+      &roots,
+      &filters,
+      previous.as_ref().unwrap_or(&Default::default()),
+    );
+    let changed = previous.map(|p| current.changes(&p)).unwrap_or_default();
+    let due: Vec<(usize, String, Vec<PathBuf>)> = (0..slots.len())
+      .filter(|&i| changed.iter().any(|p| slots[i].filter.matches(p)))
+      .map(|i| (i, slots[i].job.command.clone(), changed.clone()))
+      .collect();
+    if !due.is_empty() {
+      let _span = info_span!("trigger").entered();
+      match changed.len() {
+        1 => info!("{:?} changed while the watcher was stopped", changed[0]),
+        n => info!("{n} files changed while the watcher was stopped"),
+      }
+      request(&mut slots, due, &opts, Priority::Normal, &mut timers);
+    }
+    Some((path, current))
+  });
+
+  if !carried.is_empty() {
+    let due: Vec<(usize, String, Vec<PathBuf>)> = carried
+      .into_iter()
+      .filter_map(|c| {
+        let i = slots.iter().position(|s| s.key() == c.job);
+        if i.is_none() {
+          warn!(
+            "Not resuming the run of {:?}: no such job since the reload",
+            c.job
+          );
+        }
+        i.map(|i| (i, slots[i].job.command.clone(), c.trigger))
+      })
+      .collect();
+    if !due.is_empty() {
+      let _span = info_span!("trigger").entered();
+      info!("Resuming {} run(s) from before the reload", due.len());
+      request(&mut slots, due, &opts, Priority::Normal, &mut timers);
+    }
+  }
+
+  'events: loop {
+    if let Some(status_file) = &mut status_file {
+      let running = slots
+        .iter()
+        .filter_map(|s| s.runs.child.as_ref())
+        .filter(|r| r.ended().is_none())
+        .map(|r| r.id)
+        .max();
+      let state = match (running, &paused, &quiet) {
+        (Some(id), _, _) => status::State::Running(id),
+        (None, Some(_), _) => status::State::Paused,
+        (None, None, Some(_)) => status::State::Quiet,
+        (None, None, None) => status::State::Idle,
+      };
+      status_file.update(state, summary.runs.last());
+    }
+    if !timers.is_set(Timer::Dropped)
+      && let Some(wait) = process::finish_dropped()
+    {
+      timers.set(Timer::Dropped, wait);
+    }
+    if !timers.is_set(Timer::Hooks)
+      && let Some(wait) = hooks::finish()
+    {
+      timers.set(Timer::Hooks, wait);
+    }
+    // Timers come first, so a steady stream of messages can't hold them up.
+    let received = match timers.pop_expired() {
+      Some(timer) => Ok(Msg::Timer(timer)),
+      None => match (backlog.pop_front(), timers.timeout()) {
+        (Some(msg), _) => Ok(msg),
+        (None, Some(timeout)) => rx.recv_timeout(timeout),
+        (None, None) => rx.recv().map_err(|_| RecvTimeoutError::Disconnected),
+      },
+    };
+    let msg = match received {
+      Ok(msg) => msg,
+      Err(RecvTimeoutError::Timeout) => continue, // popped above
+      Err(RecvTimeoutError::Disconnected) => break,
+    };
+    // A new burst: whatever else is waiting is filtered along with it.
+    if opts.filter_threads > 1 && backlog.is_empty() && matches!(msg, Msg::Fs(Ok(_))) {
+      pipeline::drain(&rx, &mut backlog);
+      let filters: Vec<&Filter> = slots.iter().map(|s| &s.filter).collect();
+      prefiltered = pipeline::prefilter(
+        std::iter::once(&msg).chain(&backlog),
+        &filters,
+        significance.as_ref(),
+        opts.filter_threads,
+      );
+    }
+    match msg {
+      Msg::Fs(Ok(event)) => {
+        if let Some(recorder) = &mut recorder {
+          recorder.record(&event);
+        }
+        debug!(
+          target: EVENTS,
+          kind = ?event.kind,
+          paths = ?event.paths,
+          flag = ?event.flag(),
+          info = event.info(),
+          source = event.source(),
+          backend,
+          "event"
+        );
+        if let Some(wait) = poll_backoff.as_mut().and_then(|b| b.changed()) {
+          debug!("polling as often as before again");
+          timers.set(Timer::Poll, wait);
+        }
+        if let Some(duplicates) = &mut duplicates
+          && duplicates.seen(&event)
+        {
+          debug!(target: EVENTS, "delivered again by another watch, ignored");
+          continue;
+        }
+        watch_set.on_event(&mut watchers, &event);
+        counters.received += 1;
+        let events = renames.feed(event);
+        if renames.waiting() && !timers.is_set(Timer::Rename) {
+          timers.set(Timer::Rename, rename::WAIT);
+        }
+        for Event { kind, paths, .. } in events.into_iter().filter_map(editor::normalize) {
+          if let Some(path) = new_files.as_ref().and_then(|t| t.new_file(&kind, &paths)) {
+            info!(
+              "New file {:?}, exiting so the file list can be rebuilt",
+              path
+            );
+            break 'events;
+          }
+          if let Some(exe) = &self_exe
+            && self_reload::is_replaced(exe, &kind, &paths)
+          {
+            debug!(target: EVENTS, "watcher binary written, waiting for it to settle");
+            timers.set(Timer::Reload, self_reload::SETTLE);
             continue;
           }
-        }
-        last_event = Some(Instant::now());
-        log("File change detected");
-
-        // Kill previous run if still alive
-        if let Some(mut c) = child.take() {
-          let pgid = -(c.id() as i32); // negative ⇒ process‑group id
-          kill(Pid::from_raw(pgid), SIGTERM).ok(); // politely ask entire group
-          let _ = c.wait(); // reap the leader
-          std::thread::sleep(Duration::from_millis(700)); // TIME_WAIT drain
-        }
-
-        // Spawn new run
-        log(&format!("Executing: {cmd_string}"));
-        let tmp_child = unsafe {
-          // <- acknowledge the unsafety
-          Command::new("/bin/sh")
-            .arg("-c")
-            .arg(&cmd_string)
-            .stdout(Stdio::inherit())
-            .stderr(Stdio::inherit())
-            .pre_exec(|| {
-              // SAFETY: setpgid is async‑signal‑safe and we do nothing else here
-              unsafe { libc::setpgid(0, 0) };
-              Ok(())
+          if backend_live
+            && watching.elapsed().is_ok_and(|t| t < stale_window)
+            && stale(&kind, &paths, watching)
+          {
+            debug!(target: EVENTS, "file older than the watches, ignored");
+            continue;
+          }
+          let forced = trigger_files.find(&paths);
+          // The paths as handed on, which --relative-to may respell.
+          let shown = match &relative {
+            Some(relative) => relative.report_all(&paths),
+            None => paths.clone(),
+          };
+          if let Some(file) = &forced {
+            if !file.is_file() {
+              continue; // removed, likely by us
+            }
+            if let Err(e) = std::fs::remove_file(file) {
+              warn!("Could not remove trigger file {:?}: {e}", file);
+            }
+          }
+          let git_entries = reflog
+            .as_mut()
+            .filter(|r| paths.iter().any(|p| r.is_reflog(p)))
+            .map(|r| r.read_new());
+          let every_job = (0..slots.len()).collect();
+          // Commands the script picked instead of the jobs' own.
+          let mut scripted = BTreeMap::new();
+          // Jobs whose writes led to the change, by the job it triggers.
+          let mut chains: BTreeMap<usize, Vec<usize>> = BTreeMap::new();
+          let (git_cause, targets): (_, Vec<usize>) = match git_entries {
+            Some(entries) => {
+              let Some((event, message)) = entries.into_iter().last() else {
+                continue; // an event that wasn't asked for
+              };
+              (Some((event, message)), every_job)
+            }
+            None if forced.is_some() => (None, every_job),
+            None => {
+              if !matches!(
+                kind,
+                EventKind::Create(_) | EventKind::Modify(_) | EventKind::Remove(_)
+              ) {
+                debug!(target: EVENTS, "not a creation, change or removal, ignored");
+                continue;
+              }
+              if !opts.include_metadata && metadata_only(&kind, &paths) {
+                debug!(target: EVENTS, "only metadata changed, ignored");
+                continue;
+              }
+              let mut targets: Vec<usize> = (0..slots.len())
+                .filter(|&i| !slots[i].job.watch.is_empty())
+                .filter(|&i| {
+                  paths
+                    .iter()
+                    .any(|p| prefiltered.matches(i, &slots[i].filter, p))
+                })
+                .collect();
+              if targets.is_empty() {
+                debug!(target: EVENTS, "filtered out, ignored");
+                continue;
+              }
+              if let Some(tracked) = &mut tracked
+                && !paths.iter().any(|p| tracked.contains(p))
+              {
+                debug!(target: EVENTS, "not tracked by git, ignored");
+                continue;
+              }
+              if opts.report_writes {
+                for &i in &targets {
+                  let slot = &mut slots[i];
+                  if slot.runs.writing()
+                    && let Some(writes) = &mut slot.writes
+                  {
+                    for path in paths.iter().filter(|p| slot.filter.matches(p)) {
+                      writes.record(path);
+                    }
+                  }
+                }
+              }
+              targets.retain(|&i| {
+                let slot = &mut slots[i];
+                let own =
+                  opts.ignore_during_run || paths.iter().all(|p| slot.filter.written_by_command(p));
+                !(own && slot.runs.writing())
+              });
+              if targets.is_empty() {
+                debug!(target: EVENTS, "written while the command ran, ignored");
+                continue;
+              }
+              // Other jobs still writing are taken to have made the change.
+              if !opts.allow_cycles && slots.len() > 1 {
+                let writers: Vec<usize> = (0..slots.len())
+                  .filter(|&j| slots[j].runs.writing())
+                  .collect();
+                for &i in &targets {
+                  let chain = writers
+                    .iter()
+                    .filter(|&&j| j != i)
+                    .flat_map(|&j| slots[j].lineage.iter().copied().chain([j]))
+                    .collect();
+                  chains.insert(i, chain);
+                }
+              }
+              if let Some(wait) = opts.settle
+                && !settling.admit(&paths)
+              {
+                debug!(target: EVENTS, "waiting for the file to settle");
+                if !timers.is_set(Timer::Settle) {
+                  timers.set(Timer::Settle, wait);
+                }
+                continue;
+              }
+              if let Some(significance) = &mut significance
+                && !significance.significant(&paths, &prefiltered.hashes)
+              {
+                debug!(target: EVENTS, "only whitespace or comments changed, ignored");
+                continue;
+              }
+              if let Some(script) = &script {
+                targets.retain(|&i| match script.judge(&slots[i].job.name, &kind, &shown) {
+                  script::Verdict::Skip => false,
+                  script::Verdict::Run => true,
+                  script::Verdict::Command(command) => {
+                    scripted.insert(i, command);
+                    true
+                  }
+                });
+                if targets.is_empty() {
+                  debug!(target: EVENTS, "turned down by the script, ignored");
+                  continue;
+                }
+              }
+              if !listeners.plugins.is_empty() {
+                let kind = watch::kind_name(&kind);
+                targets.retain(|&i| {
+                  listeners
+                    .plugins
+                    .iter_mut()
+                    .all(|p| p.admits(&slots[i].job.name, kind, &shown))
+                });
+                if targets.is_empty() {
+                  debug!(target: EVENTS, "turned down by a plugin, ignored");
+                  continue;
+                }
+              }
+              (None, targets)
+            }
+          };
+
+          // A branch switch takes over from the edits git made to get there.
+          let checkout = matches!(git_cause, Some((git::GitEvent::Checkout, _)));
+          let overridden = command_for.of(&paths);
+
+          counters.matched += 1;
+
+          if let Some(p) = paused.as_mut().or(quiet.as_mut()) {
+            debug!(target: EVENTS, "paused, ignored");
+            p.changes += 1;
+            for i in targets {
+              let command = scripted
+                .remove(&i)
+                .unwrap_or_else(|| slots[i].command(&opts, checkout, overridden));
+              p.missed.insert(i, (command, shown.clone()));
+            }
+            continue;
+          }
+
+          // debounce, per job
+          let window = windows.window(&paths, debounce);
+          let priority = priorities.of(&paths);
+          let urgent = checkout || forced.is_some() || priority == Priority::High;
+          let mut due: Vec<usize> = targets
+            .into_iter()
+            .filter(|&i| urgent || slots[i].outside_window(window, opts.max_debounce))
+            .collect();
+          if due.is_empty() {
+            debug!(target: EVENTS, "within debounce window, ignored");
+            continue;
+          }
+          due.retain(|i| {
+            let Some(chain) = chains.get(i) else {
+              return true;
+            };
+            let laps = chain.iter().filter(|&j| j == i).count();
+            let names = || {
+              let names: Vec<&str> = chain
+                .iter()
+                .chain([i])
+                .map(|&j| slots[j].job.name.as_str())
+                .collect();
+              names.join(" → ")
+            };
+            match laps {
+              0 => {}
+              1 => warn!(
+                "Jobs trigger each other in a cycle: {} (broken after {MAX_LAPS} rounds unless --allow-cycles)",
+                names()
+              ),
+              _ if laps >= MAX_LAPS => {
+                warn!(
+                  "Not running {}: jobs keep triggering each other ({})",
+                  slots[*i].job.name,
+                  names()
+                );
+                slots[*i].last_event = Some(Instant::now()); // and the rest of the write
+                return false;
+              }
+              _ => {}
+            }
+            true
+          });
+          if due.is_empty() {
+            debug!(target: EVENTS, "cycle of jobs broken, ignored");
+            continue;
+          }
+          debug!(target: EVENTS, jobs = ?due, ?priority, "triggers");
+          counters.triggered += 1;
+          let _span = info_span!("trigger").entered();
+          match (git_cause, &forced) {
+            (_, Some(file)) => info!("Run requested through {:?}", file),
+            (Some((event, message)), None) => info!("Git {event}: {message}"),
+            (None, None)
+              if kind == EventKind::Modify(ModifyKind::Name(RenameMode::Both))
+                && paths.len() == 2 =>
+            {
+              info!("File renamed: {:?} -> {:?}", shown[0], shown[1])
+            }
+            (None, None) => info!("File change detected"),
+          }
+          let described = changes.as_mut().and_then(|c| c.since_last());
+          if let Some(stat) = &described {
+            info!("{}: {}", stat.summary(), stat.details(5));
+          }
+
+          // Stop the previous runs; the new ones start once they are gone.
+          if let Some(proxy) = &proxy
+            && priority != Priority::Low
+          {
+            proxy.hold();
+          }
+          if let Some((_, manifest)) = &mut catch_up {
+            manifest.update(&paths);
+          }
+          let due = due
+            .into_iter()
+            .map(|i| {
+              slots[i].last_event = Some(Instant::now());
+              let command = scripted
+                .remove(&i)
+                .unwrap_or_else(|| slots[i].command(&opts, checkout, overridden));
+              slots[i].changes = described.as_ref().map(git::DiffStat::summary);
+              slots[i].lineage = chains.remove(&i).unwrap_or_default();
+              (i, command, shown.clone())
             })
-            .spawn()
-        }?; //  <- keep the Result from spawn()
-        child = Some(tmp_child);
+            .collect();
+          request(&mut slots, due, &opts, priority, &mut timers);
+        }
+      }
+      Msg::Timer(Timer::Rename) => {
+        if let Some(event) = renames.expire() {
+          let _ = tx.send(Msg::Fs(Ok(event))); // through the usual path, alone
+        }
+      }
+      Msg::Timer(Timer::Settle) => {
+        if let Some(event) = settling.check() {
+          let _ = tx.send(Msg::Fs(Ok(event))); // settled, trigger for real
+        }
+        if let Some(wait) = opts.settle
+          && settling.waiting()
+        {
+          timers.set(Timer::Settle, wait);
+        }
+      }
+      Msg::Timer(Timer::Cancel(i)) => {
+        if slots[i].runs.pending.is_some() {
+          slots[i].runs.cancel(i, opts.signal, &mut timers);
+        }
       }
-      Err(e) => log(&format!("Watcher error: {e:?}")),
+      Msg::Timer(Timer::Spawn(i)) => {
+        // Jobs of the group stopped to make way have to be gone first.
+        let group = slots[i].job.group.as_ref();
+        let waiting_for_group = group.is_some()
+          && slots
+            .iter()
+            .enumerate()
+            .any(|(j, s)| j != i && s.job.group.as_ref() == group && s.runs.stopping.is_some());
+        if waiting_for_group {
+          timers.set(Timer::Spawn(i), RESTART_POLL);
+          continue;
+        }
+        if slots[i].runs.stopping.is_some() && !leaders_pending(&mut slots, &mut detached) {
+          process::reap_descendants();
+        }
+        let slot = &mut slots[i];
+        if let Some(s) = &slot.runs.stopping {
+          let busy = opts.wait_ports.iter().find(|&&p| !process::port_free(p));
+          let ready = s.group_gone() && busy.is_none();
+          if !ready && s.since_exit() < RESTART_LIMIT {
+            timers.set(Timer::Spawn(i), RESTART_POLL);
+            continue;
+          }
+          if !ready {
+            match busy {
+              Some(&port) => warn!("{}, starting anyway", process::describe_port(port)),
+              None => warn!("The previous run's process group is still alive, starting anyway"),
+            }
+          }
+          debug!(
+            waited_ms = s.since_exit().as_millis() as u64,
+            "previous run gone"
+          );
+        }
+        if let Some(s) = slot.runs.stopping.take() {
+          record_stopped(&s, &mut summary);
+        }
+        if !slot.blocked.is_empty() {
+          debug!(job = %slot.job.name, "waiting for the jobs it needs");
+          continue;
+        }
+        let Some((command, trigger)) = slot.runs.pending.take() else {
+          continue;
+        };
+        if let Some(coordinator) = &mut coordinator
+          && !coordinator.leads(slot.key())
+        {
+          // The dependents get to ask for their own locks.
+          resolve_needs(&mut slots, i, true, &mut timers);
+          continue;
+        }
+        if let Some(&port) = opts.expect_ports.iter().find(|&&p| !process::port_free(p)) {
+          let since = *slot.port_wait.get_or_insert_with(|| {
+            warn!(
+              "{}, waiting for it to be free",
+              process::describe_port(port)
+            );
+            Instant::now()
+          });
+          if since.elapsed() < RESTART_LIMIT {
+            slot.runs.pending = Some((command, trigger));
+            timers.set(Timer::Spawn(i), RESTART_POLL);
+            continue;
+          }
+          slot.port_wait = None;
+          let message = format!("{}; not starting {command}", process::describe_port(port));
+          if opts.exit_on_spawn_error {
+            failure = Some(exit::Error::Spawn(message));
+            break 'events;
+          }
+          error!("{message}");
+          notification::send(&opts.notify, false, &message);
+          resolve_needs(&mut slots, i, false, &mut timers);
+          continue;
+        }
+        slot.port_wait = None;
+        if opts.paranoid
+          && let Err(e) = paranoid::check(&opts, &command)
+        {
+          let message = format!("{e}; not starting {command}");
+          if opts.exit_on_spawn_error {
+            failure = Some(exit::Error::Spawn(message));
+            break 'events;
+          }
+          error!("{message}");
+          notification::send(&opts.notify, false, &message);
+          resolve_needs(&mut slots, i, false, &mut timers);
+          continue;
+        }
+        // The before_each hook runs first, polled here until it is over.
+        let id = match slot.hook.as_mut().map(|(id, hook)| (*id, hook.poll())) {
+          Some((_, None)) => {
+            slot.runs.pending = Some((command, trigger));
+            timers.set(Timer::Spawn(i), HOOK_POLL);
+            continue;
+          }
+          Some((id, Some(success))) => {
+            slot.hook = None;
+            if !success {
+              warn!("Run skipped as the before_each hook failed");
+              resolve_needs(&mut slots, i, false, &mut timers);
+              continue;
+            }
+            id
+          }
+          None => {
+            next_run_id += 1;
+            let env = hooks::run_env(&slot.job.name, &command, next_run_id, &trigger);
+            match hooks::start("before_each", &opts.hooks.before_each, env) {
+              Ok(Some(hook)) => {
+                slot.hook = Some((next_run_id, hook));
+                slot.runs.pending = Some((command, trigger));
+                timers.set(Timer::Spawn(i), HOOK_POLL);
+                continue;
+              }
+              Ok(None) => next_run_id,
+              Err(e) => {
+                warn!("Run skipped as the before_each hook could not start: {e}");
+                resolve_needs(&mut slots, i, false, &mut timers);
+                continue;
+              }
+            }
+          }
+        };
+        let slot = &mut slots[i];
+        let new_child = start(&opts, id, slot, &command, trigger, run_logs.as_ref(), &tx);
+        let mut new_child = match new_child {
+          Ok(run) => run,
+          Err(e) if opts.exit_on_spawn_error => {
+            failure = Some(exit::Error::Spawn(format!(
+              "could not start {command}: {e}"
+            )));
+            break 'events;
+          }
+          Err(e) => {
+            let message = format!("Could not start {command}: {e}");
+            error!("{message}; waiting for the next change");
+            notification::send(&opts.notify, false, &message);
+            resolve_needs(&mut slots, i, false, &mut timers);
+            continue;
+          }
+        };
+        if opts.report_writes {
+          timers.cancel(Timer::Writes(i));
+          report_writes(slot, &root_paths);
+          slot.writes = Some(writes::Writes::new(id, &command));
+        }
+        new_child.changes = slot.changes.take();
+        new_child.follow_up = std::mem::take(&mut slot.runs.follow_up);
+        listeners.started(&slot.job.name, &new_child);
+        if opts.detach {
+          detached.retain_mut(process::is_running); // reap finished ones
+          detached.push(new_child);
+        } else {
+          slot.runs.child = Some(new_child);
+        }
+      }
+      Msg::Timer(Timer::Quiet) => {
+        let now = chrono::Local::now();
+        timers.set(Timer::Quiet, cron::until_change(&opts.quiet_hours, now));
+        match (cron::quiet(&opts.quiet_hours, now), quiet.take()) {
+          (true, None) => {
+            info!("Quiet hours: changes wait until they are over");
+            notification::mute(opts.quiet_hours_mute);
+            quiet = Some(Paused::default());
+          }
+          (true, Some(q)) => quiet = Some(q),
+          (false, None) => {}
+          (false, Some(q)) => {
+            info!("Quiet hours over ({} matching events meanwhile)", q.changes);
+            notification::mute(false);
+            if !q.missed.is_empty() {
+              let _span = info_span!("trigger").entered();
+              info!("Running for the changes made in quiet hours");
+              counters.triggered += 1;
+              if let Some(proxy) = &proxy {
+                proxy.hold();
+              }
+              let due = q
+                .missed
+                .into_iter()
+                .map(|(i, (command, trigger))| {
+                  slots[i].last_event = Some(Instant::now());
+                  (i, command, trigger)
+                })
+                .collect();
+              request(&mut slots, due, &opts, Priority::Normal, &mut timers);
+            }
+          }
+        }
+      }
+      Msg::Timer(timer @ (Timer::Every | Timer::Cron)) => {
+        match (timer, opts.every, &opts.cron) {
+          (Timer::Every, Some(every), _) => timers.set(Timer::Every, every),
+          (_, _, Some(schedule)) => arm_cron(schedule, &mut timers),
+          _ => {}
+        }
+        if paused.is_some() || quiet.is_some() {
+          debug!(target: EVENTS, "paused, scheduled run skipped");
+          continue;
+        }
+        let due: Vec<usize> = (0..slots.len())
+          .filter(|&i| !slots[i].runs.busy(i, &timers))
+          .collect();
+        if due.is_empty() {
+          debug!(target: EVENTS, "previous run still going, scheduled run skipped");
+          continue;
+        }
+        let _span = info_span!("trigger").entered();
+        info!("Scheduled run");
+        if let Some(proxy) = &proxy {
+          proxy.hold();
+        }
+        let due = due
+          .into_iter()
+          .map(|i| (i, slots[i].job.command.clone(), Vec::new()))
+          .collect();
+        request(&mut slots, due, &opts, Priority::Normal, &mut timers);
+      }
+      Msg::Timer(Timer::Deadline) => {
+        warn!(
+          "No run exited with code {} within {:.1}s",
+          opts.expect_exit.unwrap_or(0),
+          opts.within.unwrap_or_default().as_secs_f64()
+        );
+        break;
+      }
+      Msg::Timer(Timer::Reload) => {
+        reload = true;
+        break;
+      }
+      Msg::Fs(Err(e)) => {
+        if backend_live
+          && let Some(wait) = watch_set.on_error(&e)
+          && !timers.is_set(Timer::Rewatch)
+        {
+          timers.set(Timer::Rewatch, wait);
+        }
+      }
+      Msg::Timer(Timer::Recheck) => {
+        if watch_set.recheck(&mut watchers) {
+          timers.set(Timer::Recheck, RECHECK);
+        }
+      }
+      Msg::Timer(Timer::Writes(i)) => report_writes(&mut slots[i], &root_paths),
+      Msg::Timer(Timer::Dropped | Timer::Hooks) => {} // looked at before the next message
+      Msg::Timer(Timer::Poll) => {
+        if let (Some(poll), Some(backoff)) = (&watchers.poll, &mut poll_backoff) {
+          if let Err(e) = poll.poll() {
+            warn!("Could not poll: {e}");
+          }
+          timers.set(Timer::Poll, backoff.next());
+        }
+      }
+      Msg::Timer(Timer::Rewatch) => {
+        let (next, persistent) = watch_set.retry(&mut watchers);
+        if let Some(wait) = next {
+          timers.set(Timer::Rewatch, wait);
+        }
+        for root in persistent {
+          let message = format!("No longer watching {}", root.display());
+          notification::send(&opts.notify, false, &message);
+        }
+      }
+      Msg::OutputClosed(id) => {
+        let mut finished = Vec::new();
+        for (i, slot) in slots.iter_mut().enumerate() {
+          if let Some(run) = slot.runs.child.as_mut().filter(|r| r.id == id) {
+            run.output_closed();
+            finished.extend(
+              complete(
+                &slot.job.name,
+                run,
+                &opts,
+                &mut summary,
+                livereload.as_ref(),
+                &mut slot.previous_output,
+                &mut listeners,
+              )
+              .map(|success| (i, success)),
+            );
+          }
+        }
+        let any_finished = !finished.is_empty();
+        for (i, success) in finished {
+          follow_up(&mut slots, i, success, &opts, &mut timers);
+          if opts.report_writes {
+            timers.set(Timer::Writes(i), WRITE_WINDOW);
+          }
+        }
+        if any_finished && done(&opts, &summary) {
+          break 'events;
+        }
+      }
+      Msg::Ready(id) => {
+        let job = slots.iter().position(|slot| {
+          slot
+            .runs
+            .child
+            .as_ref()
+            .is_some_and(|r| r.id == id && r.ready_after.is_none())
+        });
+        if let Some(i) = job {
+          let run = slots[i].runs.child.as_mut().expect("found above");
+          let after = run.started.elapsed();
+          run.ready_after = Some(after);
+          run.ready = None;
+          info!(
+            job = %run.job,
+            ready_ms = after.as_millis() as u64,
+            "Ready in {:.1}s",
+            after.as_secs_f64()
+          );
+          if let Some(proxy) = &proxy {
+            proxy.release();
+          }
+          if let Some(livereload) = &livereload {
+            livereload.reload();
+          }
+          if slots[i].ready.is_some() {
+            resolve_needs(&mut slots, i, true, &mut timers);
+          }
+        }
+      }
+      Msg::MemoryExceeded(id, rss_kb) => {
+        let run = slots
+          .iter()
+          .find_map(|slot| slot.runs.child.as_ref().filter(|r| r.id == id));
+        if let Some(run) = run {
+          let _span = run.span().entered();
+          let message = format!(
+            "Killing {}: it uses {} MB, over the {} MB limit",
+            run.command,
+            rss_kb / 1024,
+            opts.rss_limit_kb.unwrap_or(0) / 1024
+          );
+          warn!(job = %run.job, rss_kb, "{message}");
+          notification::send(&opts.notify, false, &message);
+          process::signal_group(run, Signal::SIGKILL);
+        }
+      }
+      Msg::Signal(Signal::SIGCHLD) => {
+        let mut finished = Vec::new();
+        for (i, slot) in slots.iter_mut().enumerate() {
+          if let Some(run) = slot.runs.child.as_mut() {
+            finished.extend(
+              complete(
+                &slot.job.name,
+                run,
+                &opts,
+                &mut summary,
+                livereload.as_ref(),
+                &mut slot.previous_output,
+                &mut listeners,
+              )
+              .map(|success| (i, success)),
+            );
+          }
+          if let Some(s) = slot.runs.stopping.as_mut()
+            && !timers.is_set(Timer::Spawn(i))
+            && s.poll()
+          {
+            timers.set(Timer::Spawn(i), Duration::ZERO);
+          }
+          if let Some((_, hook)) = slot.hook.as_mut()
+            && hook.poll().is_some()
+          {
+            timers.set(Timer::Spawn(i), Duration::ZERO);
+          }
+        }
+        detached.retain_mut(process::is_running); // reap finished ones
+        let any_finished = !finished.is_empty();
+        for (i, success) in finished {
+          follow_up(&mut slots, i, success, &opts, &mut timers);
+          if opts.report_writes {
+            timers.set(Timer::Writes(i), WRITE_WINDOW);
+          }
+        }
+        if any_finished && done(&opts, &summary) {
+          break 'events;
+        }
+      }
+      Msg::Signal(Signal::SIGINT) => {
+        // First Ctrl-C goes to the running commands, a quick second one quits.
+        let repeated = last_interrupt.is_some_and(|t| t.elapsed() < QUIT_WINDOW);
+        last_interrupt = Some(Instant::now());
+        let running: Vec<&Run> = slots
+          .iter_mut()
+          .filter_map(|slot| slot.runs.child.as_mut())
+          .filter_map(|c| process::is_running(c).then_some(&*c))
+          .collect();
+        if running.is_empty() || repeated {
+          stopped_by = Some(Signal::SIGINT);
+          break;
+        }
+        for c in running {
+          process::signal_group(c, Signal::SIGINT);
+        }
+        info!("Forwarded SIGINT to the running command (Ctrl-C again to quit)");
+      }
+      Msg::Signal(Signal::SIGUSR1) => stats::report(&watch_set, &counters, &summary),
+      msg @ (Msg::Control(control::Command::Trigger) | Msg::Fifo(_)) => {
+        let _span = info_span!("trigger").entered();
+        let message = match msg {
+          Msg::Fifo(line) => {
+            info!("Run requested through the FIFO: {line:?}");
+            Some(line)
+          }
+          _ => {
+            info!("Run requested on stdin");
+            None
+          }
+        };
+        counters.triggered += 1;
+        if let Some(proxy) = &proxy {
+          proxy.hold();
+        }
+        let due = (0..slots.len())
+          .map(|i| {
+            slots[i].last_event = Some(Instant::now());
+            (i, slots[i].job.command.clone(), Vec::new())
+          })
+          .collect();
+        request(&mut slots, due, &opts, Priority::Normal, &mut timers);
+        for slot in &mut slots {
+          slot.runs.message.clone_from(&message);
+        }
+      }
+      Msg::Control(control::Command::Profile(name)) => {
+        if opts.config.is_none() {
+          warn!("Ignoring profile {name:?}: there are profiles only with --config");
+        } else if !opts.profiles.contains(&name) {
+          warn!("Ignoring unknown profile {name:?}");
+        } else if opts.profile.as_ref() != Some(&name) {
+          profile = Some(name);
+          break;
+        }
+      }
+      Msg::Control(control::Command::Quit) => {
+        info!("Quit requested on stdin");
+        break;
+      }
+      Msg::Control(control::Command::SetDebounce(window)) => {
+        info!("Debounce window set to {}ms", window.as_millis());
+        debounce = window;
+      }
+      Msg::Control(control::Command::Watch(change)) => {
+        let result = match (slots.as_mut_slice(), backend_live) {
+          (_, false) => Err("events come from elsewhere than the filesystem".to_string()),
+          ([slot], true) => rewatch(
+            &change,
+            &mut slot.filter,
+            &mut watch_set,
+            &mut watchers,
+            &mut root_paths,
+            &opts,
+          ),
+          _ => Err("with --config the watches are the jobs'".to_string()),
+        };
+        prefiltered = pipeline::Prefiltered::default(); // worked out with the old filter
+        change.answer(result);
+      }
+      Msg::Control(control::Command::Commands) => {
+        for (n, command) in commands.iter().enumerate() {
+          info!("!{}  {command}", n + 1);
+        }
+      }
+      Msg::Control(control::Command::Replace(command)) => {
+        if opts.config.is_some() {
+          warn!("Ignoring command {command:?}: with --config the commands are the jobs'");
+          continue;
+        }
+        let command = match command.strip_prefix('!').map(str::parse::<usize>) {
+          Some(Ok(n)) if (1..=commands.len()).contains(&n) => commands.remove(n - 1),
+          Some(_) => {
+            warn!("Ignoring {command:?}: `command` lists the commands to go back to");
+            continue;
+          }
+          None => command,
+        };
+        commands.retain(|c| *c != command);
+        commands.push(command.clone());
+        info!("Command is now {command:?}");
+        let _span = info_span!("trigger").entered();
+        counters.triggered += 1;
+        slots[0].job.command = command.clone();
+        slots[0].last_event = Some(Instant::now());
+        request(
+          &mut slots,
+          vec![(0, command, Vec::new())],
+          &opts,
+          Priority::Normal,
+          &mut timers,
+        );
+      }
+      msg @ (Msg::Signal(Signal::SIGUSR2)
+      | Msg::Control(control::Command::Pause | control::Command::Resume)) => {
+        let pause = match msg {
+          Msg::Control(control::Command::Pause) => true,
+          Msg::Control(control::Command::Resume) => false,
+          _ => paused.is_none(), // SIGUSR2 toggles
+        };
+        match (pause, paused.take()) {
+          (true, None) => {
+            match msg {
+              Msg::Signal(_) => info!("Paused, send SIGUSR2 again to resume"),
+              _ => info!("Paused"),
+            }
+            paused = Some(Paused::default());
+          }
+          (true, Some(p)) => paused = Some(p),
+          (false, None) => {}
+          (false, Some(p)) => {
+            info!("Resumed ({} matching events while paused)", p.changes);
+            if opts.run_on_resume && !p.missed.is_empty() {
+              let _span = info_span!("trigger").entered();
+              info!("Running for the changes made while paused");
+              counters.triggered += 1;
+              if let Some(proxy) = &proxy {
+                proxy.hold();
+              }
+              let due = p
+                .missed
+                .into_iter()
+                .map(|(i, (command, trigger))| {
+                  slots[i].last_event = Some(Instant::now());
+                  (i, command, trigger)
+                })
+                .collect();
+              request(&mut slots, due, &opts, Priority::Normal, &mut timers);
+            }
+          }
+        }
+      }
+      Msg::Signal(Signal::SIGHUP) if opts.seamless_reload => {
+        info!("Received SIGHUP, reloading");
+        hangup = true;
+        break;
+      }
+      Msg::Signal(sig) => {
+        info!("Received {sig}");
+        stopped_by = Some(sig);
+        break;
+      }
+    }
+  }
+
+  if let Some((path, manifest)) = &catch_up
+    && let Err(e) = manifest.save(path)
+  {
+    warn!(
+      "Could not save the file manifest to {}: {e}",
+      path.display()
+    );
+  }
+  let restart = match &profile {
+    Some(_) => self_reload::current_exe(),
+    None if reload => self_exe.clone(),
+    None if hangup => self_reload::current_exe(),
+    None => None,
+  };
+  if let Some(exe) = restart {
+    match &profile {
+      Some(name) => info!("Switching to profile {name}"),
+      None if hangup => {}
+      None => info!("Watcher binary changed, reloading"),
     }
+    let carried = match opts.seamless_reload {
+      true => carry(&mut slots),
+      false => Vec::new(),
+    };
+    stop_all(&mut slots, &opts, &mut summary);
+    cleanup::restore_terminal(false);
+    drop(watchers);
+    let err = match &profile {
+      Some(name) => self_reload::exec_profile(&exe, name, &carried),
+      None => self_reload::exec(&exe, &carried),
+    };
+    warn!("Could not re-exec {}: {err}", exe.display());
+  }
+
+  if opts.stats {
+    stats::report(&watch_set, &counters, &summary);
+  }
+  info!("Shutting down");
+  stop_all(&mut slots, &opts, &mut summary);
+  cleanup::restore_terminal(false);
+  for slot in &mut slots {
+    slot.hook = None; // killed: its run won't start now
+  }
+  hooks::wait();
+
+  let failures = summary
+    .runs
+    .iter()
+    .filter(|r| !r.stopped && r.exit_code != Some(0))
+    .count();
+  hooks::run(
+    "after_all",
+    &opts.hooks.after_all,
+    vec![
+      ("ROF_RUNS", summary.runs.len().to_string()),
+      ("ROF_FAILURES", failures.to_string()),
+    ],
+  );
+  if let Some(hook) = &opts.on_exit {
+    process::run_hook("exit", hook, &[], opts.on_exit_timeout);
+  }
+  if let Some(path) = &opts.summary_file
+    && let Err(e) = summary.write(path, &session)
+  {
+    warn!("Could not write summary to {}: {e}", path.display());
+  }
+  if let Some(e) = failure {
+    return Err(e);
+  }
+  if opts.expect_exit.is_some() && !expectation_met(&opts, &summary) {
+    return Ok(exit::EXPECTATION_FAILED);
   }
-  Ok(())
+  Ok(stopped_by.map_or(0, exit::signaled))
 }
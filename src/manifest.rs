@@ -0,0 +1,206 @@
+// A record of the watched files, with their modification time, size and
+// content hash, kept between sessions by `--catch-up` to find what changed
+// while the watcher wasn't running.
+
+use crate::filter::Filter;
+use crate::watch::Root;
+use serde::{Deserialize, Serialize};
+use std::{
+  collections::BTreeMap,
+  env, fs,
+  io::{self, Write},
+  path::{Path, PathBuf},
+  time::UNIX_EPOCH,
+};
+
+#[derive(Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Entry {
+  /// Nanoseconds since the epoch.
+  pub mtime_ns: u64,
+  pub size: u64,
+  pub sha1: String,
+}
+
+#[derive(Default, Serialize, Deserialize)]
+pub struct Manifest {
+  pub files: BTreeMap<PathBuf, Entry>,
+}
+
+/// The entry for `path` as it is now, or `None` if it isn't a readable
+/// regular file. The hash is taken from `known` when time and size match.
+fn entry(path: &Path, known: Option<&Entry>) -> Option<Entry> {
+  let meta = fs::symlink_metadata(path).ok()?;
+  if !meta.is_file() {
+    return None;
+  }
+  let mtime_ns = meta
+    .modified()
+    .ok()?
+    .duration_since(UNIX_EPOCH)
+    .ok()?
+    .as_nanos() as u64;
+  let size = meta.len();
+  if let Some(known) = known.filter(|k| k.mtime_ns == mtime_ns && k.size == size) {
+    return Some(known.clone());
+  }
+  let sha1 = sha1_smol::Sha1::from(fs::read(path).ok()?)
+    .digest()
+    .to_string();
+  Some(Entry {
+    mtime_ns,
+    size,
+    sha1,
+  })
+}
+
+/// Calls `visit` for each file below `roots` that some filter lets
+/// through, skipping the directories every filter prunes.
+pub fn walk(roots: &[Root], filters: &[&Filter], mut visit: impl FnMut(PathBuf)) {
+  fn walk_dir(
+    dir: &Path,
+    levels: Option<usize>,
+    filters: &[&Filter],
+    visit: &mut impl FnMut(PathBuf),
+  ) {
+    let Ok(entries) = fs::read_dir(dir) else {
+      return;
+    };
+    for item in entries.flatten() {
+      let path = item.path();
+      let Ok(kind) = item.file_type() else {
+        continue;
+      };
+      if kind.is_dir() {
+        if levels != Some(0) && !filters.iter().all(|f| f.prunes(&path)) {
+          walk_dir(&path, levels.map(|l| l - 1), filters, visit);
+        }
+      } else if filters.iter().any(|f| f.matches(&path)) {
+        visit(path);
+      }
+    }
+  }
+  for root in roots {
+    let dir = root
+      .path
+      .canonicalize()
+      .unwrap_or_else(|_| root.path.clone());
+    walk_dir(&dir, root.depth, filters, &mut visit);
+  }
+}
+
+impl Manifest {
+  /// Walks `roots`, taking in the files some filter lets through and
+  /// skipping the directories every filter prunes. Hashes already in
+  /// `known` are reused for files whose time and size are unchanged.
+  pub fn scan(roots: &[Root], filters: &[&Filter], known: &Manifest) -> Manifest {
+    let mut files = BTreeMap::new();
+    walk(roots, filters, |path| {
+      if let Some(entry) = entry(&path, known.files.get(&path)) {
+        files.insert(path, entry);
+      }
+    });
+    Manifest { files }
+  }
+
+  /// Paths added, removed or with other contents in `self` than in `old`.
+  pub fn changes(&self, old: &Manifest) -> Vec<PathBuf> {
+    let mut changed: Vec<PathBuf> = self
+      .files
+      .iter()
+      .filter(|(path, entry)| old.files.get(*path).is_none_or(|o| o.sha1 != entry.sha1))
+      .map(|(path, _)| path.clone())
+      .collect();
+    changed.extend(
+      old
+        .files
+        .keys()
+        .filter(|p| !self.files.contains_key(*p))
+        .cloned(),
+    );
+    changed.sort();
+    changed
+  }
+
+  /// Brings the entries for `paths` up to date.
+  pub fn update(&mut self, paths: &[PathBuf]) {
+    for path in paths {
+      match entry(path, self.files.get(path)) {
+        Some(entry) => self.files.insert(path.clone(), entry),
+        None => self.files.remove(path),
+      };
+    }
+  }
+
+  pub fn load(path: &Path) -> io::Result<Manifest> {
+    let text = fs::read_to_string(path)?;
+    serde_json::from_str(&text).map_err(io::Error::other)
+  }
+
+  pub fn write(&self, mut out: impl Write) -> io::Result<()> {
+    serde_json::to_writer_pretty(&mut out, self).map_err(io::Error::other)?;
+    writeln!(out)
+  }
+
+  pub fn save(&self, path: &Path) -> io::Result<()> {
+    if let Some(dir) = path.parent() {
+      fs::create_dir_all(dir)?;
+    }
+    // Through a temporary file, so a crash never leaves half a manifest.
+    let tmp = path.with_extension("tmp");
+    fs::write(
+      &tmp,
+      serde_json::to_vec_pretty(self).map_err(io::Error::other)?,
+    )?;
+    fs::rename(&tmp, path)
+  }
+}
+
+/// Where `--catch-up` keeps the manifest for a session identified by
+/// `key` (the command and what it watches): under `$XDG_STATE_HOME`, or
+/// `~/.local/state`.
+pub fn state_path(key: &str) -> Option<PathBuf> {
+  let base = match env::var_os("XDG_STATE_HOME").filter(|v| !v.is_empty()) {
+    Some(dir) => PathBuf::from(dir),
+    None => PathBuf::from(env::var_os("HOME")?).join(".local/state"),
+  };
+  let name = format!("{}.json", sha1_smol::Sha1::from(key).digest());
+  Some(base.join("run_on_file_change").join(name))
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn manifest(files: &[(&str, u64, &str)]) -> Manifest {
+    let files = files
+      .iter()
+      .map(|&(path, mtime_ns, sha1)| {
+        let entry = Entry {
+          mtime_ns,
+          size: 1,
+          sha1: sha1.to_string(),
+        };
+        (PathBuf::from(path), entry)
+      })
+      .collect();
+    Manifest { files }
+  }
+
+  #[test]
+  fn changes() {
+    let old = manifest(&[("/w/a", 1, "aa"), ("/w/b", 1, "bb"), ("/w/c", 1, "cc")]);
+    let new = manifest(&[("/w/a", 2, "aa"), ("/w/b", 2, "b2"), ("/w/d", 1, "dd")]);
+    // Touching without changing the contents isn't a change.
+    assert_eq!(
+      new.changes(&old),
+      [
+        PathBuf::from("/w/b"),
+        PathBuf::from("/w/c"),
+        PathBuf::from("/w/d")
+      ]
+    );
+    assert!(old.changes(&old).is_empty());
+    assert_eq!(old.changes(&Manifest::default()).len(), 3);
+    assert_eq!(Manifest::default().changes(&old).len(), 3);
+  }
+}
@@ -0,0 +1,204 @@
+// Publishing run events to an MQTT broker (`--mqtt`, `--mqtt-topic`), for
+// build lights, dashboards and home automation. Just enough MQTT 3.1.1:
+// each message is a connect, a QoS 0 publish and a disconnect, sent in
+// order from one background thread.
+//
+// Messages are JSON objects: {"event": "started", "job", "command",
+// "run_id", "trigger"} when a run starts, and {"event": "finished", ...,
+// "success", "exit_code", "duration_ms"} when it ends. Finished messages
+// are retained, so a light that connects later still shows the status.
+
+use serde_json::Value;
+use std::{
+  io::{self, Read, Write},
+  net::{TcpStream, ToSocketAddrs},
+  process,
+  sync::atomic::{AtomicU32, Ordering},
+  sync::mpsc::{Sender, channel},
+  thread::{self, JoinHandle},
+  time::{Duration, Instant},
+};
+use tracing::warn;
+
+const TIMEOUT: Duration = Duration::from_secs(5);
+/// How long shutdown waits for the queued messages to get out, all told.
+const DRAIN: Duration = Duration::from_secs(5);
+
+/// Connections made so far, to tell their client ids apart.
+static CONNECTIONS: AtomicU32 = AtomicU32::new(0);
+
+/// A client id of its own for each connection, since a broker drops the
+/// connection it has for an id when another one uses it: short enough (23
+/// bytes at most) for every broker to take.
+fn client_id() -> String {
+  let n = CONNECTIONS.fetch_add(1, Ordering::Relaxed);
+  format!("rof-{}-{n}", process::id())
+}
+
+#[derive(Clone)]
+pub struct Broker {
+  host: String,
+  port: u16,
+  credentials: Option<(String, String)>,
+}
+
+impl Broker {
+  /// `mqtt://[USER:PASSWORD@]HOST[:PORT]`, the port defaulting to 1883.
+  pub fn parse(url: &str) -> Result<Broker, String> {
+    let rest = url
+      .strip_prefix("mqtt://")
+      .ok_or_else(|| format!("expected an mqtt:// URL, not {url:?}"))?
+      .trim_end_matches('/');
+    let (credentials, authority) = match rest.rsplit_once('@') {
+      Some((user, authority)) => {
+        let (name, password) = user.split_once(':').unwrap_or((user, ""));
+        (Some((name.to_string(), password.to_string())), authority)
+      }
+      None => (None, rest),
+    };
+    let (host, port) = match authority.rsplit_once(':') {
+      Some((host, port)) => (
+        host,
+        port
+          .parse()
+          .map_err(|_| format!("invalid port in {url:?}"))?,
+      ),
+      None => (authority, 1883),
+    };
+    if host.is_empty() {
+      return Err(format!("no host in {url:?}"));
+    }
+    Ok(Broker {
+      host: host.to_string(),
+      port,
+      credentials,
+    })
+  }
+}
+
+/// Appends MQTT's variable-length encoding of `len`.
+fn push_length(out: &mut Vec<u8>, mut len: usize) {
+  loop {
+    let mut byte = (len % 128) as u8;
+    len /= 128;
+    if len > 0 {
+      byte |= 0x80;
+    }
+    out.push(byte);
+    if len == 0 {
+      break;
+    }
+  }
+}
+
+fn push_str(out: &mut Vec<u8>, s: &[u8]) {
+  out.extend((s.len() as u16).to_be_bytes());
+  out.extend(s);
+}
+
+/// A packet of type `header` around `body`.
+fn packet(header: u8, body: &[u8]) -> Vec<u8> {
+  let mut out = vec![header];
+  push_length(&mut out, body.len());
+  out.extend(body);
+  out
+}
+
+fn publish_once(broker: &Broker, topic: &str, payload: &[u8], retain: bool) -> io::Result<()> {
+  let addr = (broker.host.as_str(), broker.port)
+    .to_socket_addrs()?
+    .next()
+    .ok_or_else(|| io::Error::other("host has no address"))?;
+  let mut stream = TcpStream::connect_timeout(&addr, TIMEOUT)?;
+  stream.set_read_timeout(Some(TIMEOUT))?;
+  stream.set_write_timeout(Some(TIMEOUT))?;
+
+  let mut connect = Vec::new();
+  push_str(&mut connect, b"MQTT");
+  connect.push(4); // protocol level 3.1.1
+  let flags = match broker.credentials {
+    Some(_) => 0xC2, // user name, password, clean session
+    None => 0x02,
+  };
+  connect.push(flags);
+  connect.extend(30u16.to_be_bytes()); // keep-alive, never reached
+  push_str(&mut connect, client_id().as_bytes());
+  if let Some((user, password)) = &broker.credentials {
+    push_str(&mut connect, user.as_bytes());
+    push_str(&mut connect, password.as_bytes());
+  }
+  stream.write_all(&packet(0x10, &connect))?;
+  let mut connack = [0; 4];
+  stream.read_exact(&mut connack)?;
+  if connack[0] != 0x20 {
+    return Err(io::Error::other(
+      "the broker did not acknowledge the connection",
+    ));
+  }
+  if connack[3] != 0 {
+    return Err(io::Error::other(format!(
+      "the broker refused the connection (code {})",
+      connack[3]
+    )));
+  }
+
+  let mut publish = Vec::new();
+  push_str(&mut publish, topic.as_bytes());
+  publish.extend(payload);
+  stream.write_all(&packet(0x30 | retain as u8, &publish))?;
+  stream.write_all(&packet(0xE0, &[]))?;
+  Ok(())
+}
+
+/// Hands messages to the background thread that publishes them. Dropping
+/// it waits up to [`DRAIN`] for the queue to drain, so the last run's
+/// status gets out.
+pub struct Publisher {
+  tx: Option<Sender<(Value, bool)>>,
+  thread: Option<JoinHandle<()>>,
+}
+
+impl Publisher {
+  pub fn start(broker: Broker, topic: String) -> Publisher {
+    let (tx, rx) = channel::<(Value, bool)>();
+    let thread = thread::spawn(move || {
+      for (message, retain) in rx {
+        if let Err(e) = publish_once(&broker, &topic, message.to_string().as_bytes(), retain) {
+          warn!(
+            "Could not publish to MQTT broker {}:{}: {e}",
+            broker.host, broker.port
+          );
+        }
+      }
+    });
+    Publisher {
+      tx: Some(tx),
+      thread: Some(thread),
+    }
+  }
+
+  pub fn publish(&self, message: Value, retain: bool) {
+    if let Some(tx) = &self.tx {
+      let _ = tx.send((message, retain));
+    }
+  }
+}
+
+impl Drop for Publisher {
+  fn drop(&mut self) {
+    self.tx = None;
+    let Some(thread) = self.thread.take() else {
+      return;
+    };
+    let start = Instant::now();
+    while !thread.is_finished() && start.elapsed() < DRAIN {
+      thread::sleep(Duration::from_millis(10));
+    }
+    match thread.is_finished() {
+      true => {
+        let _ = thread.join();
+      }
+      false => warn!("Gave up publishing the last MQTT messages"),
+    }
+  }
+}
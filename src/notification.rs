@@ -0,0 +1,139 @@
+// Telling the user a run has ended (`--notify`, `--notify-on`), unless
+// muted for `--quiet-hours`.
+
+use crate::{http, signals};
+use serde_json::json;
+use std::{
+  io::{self, Write},
+  os::unix::process::CommandExt,
+  process::{Command, Stdio},
+  sync::atomic::{AtomicBool, Ordering},
+  thread,
+};
+use tracing::warn;
+
+#[derive(Clone)]
+pub enum Sink {
+  /// The terminal bell.
+  Bell,
+  /// `notify-send` (or `osascript` on macOS).
+  Desktop,
+  /// A JSON POST to a plain-HTTP endpoint.
+  Webhook {
+    host: String,
+    port: u16,
+    path: String,
+  },
+}
+
+impl Sink {
+  /// `bell`, `desktop` or `webhook:http://HOST[:PORT]/PATH`.
+  pub fn parse(s: &str) -> Result<Sink, String> {
+    match s {
+      "bell" => Ok(Sink::Bell),
+      "desktop" => Ok(Sink::Desktop),
+      _ => {
+        let url = s
+          .strip_prefix("webhook:")
+          .ok_or_else(|| format!("unknown notification target {s:?}"))?;
+        let rest = url
+          .strip_prefix("http:")
+          .ok_or_else(|| format!("only http:// webhooks are supported, not {url:?}"))?;
+        let (host, port, path) =
+          http::parse_url(rest).ok_or_else(|| format!("invalid URL {url:?}"))?;
+        Ok(Sink::Webhook { host, port, path })
+      }
+    }
+  }
+}
+
+/// Which finished runs are worth a notification.
+#[derive(Clone, Copy, Default, PartialEq, Eq)]
+pub enum When {
+  #[default]
+  Always,
+  Failure,
+  /// Success turned into failure or back. A first run counts if it fails.
+  Change,
+}
+
+impl When {
+  pub fn parse(s: &str) -> Option<When> {
+    match s {
+      "always" => Some(When::Always),
+      "failure" => Some(When::Failure),
+      "change" => Some(When::Change),
+      _ => None,
+    }
+  }
+
+  /// Whether a run that ended with `success` should notify, given how the
+  /// previous finished run ended.
+  pub fn applies(self, success: bool, previous: Option<bool>) -> bool {
+    match self {
+      When::Always => true,
+      When::Failure => !success,
+      When::Change => previous.unwrap_or(true) != success,
+    }
+  }
+}
+
+/// Set during `--quiet-hours` with `--quiet-hours-mute`.
+static MUTED: AtomicBool = AtomicBool::new(false);
+
+/// Holds back every notification until unmuted.
+pub fn mute(muted: bool) {
+  MUTED.store(muted, Ordering::Relaxed);
+}
+
+/// Sends `message` to every sink, in the background; failures are logged.
+pub fn send(sinks: &[Sink], success: bool, message: &str) {
+  if MUTED.load(Ordering::Relaxed) {
+    return;
+  }
+  for sink in sinks {
+    match sink {
+      Sink::Bell => {
+        let _ = io::stderr().write_all(b"\x07");
+      }
+      Sink::Desktop => desktop(message),
+      Sink::Webhook { host, port, path } => {
+        let (host, port, path) = (host.clone(), *port, path.clone());
+        let body = json!({ "success": success, "message": message }).to_string();
+        thread::spawn(move || {
+          if let Err(e) = http::post_ok(&host, port, &path, body.as_bytes()) {
+            warn!("Webhook {host}:{port}{path} {e}");
+          }
+        });
+      }
+    }
+  }
+}
+
+fn desktop(message: &str) {
+  let mut command = if cfg!(target_os = "macos") {
+    let script = format!(
+      "display notification {:?} with title \"run_on_file_change\"",
+      message
+    );
+    let mut c = Command::new("osascript");
+    c.arg("-e").arg(script);
+    c
+  } else {
+    let mut c = Command::new("notify-send");
+    c.arg("run_on_file_change").arg(message);
+    c
+  };
+  command
+    .stdin(Stdio::null())
+    .stdout(Stdio::null())
+    .stderr(Stdio::null());
+  // SAFETY: sigprocmask is async-signal-safe
+  unsafe { command.pre_exec(signals::clear_mask) };
+  match command.spawn() {
+    Ok(mut child) => {
+      thread::spawn(move || child.wait());
+    }
+    Err(e) => warn!("Could not show a desktop notification: {e}"),
+  }
+}
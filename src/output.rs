@@ -0,0 +1,467 @@
+// Capturing the command's stdout/stderr instead of inheriting them.
+//
+// `--output-fifo PATH` creates a named pipe that gets a copy of the runs'
+// output, both streams, for viewers to attach to and leave as they like:
+//
+//   cat /tmp/rof.out      # or fzf, less +F, an editor terminal
+//
+// Lines written while nothing reads, or faster than the reader takes them,
+// are dropped: a viewer never holds the runs up. Lines longer than the
+// kernel writes in one go (PIPE_BUF, 4 KiB on Linux) are cut short.
+
+use crate::Msg;
+use crate::diff;
+use nix::libc;
+use nix::sys::stat::Mode;
+use regex::Regex;
+use std::{
+  fs::{self, File},
+  io::{self, BufRead, BufReader, IsTerminal, Read, Write},
+  os::unix::fs::{FileTypeExt, OpenOptionsExt},
+  path::{Path, PathBuf},
+  process::Child,
+  sync::{
+    Arc, Mutex,
+    atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering},
+    mpsc::Sender,
+  },
+  time::Instant,
+};
+use tracing::info;
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Stream {
+  Stdout,
+  Stderr,
+}
+
+#[derive(Clone, Default)]
+pub struct OutputConfig {
+  /// Hold output back and only show it when the run fails.
+  pub quiet_success: bool,
+  /// With `quiet_success`, show only this many trailing lines on failure.
+  pub tail_on_failure: Option<usize>,
+  /// Remove ANSI escape sequences from output that doesn't go to a terminal.
+  pub strip_ansi: bool,
+  /// Whether output goes to a terminal, if `--tty` or `--no-tty` says
+  /// rather than stdout and stderr.
+  pub tty: Option<bool>,
+  /// Prefix every line with the time elapsed since the run started.
+  pub timestamps: bool,
+  /// Drop output beyond this many bytes per run, leaving a marker.
+  pub max_output: Option<u64>,
+  /// `--ready-when log:…`: the line that says the run is ready.
+  pub ready_log: Option<Regex>,
+  /// Hold output back and show how it differs from the previous run's.
+  pub diff_output: bool,
+  /// Put before every line, to tell jobs apart.
+  pub prefix: Option<String>,
+  /// Files every line is also appended to as it arrives (`--tee`,
+  /// `--log-per-run`).
+  pub copies: Vec<Arc<Mutex<dyn Write + Send>>>,
+}
+
+impl OutputConfig {
+  /// Whether runs need piped rather than inherited output.
+  pub fn captures(&self) -> bool {
+    self.holds_back()
+      || self.timestamps
+      || self.ready_log.is_some()
+      || self.prefix.is_some()
+      || !self.copies.is_empty()
+  }
+
+  /// Whether lines for `stream` lose their escape sequences.
+  fn strips(&self, stream: Stream) -> bool {
+    self.strip_ansi
+      && !self.tty.unwrap_or_else(|| match stream {
+        Stream::Stdout => io::stdout().is_terminal(),
+        Stream::Stderr => io::stderr().is_terminal(),
+      })
+  }
+
+  /// Whether output is kept until the run ends rather than passed through.
+  fn holds_back(&self) -> bool {
+    self.quiet_success || self.diff_output
+  }
+}
+
+/// Longest chunk read as one "line", so output without newlines (an
+/// accidental `cat` of a binary) can't grow a buffer without bound.
+const MAX_LINE: u64 = 64 * 1024;
+
+/// Everything a run wrote so far, in arrival order.
+#[derive(Default)]
+pub struct Captured {
+  lines: Mutex<Vec<(Stream, Vec<u8>)>>,
+  /// Bytes received across both streams, including dropped ones.
+  total: AtomicU64,
+  /// The ready line has been seen.
+  ready: AtomicBool,
+}
+
+impl Captured {
+  /// Accounts for `len` more bytes and says whether they still fit under
+  /// `max`; the moment the limit is crossed, writes the truncation marker
+  /// to the copies, and prints it when output is passed through live.
+  fn admit(&self, len: usize, config: &OutputConfig) -> bool {
+    let Some(max) = config.max_output else {
+      return true;
+    };
+    let before = self.total.fetch_add(len as u64, Ordering::AcqRel);
+    let fits = before + len as u64 <= max;
+    if !fits && before <= max {
+      let marker = truncation_marker(max);
+      for copy in &config.copies {
+        let _ = copy.lock().unwrap().write_all(marker.as_bytes());
+      }
+      if !config.holds_back() {
+        emit(Stream::Stderr, marker.as_bytes(), false);
+      }
+    }
+    fits
+  }
+
+  fn truncated(&self, config: &OutputConfig) -> Option<u64> {
+    config
+      .max_output
+      .filter(|&max| self.total.load(Ordering::Acquire) > max)
+  }
+}
+
+fn truncation_marker(max: u64) -> String {
+  format!("[run_on_file_change: output truncated after {max} bytes]\n")
+}
+
+/// Takes the child's piped stdout/stderr and drains them on two threads,
+/// line by line, either passing lines through right away or holding them
+/// back for [`report`]. Once both have reached EOF, [`Msg::OutputClosed`]
+/// is sent with `run_id`; [`Msg::Ready`] when the ready line shows up.
+pub fn capture(
+  child: &mut Child,
+  run_id: u64,
+  started: Instant,
+  config: &OutputConfig,
+  tx: Sender<Msg>,
+) -> Arc<Captured> {
+  let captured = Arc::new(Captured::default());
+  let open = Arc::new(AtomicUsize::new(2));
+  let streams: [(Stream, Option<Box<dyn Read + Send>>); 2] = [
+    (
+      Stream::Stdout,
+      child.stdout.take().map(|s| Box::new(s) as _),
+    ),
+    (
+      Stream::Stderr,
+      child.stderr.take().map(|s| Box::new(s) as _),
+    ),
+  ];
+  for (stream, pipe) in streams {
+    let captured = Arc::clone(&captured);
+    let open = Arc::clone(&open);
+    let tx = tx.clone();
+    let config = config.clone();
+    std::thread::spawn(move || {
+      if let Some(pipe) = pipe {
+        let mut reader = BufReader::new(pipe);
+        let mut line = Vec::new();
+        while matches!((&mut reader).take(MAX_LINE).read_until(b'\n', &mut line), Ok(n) if n > 0) {
+          let ready = config.ready_log.as_ref().is_some_and(|pattern| {
+            !captured.ready.load(Ordering::Acquire)
+              && pattern.is_match(&String::from_utf8_lossy(&strip_ansi(&line)))
+              && !captured.ready.swap(true, Ordering::AcqRel)
+          });
+          if !captured.admit(line.len(), &config) {
+            line.clear(); // keep draining so the child never blocks on a full pipe
+          } else {
+            if config.timestamps {
+              let elapsed = started.elapsed().as_secs_f64();
+              line.splice(0..0, format!("[{elapsed:>8.3}s] ").into_bytes());
+            }
+            if let Some(prefix) = &config.prefix {
+              line.splice(0..0, prefix.bytes());
+            }
+            for copy in &config.copies {
+              let mut file = copy.lock().unwrap();
+              let _ = match config.strip_ansi {
+                true => file.write_all(&strip_ansi(&line)),
+                false => file.write_all(&line),
+              };
+            }
+            if config.holds_back() {
+              captured
+                .lines
+                .lock()
+                .unwrap()
+                .push((stream, std::mem::take(&mut line)));
+            } else {
+              emit(stream, &line, config.strips(stream));
+              line.clear();
+            }
+          }
+          if ready {
+            let _ = tx.send(Msg::Ready(run_id)); // after the line itself
+          }
+        }
+      }
+      if open.fetch_sub(1, Ordering::AcqRel) == 1 {
+        let _ = tx.send(Msg::OutputClosed(run_id));
+      }
+    });
+  }
+  captured
+}
+
+/// Removes ANSI escape sequences (colors, cursor movement, OSC titles and
+/// hyperlinks) so output stays readable in files.
+pub fn strip_ansi(input: &[u8]) -> Vec<u8> {
+  const ESC: u8 = 0x1b;
+  const BEL: u8 = 0x07;
+  let mut out = Vec::with_capacity(input.len());
+  let mut i = 0;
+  while i < input.len() {
+    if input[i] != ESC {
+      out.push(input[i]);
+      i += 1;
+      continue;
+    }
+    i += 1;
+    match input.get(i) {
+      // CSI: parameters and intermediates up to a final byte in @..~
+      Some(b'[') => {
+        i += 1;
+        while i < input.len() && !(0x40..=0x7e).contains(&input[i]) {
+          i += 1;
+        }
+        i += 1;
+      }
+      // OSC, DCS, SOS, PM, APC: strings up to BEL or ESC \
+      Some(b']' | b'P' | b'X' | b'^' | b'_') => {
+        i += 1;
+        while i < input.len() {
+          if input[i] == BEL {
+            i += 1;
+            break;
+          }
+          if input[i] == ESC && input.get(i + 1) == Some(&b'\\') {
+            i += 2;
+            break;
+          }
+          i += 1;
+        }
+      }
+      // Two-byte sequences like ESC 7 / ESC =
+      Some(_) => i += 1,
+      None => {}
+    }
+  }
+  out
+}
+
+/// The `--output-fifo` pipe, removed when dropped.
+pub struct OutputFifo {
+  path: PathBuf,
+  /// Open while a reader has it open too.
+  pipe: Option<File>,
+}
+
+impl OutputFifo {
+  /// Creates the named pipe at `path`, replacing one left behind.
+  pub fn create(path: &Path) -> io::Result<OutputFifo> {
+    if fs::symlink_metadata(path).is_ok_and(|m| m.file_type().is_fifo()) {
+      fs::remove_file(path)?;
+    }
+    nix::unistd::mkfifo(path, Mode::from_bits_truncate(0o600))?;
+    Ok(OutputFifo {
+      path: path.to_path_buf(),
+      pipe: None,
+    })
+  }
+}
+
+impl Write for OutputFifo {
+  /// Unless the line is dropped, writes it whole, in one write of at most
+  /// PIPE_BUF bytes, which the kernel makes at once or not at all: longer
+  /// lines are cut short to fit, so a reader never gets part of one.
+  fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+    let cut;
+    let line = match buf.len() > libc::PIPE_BUF {
+      true => {
+        cut = [&buf[..libc::PIPE_BUF - 1], b"\n"].concat();
+        &cut
+      }
+      false => buf,
+    };
+    if self.pipe.is_none() {
+      // Fails while no one has the pipe open for reading.
+      self.pipe = File::options()
+        .write(true)
+        .custom_flags(libc::O_NONBLOCK)
+        .open(&self.path)
+        .ok();
+    }
+    if let Some(pipe) = &mut self.pipe
+      && let Err(e) = pipe.write(line)
+      && e.kind() != io::ErrorKind::WouldBlock
+    {
+      self.pipe = None; // the reader has gone
+    }
+    Ok(buf.len())
+  }
+
+  fn flush(&mut self) -> io::Result<()> {
+    Ok(())
+  }
+}
+
+impl Drop for OutputFifo {
+  fn drop(&mut self) {
+    let _ = fs::remove_file(&self.path);
+  }
+}
+
+/// Writes one line to the terminal stream it came from.
+fn emit(stream: Stream, line: &[u8], strip: bool) {
+  let _ = match stream {
+    Stream::Stdout => write_to(&mut io::stdout().lock(), line, strip),
+    Stream::Stderr => write_to(&mut io::stderr().lock(), line, strip),
+  };
+}
+
+fn write_to(out: &mut impl Write, line: &[u8], strip: bool) -> io::Result<()> {
+  if strip {
+    out.write_all(&strip_ansi(line))?;
+  } else {
+    out.write_all(line)?;
+  }
+  out.flush()
+}
+
+/// Prints what a finished run held back, according to `config`. Output
+/// that was passed through live has nothing left to show.
+pub fn report(captured: &Captured, success: bool, config: &OutputConfig) {
+  if !config.quiet_success || success {
+    return;
+  }
+  let lines = captured.lines.lock().unwrap();
+  let skip = match config.tail_on_failure {
+    Some(n) => lines.len().saturating_sub(n),
+    None => 0,
+  };
+  for (stream, line) in &lines[skip..] {
+    emit(*stream, line, config.strips(*stream));
+  }
+  if let Some(max) = captured.truncated(config) {
+    emit(Stream::Stderr, truncation_marker(max).as_bytes(), false);
+  }
+}
+
+/// `--diff-output`: prints how a finished run's output differs from
+/// `previous` (the last finished run's), each stream's diff on that
+/// stream, or all of it when there is no previous run or the two are
+/// unrelated, and keeps it for next time.
+pub fn report_diff(
+  captured: &Captured,
+  run_id: u64,
+  previous: &mut Option<Vec<(Stream, Vec<u8>)>>,
+) {
+  let lines = captured.lines.lock().unwrap().clone();
+  match previous.as_ref() {
+    Some(old) if *old == lines => info!("Output unchanged from the previous run"),
+    Some(old) => {
+      for stream in [Stream::Stdout, Stream::Stderr] {
+        let _ = match stream {
+          Stream::Stdout => diff_stream(stream, old, &lines, run_id, &mut io::stdout().lock()),
+          Stream::Stderr => diff_stream(stream, old, &lines, run_id, &mut io::stderr().lock()),
+        };
+      }
+    }
+    None => {
+      for (stream, line) in &lines {
+        emit(*stream, line, false);
+      }
+    }
+  }
+  *previous = Some(lines);
+}
+
+/// One stream's part of [`report_diff`]: nothing if it is unchanged.
+fn diff_stream(
+  stream: Stream,
+  old: &[(Stream, Vec<u8>)],
+  new: &[(Stream, Vec<u8>)],
+  run_id: u64,
+  out: &mut impl Write,
+) -> io::Result<()> {
+  let of = |lines: &[(Stream, Vec<u8>)]| -> Vec<Vec<u8>> {
+    lines
+      .iter()
+      .filter(|(s, _)| *s == stream)
+      .map(|(_, line)| line.clone())
+      .collect()
+  };
+  let (old, new) = (of(old), of(new));
+  if old == new {
+    return Ok(());
+  }
+  let old: Vec<&[u8]> = old.iter().map(Vec::as_slice).collect();
+  let new: Vec<&[u8]> = new.iter().map(Vec::as_slice).collect();
+  let label = format!("run {run_id}");
+  if !diff::unified(&old, &new, ("previous run", &label), out).unwrap_or(true) {
+    for line in &new {
+      out.write_all(line)?;
+    }
+  }
+  out.flush()
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn strip(s: &str) -> String {
+    String::from_utf8(strip_ansi(s.as_bytes())).unwrap()
+  }
+
+  #[test]
+  fn strips_escapes() {
+    assert_eq!(strip("plain text\n"), "plain text\n");
+    assert_eq!(strip("\x1b[1;31merror\x1b[0m: x"), "error: x");
+    assert_eq!(strip("a\x1b[2Kb\x1b[?25lc"), "abc");
+    assert_eq!(strip("\x1b]0;title\x07after"), "after");
+    assert_eq!(
+      strip("\x1b]8;;https://example.com\x1b\\link\x1b]8;;\x1b\\"),
+      "link"
+    );
+    assert_eq!(strip("\x1b7saved\x1b8"), "saved");
+  }
+
+  #[test]
+  fn strips_cut_off_escapes() {
+    assert_eq!(strip("done\x1b"), "done");
+    assert_eq!(strip("done\x1b[1;3"), "done");
+    assert_eq!(strip("done\x1b]0;unterminated"), "done");
+  }
+
+  #[test]
+  fn diffs_each_stream_on_its_own() {
+    let old = vec![
+      (Stream::Stdout, b"ok\n".to_vec()),
+      (Stream::Stderr, b"warning: a\n".to_vec()),
+    ];
+    let new = vec![
+      (Stream::Stdout, b"ok\n".to_vec()),
+      (Stream::Stderr, b"warning: b\n".to_vec()),
+    ];
+    let diff = |stream| {
+      let mut out = Vec::new();
+      diff_stream(stream, &old, &new, 2, &mut out).unwrap();
+      String::from_utf8(out).unwrap()
+    };
+    assert_eq!(diff(Stream::Stdout), "");
+    let stderr = diff(Stream::Stderr);
+    assert!(stderr.contains("-warning: a\n"), "{stderr}");
+    assert!(stderr.contains("+warning: b\n"), "{stderr}");
+    assert!(!stderr.contains("ok"), "{stderr}");
+  }
+}
@@ -0,0 +1,286 @@
+// Running elsewhere what changes here trigger (`--pair HOST:PORT`), an
+// experimental stand-in for ssh-and-rsync scripts:
+//
+//   server$ run_on_file_change pair-serve 0.0.0.0:7878 "cargo test"
+//   laptop$ run_on_file_change --pair server:7878 src
+//
+// The laptop watches, filters and debounces as usual, but each of its runs
+// is a connection to the server (`run_on_file_change pair-run`), which
+// runs its own command for it and streams the output back; the run ends
+// with the remote command's exit status. Stopping the run, as a new change
+// does, closes the connection, and the server stops the remote command.
+// The changed paths go along, relative to the laptop's working directory,
+// in the remote command's $ROF_TRIGGER (one per line); keeping the two
+// trees in sync is left to a tool made for it (a shared mount, rsync,
+// Mutagen). With $ROF_PAIR_TOKEN set for the server, only clients sending
+// the same token get runs.
+//
+// A client sends one line of JSON, {"token", "run_id", "trigger"}; the
+// server answers with frames of a tag byte (o for output, e for errors, x
+// for the exit status), a big-endian u32 length and that many bytes.
+
+use crate::{exit, logging, process, signals};
+use nix::sys::signal::{self, Signal};
+use nix::unistd::Pid;
+use serde::{Deserialize, Serialize};
+use std::{
+  env,
+  io::{self, BufRead, BufReader, Read, Write},
+  net::{TcpListener, TcpStream},
+  os::unix::process::{CommandExt, ExitStatusExt},
+  path::{Path, PathBuf},
+  process::{Command, Stdio},
+  sync::{
+    Arc, Mutex,
+    atomic::{AtomicBool, Ordering},
+  },
+  thread,
+  time::Duration,
+};
+use tracing::{info, warn};
+
+/// The status a run ends with when the connection goes.
+const LOST: i32 = 255;
+
+/// The most a client's hello may take, trigger paths and all.
+const MAX_HELLO: u64 = 1 << 20;
+
+/// How long a client gets to send its hello.
+const HELLO_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// The largest frame a client takes from the server; the server sends at
+/// most 8 KiB at a time.
+const MAX_FRAME: usize = 1 << 20;
+
+#[derive(Serialize, Deserialize)]
+struct Hello {
+  token: Option<String>,
+  run_id: u64,
+  trigger: Vec<PathBuf>,
+}
+
+/// The command each run of a `--pair`ed watcher is: this binary's client.
+pub fn command(addr: &str) -> Result<String, String> {
+  let exe = env::current_exe().map_err(|e| format!("cannot find this program: {e}"))?;
+  Ok(format!(
+    "{} pair-run {}",
+    process::quote(&exe.to_string_lossy()),
+    process::quote(addr)
+  ))
+}
+
+/// Whether the client's `given` token is the `expected` one, compared in
+/// time that doesn't depend on where they differ.
+fn token_matches(expected: &str, given: Option<&str>) -> bool {
+  let Some(given) = given else {
+    return false;
+  };
+  let (a, b) = (expected.as_bytes(), given.as_bytes());
+  a.len() == b.len() && a.iter().zip(b).fold(0, |diff, (x, y)| diff | (x ^ y)) == 0
+}
+
+fn frame(out: &Mutex<TcpStream>, tag: u8, data: &[u8]) -> io::Result<()> {
+  let mut out = out.lock().expect("not poisoned");
+  out.write_all(&[tag])?;
+  out.write_all(&(data.len() as u32).to_be_bytes())?;
+  out.write_all(data)
+}
+
+/// Runs `command` for one client, until it exits or the client goes.
+fn serve(stream: TcpStream, command: &str, token: Option<&str>) -> io::Result<()> {
+  let peer = stream.peer_addr()?;
+  stream.set_read_timeout(Some(HELLO_TIMEOUT))?;
+  let mut reader = BufReader::new(stream.try_clone()?);
+  let mut line = String::new();
+  (&mut reader).take(MAX_HELLO).read_line(&mut line)?;
+  if !line.ends_with('\n') {
+    return Err(io::Error::new(
+      io::ErrorKind::InvalidData,
+      "hello too long or cut short",
+    ));
+  }
+  stream.set_read_timeout(None)?; // from now on, reading waits for it to go
+  let hello: Hello = serde_json::from_str(&line).map_err(io::Error::other)?;
+  let out = Arc::new(Mutex::new(stream));
+  if let Some(token) = token
+    && !token_matches(token, hello.token.as_deref())
+  {
+    warn!("Refused a run from {peer}: wrong token");
+    frame(
+      &out,
+      b'e',
+      b"the paired watcher refused the run: wrong ROF_PAIR_TOKEN\n",
+    )?;
+    return frame(&out, b'x', &exit::CONFIG.to_be_bytes());
+  }
+  info!("[run {} from {peer}] {command}", hello.run_id);
+  let trigger: Vec<String> = hello
+    .trigger
+    .iter()
+    .map(|p| p.display().to_string())
+    .collect();
+  let mut child = Command::new("/bin/sh");
+  child
+    .args(["-c", command])
+    .env("ROF_RUN_ID", hello.run_id.to_string())
+    .env("ROF_TRIGGER", trigger.join("\n"))
+    .stdin(Stdio::null())
+    .stdout(Stdio::piped())
+    .stderr(Stdio::piped())
+    .process_group(0);
+  // SAFETY: only resets the signal mask, which is async-signal-safe
+  unsafe { child.pre_exec(signals::clear_mask) };
+  let mut child = match child.spawn() {
+    Ok(child) => child,
+    Err(e) => {
+      frame(
+        &out,
+        b'e',
+        format!("could not start {command}: {e}\n").as_bytes(),
+      )?;
+      return frame(&out, b'x', &exit::SPAWN.to_be_bytes());
+    }
+  };
+  let group = Pid::from_raw(child.id() as i32);
+  let done = Arc::new(AtomicBool::new(false));
+
+  // The client never writes again: reading returns once it has gone.
+  let watching = Arc::clone(&done);
+  thread::spawn(move || {
+    let _ = reader.read(&mut [0; 1]);
+    if !watching.load(Ordering::SeqCst) {
+      info!("[run {}] client gone, stopping it", hello.run_id);
+      let _ = signal::killpg(group, Signal::SIGTERM);
+    }
+  });
+  let pipes: Vec<(u8, Box<dyn Read + Send>)> = vec![
+    (b'o', Box::new(child.stdout.take().expect("piped"))),
+    (b'e', Box::new(child.stderr.take().expect("piped"))),
+  ];
+  let copies: Vec<_> = pipes
+    .into_iter()
+    .map(|(tag, mut pipe)| {
+      let out = Arc::clone(&out);
+      thread::spawn(move || {
+        let mut buf = [0; 8192];
+        while let Ok(n @ 1..) = pipe.read(&mut buf) {
+          if frame(&out, tag, &buf[..n]).is_err() {
+            return;
+          }
+        }
+      })
+    })
+    .collect();
+  for copy in copies {
+    let _ = copy.join();
+  }
+  let status = child.wait()?;
+  done.store(true, Ordering::SeqCst);
+  let code = match (status.code(), status.signal()) {
+    (Some(code), _) => code,
+    (None, Some(sig)) => 128 + sig,
+    (None, None) => LOST,
+  };
+  info!("[run {}] exited with status {code}", hello.run_id);
+  let _ = frame(&out, b'x', &code.to_be_bytes()); // unless the client has gone
+  Ok(())
+}
+
+/// `run_on_file_change pair-serve ADDR COMMAND`.
+pub fn run(mut args: impl Iterator<Item = String>) -> ! {
+  let (Some(addr), Some(command), None) = (args.next(), args.next(), args.next()) else {
+    eprintln!("Usage: run_on_file_change pair-serve <ADDR:PORT> \"<command>\"");
+    std::process::exit(exit::CONFIG);
+  };
+  if let Err(e) = logging::init(Default::default(), &[], Default::default(), false) {
+    eprintln!("Error: could not set up logging: {e}");
+    std::process::exit(exit::STARTUP);
+  }
+  let listener = match TcpListener::bind(&addr) {
+    Ok(listener) => listener,
+    Err(e) => {
+      eprintln!("Error: could not listen on {addr}: {e}");
+      std::process::exit(exit::STARTUP);
+    }
+  };
+  let token = env::var("ROF_PAIR_TOKEN").ok().filter(|t| !t.is_empty());
+  if token.is_none() {
+    warn!("Without ROF_PAIR_TOKEN, anyone reaching {addr} can start runs");
+  }
+  info!("Running {command:?} for paired watchers on {addr}");
+  let (command, token) = (Arc::new(command), Arc::new(token));
+  for stream in listener.incoming().flatten() {
+    let (command, token) = (Arc::clone(&command), Arc::clone(&token));
+    thread::spawn(move || {
+      if let Err(e) = serve(stream, &command, token.as_deref()) {
+        warn!("A paired run failed: {e}");
+      }
+    });
+  }
+  std::process::exit(exit::STARTUP);
+}
+
+/// `run_on_file_change pair-run ADDR`, as a `--pair`ed watcher's runs:
+/// asks the server for a run and relays it.
+pub fn client(mut args: impl Iterator<Item = String>) -> ! {
+  let Some(addr) = args.next() else {
+    eprintln!("Usage: run_on_file_change pair-run <HOST:PORT>");
+    std::process::exit(exit::CONFIG);
+  };
+  let cwd = env::current_dir().unwrap_or_default();
+  let trigger = env::var("ROF_TRIGGER")
+    .unwrap_or_default()
+    .lines()
+    .map(|p| {
+      Path::new(p)
+        .strip_prefix(&cwd)
+        .unwrap_or(Path::new(p))
+        .to_path_buf()
+    })
+    .collect();
+  let hello = Hello {
+    token: env::var("ROF_PAIR_TOKEN").ok().filter(|t| !t.is_empty()),
+    run_id: env::var("ROF_RUN_ID")
+      .ok()
+      .and_then(|id| id.parse().ok())
+      .unwrap_or(0),
+    trigger,
+  };
+  let relayed = TcpStream::connect(&addr).and_then(|mut stream| {
+    let hello = serde_json::to_string(&hello).expect("serializable");
+    writeln!(stream, "{hello}")?;
+    let mut stream = BufReader::new(stream);
+    loop {
+      let mut head = [0; 5];
+      stream.read_exact(&mut head)?;
+      let len = u32::from_be_bytes(head[1..].try_into().expect("4 bytes")) as usize;
+      if len > MAX_FRAME {
+        return Err(io::Error::new(
+          io::ErrorKind::InvalidData,
+          "frame too large",
+        ));
+      }
+      let mut data = vec![0; len];
+      stream.read_exact(&mut data)?;
+      match head[0] {
+        b'o' => {
+          let mut out = io::stdout().lock();
+          out.write_all(&data)?;
+          out.flush()?;
+        }
+        b'e' => io::stderr().write_all(&data)?,
+        b'x' if len == 4 => {
+          return Ok(i32::from_be_bytes(data.try_into().expect("4 bytes")));
+        }
+        _ => return Err(io::Error::new(io::ErrorKind::InvalidData, "bad frame")),
+      }
+    }
+  });
+  match relayed {
+    Ok(code) => std::process::exit(code),
+    Err(e) => {
+      eprintln!("Error: lost the paired watcher on {addr}: {e}");
+      std::process::exit(LOST);
+    }
+  }
+}
@@ -0,0 +1,120 @@
+// Refusing to run what other users could have changed (`--paranoid`), for
+// watchers running as root or another privileged user on a shared machine,
+// where whoever can edit the command's script gets to run code with the
+// watcher's rights.
+//
+// Before each run, the --config file, the --script and --plugin files and
+// the files the command names (`./build.sh`, or the deploy.sh of `sh
+// deploy.sh`) are checked. Each must belong to the watcher's user or root,
+// be writable by no one else, not be setuid or setgid, and have no
+// directory above it, up to /, that someone else can write to either
+// (unless it has the sticky bit, as /tmp does). A run failing the check
+// is not started, as with a command that can't be. Programs found through
+// $PATH are not checked.
+
+use crate::cli;
+use crate::process;
+use nix::libc;
+use std::{
+  fs,
+  os::unix::fs::MetadataExt,
+  path::{Path, PathBuf},
+};
+
+/// The files whose contents decide what a run of `command` does.
+fn files(opts: &cli::Options, command: &str) -> Vec<PathBuf> {
+  let named = process::split_words(command)
+    .into_iter()
+    .filter(|word| !word.starts_with('-'))
+    .map(PathBuf::from)
+    .filter(|path| path.is_file());
+  opts
+    .config
+    .iter()
+    .chain(&opts.script)
+    .chain(&opts.plugins)
+    .cloned()
+    .chain(named)
+    .collect()
+}
+
+/// Who other than its owner may write to something with `mode`.
+fn writers(mode: u32) -> Option<&'static str> {
+  match (mode & 0o020 != 0, mode & 0o002 != 0) {
+    (_, true) => Some("everyone"),
+    (true, false) => Some("its group"),
+    (false, false) => None,
+  }
+}
+
+/// What makes `path` untrustworthy, if anything does.
+fn distrust(path: &Path) -> Option<String> {
+  // SAFETY: a plain query, which always succeeds
+  let me = unsafe { libc::geteuid() };
+  let owned = |uid: u32| uid == me || uid == 0;
+  let meta = match fs::metadata(path) {
+    Ok(meta) => meta,
+    Err(e) => return Some(format!("{} can't be checked: {e}", path.display())),
+  };
+  if !owned(meta.uid()) {
+    return Some(format!("{} belongs to uid {}", path.display(), meta.uid()));
+  }
+  if let Some(who) = writers(meta.mode()) {
+    return Some(format!("{} is writable by {who}", path.display()));
+  }
+  if meta.mode() & 0o6000 != 0 {
+    return Some(format!("{} is setuid or setgid", path.display()));
+  }
+  // Whoever can write to a directory above it can put another file in its
+  // place, by renaming what is in the way: all the way up to /, both where
+  // the path is and, for a symlink, where it leads.
+  let mut dirs: Vec<&Path> = Vec::new();
+  let (given, resolved) = (std::path::absolute(path).ok(), fs::canonicalize(path).ok());
+  for dir in given
+    .iter()
+    .chain(&resolved)
+    .flat_map(|p| p.ancestors().skip(1))
+  {
+    if !dirs.contains(&dir) {
+      dirs.push(dir);
+    }
+  }
+  for dir in dirs {
+    let meta = match fs::metadata(dir) {
+      Ok(meta) => meta,
+      Err(e) => {
+        return Some(format!(
+          "{} is below {}, which can't be checked: {e}",
+          path.display(),
+          dir.display()
+        ));
+      }
+    };
+    if !owned(meta.uid()) {
+      return Some(format!(
+        "{} is below {}, which belongs to uid {}",
+        path.display(),
+        dir.display(),
+        meta.uid()
+      ));
+    }
+    if let Some(who) = writers(meta.mode())
+      && meta.mode() & 0o1000 == 0
+    {
+      return Some(format!(
+        "{} is below {}, which is writable by {who}",
+        path.display(),
+        dir.display()
+      ));
+    }
+  }
+  None
+}
+
+/// Whether a run of `command` may start, or why not.
+pub fn check(opts: &cli::Options, command: &str) -> Result<(), String> {
+  match files(opts, command).iter().find_map(|path| distrust(path)) {
+    Some(reason) => Err(format!("--paranoid: {reason}")),
+    None => Ok(()),
+  }
+}
@@ -0,0 +1,115 @@
+// Filtering bursts of events on several threads (`--filter-threads N`). A
+// branch switch in a large repository can deliver tens of thousands of
+// events in a second, and matching each path against globs, regexes and
+// ignore files, reading its size or first bytes for --max-file-size and
+// --text-only, and hashing it for --ignore-comments adds up.
+//
+// The event loop stays one thread; what it can't keep up with piles up in
+// its channel. When an event arrives, whatever else is waiting is taken in
+// the same batch, and the work that depends on a path alone (each job's
+// filter verdict and the content hash) is done for all the batch's paths
+// at once, split across the threads. Everything that depends on order and
+// state (renames, debounce, settling, what was written while a run went
+// on) then happens as before, one event after another, looking the
+// answers up rather than working them out.
+
+use crate::Msg;
+use crate::filter::Filter;
+use crate::significance::Significance;
+use std::{
+  collections::{HashMap, HashSet, VecDeque},
+  path::{Path, PathBuf},
+  sync::mpsc::Receiver,
+  thread,
+};
+
+/// The most messages taken into one batch.
+const BATCH: usize = 4096;
+
+/// Fewer paths than this per thread are filtered as they come, which is
+/// quicker than starting the threads.
+const MIN_PER_THREAD: usize = 64;
+
+/// What the threads made of a batch's paths.
+#[derive(Default)]
+pub struct Prefiltered {
+  /// Whether each job's filter lets the path through.
+  verdicts: HashMap<PathBuf, Vec<bool>>,
+  /// The path's content hash, for paths some filter lets through.
+  pub hashes: HashMap<PathBuf, Option<String>>,
+}
+
+impl Prefiltered {
+  /// Whether job `i`'s `filter` lets `path` through, worked out ahead if the
+  /// path was in the batch.
+  pub fn matches(&self, i: usize, filter: &Filter, path: &Path) -> bool {
+    match self.verdicts.get(path) {
+      Some(verdicts) => verdicts[i],
+      None => filter.matches(path),
+    }
+  }
+}
+
+/// Moves what is waiting on `rx` to `backlog`, up to a batch.
+pub fn drain(rx: &Receiver<Msg>, backlog: &mut VecDeque<Msg>) {
+  while backlog.len() < BATCH {
+    match rx.try_recv() {
+      Ok(msg) => backlog.push_back(msg),
+      Err(_) => break,
+    }
+  }
+}
+
+/// Works out the verdicts and hashes for the paths of the events among
+/// `batch`, on `threads` threads.
+pub fn prefilter<'a>(
+  batch: impl Iterator<Item = &'a Msg>,
+  filters: &[&Filter],
+  significance: Option<&Significance>,
+  threads: usize,
+) -> Prefiltered {
+  let mut seen = HashSet::new();
+  let paths: Vec<&PathBuf> = batch
+    .filter_map(|msg| match msg {
+      Msg::Fs(Ok(event)) => Some(&event.paths),
+      _ => None,
+    })
+    .flatten()
+    .filter(|p| seen.insert(*p))
+    .collect();
+  let mut prefiltered = Prefiltered::default();
+  if paths.len() < MIN_PER_THREAD * threads {
+    return prefiltered;
+  }
+  let chunk = paths.len().div_ceil(threads);
+  let done: Vec<_> = thread::scope(|scope| {
+    let working: Vec<_> = paths
+      .chunks(chunk)
+      .map(|paths| {
+        scope.spawn(move || {
+          paths
+            .iter()
+            .map(|&path| {
+              let verdicts: Vec<bool> = filters.iter().map(|f| f.matches(path)).collect();
+              let hash = significance
+                .filter(|_| verdicts.contains(&true))
+                .map(|s| s.hash(path));
+              (path.clone(), verdicts, hash)
+            })
+            .collect::<Vec<_>>()
+        })
+      })
+      .collect();
+    working
+      .into_iter()
+      .flat_map(|w| w.join().expect("filtering panicked"))
+      .collect()
+  });
+  for (path, verdicts, hash) in done {
+    if let Some(hash) = hash {
+      prefiltered.hashes.insert(path.clone(), hash);
+    }
+    prefiltered.verdicts.insert(path, verdicts);
+  }
+  prefiltered
+}
@@ -0,0 +1,243 @@
+// WebAssembly plugins (`--plugin <file.wasm>`), run in an interpreter.
+//
+// A plugin exports its `memory` and `rof_alloc(len) -> ptr`, which the
+// watcher calls to place a JSON message in that memory, and any of:
+//
+//   rof_filter(ptr, len) -> i32      {"job", "kind", "paths"} of a change
+//                                    that passed the filters; 0 skips it
+//   rof_run_started(ptr, len)        {"event", "job", "command", "run_id",
+//                                    "trigger"}
+//   rof_run_finished(ptr, len)       the same plus "success", "exit_code"
+//                                    (null after a signal), "duration_ms"
+//
+// It may import `rof.log(ptr, len)` to log a line of UTF-8, and the WASI
+// preview 1 functions a `wasm32-wasip1` build pulls in: `fd_write` to
+// stdout or stderr goes to the watcher's stderr and `proc_exit` ends the
+// call, while the rest (files, clocks, environment) report ENOSYS, so
+// plugins compute on what they are given and nothing else. Each call may
+// run for a bounded number of instructions; a plugin that fails or runs
+// out is logged and treated as having no opinion.
+
+use serde_json::{Value, json};
+use std::{
+  io::{self, Write},
+  path::{Path, PathBuf},
+};
+use tracing::{info, warn};
+use wasmi::{
+  Caller, Config, Engine, Error, Extern, ExternType, Instance, Linker, Memory, Module, Store,
+  TypedFunc, Val,
+};
+
+const WASI: &str = "wasi_snapshot_preview1";
+/// WASI's errno for an unsupported function.
+const ENOSYS: i32 = 52;
+/// Instructions a single call may execute.
+const FUEL: u64 = 100_000_000;
+
+pub struct Plugin {
+  name: String,
+  store: Store<String>,
+  memory: Memory,
+  alloc: TypedFunc<i32, i32>,
+  filter: Option<TypedFunc<(i32, i32), i32>>,
+  run_started: Option<TypedFunc<(i32, i32), ()>>,
+  run_finished: Option<TypedFunc<(i32, i32), ()>>,
+}
+
+/// The guest memory a host function was called from.
+fn caller_memory(caller: &Caller<'_, String>) -> Result<Memory, Error> {
+  caller
+    .get_export("memory")
+    .and_then(Extern::into_memory)
+    .ok_or_else(|| Error::new("plugin exports no memory"))
+}
+
+/// `len` bytes of the guest's memory from `ptr`, checked to lie within it
+/// before anything is allocated for them.
+fn read_bytes(caller: &Caller<'_, String>, ptr: i32, len: i32) -> Result<Vec<u8>, Error> {
+  let memory = caller_memory(caller)?;
+  let (ptr, len) = (ptr as u32 as usize, len as u32 as usize);
+  if ptr
+    .checked_add(len)
+    .is_none_or(|end| end > memory.data_size(caller))
+  {
+    return Err(Error::new("plugin passed memory out of bounds"));
+  }
+  let mut buf = vec![0; len];
+  memory
+    .read(caller, ptr, &mut buf)
+    .map_err(|e| Error::new(e.to_string()))?;
+  Ok(buf)
+}
+
+fn define_imports(linker: &mut Linker<String>, module: &Module) -> Result<(), String> {
+  let err = |e: wasmi::errors::LinkerError| e.to_string();
+  linker
+    .func_wrap(
+      "rof",
+      "log",
+      |caller: Caller<'_, String>, ptr: i32, len: i32| -> Result<(), Error> {
+        let text = read_bytes(&caller, ptr, len)?;
+        info!("{}: {}", caller.data(), String::from_utf8_lossy(&text));
+        Ok(())
+      },
+    )
+    .map_err(err)?;
+  linker
+    .func_wrap(
+      WASI,
+      "fd_write",
+      |mut caller: Caller<'_, String>,
+       fd: i32,
+       iovs: i32,
+       count: i32,
+       written: i32|
+       -> Result<i32, Error> {
+        if fd != 1 && fd != 2 {
+          return Ok(ENOSYS);
+        }
+        let out_of_bounds = || Error::new("iovec out of bounds");
+        let mut total = 0u32;
+        for i in 0..count {
+          let iov = (i as u32)
+            .checked_mul(8)
+            .and_then(|offset| (iovs as u32).checked_add(offset))
+            .ok_or_else(out_of_bounds)?;
+          let iov = read_bytes(&caller, iov as i32, 8)?;
+          let ptr = i32::from_le_bytes(iov[..4].try_into().unwrap());
+          let len = i32::from_le_bytes(iov[4..].try_into().unwrap());
+          let _ = io::stderr().write_all(&read_bytes(&caller, ptr, len)?);
+          total = total.checked_add(len as u32).ok_or_else(out_of_bounds)?;
+        }
+        caller_memory(&caller)?
+          .write(&mut caller, written as u32 as usize, &total.to_le_bytes())
+          .map_err(|e| Error::new(e.to_string()))?;
+        Ok(0)
+      },
+    )
+    .map_err(err)?;
+  linker
+    .func_wrap(
+      WASI,
+      "proc_exit",
+      |_: Caller<'_, String>, code: i32| -> Result<(), Error> { Err(Error::i32_exit(code)) },
+    )
+    .map_err(err)?;
+  // Whatever else WASI offers is refused rather than missing, so a
+  // stock wasip1 build still loads.
+  for import in module.imports() {
+    let ExternType::Func(ty) = import.ty() else {
+      continue;
+    };
+    if import.module() != WASI || matches!(import.name(), "fd_write" | "proc_exit") {
+      continue;
+    }
+    let results: Vec<Val> = ty
+      .results()
+      .iter()
+      .map(|t| Val::default_for_ty(*t))
+      .collect();
+    linker
+      .func_new(WASI, import.name(), ty.clone(), move |_, _, out| {
+        out.clone_from_slice(&results);
+        if let Some(Val::I32(errno)) = out.first_mut() {
+          *errno = ENOSYS;
+        }
+        Ok(())
+      })
+      .map_err(err)?;
+  }
+  Ok(())
+}
+
+impl Plugin {
+  pub fn load(path: &Path) -> Result<Plugin, String> {
+    let fail = |e: &dyn std::fmt::Display| format!("{}: {e}", path.display());
+    let bytes = std::fs::read(path).map_err(|e| fail(&e))?;
+    let mut config = Config::default();
+    config.consume_fuel(true);
+    let engine = Engine::new(&config);
+    let module = Module::new(&engine, &bytes).map_err(|e| fail(&e))?;
+    let name = path
+      .file_stem()
+      .map_or_else(|| "plugin".into(), |s| s.to_string_lossy().into_owned());
+    let mut store = Store::new(&engine, name.clone());
+    let mut linker = Linker::new(&engine);
+    define_imports(&mut linker, &module).map_err(|e| fail(&e))?;
+    store.set_fuel(FUEL).map_err(|e| fail(&e))?;
+    let instance: Instance = linker
+      .instantiate_and_start(&mut store, &module)
+      .map_err(|e| fail(&e))?;
+    let memory = instance
+      .get_memory(&store, "memory")
+      .ok_or_else(|| fail(&"exports no memory"))?;
+    let alloc = instance
+      .get_typed_func(&store, "rof_alloc")
+      .map_err(|e| fail(&format!("rof_alloc: {e}")))?;
+    let filter = instance.get_typed_func(&store, "rof_filter").ok();
+    let run_started = instance.get_typed_func(&store, "rof_run_started").ok();
+    let run_finished = instance.get_typed_func(&store, "rof_run_finished").ok();
+    if filter.is_none() && run_started.is_none() && run_finished.is_none() {
+      return Err(fail(
+        &"exports none of rof_filter, rof_run_started, rof_run_finished",
+      ));
+    }
+    Ok(Plugin {
+      name,
+      store,
+      memory,
+      alloc,
+      filter,
+      run_started,
+      run_finished,
+    })
+  }
+
+  /// Copies `message` into the plugin and calls `func` on it.
+  fn call<R: wasmi::WasmResults>(
+    &mut self,
+    func: TypedFunc<(i32, i32), R>,
+    message: &Value,
+  ) -> Result<R, Error> {
+    let bytes = message.to_string().into_bytes();
+    self.store.set_fuel(FUEL)?;
+    let ptr = self.alloc.call(&mut self.store, bytes.len() as i32)?;
+    self
+      .memory
+      .write(&mut self.store, ptr as u32 as usize, &bytes)
+      .map_err(|e| Error::new(e.to_string()))?;
+    func.call(&mut self.store, (ptr, bytes.len() as i32))
+  }
+
+  /// Whether the plugin lets a change of `kind` to `paths` run `job`.
+  pub fn admits(&mut self, job: &str, kind: &str, paths: &[PathBuf]) -> bool {
+    let Some(filter) = self.filter else {
+      return true;
+    };
+    let message = json!({ "job": job, "kind": kind, "paths": paths });
+    match self.call(filter, &message) {
+      Ok(verdict) => verdict != 0,
+      Err(e) => {
+        warn!("Plugin {} failed to filter, running anyway: {e}", self.name);
+        true
+      }
+    }
+  }
+
+  pub fn started(&mut self, message: &Value) {
+    self.tell(self.run_started, message);
+  }
+
+  pub fn finished(&mut self, message: &Value) {
+    self.tell(self.run_finished, message);
+  }
+
+  fn tell(&mut self, func: Option<TypedFunc<(i32, i32), ()>>, message: &Value) {
+    if let Some(func) = func
+      && let Err(e) = self.call(func, message)
+    {
+      warn!("Plugin {} failed: {e}", self.name);
+    }
+  }
+}
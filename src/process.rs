@@ -0,0 +1,841 @@
+// Spawning, terminating and reaping of the watched command.
+
+use crate::Msg;
+use crate::cleanup;
+use crate::cli::Options;
+use crate::jobs;
+use crate::output::{self, Captured, OutputConfig};
+use crate::ready;
+use crate::signals;
+use crate::watchdog;
+use chrono::{DateTime, Local};
+use nix::errno::Errno;
+use nix::libc;
+use nix::sys::signal::{Signal, kill};
+use nix::sys::wait::{WaitPidFlag, WaitStatus, waitpid};
+use nix::unistd::Pid;
+use std::os::unix::process::{CommandExt, ExitStatusExt}; // for .pre_exec
+use std::{
+  fs::{self, File},
+  io::{self, Read},
+  net::{Ipv4Addr, TcpListener},
+  path::PathBuf,
+  process::{Child, Command, ExitStatus, Stdio},
+  sync::{Arc, Mutex, mpsc::Sender},
+  thread,
+  time::{Duration, Instant},
+};
+use tracing::{Span, debug, info, info_span, warn};
+
+/// How often the groups of dropped runs are looked at while they stop.
+const DROPPED_POLL: Duration = Duration::from_millis(100);
+
+/// The group of a run dropped while still going, signalled to stop.
+struct Dropped {
+  leader: i32,
+  /// When the group is killed if it is still there (`--drop-grace`).
+  kill_at: Instant,
+  reaped: bool,
+}
+
+/// Dropped runs' groups on their way out, for [`finish_dropped`].
+static DROPPED: Mutex<Vec<Dropped>> = Mutex::new(Vec::new());
+
+/// CPU time and memory used by a run's leader and the descendants it
+/// waited for, from `wait4`.
+#[derive(Clone, Copy)]
+pub struct Usage {
+  pub user: Duration,
+  pub system: Duration,
+  /// Peak resident set size of the largest of them.
+  pub max_rss_kb: u64,
+}
+
+impl Usage {
+  fn from_rusage(usage: &libc::rusage) -> Usage {
+    let time = |t: libc::timeval| Duration::new(t.tv_sec as u64, t.tv_usec as u32 * 1000);
+    Usage {
+      user: time(usage.ru_utime),
+      system: time(usage.ru_stime),
+      max_rss_kb: usage.ru_maxrss as u64,
+    }
+  }
+}
+
+/// A spawned invocation of the command.
+pub struct Run {
+  pub id: u64,
+  /// Random identifier for the run (`--run-uuid`).
+  pub uuid: Option<String>,
+  pub child: Child,
+  pub command: String,
+  /// Name of the config job it is a run of, empty for the command-line job.
+  pub job: String,
+  /// Paths of the event that started this run.
+  pub trigger: Vec<PathBuf>,
+  /// `--log-per-run`'s file for this run.
+  pub log: Option<PathBuf>,
+  /// `--tmpdir-per-run`'s directory, deleted along with the run unless
+  /// taken out to keep it.
+  pub tmpdir: Option<PathBuf>,
+  /// What changed since the previous run (`--describe-changes`).
+  pub changes: Option<String>,
+  /// Whether this is `--on-success`'s or `--on-failure`'s command.
+  pub follow_up: bool,
+  /// The signal that stops the run if it is dropped still going, and how
+  /// long it then gets, or `None` for a `--detach`ed one, which is left to
+  /// carry on.
+  stop_on_drop: Option<(Signal, Duration)>,
+  pub started: Instant,
+  pub started_at: DateTime<Local>,
+  pub captured: Option<Arc<Captured>>,
+  /// Running readiness check, dropped to cancel it.
+  pub ready: Option<ready::Check>,
+  /// Time from spawn until the run reported ready.
+  pub ready_after: Option<Duration>,
+  status: Option<ExitStatus>,
+  /// Known once the leader has been collected.
+  pub usage: Option<Usage>,
+  /// Time from spawn until the leader's exit was noticed.
+  elapsed: Option<Duration>,
+  output_open: bool,
+  completed: bool,
+}
+
+impl Run {
+  /// The span that tags log lines about this run with its identifiers.
+  pub fn span(&self) -> Span {
+    run_span(self.id, self.uuid.as_deref())
+  }
+
+  /// How long the run took, or has been running so far.
+  pub fn duration(&self) -> Duration {
+    self.elapsed.unwrap_or_else(|| self.started.elapsed())
+  }
+
+  /// When the leader's exit was noticed.
+  pub fn ended(&self) -> Option<Instant> {
+    self.elapsed.map(|e| self.started + e)
+  }
+
+  /// Collects the leader, with its resource usage, if it has exited (or
+  /// once it has, with `block`), and returns its exit status.
+  fn reap(&mut self, block: bool) -> nix::Result<Option<ExitStatus>> {
+    if self.status.is_some() {
+      return Ok(self.status);
+    }
+    let flags = if block { 0 } else { libc::WNOHANG };
+    let mut status = 0;
+    // SAFETY: rusage is plain data, filled in by wait4
+    let mut usage: libc::rusage = unsafe { std::mem::zeroed() };
+    loop {
+      // SAFETY: both pointers are valid for the duration of the call
+      let pid = unsafe { libc::wait4(self.child.id() as i32, &mut status, flags, &mut usage) };
+      match pid {
+        0 => return Ok(None),
+        -1 if Errno::last() == Errno::EINTR => continue,
+        -1 => return Err(Errno::last()),
+        _ => break,
+      }
+    }
+    self.status = Some(ExitStatus::from_raw(status));
+    self.usage = Some(Usage::from_rusage(&usage));
+    self.elapsed = Some(self.started.elapsed());
+    Ok(self.status)
+  }
+
+  /// Marks the captured output as fully read.
+  pub fn output_closed(&mut self) {
+    self.output_open = false;
+  }
+
+  /// Returns the exit status exactly once, as soon as the leader has exited
+  /// and all of its captured output has been read.
+  pub fn take_completion(&mut self) -> Option<ExitStatus> {
+    let _ = self.reap(false);
+    if self.completed || self.output_open {
+      return None;
+    }
+    self.completed = self.status.is_some();
+    self.status
+  }
+}
+
+/// Makes the watcher the reaper for orphaned descendants of its children,
+/// so grandchildren that outlive their parent are re-parented to us rather
+/// than to init and can be collected in [`reap_orphans`].
+pub fn become_subreaper() -> nix::Result<()> {
+  nix::sys::prctl::set_child_subreaper(true)
+}
+
+/// Splits a command into words for `--shell none`: whitespace separates,
+/// single and double quotes group, and a backslash escapes one character.
+pub fn split_words(command: &str) -> Vec<String> {
+  let mut words = Vec::new();
+  let mut word: Option<String> = None;
+  let mut quote = None;
+  let mut chars = command.chars();
+  while let Some(c) = chars.next() {
+    match (c, quote) {
+      ('\\', q) if q != Some('\'') => {
+        if let Some(next) = chars.next() {
+          word.get_or_insert_default().push(next);
+        }
+      }
+      (c, Some(q)) if c == q => quote = None,
+      (c, Some(_)) => word.get_or_insert_default().push(c),
+      ('\'' | '"', None) => {
+        quote = Some(c);
+        word.get_or_insert_default();
+      }
+      (c, None) if c.is_whitespace() => words.extend(word.take()),
+      (c, None) => word.get_or_insert_default().push(c),
+    }
+  }
+  words.extend(word);
+  words
+}
+
+/// `word` quoted for `sh`.
+pub fn quote(word: &str) -> String {
+  format!("'{}'", word.replace('\'', r"'\''"))
+}
+
+/// The affinity mask for `cpus`, which must all be among the CPUs the
+/// watcher itself may use.
+pub fn cpu_set(cpus: &[usize]) -> Result<libc::cpu_set_t, String> {
+  // SAFETY: cpu_set_t is plain data, and sched_getaffinity fills it in
+  let mut allowed: libc::cpu_set_t = unsafe { std::mem::zeroed() };
+  let size = std::mem::size_of::<libc::cpu_set_t>();
+  if unsafe { libc::sched_getaffinity(0, size, &mut allowed) } != 0 {
+    return Err(io::Error::last_os_error().to_string());
+  }
+  let mut set: libc::cpu_set_t = unsafe { std::mem::zeroed() };
+  for &cpu in cpus {
+    // SAFETY: CPU_ISSET and CPU_SET are bounds-checked against the set
+    if cpu >= libc::CPU_SETSIZE as usize || !unsafe { libc::CPU_ISSET(cpu, &allowed) } {
+      return Err(format!("CPU {cpu} is not available"));
+    }
+    unsafe { libc::CPU_SET(cpu, &mut set) };
+  }
+  Ok(set)
+}
+
+impl Drop for Run {
+  fn drop(&mut self) {
+    // Whatever way the run was let go of, its group doesn't outlive it;
+    // finish_dropped sees it gone, without holding up whoever dropped it.
+    let (leader, stop) = (self.child.id() as i32, self.stop_on_drop);
+    match stop {
+      Some((signal, grace)) if matches!(self.reap(false), Ok(None)) => {
+        let _ = kill(Pid::from_raw(-leader), signal);
+        DROPPED
+          .lock()
+          .unwrap_or_else(|e| e.into_inner())
+          .push(Dropped {
+            leader,
+            kill_at: Instant::now() + grace,
+            reaped: false,
+          });
+      }
+      _ => cleanup::untrack(leader),
+    }
+    if let Some(dir) = &self.tmpdir
+      && let Err(e) = fs::remove_dir_all(dir)
+    {
+      warn!("Could not delete {}: {e}", dir.display());
+    }
+  }
+}
+
+/// Starts `command` through the configured shell (or directly, without
+/// one) in its own process group so it can be signalled as a whole.
+/// Detached runs get their own session instead, which keeps them clear of
+/// terminal job control once the watcher is gone.
+pub fn spawn(
+  opts: &Options,
+  command: &str,
+  id: u64,
+  output: OutputConfig,
+  job_output: &jobs::Output,
+  env: &[(&str, String)],
+  tx: &Sender<Msg>,
+) -> io::Result<Run> {
+  let _span = info_span!("spawn").entered();
+  let detach = opts.detach;
+  let captures = match job_output {
+    jobs::Output::Auto => output.captures(),
+    jobs::Output::Capture => true,
+    _ => false,
+  };
+  let (stdout, stderr) = match job_output {
+    _ if captures => (Stdio::piped(), Stdio::piped()),
+    jobs::Output::Null => (Stdio::null(), Stdio::null()),
+    jobs::Output::File(path) => {
+      let file = File::options()
+        .create(true)
+        .append(true)
+        .open(path)
+        .map_err(|e| io::Error::new(e.kind(), format!("{}: {e}", path.display())))?;
+      (file.try_clone()?.into(), file.into())
+    }
+    _ => (Stdio::inherit(), Stdio::inherit()),
+  };
+  let mut cmd = match &opts.shell {
+    Some(shell) => {
+      let mut cmd = Command::new(shell);
+      cmd.arg("-c").arg(command);
+      cmd
+    }
+    None => {
+      let words = split_words(command);
+      let Some((program, args)) = words.split_first() else {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, "empty command"));
+      };
+      let mut cmd = Command::new(program);
+      cmd.args(args);
+      cmd
+    }
+  };
+  cmd.envs(env.iter().map(|(k, v)| (k, v)));
+  if opts.control_stdin {
+    cmd.stdin(Stdio::null()); // the watcher's commands aren't for the run
+  }
+  let cpu_set = match &opts.cpuset {
+    Some(cpus) => Some(cpu_set(cpus).map_err(io::Error::other)?),
+    None => None,
+  };
+  let mut child = unsafe {
+    // <- acknowledge the unsafety
+    cmd
+      .stdout(stdout)
+      .stderr(stderr)
+      .pre_exec(move || {
+        // SAFETY: setsid/setpgid/sched_setaffinity/sigprocmask are
+        // async‑signal‑safe and we do nothing else here
+        if detach {
+          libc::setsid();
+        } else {
+          libc::setpgid(0, 0);
+        }
+        if let Some(set) = &cpu_set
+          && libc::sched_setaffinity(0, std::mem::size_of::<libc::cpu_set_t>(), set) != 0
+        {
+          return Err(io::Error::last_os_error());
+        }
+        signals::clear_mask()
+      })
+      .spawn()
+  }?;
+  debug!(pid = child.id(), detach, "spawned");
+  cleanup::track(child.id() as i32);
+  let started = Instant::now();
+  let captured = captures.then(|| output::capture(&mut child, id, started, &output, tx.clone()));
+  let ready = opts
+    .ready_when
+    .as_ref()
+    .map(|probe| ready::start(probe, id, tx.clone()));
+  if let Some(limit) = opts.rss_limit_kb {
+    watchdog::start(child.id(), limit, id, tx.clone());
+  }
+  Ok(Run {
+    id,
+    uuid: None,
+    ready,
+    ready_after: None,
+    output_open: captured.is_some(),
+    captured,
+    child,
+    command: command.to_string(),
+    job: String::new(),
+    trigger: Vec::new(),
+    log: None,
+    tmpdir: None,
+    changes: None,
+    follow_up: false,
+    stop_on_drop: (!detach).then_some((opts.signal, opts.drop_grace)),
+    started,
+    started_at: Local::now(),
+    status: None,
+    usage: None,
+    elapsed: None,
+    completed: false,
+  })
+}
+
+/// Creates the empty scratch directory of run `id` for `--tmpdir-per-run`,
+/// in the watcher's own temporary directory.
+pub fn create_tmpdir(id: u64) -> io::Result<PathBuf> {
+  let dir = std::env::temp_dir().join(format!(
+    "run_on_file_change-{}-run-{id}",
+    std::process::id()
+  ));
+  match fs::remove_dir_all(&dir) {
+    Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e),
+    _ => {} // left behind by an earlier watcher of the same pid
+  }
+  fs::create_dir(&dir)?;
+  Ok(dir)
+}
+
+/// The `run` span for run `id`, whose fields the log lines show.
+pub fn run_span(id: u64, uuid: Option<&str>) -> Span {
+  info_span!("run", run_id = id, run_uuid = uuid)
+}
+
+/// A version 4 UUID from the kernel's random bytes.
+pub fn uuid() -> io::Result<String> {
+  let mut bytes = [0u8; 16];
+  File::open("/dev/urandom")?.read_exact(&mut bytes)?;
+  bytes[6] = bytes[6] & 0x0f | 0x40;
+  bytes[8] = bytes[8] & 0x3f | 0x80;
+  let hex: String = bytes.iter().map(|b| format!("{b:02x}")).collect();
+  Ok(format!(
+    "{}-{}-{}-{}-{}",
+    &hex[..8],
+    &hex[8..12],
+    &hex[12..16],
+    &hex[16..20],
+    &hex[20..]
+  ))
+}
+
+/// Whether the group leader has not exited yet.
+pub fn is_running(run: &mut Run) -> bool {
+  matches!(run.reap(false), Ok(None))
+}
+
+/// Sends `signal` to the run's whole process group.
+pub fn signal_group(run: &Run, signal: Signal) {
+  let pgid = -(run.child.id() as i32); // negative ⇒ process‑group id
+  kill(Pid::from_raw(pgid), signal).ok();
+}
+
+/// "exit code 1" or "signal SIGTERM".
+pub fn describe(status: ExitStatus) -> String {
+  match (status.code(), status.signal()) {
+    (Some(code), _) => format!("exit code {code}"),
+    (None, Some(sig)) => match Signal::try_from(sig) {
+      Ok(name) => format!("signal {name}"),
+      Err(_) => format!("signal {sig}"),
+    },
+    (None, None) => status.to_string(),
+  }
+}
+
+/// "0.4s user, 0.1s system, 35.2 MB peak RSS".
+pub fn describe_usage(usage: &Usage) -> String {
+  format!(
+    "{:.1}s user, {:.1}s system, {:.1} MB peak RSS",
+    usage.user.as_secs_f64(),
+    usage.system.as_secs_f64(),
+    usage.max_rss_kb as f64 / 1024.0
+  )
+}
+
+/// Logs how a run ended, with the exit code, duration and resource usage
+/// as structured fields for journald.
+fn log_end(run: &Run, status: ExitStatus, elapsed: Option<Duration>) {
+  let _span = run.span().entered();
+  let how = describe(status);
+  let usage = run.usage.as_ref();
+  match elapsed {
+    Some(d) => info!(
+      job = %run.job,
+      exit_code = status.code(),
+      exit_signal = status.signal(),
+      duration_ms = d.as_millis() as u64,
+      user_ms = usage.map(|u| u.user.as_millis() as u64),
+      system_ms = usage.map(|u| u.system.as_millis() as u64),
+      max_rss_kb = usage.map(|u| u.max_rss_kb),
+      "Run stopped after {:.1}s ({how})",
+      d.as_secs_f64()
+    ),
+    None => info!(
+      job = %run.job,
+      exit_code = status.code(),
+      exit_signal = status.signal(),
+      user_ms = usage.map(|u| u.user.as_millis() as u64),
+      system_ms = usage.map(|u| u.system.as_millis() as u64),
+      max_rss_kb = usage.map(|u| u.max_rss_kb),
+      "Run had exited ({how})"
+    ),
+  }
+}
+
+/// A run whose group has been asked to terminate, until its leader has
+/// been collected.
+pub struct Stopping {
+  pub run: Run,
+  /// Run time up to the signal, or `None` if it had already exited.
+  elapsed: Option<Duration>,
+  /// When the leader was collected.
+  exited: Option<Instant>,
+}
+
+/// Sends `signal` to a previous run's whole process group without waiting
+/// for it; the event loop learns of the leader's exit through SIGCHLD and
+/// calls [`Stopping::poll`].
+pub fn begin_stop(mut run: Run, signal: Signal) -> Stopping {
+  let _run = run.span().entered();
+  let _span = info_span!("kill", pid = run.child.id()).entered();
+  run.ready = None;
+  let finished = run.reap(false).ok().flatten();
+  signal_group(&run, signal); // politely ask entire group
+  let elapsed = match finished {
+    Some(_) => None,
+    None => Some(run.started.elapsed()),
+  };
+  Stopping {
+    run,
+    elapsed,
+    exited: None,
+  }
+}
+
+impl Stopping {
+  /// Collects the leader if it has exited, logging how the run ended, and
+  /// returns whether it is gone.
+  pub fn poll(&mut self) -> bool {
+    if self.exited.is_some() {
+      return true;
+    }
+    let Ok(Some(status)) = self.run.reap(false) else {
+      return false;
+    };
+    log_end(&self.run, status, self.elapsed);
+    self.exited = Some(Instant::now());
+    true
+  }
+
+  /// Time since the leader was collected.
+  pub fn since_exit(&self) -> Duration {
+    self.exited.map_or(Duration::ZERO, |t| t.elapsed())
+  }
+
+  /// Whether no member of the run's process group is left. Zombies count
+  /// as members, so exited descendants must have been reaped first.
+  pub fn group_gone(&self) -> bool {
+    let pgid = -(self.run.child.id() as i32);
+    kill(Pid::from_raw(pgid), None) == Err(Errno::ESRCH)
+  }
+
+  /// Blocks until the leader has exited. Collects nothing else, so that
+  /// several can wait side by side without taking each other's leaders.
+  fn wait(mut self) -> Self {
+    if !self.poll() {
+      let _ = self.run.reap(true);
+      self.poll();
+    }
+    self
+  }
+
+  /// The leader's exit status if stopping cut the run short, i.e. it had
+  /// not completed on its own.
+  pub fn interrupted(&self) -> Option<ExitStatus> {
+    if self.run.completed {
+      None
+    } else {
+      self.run.status
+    }
+  }
+}
+
+/// Terminates `runs` and blocks until their leaders, and those of the
+/// `stopping` ones, have exited, then collects every other descendant that
+/// has exited in the meantime. Used where the event loop is no longer
+/// running (shutdown, reload). All groups are signalled first and waited
+/// for on a thread each, so a slow job doesn't hold up the others' stop.
+pub fn stop_all(runs: Vec<Run>, mut stopping: Vec<Stopping>, signal: Signal) -> Vec<Stopping> {
+  stopping.extend(runs.into_iter().map(|run| begin_stop(run, signal)));
+  let stopped = thread::scope(|scope| {
+    let waits: Vec<_> = stopping
+      .into_iter()
+      .map(|s| scope.spawn(move || s.wait()))
+      .collect();
+    waits
+      .into_iter()
+      .map(|w| w.join().expect("waiting for a run panicked"))
+      .collect()
+  });
+  while let Some(wait) = finish_dropped() {
+    thread::sleep(wait);
+  }
+  reap_descendants();
+  stopped
+}
+
+/// Collects the leaders of dropped runs that have exited, and kills the
+/// groups whose grace is up. Returns when to look again, or `None` once
+/// they are all gone.
+pub fn finish_dropped() -> Option<Duration> {
+  let mut dropped = DROPPED.lock().unwrap_or_else(|e| e.into_inner());
+  let now = Instant::now();
+  dropped.retain_mut(|d| {
+    let leader = Pid::from_raw(d.leader);
+    if !d.reaped {
+      d.reaped = !matches!(
+        waitpid(leader, Some(WaitPidFlag::WNOHANG)),
+        Ok(WaitStatus::StillAlive) | Err(Errno::EINTR)
+      );
+    }
+    let overdue = now >= d.kill_at;
+    if overdue {
+      let _ = kill(Pid::from_raw(-d.leader), Signal::SIGKILL);
+      if !d.reaped {
+        let _ = waitpid(leader, None);
+      }
+    }
+    let gone = overdue || (d.reaped && kill(Pid::from_raw(-d.leader), None) == Err(Errno::ESRCH));
+    if gone {
+      cleanup::untrack(d.leader);
+    }
+    !gone
+  });
+  dropped
+    .iter()
+    .map(|d| d.kill_at.saturating_duration_since(now))
+    .min()
+    .map(|until| until.min(DROPPED_POLL))
+}
+
+/// Whether nothing is listening on `port` any more, so a restarted server
+/// can bind it. Sockets in TIME_WAIT don't count, as servers normally set
+/// SO_REUSEADDR (std's listener does too).
+pub fn port_free(port: u16) -> bool {
+  TcpListener::bind((Ipv4Addr::UNSPECIFIED, port)).is_ok()
+}
+
+/// The inodes of the sockets listening on TCP `port`, from /proc.
+fn listening_sockets(port: u16) -> Vec<String> {
+  let mut inodes = Vec::new();
+  for table in ["/proc/net/tcp", "/proc/net/tcp6"] {
+    let Ok(table) = fs::read_to_string(table) else {
+      continue;
+    };
+    for line in table.lines().skip(1) {
+      // sl local_address rem_address st tx:rx tr:when retrnsmt uid timeout inode
+      let cols: Vec<&str> = line.split_whitespace().collect();
+      let local = cols.get(1).and_then(|a| a.rsplit_once(':'));
+      let listens = local.is_some_and(|(_, p)| u16::from_str_radix(p, 16) == Ok(port))
+        && cols.get(3) == Some(&"0A");
+      if listens && let Some(inode) = cols.get(9) {
+        inodes.push(inode.to_string());
+      }
+    }
+  }
+  inodes
+}
+
+/// Which process listens on TCP `port`, as "pid 4242 (python3 -m
+/// http.server)", if /proc lets us see it.
+fn port_owner(port: u16) -> Option<String> {
+  let sockets: Vec<String> = listening_sockets(port)
+    .iter()
+    .map(|inode| format!("socket:[{inode}]"))
+    .collect();
+  if sockets.is_empty() {
+    return None;
+  }
+  for entry in fs::read_dir("/proc").ok()?.flatten() {
+    let Some(pid) = entry
+      .file_name()
+      .to_str()
+      .and_then(|s| s.parse::<u32>().ok())
+    else {
+      continue;
+    };
+    let Ok(fds) = fs::read_dir(entry.path().join("fd")) else {
+      continue;
+    };
+    let owns = fds.flatten().any(|fd| {
+      fs::read_link(fd.path())
+        .is_ok_and(|link| sockets.iter().any(|s| link.as_os_str() == s.as_str()))
+    });
+    if owns {
+      let cmdline = fs::read(entry.path().join("cmdline")).unwrap_or_default();
+      let cmdline = String::from_utf8_lossy(&cmdline).replace('\0', " ");
+      return Some(format!("pid {pid} ({})", cmdline.trim()));
+    }
+  }
+  None
+}
+
+/// "Port 8080 is in use by pid 4242 (…)", or without the owner if it
+/// can't be found.
+pub fn describe_port(port: u16) -> String {
+  match port_owner(port) {
+    Some(owner) => format!("Port {port} is in use by {owner}"),
+    None => format!("Port {port} is in use"),
+  }
+}
+
+/// Reaps what a stopped group left behind. Only once the leaders of all
+/// runs have been collected: see [`reap_orphans`].
+pub fn reap_descendants() {
+  let reaped = reap_orphans();
+  if reaped > 0 {
+    info!("Reaped {reaped} orphaned descendant(s)");
+  }
+}
+
+/// A hook command started in its own process group, so it can be killed
+/// as a whole if it overruns its limit. Killed if dropped still going.
+pub struct Hook {
+  name: &'static str,
+  child: Child,
+  limit: Duration,
+  deadline: Instant,
+  /// Whether it succeeded, once it is over.
+  outcome: Option<bool>,
+}
+
+impl Hook {
+  pub fn start(
+    name: &'static str,
+    command: &str,
+    env: &[(&str, String)],
+    limit: Duration,
+  ) -> io::Result<Hook> {
+    let _span = info_span!("hook", name).entered();
+    info!("Running {name} hook: {command}");
+    let child = unsafe {
+      Command::new("/bin/sh")
+        .arg("-c")
+        .arg(command)
+        .envs(env.iter().map(|(k, v)| (k, v)))
+        .pre_exec(|| {
+          // SAFETY: setpgid/sigprocmask are async‑signal‑safe and we do nothing else here
+          if libc::setpgid(0, 0) != 0 {
+            return Err(io::Error::last_os_error());
+          }
+          signals::clear_mask()
+        })
+        .spawn()
+    }?;
+    Ok(Hook {
+      name,
+      child,
+      limit,
+      deadline: Instant::now() + limit,
+      outcome: None,
+    })
+  }
+
+  /// Whether the hook succeeded, once it is over; one that overruns its
+  /// limit is killed and counts as failed.
+  pub fn poll(&mut self) -> Option<bool> {
+    if self.outcome.is_some() {
+      return self.outcome;
+    }
+    let (name, child) = (self.name, &mut self.child);
+    let success = match child.try_wait() {
+      Ok(Some(status)) => {
+        if !status.success() {
+          warn!("The {name} hook failed ({})", describe(status));
+        }
+        status.success()
+      }
+      Ok(None) if Instant::now() < self.deadline => return None,
+      Ok(None) => {
+        warn!(
+          "{name} hook exceeded {:.1}s, killing it",
+          self.limit.as_secs_f64()
+        );
+        kill(Pid::from_raw(-(child.id() as i32)), Signal::SIGKILL).ok();
+        let _ = child.wait();
+        false
+      }
+      Err(e) => {
+        warn!("Could not wait for the {name} hook: {e}");
+        false
+      }
+    };
+    self.outcome = Some(success);
+    self.outcome
+  }
+
+  /// Waits for the hook to be over, and returns whether it succeeded.
+  pub fn wait(mut self) -> bool {
+    loop {
+      if let Some(success) = self.poll() {
+        return success;
+      }
+      thread::sleep(Duration::from_millis(50));
+    }
+  }
+}
+
+impl Drop for Hook {
+  fn drop(&mut self) {
+    if self.outcome.is_none() {
+      kill(Pid::from_raw(-(self.child.id() as i32)), Signal::SIGKILL).ok();
+      let _ = self.child.wait();
+    }
+  }
+}
+
+/// Runs a one-off hook command to completion, as a [`Hook`]. Returns
+/// whether it succeeded.
+pub fn run_hook(
+  name: &'static str,
+  command: &str,
+  env: &[(&str, String)],
+  limit: Duration,
+) -> bool {
+  match Hook::start(name, command, env, limit) {
+    Ok(hook) => hook.wait(),
+    Err(e) => {
+      warn!("Could not start {name} hook: {e}");
+      false
+    }
+  }
+}
+
+/// Orphaned descendants that have exited and wait to be collected: zombie
+/// children outside the watcher's own process group. Helpers such as
+/// dbus-send or git stay in that group and are left to the threads that
+/// wait for them.
+fn exited_orphans() -> Vec<Pid> {
+  let (me, group) = (std::process::id(), nix::unistd::getpgrp().as_raw());
+  let Ok(entries) = fs::read_dir("/proc") else {
+    return Vec::new();
+  };
+  entries
+    .flatten()
+    .filter_map(|entry| {
+      let pid = entry.file_name().to_str()?.parse::<i32>().ok()?;
+      let stat = fs::read_to_string(entry.path().join("stat")).ok()?;
+      // After the command in parentheses: state, ppid, pgrp.
+      let mut fields = stat[stat.rfind(')')? + 1..].split_whitespace();
+      let state = fields.next()?;
+      let ppid = fields.next()?.parse::<u32>().ok()?;
+      let pgrp = fields.next()?.parse::<i32>().ok()?;
+      (state == "Z" && ppid == me && pgrp != group).then(|| Pid::from_raw(pid))
+    })
+    .collect()
+}
+
+/// Collects terminated orphans without blocking and returns how many
+/// were reaped. Must only be called while no run is active, since it would
+/// otherwise steal the exit status of the current group leader.
+pub fn reap_orphans() -> usize {
+  let mut reaped = 0;
+  for pid in exited_orphans() {
+    loop {
+      match waitpid(pid, Some(WaitPidFlag::WNOHANG)) {
+        Ok(WaitStatus::StillAlive) | Err(Errno::ECHILD) => break,
+        Ok(_) => {
+          reaped += 1;
+          break;
+        }
+        Err(Errno::EINTR) => continue,
+        Err(e) => {
+          warn!("waitpid failed: {e}");
+          break;
+        }
+      }
+    }
+  }
+  reaped
+}
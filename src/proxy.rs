@@ -0,0 +1,96 @@
+// Hold-and-forward reverse proxy (`--proxy FRONT->BACK`).
+//
+// Connections to the front port wait while the backend is being rebuilt
+// or restarted and are forwarded once its readiness check has passed, so
+// the browser sees a slow response instead of "connection refused".
+
+use std::{
+  io,
+  net::{Ipv4Addr, Shutdown, TcpListener, TcpStream},
+  sync::{Arc, Condvar, Mutex},
+  thread,
+  time::Duration,
+};
+use tracing::{debug, info, warn};
+
+/// How long a held connection waits for the backend before giving up.
+const HOLD_LIMIT: Duration = Duration::from_secs(60);
+
+/// `FRONT->BACK` or `FRONT:BACK`.
+pub fn parse(s: &str) -> Option<(u16, u16)> {
+  let (front, back) = s.split_once("->").or_else(|| s.split_once(':'))?;
+  Some((front.trim().parse().ok()?, back.trim().parse().ok()?))
+}
+
+#[derive(Clone)]
+pub struct Proxy {
+  backend: u16,
+  ready: Arc<(Mutex<bool>, Condvar)>,
+}
+
+impl Proxy {
+  /// Starts accepting on `front`; connections are held until [`release`].
+  ///
+  /// [`release`]: Proxy::release
+  pub fn start(front: u16, backend: u16) -> io::Result<Proxy> {
+    let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, front))?;
+    let proxy = Proxy {
+      backend,
+      ready: Arc::new((Mutex::new(false), Condvar::new())),
+    };
+    let accepting = proxy.clone();
+    thread::spawn(move || {
+      for stream in listener.incoming().flatten() {
+        let proxy = accepting.clone();
+        thread::spawn(move || proxy.forward(stream));
+      }
+    });
+    info!("Proxying http://localhost:{front}/ to port {backend}");
+    Ok(proxy)
+  }
+
+  /// Holds new connections while the backend restarts.
+  pub fn hold(&self) {
+    *self.ready.0.lock().unwrap() = false;
+  }
+
+  /// Lets held and new connections through to the backend.
+  pub fn release(&self) {
+    *self.ready.0.lock().unwrap() = true;
+    self.ready.1.notify_all();
+  }
+
+  fn forward(&self, client: TcpStream) {
+    let (lock, changed) = &*self.ready;
+    let (ready, timeout) = changed
+      .wait_timeout_while(lock.lock().unwrap(), HOLD_LIMIT, |ready| !*ready)
+      .unwrap();
+    drop(ready);
+    if timeout.timed_out() {
+      warn!(
+        "Backend not ready after {}s, dropping a held connection",
+        HOLD_LIMIT.as_secs()
+      );
+      return;
+    }
+    let backend = match TcpStream::connect((Ipv4Addr::LOCALHOST, self.backend)) {
+      Ok(backend) => backend,
+      Err(e) => {
+        debug!("proxy could not reach backend: {e}");
+        return;
+      }
+    };
+    let (Ok(mut client_read), Ok(mut backend_write)) = (client.try_clone(), backend.try_clone())
+    else {
+      return;
+    };
+    let upstream = thread::spawn(move || {
+      let _ = io::copy(&mut client_read, &mut backend_write);
+      let _ = backend_write.shutdown(Shutdown::Write);
+    });
+    let (mut backend_read, mut client_write) = (backend, client);
+    let _ = io::copy(&mut backend_read, &mut client_write);
+    let _ = client_write.shutdown(Shutdown::Write);
+    let _ = upstream.join();
+  }
+}
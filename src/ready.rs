@@ -0,0 +1,113 @@
+// Deciding when a restarted server is ready (`--ready-when`).
+//
+// Network probes run on a thread of their own and report through the event
+// loop's channel; log patterns are matched by the output readers instead.
+
+use crate::Msg;
+use crate::http;
+use regex::Regex;
+use std::{
+  io::{Read, Write},
+  net::{TcpStream, ToSocketAddrs},
+  sync::{
+    Arc,
+    atomic::{AtomicBool, Ordering},
+    mpsc::Sender,
+  },
+  thread,
+  time::Duration,
+};
+
+const INTERVAL: Duration = Duration::from_millis(100);
+const CONNECT_TIMEOUT: Duration = Duration::from_millis(500);
+
+#[derive(Clone)]
+pub enum Probe {
+  /// Something accepts connections on this local port.
+  Tcp(u16),
+  /// A GET of `path` on `host:port` answers with a 2xx or 3xx status.
+  Http {
+    host: String,
+    port: u16,
+    path: String,
+  },
+  /// The run prints a line matching this pattern.
+  Log(Regex),
+}
+
+impl Probe {
+  /// `tcp:PORT`, `http:[//]HOST[:PORT][/PATH]` or `log:REGEX`.
+  pub fn parse(s: &str) -> Result<Probe, String> {
+    let (kind, arg) = s
+      .split_once(':')
+      .ok_or_else(|| format!("unknown readiness check {s:?}"))?;
+    match kind {
+      "tcp" => arg
+        .parse()
+        .map(Probe::Tcp)
+        .map_err(|_| format!("invalid port {arg:?}")),
+      "http" => {
+        let (host, port, path) =
+          http::parse_url(arg).ok_or_else(|| format!("invalid URL in {s:?}"))?;
+        Ok(Probe::Http { host, port, path })
+      }
+      "log" => Regex::new(arg)
+        .map(Probe::Log)
+        .map_err(|e| format!("invalid pattern {arg:?}: {e}")),
+      _ => Err(format!("unknown readiness check {s:?}")),
+    }
+  }
+
+  fn succeeds(&self) -> bool {
+    match self {
+      Probe::Tcp(port) => connect("localhost", *port).is_some(),
+      Probe::Http { host, port, path } => {
+        let Some(mut stream) = connect(host, *port) else {
+          return false;
+        };
+        let _ = stream.set_read_timeout(Some(CONNECT_TIMEOUT));
+        let request = format!("GET {path} HTTP/1.0\r\nHost: {host}\r\nConnection: close\r\n\r\n");
+        let mut head = [0; 12]; // "HTTP/1.x NNN"
+        stream.write_all(request.as_bytes()).is_ok()
+          && stream.read_exact(&mut head).is_ok()
+          && matches!(head[9], b'2' | b'3')
+      }
+      Probe::Log(_) => false,
+    }
+  }
+}
+
+fn connect(host: &str, port: u16) -> Option<TcpStream> {
+  (host, port)
+    .to_socket_addrs()
+    .ok()?
+    .find_map(|addr| TcpStream::connect_timeout(&addr, CONNECT_TIMEOUT).ok())
+}
+
+/// Keeps a network probe running until dropped.
+pub struct Check {
+  cancelled: Arc<AtomicBool>,
+}
+
+impl Drop for Check {
+  fn drop(&mut self) {
+    self.cancelled.store(true, Ordering::Release);
+  }
+}
+
+/// Polls `probe` until it succeeds, then sends [`Msg::Ready`] for `run_id`.
+pub fn start(probe: &Probe, run_id: u64, tx: Sender<Msg>) -> Check {
+  let cancelled = Arc::new(AtomicBool::new(false));
+  let flag = Arc::clone(&cancelled);
+  let probe = probe.clone();
+  thread::spawn(move || {
+    while !flag.load(Ordering::Acquire) {
+      if probe.succeeds() {
+        let _ = tx.send(Msg::Ready(run_id));
+        return;
+      }
+      thread::sleep(INTERVAL);
+    }
+  });
+  Check { cancelled }
+}
@@ -0,0 +1,107 @@
+// Recording the raw events the watcher receives (`--record FILE`) and
+// playing them back (`--replay FILE`), to reproduce a "why did it trigger
+// twice" report on another machine.
+//
+// The file has a JSON object per line: {"t_ms": time since the watcher
+// started, "at": wall-clock time, "event": notify's event}. A replay
+// watches nothing itself and feeds the events back with their original
+// spacing, divided by `--replay-speed` (0 for no pauses at all).
+
+use chrono::Local;
+use notify::Event;
+use run_on_file_change::source::{EventSource, Events};
+use serde::{Deserialize, Serialize};
+use std::{
+  fs::File,
+  io::{self, BufRead, BufReader, BufWriter, Write},
+  path::{Path, PathBuf},
+  thread,
+  time::{Duration, Instant},
+};
+use tracing::{info, warn};
+
+#[derive(Serialize, Deserialize)]
+struct Entry {
+  t_ms: u64,
+  #[serde(default)]
+  at: String,
+  event: Event,
+}
+
+pub struct Recorder {
+  out: BufWriter<File>,
+  started: Instant,
+}
+
+impl Recorder {
+  pub fn create(path: &Path) -> io::Result<Recorder> {
+    Ok(Recorder {
+      out: BufWriter::new(File::create(path)?),
+      started: Instant::now(),
+    })
+  }
+
+  pub fn record(&mut self, event: &Event) {
+    let entry = Entry {
+      t_ms: self.started.elapsed().as_millis() as u64,
+      at: Local::now().to_rfc3339(),
+      event: event.clone(),
+    };
+    let line = serde_json::to_string(&entry).expect("serializable");
+    // Flushed line by line so a crash keeps what led up to it.
+    if let Err(e) = writeln!(self.out, "{line}").and_then(|_| self.out.flush()) {
+      warn!("Could not record event: {e}");
+    }
+  }
+}
+
+/// `--replay`: sends the events of the recording at `path` on a thread of
+/// their own, `speed` times as fast as they came.
+pub struct Replay {
+  pub path: PathBuf,
+  pub speed: f64,
+}
+
+impl Replay {
+  fn read(&self) -> io::Result<Vec<Entry>> {
+    let mut entries = Vec::new();
+    for (n, line) in BufReader::new(File::open(&self.path)?).lines().enumerate() {
+      let line = line?;
+      if line.trim().is_empty() {
+        continue;
+      }
+      let entry: Entry = serde_json::from_str(&line)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, format!("line {}: {e}", n + 1)))?;
+      entries.push(entry);
+    }
+    Ok(entries)
+  }
+}
+
+impl EventSource for Replay {
+  fn name(&self) -> &'static str {
+    "replay"
+  }
+
+  fn start(self: Box<Self>, events: Events) -> Result<(), String> {
+    let entries = self
+      .read()
+      .map_err(|e| format!("could not replay {}: {e}", self.path.display()))?;
+    info!("Replaying {} recorded event(s)", entries.len());
+    let speed = self.speed;
+    thread::spawn(move || {
+      let started = Instant::now();
+      for entry in entries {
+        if speed > 0.0 {
+          let due = Duration::from_millis(entry.t_ms).div_f64(speed);
+          thread::sleep(due.saturating_sub(started.elapsed()));
+        }
+        if !events.send(entry.event) {
+          return;
+        }
+      }
+      info!("Replay finished");
+    });
+    Ok(())
+  }
+}
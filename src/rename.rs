@@ -0,0 +1,162 @@
+// Pairing the two halves of a rename.
+//
+// Backends report a rename as a `From` event for the old path and a `To`
+// event for the new one, linked by a tracker (inotify's cookie). The halves
+// are joined into a single `Both` event carrying `[old, new]`; a half whose
+// partner doesn't show up in time (moved out of or into the watched tree)
+// goes on alone.
+
+use notify::Event;
+use notify::event::{EventKind, ModifyKind, RenameMode};
+use std::time::Duration;
+
+/// How long the `From` half waits for its partner.
+pub const WAIT: Duration = Duration::from_millis(100);
+
+#[derive(Default)]
+pub struct Renames {
+  /// A `From` half waiting for its `To`.
+  held: Option<Event>,
+  /// Trackers paired here, whose backend-made `Both` event is redundant.
+  paired: Vec<usize>,
+}
+
+fn mode(event: &Event) -> Option<RenameMode> {
+  match event.kind {
+    EventKind::Modify(ModifyKind::Name(mode)) => Some(mode),
+    _ => None,
+  }
+}
+
+impl Renames {
+  /// Takes an event from the backend and returns the events to handle now.
+  pub fn feed(&mut self, event: Event) -> Vec<Event> {
+    let tracker = event.tracker();
+    match (mode(&event), tracker) {
+      (Some(RenameMode::From), Some(_)) => self.held.replace(event).into_iter().collect(),
+      (Some(RenameMode::To), Some(t)) => {
+        let Some(from) = self.held.take_if(|held| held.tracker() == Some(t)) else {
+          return vec![event];
+        };
+        self.paired.push(t);
+        if self.paired.len() > 16 {
+          self.paired.remove(0);
+        }
+        let mut both = Event::new(EventKind::Modify(ModifyKind::Name(RenameMode::Both)))
+          .set_tracker(t)
+          .add_some_path(from.paths.into_iter().next());
+        both.paths.extend(event.paths);
+        vec![both]
+      }
+      (Some(RenameMode::Both), Some(t)) if self.paired.contains(&t) => {
+        self.paired.retain(|&p| p != t);
+        Vec::new()
+      }
+      _ => vec![event],
+    }
+  }
+
+  pub fn waiting(&self) -> bool {
+    self.held.is_some()
+  }
+
+  /// The held half, once its partner is overdue, without its tracker so it
+  /// goes through [`feed`](Renames::feed) unchanged.
+  pub fn expire(&mut self) -> Option<Event> {
+    let held = self.held.take()?;
+    let mut event = Event::new(held.kind);
+    event.paths = held.paths;
+    Some(event)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use std::path::PathBuf;
+
+  fn rename(mode: RenameMode, tracker: usize, paths: &[&str]) -> Event {
+    let mut event = Event::new(EventKind::Modify(ModifyKind::Name(mode))).set_tracker(tracker);
+    event.paths = paths.iter().map(PathBuf::from).collect();
+    event
+  }
+
+  #[test]
+  fn pairs_halves() {
+    let mut renames = Renames::default();
+    assert!(renames.feed(rename(RenameMode::From, 1, &["a"])).is_empty());
+    assert!(renames.waiting());
+    let out = renames.feed(rename(RenameMode::To, 1, &["b"]));
+    assert!(!renames.waiting());
+    assert_eq!(out.len(), 1);
+    assert_eq!(mode(&out[0]), Some(RenameMode::Both));
+    assert_eq!(out[0].paths, [PathBuf::from("a"), PathBuf::from("b")]);
+    // The backend's own `Both` for the same rename is dropped.
+    assert!(
+      renames
+        .feed(rename(RenameMode::Both, 1, &["a", "b"]))
+        .is_empty()
+    );
+    assert_eq!(
+      renames.feed(rename(RenameMode::Both, 2, &["c", "d"])).len(),
+      1
+    );
+  }
+
+  #[test]
+  fn unpaired_halves_go_alone() {
+    let mut renames = Renames::default();
+    renames.feed(rename(RenameMode::From, 1, &["a"]));
+    // A new `From` lets the held one go.
+    let out = renames.feed(rename(RenameMode::From, 2, &["b"]));
+    assert_eq!(out[0].paths, [PathBuf::from("a")]);
+    // A `To` for another tracker passes through and leaves it held.
+    let out = renames.feed(rename(RenameMode::To, 3, &["c"]));
+    assert_eq!(out[0].paths, [PathBuf::from("c")]);
+    assert!(renames.waiting());
+    let expired = renames.expire().unwrap();
+    assert_eq!(expired.paths, [PathBuf::from("b")]);
+    assert_eq!(expired.tracker(), None);
+    assert!(!renames.waiting());
+    assert!(renames.expire().is_none());
+    // Without its tracker it goes through unchanged.
+    assert_eq!(renames.feed(expired).len(), 1);
+    assert!(!renames.waiting());
+  }
+
+  #[test]
+  fn other_events_pass_through() {
+    let mut renames = Renames::default();
+    let mut untracked = rename(RenameMode::From, 0, &["a"]);
+    untracked.attrs = Default::default();
+    assert_eq!(renames.feed(untracked).len(), 1);
+    assert!(!renames.waiting());
+    renames.feed(rename(RenameMode::From, 1, &["a"]));
+    let create = Event::new(EventKind::Create(notify::event::CreateKind::File));
+    assert_eq!(renames.feed(create).len(), 1);
+    assert!(renames.waiting());
+    // A `Both` nothing was paired for is kept.
+    assert_eq!(
+      renames.feed(rename(RenameMode::Both, 9, &["x", "y"])).len(),
+      1
+    );
+  }
+
+  #[test]
+  fn remembers_recent_pairs_only() {
+    let mut renames = Renames::default();
+    for t in 1..=17 {
+      renames.feed(rename(RenameMode::From, t, &["a"]));
+      assert_eq!(renames.feed(rename(RenameMode::To, t, &["b"])).len(), 1);
+    }
+    assert_eq!(
+      renames.feed(rename(RenameMode::Both, 1, &["a", "b"])).len(),
+      1
+    );
+    assert!(
+      renames
+        .feed(rename(RenameMode::Both, 17, &["a", "b"]))
+        .is_empty()
+    );
+  }
+}
@@ -0,0 +1,105 @@
+// Keeping the watcher's log files in bounds (`--keep-logs`,
+// `--max-log-size`), so a long-lived watcher doesn't fill the disk.
+//
+// Single files (`--log-target file:…`, `--tee`) are rotated once they
+// outgrow the size limit, to LOG.1, LOG.2 and so on, keeping that many old
+// files. Per-run logs (`--log-per-run`) are pruned oldest first, down to
+// the count and the total size.
+
+use std::{
+  fs::{self, File},
+  io::{self, Write},
+  path::{Path, PathBuf},
+};
+
+/// Rotated files kept when only a size limit is given.
+const DEFAULT_KEEP: usize = 5;
+
+#[derive(Clone, Copy, Default)]
+pub struct Retention {
+  pub keep: Option<usize>,
+  pub max_size: Option<u64>,
+}
+
+/// A file appended to, rotated as `retention` says.
+pub struct RotatingFile {
+  path: PathBuf,
+  file: File,
+  written: u64,
+  retention: Retention,
+}
+
+fn append(path: &Path) -> io::Result<File> {
+  File::options().create(true).append(true).open(path)
+}
+
+/// `LOG.n`.
+fn numbered(path: &Path, n: usize) -> PathBuf {
+  let mut name = path.as_os_str().to_owned();
+  name.push(format!(".{n}"));
+  PathBuf::from(name)
+}
+
+impl RotatingFile {
+  pub fn open(path: &Path, retention: Retention) -> io::Result<RotatingFile> {
+    let file = append(path)?;
+    Ok(RotatingFile {
+      path: path.to_path_buf(),
+      written: file.metadata()?.len(),
+      file,
+      retention,
+    })
+  }
+
+  /// Moves the current file aside and starts a new one.
+  fn rotate(&mut self) -> io::Result<()> {
+    let keep = self.retention.keep.unwrap_or(DEFAULT_KEEP);
+    let _ = fs::remove_file(numbered(&self.path, keep.max(1)));
+    for n in (1..keep).rev() {
+      let _ = fs::rename(numbered(&self.path, n), numbered(&self.path, n + 1));
+    }
+    match keep {
+      0 => fs::remove_file(&self.path)?,
+      _ => fs::rename(&self.path, numbered(&self.path, 1))?,
+    }
+    self.file = append(&self.path)?;
+    self.written = 0;
+    Ok(())
+  }
+}
+
+impl Write for RotatingFile {
+  fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+    if let Some(max) = self.retention.max_size
+      && self.written > 0
+      && self.written + buf.len() as u64 > max
+    {
+      self.rotate()?;
+    }
+    let n = self.file.write(buf)?;
+    self.written += n as u64;
+    Ok(n)
+  }
+
+  fn flush(&mut self) -> io::Result<()> {
+    self.file.flush()
+  }
+}
+
+/// Deletes the oldest of `files` (oldest first, with their sizes) until
+/// no more than `retention` allows are left. The newest is always kept.
+pub fn prune(files: &[(PathBuf, u64)], retention: Retention) {
+  let mut count = files.len();
+  let mut total: u64 = files.iter().map(|(_, size)| size).sum();
+  for (path, size) in &files[..files.len().saturating_sub(1)] {
+    let over_count = retention.keep.is_some_and(|keep| count > keep);
+    let over_size = retention.max_size.is_some_and(|max| total > max);
+    if !over_count && !over_size {
+      break;
+    }
+    if fs::remove_file(path).is_ok() {
+      count -= 1;
+      total -= size;
+    }
+  }
+}
@@ -0,0 +1,108 @@
+// A log file for each run (`--log-per-run <DIR>`): run-0001.log,
+// run-0002.log, … with a header saying what ran and why, the captured
+// output, and a footer with how it ended. Numbering carries on from the
+// files already in the directory, so a restarted watcher doesn't
+// overwrite them. Old ones are pruned as `--keep-logs` and
+// `--max-log-size` say.
+
+use crate::process;
+use crate::rotate::{self, Retention};
+use chrono::Local;
+use std::{
+  fs::{self, File},
+  io::{self, Write},
+  path::{Path, PathBuf},
+  process::ExitStatus,
+  time::Duration,
+};
+
+pub struct RunLogs {
+  dir: PathBuf,
+  /// Added to run ids to number the files.
+  offset: u64,
+  retention: Retention,
+}
+
+/// The number in a `run-NNNN.log` file name.
+fn number(name: &str) -> Option<u64> {
+  name
+    .strip_prefix("run-")?
+    .strip_suffix(".log")?
+    .parse()
+    .ok()
+}
+
+impl RunLogs {
+  pub fn new(dir: PathBuf, retention: Retention) -> io::Result<RunLogs> {
+    fs::create_dir_all(&dir)?;
+    let offset = fs::read_dir(&dir)?
+      .flatten()
+      .filter_map(|e| number(&e.file_name().to_string_lossy()))
+      .max()
+      .unwrap_or(0);
+    Ok(RunLogs {
+      dir,
+      offset,
+      retention,
+    })
+  }
+
+  /// Deletes the oldest logs beyond the retention limits.
+  fn prune(&self) {
+    let Ok(entries) = fs::read_dir(&self.dir) else {
+      return;
+    };
+    let mut logs: Vec<(u64, PathBuf, u64)> = entries
+      .flatten()
+      .filter_map(|e| {
+        let n = number(&e.file_name().to_string_lossy())?;
+        Some((n, e.path(), e.metadata().ok()?.len()))
+      })
+      .collect();
+    logs.sort();
+    let files: Vec<(PathBuf, u64)> = logs.into_iter().map(|(_, p, size)| (p, size)).collect();
+    rotate::prune(&files, self.retention);
+  }
+
+  /// Creates the file for run `id` and writes its header.
+  pub fn create(
+    &self,
+    id: u64,
+    job: &str,
+    command: &str,
+    trigger: &[PathBuf],
+  ) -> io::Result<(PathBuf, File)> {
+    let path = self.dir.join(format!("run-{:04}.log", self.offset + id));
+    let mut file = File::create(&path)?;
+    self.prune();
+    writeln!(file, "# run {id}: {command}")?;
+    if !job.is_empty() {
+      writeln!(file, "# job: {job}")?;
+    }
+    writeln!(file, "# started: {}", Local::now().to_rfc3339())?;
+    match trigger {
+      [] => writeln!(file, "# trigger: none")?,
+      paths => {
+        writeln!(file, "# trigger:")?;
+        for path in paths {
+          writeln!(file, "#   {}", path.display())?;
+        }
+      }
+    }
+    writeln!(file)?;
+    Ok((path, file))
+  }
+}
+
+/// Appends how the run ended to its log.
+pub fn finish(path: &Path, status: ExitStatus, duration: Duration) {
+  let footer = format!(
+    "\n# ended after {:.1}s ({})\n",
+    duration.as_secs_f64(),
+    process::describe(status)
+  );
+  let _ = File::options()
+    .append(true)
+    .open(path)
+    .and_then(|mut file| file.write_all(footer.as_bytes()));
+}
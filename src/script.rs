@@ -0,0 +1,91 @@
+// A Rhai script deciding what a change runs (`--script`).
+//
+//   fn on_event(event) {
+//     // event.paths: the changed paths, event.path: the first of them,
+//     // event.kind: "create", "modify", "remove" or "rename",
+//     // event.job: the job the change would run ("" for the command line).
+//     if event.path.ends_with(".rs") {
+//       return read(event.path).contains("#[test]");
+//     }
+//     true
+//   }
+//
+// `on_event` returns true to run, false to skip, or a string to run as the
+// command instead. `read(path)` gives a file's text ("" if unreadable).
+
+use crate::watch;
+use notify::EventKind;
+use rhai::{AST, Array, Dynamic, Engine, Map, Scope};
+use std::{fs, path::Path, path::PathBuf};
+use tracing::warn;
+
+/// Bounds a script's work per event, so a runaway loop can't hang the
+/// watcher.
+const MAX_OPERATIONS: u64 = 10_000_000;
+
+pub enum Verdict {
+  Skip,
+  Run,
+  /// Run this command instead of the job's.
+  Command(String),
+}
+
+pub struct Script {
+  engine: Engine,
+  ast: AST,
+}
+
+impl Script {
+  pub fn load(path: &Path) -> Result<Script, String> {
+    let mut engine = Engine::new();
+    engine.set_max_operations(MAX_OPERATIONS);
+    engine.register_fn("read", |path: &str| {
+      fs::read_to_string(path).unwrap_or_default()
+    });
+    let ast = engine
+      .compile_file(path.to_path_buf())
+      .map_err(|e| format!("{}: {e}", path.display()))?;
+    if !ast
+      .iter_functions()
+      .any(|f| f.name == "on_event" && f.params.len() == 1)
+    {
+      return Err(format!("{}: no on_event(event) function", path.display()));
+    }
+    Ok(Script { engine, ast })
+  }
+
+  /// Asks the script about a change of `kind` to `paths` for `job`. A
+  /// script that fails or returns something else lets the run go ahead.
+  pub fn judge(&self, job: &str, kind: &EventKind, paths: &[PathBuf]) -> Verdict {
+    let paths: Array = paths
+      .iter()
+      .map(|p| Dynamic::from(p.display().to_string()))
+      .collect();
+    let mut event = Map::new();
+    event.insert("path".into(), paths.first().cloned().unwrap_or_default());
+    event.insert("paths".into(), paths.into());
+    event.insert("kind".into(), watch::kind_name(kind).into());
+    event.insert("job".into(), job.into());
+    let result = self
+      .engine
+      .call_fn::<Dynamic>(&mut Scope::new(), &self.ast, "on_event", (event,));
+    match result {
+      Ok(value) if value.is_bool() => match value.as_bool() {
+        Ok(true) => Verdict::Run,
+        _ => Verdict::Skip,
+      },
+      Ok(value) if value.is_string() => Verdict::Command(value.to_string()),
+      Ok(value) => {
+        warn!(
+          "on_event returned a {}, not a bool or string; running",
+          value.type_name()
+        );
+        Verdict::Run
+      }
+      Err(e) => {
+        warn!("Script failed, running anyway: {e}");
+        Verdict::Run
+      }
+    }
+  }
+}
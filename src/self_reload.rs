@@ -0,0 +1,100 @@
+// Re-executing the watcher when its own binary is replaced (`--self-reload`).
+//
+// The same re-exec switches profiles and, with `--seamless-reload`, takes
+// SIGHUP to pick up an edited --config. A new process starts with nothing
+// queued, so with `--seamless-reload` the runs that were waiting to start,
+// or cut short by the reload, are handed over in $ROF_CARRIED (JSON) and
+// started again by the jobs of the same name once the watches are up. A
+// command that was running doesn't keep going across the exec: it is
+// stopped like any other run, and what carries over is its trigger, for
+// a fresh run.
+
+use notify::{EventKind, RecommendedWatcher, RecursiveMode, Watcher};
+use serde::{Deserialize, Serialize};
+use std::os::unix::process::CommandExt;
+use std::{
+  env, io,
+  path::{Path, PathBuf},
+  process::Command,
+  time::Duration,
+};
+
+const CARRIED: &str = "ROF_CARRIED";
+
+/// A run to start again after a reload.
+#[derive(Serialize, Deserialize)]
+pub struct Carried {
+  /// The job's name, or the command-line job's command.
+  pub job: String,
+  pub trigger: Vec<PathBuf>,
+}
+
+/// The runs the watcher this process replaced handed over, taken out of
+/// the environment so that runs don't inherit them. Must run before any
+/// other thread is started.
+pub fn take_carried() -> Vec<Carried> {
+  let Some(carried) = env::var_os(CARRIED) else {
+    return Vec::new();
+  };
+  // SAFETY: no other thread exists yet to read the environment
+  unsafe { env::remove_var(CARRIED) };
+  serde_json::from_str(&carried.to_string_lossy()).unwrap_or_default()
+}
+
+/// Quiet period after the last write to the binary before re-executing it,
+/// so the build that replaces it can finish.
+pub const SETTLE: Duration = Duration::from_millis(500);
+
+/// The running binary, resolved now: once a rebuild replaces the file,
+/// `/proc/self/exe` points at the deleted original.
+pub fn current_exe() -> Option<PathBuf> {
+  env::current_exe().ok()
+}
+
+/// Watches the binary's directory rather than the file itself, since
+/// builds usually replace it with a new inode.
+pub fn watch(watcher: &mut RecommendedWatcher, exe: &Path) -> notify::Result<()> {
+  if let Some(dir) = exe.parent() {
+    watcher.watch(dir, RecursiveMode::NonRecursive)?;
+  }
+  Ok(())
+}
+
+/// Whether an event means the binary was rewritten.
+pub fn is_replaced(exe: &Path, kind: &EventKind, paths: &[PathBuf]) -> bool {
+  matches!(kind, EventKind::Create(_) | EventKind::Modify(_)) && paths.iter().any(|p| p == exe)
+}
+
+/// The command re-executing `exe`, handing `carried` over.
+fn command(exe: &Path, carried: &[Carried]) -> Command {
+  let mut command = Command::new(exe);
+  if !carried.is_empty() {
+    command.env(
+      CARRIED,
+      serde_json::to_string(carried).expect("serializable"),
+    );
+  }
+  command
+}
+
+/// Replaces the current process with a fresh copy of the binary, started
+/// with the same arguments. Only returns on failure.
+pub fn exec(exe: &Path, carried: &[Carried]) -> io::Error {
+  command(exe, carried).args(env::args_os().skip(1)).exec()
+}
+
+/// Like [`exec`], with `--profile profile` in place of the one given.
+pub fn exec_profile(exe: &Path, profile: &str, carried: &[Carried]) -> io::Error {
+  let mut args = env::args_os().skip(1);
+  let mut kept = Vec::new();
+  while let Some(arg) = args.next() {
+    match arg == "--profile" {
+      true => drop(args.next()),
+      false => kept.push(arg),
+    }
+  }
+  command(exe, carried)
+    .args(["--profile", profile])
+    .args(kept)
+    .exec()
+}
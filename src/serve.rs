@@ -0,0 +1,131 @@
+// Built-in static file server (`--serve DIR:PORT`).
+//
+// HTML pages get the live reload script injected, so a frontend build
+// loop needs nothing but this tool and a browser.
+
+use crate::http;
+use crate::livereload;
+use std::{
+  fs, io,
+  net::{Ipv4Addr, TcpListener, TcpStream},
+  path::{Component, Path, PathBuf},
+  thread,
+};
+use tracing::{debug, info};
+
+pub const DEFAULT_PORT: u16 = 8080;
+
+/// `DIR` or `DIR:PORT`.
+pub fn parse(s: &str) -> Option<(PathBuf, u16)> {
+  match s.rsplit_once(':') {
+    Some((dir, port)) if !dir.is_empty() => Some((dir.into(), port.parse().ok()?)),
+    _ => Some((s.into(), DEFAULT_PORT)),
+  }
+}
+
+fn content_type(path: &Path) -> &'static str {
+  match path.extension().and_then(|e| e.to_str()).unwrap_or("") {
+    "html" | "htm" => "text/html; charset=utf-8",
+    "css" => "text/css",
+    "js" | "mjs" => "application/javascript",
+    "json" | "map" => "application/json",
+    "svg" => "image/svg+xml",
+    "png" => "image/png",
+    "jpg" | "jpeg" => "image/jpeg",
+    "gif" => "image/gif",
+    "ico" => "image/x-icon",
+    "wasm" => "application/wasm",
+    "woff2" => "font/woff2",
+    "txt" => "text/plain; charset=utf-8",
+    _ => "application/octet-stream",
+  }
+}
+
+/// Decodes `%XX` escapes in a request path.
+fn percent_decode(s: &str) -> Vec<u8> {
+  let bytes = s.as_bytes();
+  let mut out = Vec::with_capacity(bytes.len());
+  let mut i = 0;
+  while i < bytes.len() {
+    let hex = bytes
+      .get(i + 1..i + 3)
+      .and_then(|h| std::str::from_utf8(h).ok())
+      .and_then(|h| u8::from_str_radix(h, 16).ok());
+    match (bytes[i], hex) {
+      (b'%', Some(b)) => {
+        out.push(b);
+        i += 3;
+      }
+      (b, _) => {
+        out.push(b);
+        i += 1;
+      }
+    }
+  }
+  out
+}
+
+/// Maps a request path into `root`, refusing anything that climbs out.
+fn resolve(root: &Path, request: &str) -> Option<PathBuf> {
+  let path = request.split(['?', '#']).next().unwrap_or("/");
+  let decoded = String::from_utf8(percent_decode(path)).ok()?;
+  let relative = Path::new(decoded.trim_start_matches('/'));
+  if relative
+    .components()
+    .any(|c| !matches!(c, Component::Normal(_) | Component::CurDir))
+  {
+    return None;
+  }
+  let file = root.join(relative);
+  if file.is_dir() {
+    Some(file.join("index.html"))
+  } else {
+    Some(file)
+  }
+}
+
+/// Puts the reload script before `</body>`, or at the end without one.
+fn inject(mut html: Vec<u8>, snippet: &str) -> Vec<u8> {
+  let lower = html.to_ascii_lowercase();
+  let at = lower
+    .windows(7)
+    .rposition(|w| w == b"</body>")
+    .unwrap_or(html.len());
+  html.splice(at..at, snippet.bytes());
+  html
+}
+
+fn handle(mut stream: TcpStream, root: &Path, reload_port: Option<u16>) {
+  let Some(head) = http::read_head(&stream) else {
+    return;
+  };
+  debug!(method = %head.method, path = %head.path, "serve request");
+  if head.method != "GET" {
+    let _ = http::respond(&mut stream, "405 Method Not Allowed", "text/plain", b"");
+    return;
+  }
+  let found = resolve(root, &head.path).and_then(|file| Some((fs::read(&file).ok()?, file)));
+  let Some((body, file)) = found else {
+    let _ = http::respond(&mut stream, "404 Not Found", "text/plain", b"Not found\n");
+    return;
+  };
+  let kind = content_type(&file);
+  let body = match reload_port {
+    Some(port) if kind.starts_with("text/html") => inject(body, &livereload::snippet(port)),
+    _ => body,
+  };
+  let _ = http::respond(&mut stream, "200 OK", kind, &body);
+}
+
+/// Starts serving `root` on `port` in the background.
+pub fn start(root: PathBuf, port: u16, reload_port: Option<u16>) -> io::Result<()> {
+  let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, port))?;
+  info!("Serving {:?} on http://localhost:{port}/", root);
+  thread::spawn(move || {
+    for stream in listener.incoming().flatten() {
+      let root = root.clone();
+      thread::spawn(move || handle(stream, &root, reload_port));
+    }
+  });
+  Ok(())
+}
@@ -0,0 +1,80 @@
+// `--settle`: waiting for changed files to stop growing.
+//
+// A file dropped into a watched directory by a slow copy produces a stream
+// of writes. Instead of triggering on the first one, a matching change is
+// held until the file's size and modification time stay the same for a
+// whole interval; then it goes through the usual path again, marked as
+// settled.
+
+use notify::Event;
+use notify::event::{DataChange, EventKind, ModifyKind};
+use std::{
+  collections::{HashMap, HashSet},
+  fs,
+  path::{Path, PathBuf},
+  time::SystemTime,
+};
+
+/// What is compared between two looks at a file.
+type Shape = (u64, Option<SystemTime>);
+
+fn shape(path: &Path) -> Option<Shape> {
+  let meta = fs::metadata(path).ok().filter(|m| m.is_file())?;
+  Some((meta.len(), meta.modified().ok()))
+}
+
+#[derive(Default)]
+pub struct Settling {
+  /// Files still being watched, with how they looked last time.
+  files: HashMap<PathBuf, Shape>,
+  /// Files that have settled and are on their way back through.
+  settled: HashSet<PathBuf>,
+}
+
+impl Settling {
+  /// Whether a change to `paths` may trigger now: yes if they have just
+  /// settled or aren't regular files (removed, directories). Otherwise
+  /// they are held until they stop changing.
+  pub fn admit(&mut self, paths: &[PathBuf]) -> bool {
+    let mut ready = true;
+    for path in paths {
+      if self.settled.remove(path) {
+        continue;
+      }
+      if let Some(shape) = shape(path) {
+        self.files.entry(path.clone()).or_insert(shape);
+        ready = false;
+      }
+    }
+    ready
+  }
+
+  pub fn waiting(&self) -> bool {
+    !self.files.is_empty()
+  }
+
+  /// Looks at the held files again, after one interval. Those unchanged
+  /// since the last look come back as one event to handle; files that
+  /// disappeared are dropped.
+  pub fn check(&mut self) -> Option<Event> {
+    let mut stable = Vec::new();
+    self.files.retain(|path, last| match shape(path) {
+      Some(now) if now == *last => {
+        stable.push(path.clone());
+        false
+      }
+      Some(now) => {
+        *last = now;
+        true
+      }
+      None => false,
+    });
+    if stable.is_empty() {
+      return None;
+    }
+    self.settled.extend(stable.iter().cloned());
+    let mut event = Event::new(EventKind::Modify(ModifyKind::Data(DataChange::Any)));
+    event.paths = stable;
+    Some(event)
+  }
+}
@@ -0,0 +1,55 @@
+// Signal handling thread.
+
+use crate::Msg;
+use nix::sys::signal::{SigSet, Signal};
+use std::sync::mpsc::Sender;
+
+/// Signals the watcher handles itself instead of dying from them, plus
+/// SIGCHLD so the event loop learns when a run has exited, SIGUSR1 to ask
+/// for statistics and SIGUSR2 to pause and resume.
+const HANDLED: [Signal; 6] = [
+  Signal::SIGINT,
+  Signal::SIGTERM,
+  Signal::SIGHUP,
+  Signal::SIGCHLD,
+  Signal::SIGUSR1,
+  Signal::SIGUSR2,
+];
+
+/// Blocks the handled signals and starts a thread that turns them into
+/// [`Msg::Signal`] messages for the event loop.
+///
+/// Must run before any other thread is spawned (including the notify
+/// backend), since threads inherit the signal mask of their creator and a
+/// thread without it would get the default disposition. Children inherit
+/// the mask too, so every spawn must call [`clear_mask`] before exec.
+pub fn spawn_handler(tx: Sender<Msg>) -> nix::Result<()> {
+  let mut set = SigSet::empty();
+  for sig in HANDLED {
+    set.add(sig);
+  }
+  set.thread_block()?;
+
+  std::thread::spawn(move || {
+    loop {
+      match set.wait() {
+        Ok(sig) => {
+          if tx.send(Msg::Signal(sig)).is_err() {
+            break; // event loop is gone
+          }
+        }
+        Err(_) => continue,
+      }
+    }
+  });
+  Ok(())
+}
+
+/// Unblocks all signals in a freshly forked child, for use in `pre_exec`:
+/// `std::process::Command` does not reset the mask, and a blocked SIGTERM
+/// would survive exec and make the command ignore being stopped.
+pub fn clear_mask() -> std::io::Result<()> {
+  SigSet::empty()
+    .thread_set_mask()
+    .map_err(std::io::Error::from)
+}
@@ -0,0 +1,294 @@
+// Triggering only on changes that mean something (`--ignore-whitespace-only`,
+// `--ignore-comments`), to cut rebuilds after reformatting or rewording a
+// comment.
+//
+// A hash of every matched file's content, normalized, is kept from startup
+// on; a change counts only if the hash differs afterwards. Normalizing
+// drops trailing whitespace, blank lines and line ending differences, and
+// indentation in languages where it doesn't matter. Comments are removed
+// first for the languages recognized by extension (C-like `//` and `/* */`,
+// `#`, `--`); other files only have their whitespace normalized. Files that
+// aren't UTF-8 are compared as they are.
+
+use crate::filter::Filter;
+use crate::manifest;
+use crate::watch::Root;
+use std::{
+  collections::HashMap,
+  fs,
+  path::{Path, PathBuf},
+};
+
+/// Larger files aren't hashed; every change to them counts.
+const MAX_SIZE: u64 = 16 << 20;
+
+/// How a language writes comments and strings.
+struct Syntax {
+  line: &'static [&'static str],
+  block: Option<(&'static str, &'static str)>,
+  quotes: &'static [char],
+  /// Whether `'` starts a character literal only when one follows, and
+  /// is otherwise a lifetime or label, as in Rust.
+  char_literals: bool,
+  /// Whether indentation carries meaning.
+  indented: bool,
+}
+
+const C_LIKE: Syntax = Syntax {
+  line: &["//"],
+  block: Some(("/*", "*/")),
+  quotes: &['"', '\'', '`'],
+  char_literals: false,
+  indented: false,
+};
+
+fn syntax(path: &Path) -> Option<Syntax> {
+  let ext = path.extension()?.to_str()?.to_ascii_lowercase();
+  Some(match ext.as_str() {
+    // Single quotes are lifetimes as often as characters.
+    "rs" => Syntax {
+      quotes: &['"'],
+      char_literals: true,
+      ..C_LIKE
+    },
+    "c" | "h" | "cc" | "cpp" | "cxx" | "hpp" | "hh" | "go" | "java" | "kt" | "kts" | "scala"
+    | "swift" | "cs" | "dart" | "js" | "jsx" | "mjs" | "cjs" | "ts" | "tsx" | "zig" => C_LIKE,
+    "css" | "scss" | "less" => Syntax {
+      line: &[],
+      ..C_LIKE
+    },
+    "py" | "pyi" => Syntax {
+      line: &["#"],
+      block: None,
+      quotes: &['"', '\''],
+      char_literals: false,
+      indented: true,
+    },
+    "yaml" | "yml" => Syntax {
+      line: &["#"],
+      block: None,
+      quotes: &['"', '\''],
+      char_literals: false,
+      indented: true,
+    },
+    "sh" | "bash" | "zsh" | "rb" | "pl" | "r" | "toml" | "cmake" | "nix" => Syntax {
+      line: &["#"],
+      block: None,
+      quotes: &['"', '\''],
+      char_literals: false,
+      indented: false,
+    },
+    "sql" | "lua" => Syntax {
+      line: &["--"],
+      block: None,
+      quotes: &['"', '\''],
+      char_literals: false,
+      indented: false,
+    },
+    "hs" => Syntax {
+      line: &["--"],
+      block: Some(("{-", "-}")),
+      quotes: &['"'],
+      char_literals: false,
+      indented: true,
+    },
+    _ => return None,
+  })
+}
+
+/// The length of the character literal (`'x'`, `'\''`, `'\u{7f}'`) at the
+/// start of `rest`, if there is one rather than a lifetime.
+fn char_literal(rest: &str) -> Option<usize> {
+  let body = rest.strip_prefix('\'')?;
+  let mut chars = body.chars();
+  let len = match chars.next()? {
+    '\\' => {
+      let escaped = 1 + chars.next()?.len_utf8();
+      // `\u{…}` and `\x…` go on up to the quote.
+      escaped + body[escaped..].find('\'').filter(|&n| n <= 8)?
+    }
+    '\'' => return None,
+    c if chars.next() == Some('\'') => c.len_utf8(),
+    _ => return None,
+  };
+  Some(1 + len + 1)
+}
+
+/// `text` without the comments `syntax` describes. Comments are recognized
+/// outside string literals only; block comments keep their line breaks.
+fn strip_comments(text: &str, syntax: &Syntax) -> String {
+  let mut out = String::with_capacity(text.len());
+  let mut rest = text;
+  let mut quote: Option<char> = None;
+  while let Some(c) = rest.chars().next() {
+    if let Some(q) = quote {
+      if c == '\\' {
+        let escaped: String = rest.chars().take(2).collect();
+        out.push_str(&escaped);
+        rest = &rest[escaped.len()..];
+        continue;
+      }
+      if c == q {
+        quote = None;
+      }
+      out.push(c);
+      rest = &rest[c.len_utf8()..];
+      continue;
+    }
+    // `#` starts a comment only as a word of its own, not in `$#`.
+    let word_start = out.chars().last().is_none_or(char::is_whitespace);
+    if syntax
+      .line
+      .iter()
+      .any(|&l| rest.starts_with(l) && (l != "#" || word_start))
+    {
+      rest = &rest[rest.find('\n').unwrap_or(rest.len())..];
+      continue;
+    }
+    if let Some((open, close)) = syntax.block
+      && let Some(body) = rest.strip_prefix(open)
+    {
+      let end = body.find(close).map_or(body.len(), |i| i + close.len());
+      out.extend(body[..end].chars().filter(|&c| c == '\n'));
+      rest = &body[end..];
+      continue;
+    }
+    if syntax.char_literals
+      && let Some(len) = char_literal(rest)
+    {
+      out.push_str(&rest[..len]);
+      rest = &rest[len..];
+      continue;
+    }
+    if syntax.quotes.contains(&c) {
+      quote = Some(c);
+    }
+    out.push(c);
+    rest = &rest[c.len_utf8()..];
+  }
+  out
+}
+
+/// The lines of `text` that carry content, without trailing whitespace,
+/// and without indentation unless it matters.
+fn normalize_whitespace(text: &str, indented: bool) -> String {
+  let mut out = String::with_capacity(text.len());
+  for line in text.lines().map(str::trim_end).filter(|l| !l.is_empty()) {
+    out.push_str(if indented { line } else { line.trim_start() });
+    out.push('\n');
+  }
+  out
+}
+
+pub struct Significance {
+  comments: bool,
+  /// Hash of each known file's normalized content.
+  hashes: HashMap<PathBuf, String>,
+}
+
+impl Significance {
+  /// Normalizes whitespace, and with `comments` strips comments too.
+  /// Hashes the files below `roots` the filters let through, so the first
+  /// change to each is judged as well.
+  pub fn new(comments: bool, roots: &[Root], filters: &[&Filter]) -> Significance {
+    let mut significance = Significance {
+      comments,
+      hashes: HashMap::new(),
+    };
+    manifest::walk(roots, filters, |path| {
+      if let Some(hash) = significance.hash(&path) {
+        significance.hashes.insert(path, hash);
+      }
+    });
+    significance
+  }
+
+  /// Hash of the normalized content of `path`, or `None` if it isn't a
+  /// regular file, or is too large.
+  pub fn hash(&self, path: &Path) -> Option<String> {
+    let meta = fs::metadata(path).ok().filter(|m| m.is_file())?;
+    if meta.len() > MAX_SIZE {
+      return None;
+    }
+    let bytes = fs::read(path).ok()?;
+    let Ok(text) = std::str::from_utf8(&bytes) else {
+      return Some(sha1_smol::Sha1::from(&bytes).digest().to_string());
+    };
+    let syntax = syntax(path);
+    let text = match (&syntax, self.comments) {
+      (Some(syntax), true) => strip_comments(text, syntax),
+      _ => text.to_string(),
+    };
+    let indented = syntax.is_none_or(|s| s.indented);
+    let normalized = normalize_whitespace(&text, indented);
+    Some(sha1_smol::Sha1::from(normalized).digest().to_string())
+  }
+
+  /// Whether a change to `paths` means something: one of them is new,
+  /// gone, not hashed, or its normalized content differs. Remembers how
+  /// they are now either way. Hashes already taken are in `hashed`.
+  pub fn significant(
+    &mut self,
+    paths: &[PathBuf],
+    hashed: &HashMap<PathBuf, Option<String>>,
+  ) -> bool {
+    let mut significant = false;
+    for path in paths {
+      let hash = match hashed.get(path) {
+        Some(hash) => hash.clone(),
+        None => self.hash(path),
+      };
+      significant |= hash.is_none() || self.hashes.get(path) != hash.as_ref();
+      match hash {
+        Some(hash) => self.hashes.insert(path.clone(), hash),
+        None => self.hashes.remove(path),
+      };
+    }
+    significant
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn strip(text: &str, ext: &str) -> String {
+    strip_comments(text, &syntax(Path::new(&format!("x.{ext}"))).unwrap())
+  }
+
+  #[test]
+  fn strips_comments() {
+    assert_eq!(strip("a(); // note\nb();\n", "c"), "a(); \nb();\n");
+    assert_eq!(strip("a /* one\ntwo */ b", "js"), "a \n b");
+    assert_eq!(
+      strip("s = \"// kept\" # gone\n", "py"),
+      "s = \"// kept\" \n"
+    );
+    assert_eq!(strip("echo $# # count\n", "sh"), "echo $# \n");
+    assert_eq!(strip("x = 'it''s' -- note\n", "sql"), "x = 'it''s' \n");
+    assert_eq!(strip("s = \"a \\\" // b\"\n", "c"), "s = \"a \\\" // b\"\n");
+  }
+
+  #[test]
+  fn rust_char_literals() {
+    let text = "let q = '\"'; let s = \"http://x\"; // c\n";
+    assert_eq!(strip(text, "rs"), "let q = '\"'; let s = \"http://x\"; \n");
+    let text = "f('\\'', \"//\"); '\\u{22}'; \"//\" // c\n";
+    assert_eq!(strip(text, "rs"), "f('\\'', \"//\"); '\\u{22}'; \"//\" \n");
+    // Lifetimes and labels leave the quoting alone.
+    let text = "fn f<'a>(s: &'a str) -> &'a str { \"//\" } // c\n";
+    assert_eq!(
+      strip(text, "rs"),
+      "fn f<'a>(s: &'a str) -> &'a str { \"//\" } \n"
+    );
+    assert_eq!(strip("'outer: loop {} // c", "rs"), "'outer: loop {} ");
+  }
+
+  #[test]
+  fn normalizes_whitespace() {
+    let text = "a  \r\n\n\n    b\t\n  c";
+    assert_eq!(normalize_whitespace(text, false), "a\nb\nc\n");
+    assert_eq!(normalize_whitespace(text, true), "a\n    b\n  c\n");
+    assert_eq!(normalize_whitespace("\n  \n", false), "");
+  }
+}
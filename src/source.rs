@@ -0,0 +1,39 @@
+// Where events come from when it isn't the filesystem backends: stdin
+// (`--simulate`) or a recording (`--replay`). A source runs on a thread of
+// its own and hands its events to the event loop through [`Events`], so
+// they go through the same renames, filters, debounce and runs as the
+// backends' do. The native and polling backends are set up in watch.rs and
+// don't go through here.
+//
+// This module is also the library target's (src/lib.rs), so that programs
+// embedding the watcher can write sources of their own, fed by a message
+// queue say, against the same trait.
+
+use notify::Event;
+use std::sync::Arc;
+
+/// A source of events in place of the backends.
+pub trait EventSource {
+  /// How the logs and the status name it, like a backend.
+  fn name(&self) -> &'static str;
+
+  /// Starts sending events, failing with what kept it from starting.
+  fn start(self: Box<Self>, events: Events) -> Result<(), String>;
+}
+
+/// Where a source sends its events.
+#[derive(Clone)]
+pub struct Events(Arc<dyn Fn(Event) -> bool + Send + Sync>);
+
+impl Events {
+  /// Events handed to `deliver`, which says whether it still takes them.
+  pub fn new(deliver: impl Fn(Event) -> bool + Send + Sync + 'static) -> Self {
+    Events(Arc::new(deliver))
+  }
+
+  /// Hands `event` to the event loop; false once the watcher is done and
+  /// the source should stop.
+  pub fn send(&self, event: Event) -> bool {
+    (self.0)(event)
+  }
+}
@@ -0,0 +1,98 @@
+// Watch and event statistics (`--stats`, or SIGUSR1 at any time).
+
+use crate::summary::Summary;
+use crate::watch::WatchSet;
+use std::{fs, path::Path};
+use tracing::info;
+
+/// Events seen since startup, by how far they got.
+#[derive(Default)]
+pub struct Counters {
+  /// Everything the backend reported.
+  pub received: u64,
+  /// Passed the kind, pattern and other filters.
+  pub matched: u64,
+  /// Started (or replaced) a run, i.e. also got past the debounce.
+  pub triggered: u64,
+}
+
+/// Inotify watches held by this process, from `/proc/self/fdinfo`, and
+/// how many inotify instances they belong to.
+pub fn inotify_usage() -> Option<(usize, usize)> {
+  let mut watches = 0;
+  let mut instances = 0;
+  for entry in fs::read_dir("/proc/self/fdinfo").ok()?.flatten() {
+    let Ok(info) = fs::read_to_string(entry.path()) else {
+      continue;
+    };
+    let n = info
+      .lines()
+      .filter(|l| l.starts_with("inotify wd:"))
+      .count();
+    if n > 0 {
+      watches += n;
+      instances += 1;
+    }
+  }
+  Some((watches, instances))
+}
+
+pub fn sysctl(name: &str) -> Option<u64> {
+  let path = Path::new("/proc/sys/fs/inotify").join(name);
+  fs::read_to_string(path).ok()?.trim().parse().ok()
+}
+
+fn limit(value: Option<u64>) -> String {
+  value.map_or("?".to_string(), |v| v.to_string())
+}
+
+pub fn report(watch_set: &WatchSet, counters: &Counters, summary: &Summary) {
+  info!("Watch statistics:");
+  for (root, dirs) in watch_set.dir_counts() {
+    info!(
+      "  {:?}: {dirs} director{}",
+      root,
+      if dirs == 1 { "y" } else { "ies" }
+    );
+  }
+  match inotify_usage() {
+    Some((watches, instances)) => info!(
+      "  inotify: {watches} of {} watches, {instances} of {} instances (per user)",
+      limit(sysctl("max_user_watches")),
+      limit(sysctl("max_user_instances")),
+    ),
+    None => info!("  inotify: usage unavailable"),
+  }
+  info!(
+    "  events: {} received, {} filtered out, {} debounced, {} triggered runs",
+    counters.received,
+    counters.received.saturating_sub(counters.matched),
+    counters.matched.saturating_sub(counters.triggered),
+    counters.triggered,
+  );
+  if !summary.runs.is_empty() {
+    let wall: u64 = summary.runs.iter().map(|r| r.duration_ms).sum();
+    info!(
+      "  runs: {}, {:.1}s wall, {:.1}s CPU, {} peak RSS",
+      summary.runs.len(),
+      wall as f64 / 1000.0,
+      summary.total_cpu_ms() as f64 / 1000.0,
+      summary.max_rss_kb().map_or("?".to_string(), |kb| format!(
+        "{:.1} MB",
+        kb as f64 / 1024.0
+      )),
+    );
+  }
+  if let Some(earlier) = summary.history.as_ref().map(|h| &h.earlier)
+    && earlier.runs > 0
+  {
+    info!(
+      "  earlier sessions: {}, with {} runs ({} succeeded, {} failed), {:.1}s wall",
+      earlier.sessions,
+      earlier.runs,
+      earlier.succeeded,
+      earlier.failed,
+      earlier.total_duration_ms as f64 / 1000.0,
+    );
+  }
+}
@@ -0,0 +1,94 @@
+// What the watcher is doing, as one line in a file (`--status-file PATH`)
+// for status bars to show:
+//
+//   run_on_file_change --status-file /tmp/rof.status "make" src
+//   set -g status-right '#(cat /tmp/rof.status)'        # tmux
+//
+// The line is space-separated key=value pairs,
+//
+//   state=running run=4 last_exit=0 last_duration_ms=1520
+//
+// with state idle, running, paused, quiet (--quiet-hours) or stopped
+// (once the watcher is gone), the id of the run going on, and how the
+// latest run ended: its exit code, or the signal that killed it, and how
+// long it took. The file is rewritten through a temporary one and a rename
+// whenever any of that changes, so a reader never sees half a line.
+
+use crate::summary::RunRecord;
+use nix::sys::signal::Signal;
+use std::{fs, path::PathBuf};
+use tracing::warn;
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum State {
+  Idle,
+  Running(u64),
+  Paused,
+  /// Holding changes back for `--quiet-hours`.
+  Quiet,
+}
+
+pub struct StatusFile {
+  path: PathBuf,
+  /// The line in the file.
+  written: Option<String>,
+}
+
+/// How `run` ended, for `last_exit`.
+fn exit(run: &RunRecord) -> String {
+  match (run.exit_code, run.signal) {
+    (Some(code), _) => code.to_string(),
+    (None, Some(sig)) => Signal::try_from(sig).map_or_else(|_| sig.to_string(), |s| s.to_string()),
+    (None, None) => "unknown".to_string(),
+  }
+}
+
+impl StatusFile {
+  pub fn new(path: PathBuf) -> Self {
+    StatusFile {
+      path,
+      written: None,
+    }
+  }
+
+  fn write(&mut self, line: String) {
+    if self.written.as_ref() == Some(&line) {
+      return;
+    }
+    let tmp = self.path.with_extension("tmp");
+    let written = fs::write(&tmp, format!("{line}\n")).and_then(|_| fs::rename(&tmp, &self.path));
+    if let Err(e) = written {
+      warn!("Could not write {}: {e}", self.path.display());
+    }
+    self.written = Some(line);
+  }
+
+  /// Brings the file up to date with `state` and the `last` run to end.
+  pub fn update(&mut self, state: State, last: Option<&RunRecord>) {
+    let mut line = match state {
+      State::Idle => "state=idle".to_string(),
+      State::Running(id) => format!("state=running run={id}"),
+      State::Paused => "state=paused".to_string(),
+      State::Quiet => "state=quiet".to_string(),
+    };
+    if let Some(run) = last {
+      line.push_str(&format!(
+        " last_exit={} last_duration_ms={}",
+        exit(run),
+        run.duration_ms
+      ));
+    }
+    self.write(line);
+  }
+}
+
+impl Drop for StatusFile {
+  fn drop(&mut self) {
+    let last = self
+      .written
+      .as_deref()
+      .and_then(|line| line.find(" last_").map(|at| line[at..].to_string()))
+      .unwrap_or_default();
+    self.write(format!("state=stopped{last}"));
+  }
+}
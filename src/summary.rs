@@ -0,0 +1,147 @@
+// Session summary written on exit (`--summary-file`).
+
+use crate::history::History;
+use crate::process::Run;
+use chrono::{DateTime, Local};
+use serde::{Deserialize, Serialize};
+use std::os::unix::process::ExitStatusExt;
+use std::{fs, io, path::Path, process::ExitStatus};
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct RunRecord {
+  pub id: u64,
+  /// Set with `--run-uuid`.
+  pub uuid: Option<String>,
+  /// Paths of the event that started the run.
+  pub trigger: Vec<String>,
+  pub started_at: String,
+  pub duration_ms: u64,
+  /// Time until `--ready-when` passed, if it did.
+  pub ready_ms: Option<u64>,
+  pub exit_code: Option<i32>,
+  pub signal: Option<i32>,
+  /// CPU time and peak memory, from `wait4`.
+  pub user_ms: Option<u64>,
+  pub system_ms: Option<u64>,
+  pub max_rss_kb: Option<u64>,
+  /// Cut short by the watcher (restart or shutdown) rather than finishing.
+  pub stopped: bool,
+}
+
+#[derive(Serialize)]
+struct Aggregates {
+  runs: usize,
+  succeeded: usize,
+  failed: usize,
+  stopped: usize,
+  total_duration_ms: u64,
+  mean_duration_ms: Option<u64>,
+  min_duration_ms: Option<u64>,
+  max_duration_ms: Option<u64>,
+  total_cpu_ms: u64,
+  max_rss_kb: Option<u64>,
+}
+
+#[derive(Serialize)]
+struct Document<'a> {
+  session_started_at: String,
+  session_ended_at: String,
+  command: &'a str,
+  stats: Aggregates,
+  runs: &'a [RunRecord],
+}
+
+pub struct Summary {
+  started_at: DateTime<Local>,
+  pub runs: Vec<RunRecord>,
+  /// Where runs are also kept for later sessions (`--state-dir`).
+  pub history: Option<History>,
+}
+
+impl Summary {
+  pub fn new() -> Self {
+    Summary {
+      started_at: Local::now(),
+      runs: Vec::new(),
+      history: None,
+    }
+  }
+
+  pub fn started_at(&self) -> String {
+    self.started_at.to_rfc3339()
+  }
+
+  pub fn record(&mut self, run: &Run, status: ExitStatus, stopped: bool) {
+    self.runs.push(RunRecord {
+      id: run.id,
+      uuid: run.uuid.clone(),
+      trigger: run
+        .trigger
+        .iter()
+        .map(|p| p.display().to_string())
+        .collect(),
+      started_at: run.started_at.to_rfc3339(),
+      duration_ms: run.duration().as_millis() as u64,
+      ready_ms: run.ready_after.map(|d| d.as_millis() as u64),
+      exit_code: status.code(),
+      signal: status.signal(),
+      user_ms: run.usage.map(|u| u.user.as_millis() as u64),
+      system_ms: run.usage.map(|u| u.system.as_millis() as u64),
+      max_rss_kb: run.usage.map(|u| u.max_rss_kb),
+      stopped,
+    });
+    if let Some(history) = &mut self.history {
+      history.append(&run.command, self.runs.last().expect("just pushed"));
+    }
+  }
+
+  /// Whether the last run that wasn't cut short succeeded.
+  pub fn last_success(&self) -> Option<bool> {
+    let last = self.runs.iter().rev().find(|r| !r.stopped)?;
+    Some(last.exit_code == Some(0))
+  }
+
+  /// CPU time of all runs, in milliseconds.
+  pub fn total_cpu_ms(&self) -> u64 {
+    self
+      .runs
+      .iter()
+      .map(|r| r.user_ms.unwrap_or(0) + r.system_ms.unwrap_or(0))
+      .sum()
+  }
+
+  /// The largest peak RSS of any run.
+  pub fn max_rss_kb(&self) -> Option<u64> {
+    self.runs.iter().filter_map(|r| r.max_rss_kb).max()
+  }
+
+  fn aggregates(&self) -> Aggregates {
+    let finished = || self.runs.iter().filter(|r| !r.stopped);
+    let durations: Vec<u64> = self.runs.iter().map(|r| r.duration_ms).collect();
+    let total: u64 = durations.iter().sum();
+    Aggregates {
+      runs: self.runs.len(),
+      succeeded: finished().filter(|r| r.exit_code == Some(0)).count(),
+      failed: finished().filter(|r| r.exit_code != Some(0)).count(),
+      stopped: self.runs.iter().filter(|r| r.stopped).count(),
+      total_duration_ms: total,
+      mean_duration_ms: (!durations.is_empty()).then(|| total / durations.len() as u64),
+      min_duration_ms: durations.iter().copied().min(),
+      max_duration_ms: durations.iter().copied().max(),
+      total_cpu_ms: self.total_cpu_ms(),
+      max_rss_kb: self.max_rss_kb(),
+    }
+  }
+
+  pub fn write(&self, path: &Path, command: &str) -> io::Result<()> {
+    let doc = Document {
+      session_started_at: self.started_at(),
+      session_ended_at: Local::now().to_rfc3339(),
+      command,
+      stats: self.aggregates(),
+      runs: &self.runs,
+    };
+    let json = serde_json::to_string_pretty(&doc).map_err(io::Error::other)?;
+    fs::write(path, json + "\n")
+  }
+}
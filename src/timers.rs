@@ -0,0 +1,73 @@
+// One-shot deadlines the event loop waits for alongside its messages.
+
+use std::time::{Duration, Instant};
+
+/// Pending timers, at most one per key.
+pub struct Timers<K> {
+  pending: Vec<(K, Instant)>,
+}
+
+impl<K: Copy + PartialEq> Timers<K> {
+  pub fn new() -> Self {
+    Timers {
+      pending: Vec::new(),
+    }
+  }
+
+  /// Arms `key` to fire after `delay`, replacing an earlier deadline.
+  pub fn set(&mut self, key: K, delay: Duration) {
+    self.cancel(key);
+    self.pending.push((key, Instant::now() + delay));
+  }
+
+  pub fn cancel(&mut self, key: K) {
+    self.pending.retain(|(k, _)| *k != key);
+  }
+
+  pub fn is_set(&self, key: K) -> bool {
+    self.pending.iter().any(|(k, _)| *k == key)
+  }
+
+  /// How long the loop may block before the earliest deadline, or `None`
+  /// when nothing is pending.
+  pub fn timeout(&self) -> Option<Duration> {
+    let next = self.pending.iter().map(|(_, at)| *at).min()?;
+    Some(next.saturating_duration_since(Instant::now()))
+  }
+
+  /// Removes and returns one timer whose deadline has passed.
+  pub fn pop_expired(&mut self) -> Option<K> {
+    let now = Instant::now();
+    let i = self.pending.iter().position(|(_, at)| *at <= now)?;
+    Some(self.pending.remove(i).0)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn fires_once_due() {
+    let mut timers = Timers::new();
+    assert_eq!(timers.timeout(), None);
+    timers.set('a', Duration::from_secs(60));
+    timers.set('b', Duration::ZERO);
+    assert!(timers.timeout().unwrap() <= Duration::from_millis(1));
+    assert_eq!(timers.pop_expired(), Some('b'));
+    assert_eq!(timers.pop_expired(), None);
+    assert!(timers.is_set('a') && !timers.is_set('b'));
+    assert!(timers.timeout().unwrap() > Duration::from_secs(59));
+  }
+
+  #[test]
+  fn set_replaces_and_cancel_removes() {
+    let mut timers = Timers::new();
+    timers.set('a', Duration::ZERO);
+    timers.set('a', Duration::from_secs(60));
+    assert_eq!(timers.pop_expired(), None);
+    timers.cancel('a');
+    assert!(!timers.is_set('a'));
+    assert_eq!(timers.timeout(), None);
+  }
+}
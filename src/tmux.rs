@@ -0,0 +1,49 @@
+// Sending keys to a tmux pane instead of running a command of our own
+// (`--tmux-send '<pane>:<keys>'`), so an existing shell or REPL stays the
+// place things run in:
+//
+//   run_on_file_change --tmux-send 'dev:1.0:Up Enter' src
+//
+// presses Up and Enter in pane 1.0 of session dev, re-running its last
+// command there. The pane is anything `tmux -t` accepts, up to the last
+// colon; the keys are tmux key names or words, separated by spaces. Each
+// run is the short `tmux send-keys` itself.
+
+use crate::process;
+use std::process::{Command, Stdio};
+
+/// The pane and key names in a `--tmux-send` value.
+pub fn parse(spec: &str) -> Result<(String, Vec<String>), String> {
+  let (pane, keys) = spec
+    .rsplit_once(':')
+    .ok_or_else(|| format!("expected PANE:KEYS, got {spec:?}"))?;
+  let keys: Vec<String> = keys.split_whitespace().map(String::from).collect();
+  if pane.is_empty() || keys.is_empty() {
+    return Err(format!("expected PANE:KEYS, got {spec:?}"));
+  }
+  Ok((pane.to_string(), keys))
+}
+
+/// The command that sends `keys` to `pane`.
+pub fn command(pane: &str, keys: &[String]) -> String {
+  let mut command = format!("tmux send-keys -t {}", process::quote(pane));
+  for key in keys {
+    command.push(' ');
+    command.push_str(&process::quote(key));
+  }
+  command
+}
+
+/// Makes sure `pane` exists, so a typo shows at startup rather than on
+/// the first change.
+pub fn check(pane: &str) -> Result<(), String> {
+  let output = Command::new("tmux")
+    .args(["send-keys", "-t", pane]) // no keys: only resolves the pane
+    .stdin(Stdio::null())
+    .output()
+    .map_err(|e| format!("could not run tmux: {e}"))?;
+  match output.status.success() {
+    true => Ok(()),
+    false => Err(String::from_utf8_lossy(&output.stderr).trim().to_string()),
+  }
+}
@@ -0,0 +1,1170 @@
+// The set of watched directories.
+//
+// Recursive roots are left to the backend, which follows new
+// subdirectories by itself. Depth-limited roots are watched one directory
+// at a time, so directories created later have to be added here.
+//
+// Roots inside another recursive root on the same backend are merged into
+// it, so `. src` watches `.` once. A path can still be watched twice, by
+// `--watch-threads`' shards or by polling inside a native root, and then
+// [`Duplicates`] drops the second copy of each event.
+//
+// Watch arguments may also be glob patterns (`'src/**/*.c'`) or single
+// files; those are reduced to the directory that has to be watched plus a
+// pattern that changes must match to trigger.
+//
+// When the backend reports an error for a root, the root is watched
+// afresh, retrying with a backoff for as long as that fails (a root that
+// was deleted comes back once it is recreated). With `--allow-missing`,
+// arguments that don't exist yet are taken for directories, skipped, and
+// looked for again every few seconds.
+//
+// With `--relative-to DIR`, the changed paths handed on (to the command,
+// hooks, scripts and plugins, and in the log) are given relative to DIR,
+// symlinks resolved, rather than as the backend spelled them.
+//
+// On large trees setting up the watches takes a while, within the backend,
+// so its progress is read from the inotify watches the process holds.
+// With `--watch-threads N`, native recursive roots are sharded: the root
+// itself is watched on its own, and its subdirectories are shared out
+// between N more watchers, each with its own inotify instance and thread,
+// which register them side by side. Directories created at the top of
+// a sharded root later are handed to one of them here.
+//
+// With `--poll-backoff MAX`, polled roots are polled from the event loop
+// rather than on the backend's own timer: once nothing has changed for a
+// minute, the interval doubles with every poll up to MAX, and the first
+// change brings it back to `--poll-interval`.
+
+use crate::stats;
+use nix::libc;
+use notify::event::{CreateKind, ModifyKind, RenameMode};
+use notify::{Config, Event, EventKind, PollWatcher, RecommendedWatcher, RecursiveMode, Watcher};
+use std::{
+  collections::{BTreeMap, BTreeSet, HashMap, HashSet, VecDeque},
+  fs,
+  io::BufRead,
+  path::{Component, Path, PathBuf},
+  sync::{
+    Mutex,
+    mpsc::{self, RecvTimeoutError, Sender},
+  },
+  thread,
+  time::{Duration, Instant},
+};
+use tracing::{debug, error, info, warn};
+
+/// How often the progress of setting up watches is reported.
+const PROGRESS: Duration = Duration::from_secs(1);
+
+/// Reports how setting up the watches goes until finished.
+pub struct Progress {
+  started: Instant,
+  _stop: Sender<()>,
+}
+
+impl Progress {
+  pub fn start() -> Progress {
+    let started = Instant::now();
+    let (stop, stopped) = mpsc::channel::<()>();
+    thread::spawn(move || {
+      while let Err(RecvTimeoutError::Timeout) = stopped.recv_timeout(PROGRESS) {
+        if let Some((watches, _)) = stats::inotify_usage() {
+          info!(
+            "Setting up watches: {watches} directories so far, {:.0}s",
+            started.elapsed().as_secs_f64()
+          );
+        }
+      }
+    });
+    Progress {
+      started,
+      _stop: stop,
+    }
+  }
+
+  /// Stops reporting and says how many directories are watched natively.
+  pub fn finish(self) {
+    let Progress { started, _stop } = self;
+    drop(_stop);
+    if let Some((watches, _)) = stats::inotify_usage().filter(|&(w, _)| w > 0) {
+      info!(
+        "Watching {watches} director{} in {:.1}s",
+        if watches == 1 { "y" } else { "ies" },
+        started.elapsed().as_secs_f64()
+      );
+    }
+  }
+}
+
+#[derive(Clone)]
+pub struct Root {
+  pub path: PathBuf,
+  /// `None` watches recursively; `Some(n)` watches the root and the
+  /// directories up to `n` levels below it.
+  pub depth: Option<usize>,
+  pub backend: Backend,
+}
+
+/// What notices changes below a root (`--path-backend`).
+#[derive(Clone, Copy, Default, PartialEq, Eq, Debug)]
+pub enum Backend {
+  /// The platform's own notifications (inotify, FSEvents, …).
+  #[default]
+  Native,
+  /// Looking at the files every `--poll-interval`, for filesystems that
+  /// don't notify of changes made elsewhere, such as NFS.
+  Poll,
+}
+
+impl std::str::FromStr for Backend {
+  type Err = String;
+
+  fn from_str(s: &str) -> Result<Backend, String> {
+    match s {
+      "native" => Ok(Backend::Native),
+      "poll" => Ok(Backend::Poll),
+      _ => Err(format!("unknown backend {s:?} (native or poll)")),
+    }
+  }
+}
+
+/// Backend settings (`--backend-opt KEY=VALUE`).
+#[derive(Clone, Debug)]
+pub struct BackendOpts {
+  pub follow_symlinks: bool,
+  /// Polling: tell changes apart by content, not only by time and size.
+  pub compare_contents: bool,
+}
+
+impl Default for BackendOpts {
+  fn default() -> Self {
+    BackendOpts {
+      follow_symlinks: true,
+      compare_contents: false,
+    }
+  }
+}
+
+impl BackendOpts {
+  pub fn set(&mut self, key: &str, value: &str) -> Result<(), String> {
+    let flag = || match value {
+      "true" | "yes" | "1" => Ok(true),
+      "false" | "no" | "0" => Ok(false),
+      _ => Err(format!("{key} takes true or false, not {value:?}")),
+    };
+    match key {
+      "follow_symlinks" => self.follow_symlinks = flag()?,
+      "compare_contents" => self.compare_contents = flag()?,
+      // notify creates the FSEvents stream itself, with a latency of 0
+      // and file-level events delivered without deferring.
+      "latency" | "flags" => {
+        return Err(format!(
+          "FSEvents' {key} can't be set: the notify library fixes it (latency 0, file events, no defer)"
+        ));
+      }
+      _ => {
+        return Err(format!(
+          "unknown backend option {key:?} (follow_symlinks or compare_contents)"
+        ));
+      }
+    }
+    Ok(())
+  }
+
+  pub fn config(&self) -> Config {
+    Config::default()
+      .with_follow_symlinks(self.follow_symlinks)
+      .with_compare_contents(self.compare_contents)
+  }
+}
+
+/// The watchers roots are left to: the native one, one polling for the
+/// roots on [`Backend::Poll`], if any, and `--watch-threads`' shards.
+pub struct Watchers {
+  pub native: RecommendedWatcher,
+  pub poll: Option<PollWatcher>,
+  pub shards: Vec<RecommendedWatcher>,
+}
+
+impl Watchers {
+  fn get(&mut self, backend: Backend) -> &mut dyn Watcher {
+    match backend {
+      Backend::Native => &mut self.native,
+      Backend::Poll => self
+        .poll
+        .as_mut()
+        .expect("a poll watcher is made for roots that poll"),
+    }
+  }
+}
+
+/// The backend `--path-backend` picks for `path`: that of the closest
+/// override at or above it.
+pub fn backend_for(path: &Path, overrides: &[(PathBuf, Backend)]) -> Backend {
+  let path = fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf());
+  overrides
+    .iter()
+    .filter_map(|(dir, backend)| {
+      let dir = fs::canonicalize(dir).ok()?;
+      path
+        .starts_with(&dir)
+        .then(|| (dir.components().count(), *backend))
+    })
+    .max_by_key(|(len, _)| *len)
+    .map_or(Backend::Native, |(_, backend)| backend)
+}
+
+/// Overridden directories strictly inside `roots`, as roots of their own
+/// on the backend asked for. The enclosing root still watches them too.
+pub fn override_roots(roots: &[Root], overrides: &[(PathBuf, Backend)]) -> Vec<Root> {
+  let mut extra = Vec::new();
+  for (dir, backend) in overrides {
+    let Ok(dir) = fs::canonicalize(dir) else {
+      continue;
+    };
+    for root in roots {
+      let Ok(base) = fs::canonicalize(&root.path) else {
+        continue;
+      };
+      let Ok(below) = dir.strip_prefix(&base) else {
+        continue;
+      };
+      let level = below.components().count();
+      if level == 0 || root.backend == *backend {
+        continue;
+      }
+      let depth = match root.depth {
+        None => None,
+        Some(depth) if depth >= level => Some(depth - level),
+        Some(_) => continue,
+      };
+      extra.push(Root {
+        path: dir.clone(),
+        depth,
+        backend: *backend,
+      });
+      break;
+    }
+  }
+  extra
+}
+
+/// The name of the network filesystem `path` is on, where native
+/// notifications miss changes made from other machines.
+#[cfg(target_os = "linux")]
+pub fn network_filesystem(path: &Path) -> Option<&'static str> {
+  use std::os::unix::ffi::OsStrExt;
+  let path = std::ffi::CString::new(path.as_os_str().as_bytes()).ok()?;
+  let mut stat: libc::statfs = unsafe { std::mem::zeroed() };
+  // SAFETY: a valid C string and a buffer of the right type
+  if unsafe { libc::statfs(path.as_ptr(), &mut stat) } != 0 {
+    return None;
+  }
+  Some(match stat.f_type as u32 {
+    0x6969 => "NFS",
+    0x517b => "SMB",
+    0xff53_4d42 => "CIFS",
+    0xfe53_4d42 => "SMB2",
+    0x6573_5546 => "FUSE",
+    0x0102_1997 => "9P",
+    0x00c3_6400 => "Ceph",
+    0x5346_414f => "AFS",
+    0x7375_7245 => "Coda",
+    0x786f_4256 => "vboxsf",
+    _ => return None,
+  })
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn network_filesystem(_path: &Path) -> Option<&'static str> {
+  None
+}
+
+/// What a watch argument turned into.
+pub struct Spec {
+  pub root: Root,
+  /// Absolute pattern changes must match, for glob and file arguments.
+  pub pattern: Option<String>,
+}
+
+fn is_glob_component(s: &str) -> bool {
+  s.contains(['*', '?', '[', '{'])
+}
+
+/// Interprets one watch argument. A plain directory is watched as given
+/// (recursively unless `depth` limits it). For a glob, the literal leading
+/// components name the directory to watch; a `**` in the rest makes that
+/// watch recursive, otherwise it is as deep as the pattern. A plain file
+/// (what an unquoted glob expands to) watches its directory for that file.
+pub fn interpret(arg: &Path, depth: Option<usize>) -> Result<Spec, String> {
+  let text = arg.to_string_lossy();
+  let components: Vec<&str> = text.split('/').collect();
+  let Some(first_glob) = components.iter().position(|c| is_glob_component(c)) else {
+    if arg.is_dir() {
+      return Ok(Spec {
+        root: Root {
+          path: arg.to_path_buf(),
+          depth,
+          backend: Backend::Native,
+        },
+        pattern: None,
+      });
+    }
+    if arg.is_file() {
+      let file = arg.canonicalize().map_err(|e| format!("{:?}: {e}", arg))?;
+      let dir = file.parent().unwrap_or(Path::new("/")).to_path_buf();
+      return Ok(Spec {
+        pattern: Some(globset::escape(&file.to_string_lossy())),
+        root: Root {
+          path: dir,
+          depth: Some(0),
+          backend: Backend::Native,
+        },
+      });
+    }
+    return Err(format!(
+      "{:?} is not a directory, file or glob pattern.",
+      arg
+    ));
+  };
+
+  let base = glob_base(&components[..first_glob], &text)
+    .canonicalize()
+    .ok()
+    .filter(|b| b.is_dir());
+  match base {
+    Some(base) => Ok(glob_spec(base, &components[first_glob..])),
+    None => Err(format!(
+      "{:?} is not a directory (from pattern {text:?}).",
+      glob_base(&components[..first_glob], &text)
+    )),
+  }
+}
+
+/// The directory a glob's literal leading components name.
+fn glob_base(literal: &[&str], text: &str) -> PathBuf {
+  match literal.join("/").as_str() {
+    "" if text.starts_with('/') => PathBuf::from("/"),
+    "" => PathBuf::from("."),
+    dir => PathBuf::from(dir),
+  }
+}
+
+/// Watches `base` for the glob components `rest`.
+fn glob_spec(base: PathBuf, rest: &[&str]) -> Spec {
+  let depth = if rest.contains(&"**") {
+    None
+  } else {
+    Some(rest.len() - 1)
+  };
+  let pattern = format!(
+    "{}/{}",
+    globset::escape(&base.to_string_lossy()).trim_end_matches('/'),
+    rest.join("/")
+  );
+  Spec {
+    root: Root {
+      path: base,
+      depth,
+      backend: Backend::Native,
+    },
+    pattern: Some(pattern),
+  }
+}
+
+/// What a watch argument [`interpret`] rejected stands for once it exists
+/// (`--allow-missing`): a directory, or a glob's base directory.
+pub fn missing(arg: &Path, depth: Option<usize>) -> Spec {
+  let text = arg.to_string_lossy();
+  let components: Vec<&str> = text.split('/').collect();
+  let absolute = |p: &Path| std::path::absolute(p).unwrap_or_else(|_| p.to_path_buf());
+  match components.iter().position(|c| is_glob_component(c)) {
+    Some(first_glob) => glob_spec(
+      absolute(&glob_base(&components[..first_glob], &text)),
+      &components[first_glob..],
+    ),
+    None => Spec {
+      root: Root {
+        path: absolute(arg),
+        depth,
+        backend: Backend::Native,
+      },
+      pattern: None,
+    },
+  }
+}
+
+/// How `--script` and `--plugin` name an event's kind.
+pub fn kind_name(kind: &EventKind) -> &'static str {
+  match kind {
+    EventKind::Create(_) => "create",
+    EventKind::Modify(ModifyKind::Name(_)) => "rename",
+    EventKind::Remove(_) => "remove",
+    _ => "modify",
+  }
+}
+
+/// Quiet spell after which `--poll-backoff` starts to poll less often.
+const POLL_IDLE: Duration = Duration::from_secs(60);
+
+/// `--poll-backoff`: how long to wait before each poll.
+pub struct PollBackoff {
+  base: Duration,
+  max: Duration,
+  current: Duration,
+  last_change: Instant,
+}
+
+impl PollBackoff {
+  pub fn new(base: Duration, max: Duration) -> Self {
+    PollBackoff {
+      base,
+      max,
+      current: base,
+      last_change: Instant::now(),
+    }
+  }
+
+  /// The wait until the next poll, doubled at each poll while idle.
+  pub fn next(&mut self) -> Duration {
+    if self.last_change.elapsed() >= POLL_IDLE && self.current < self.max {
+      self.current = (self.current * 2).min(self.max);
+      debug!(
+        interval_ms = self.current.as_millis() as u64,
+        "nothing changed lately, polling less often"
+      );
+    }
+    self.current
+  }
+
+  /// Notes a change. Returns the wait until the next poll if polling had
+  /// slowed down, for it to be rescheduled.
+  pub fn changed(&mut self) -> Option<Duration> {
+    self.last_change = Instant::now();
+    let slowed = std::mem::replace(&mut self.current, self.base) > self.base;
+    slowed.then_some(self.base)
+  }
+}
+
+/// Recent events, to drop those delivered again through another watch of
+/// the same path: the same kind of change to the same paths within the
+/// window.
+pub struct Duplicates {
+  window: Duration,
+  recent: VecDeque<(Instant, &'static str, Vec<PathBuf>)>,
+}
+
+impl Duplicates {
+  pub fn new(window: Duration) -> Self {
+    Duplicates {
+      window,
+      recent: VecDeque::new(),
+    }
+  }
+
+  /// Whether `event` repeats one seen within the window. Accesses, which
+  /// the watches make themselves, are never taken for repeats.
+  pub fn seen(&mut self, event: &Event) -> bool {
+    if event.kind.is_access() {
+      return false;
+    }
+    let now = Instant::now();
+    while self
+      .recent
+      .front()
+      .is_some_and(|(t, _, _)| now - *t >= self.window)
+    {
+      self.recent.pop_front();
+    }
+    let kind = kind_name(&event.kind);
+    if self
+      .recent
+      .iter()
+      .any(|(_, k, paths)| *k == kind && *paths == event.paths)
+    {
+      return true;
+    }
+    self.recent.push_back((now, kind, event.paths.clone()));
+    false
+  }
+}
+
+/// Reads the newline-separated path list of `--stdin` mode.
+pub fn read_list(input: impl BufRead) -> Vec<PathBuf> {
+  input
+    .lines()
+    .map_while(Result::ok)
+    .filter(|line| !line.trim().is_empty())
+    .map(PathBuf::from)
+    .collect()
+}
+
+/// entr's `-d`: remembers the directories of the listed files so the
+/// watcher can exit when a new file shows up in one of them, letting a
+/// surrounding `while` loop re-run the `find` that produced the list.
+pub struct NewFileTracker {
+  dirs: HashSet<PathBuf>,
+  known: HashSet<PathBuf>,
+}
+
+impl NewFileTracker {
+  pub fn new(listed: &[PathBuf]) -> Self {
+    let mut tracker = NewFileTracker {
+      dirs: HashSet::new(),
+      known: HashSet::new(),
+    };
+    for path in listed.iter().filter_map(|p| p.canonicalize().ok()) {
+      if path.is_dir() {
+        tracker.dirs.insert(path);
+      } else if let Some(dir) = path.parent() {
+        tracker.dirs.insert(dir.to_path_buf());
+        tracker.known.insert(path);
+      }
+    }
+    tracker
+  }
+
+  /// A file created in a tracked directory that wasn't in the list.
+  /// Dotfiles are ignored, as entr does.
+  pub fn new_file<'a>(&self, kind: &EventKind, paths: &'a [PathBuf]) -> Option<&'a PathBuf> {
+    if !matches!(kind, EventKind::Create(_)) {
+      return None;
+    }
+    paths.iter().find(|p| {
+      let hidden = p
+        .file_name()
+        .is_some_and(|n| n.to_string_lossy().starts_with('.'));
+      !hidden && !self.known.contains(*p) && p.parent().is_some_and(|d| self.dirs.contains(d))
+    })
+  }
+
+  pub fn dirs(&self) -> impl Iterator<Item = &PathBuf> {
+    self.dirs.iter()
+  }
+}
+
+/// `--trigger-file`: files whose appearance forces a run. A relative name
+/// stands for that file in each watched directory.
+pub struct TriggerFiles {
+  files: Vec<PathBuf>,
+}
+
+/// `path` with its directory resolved, so differently spelled paths to the
+/// same file compare equal.
+fn resolved(path: &Path) -> Option<PathBuf> {
+  let dir = match path.parent() {
+    Some(dir) if !dir.as_os_str().is_empty() => dir,
+    _ => Path::new("."),
+  };
+  Some(dir.canonicalize().ok()?.join(path.file_name()?))
+}
+
+/// `--relative-to`: reports paths relative to a base directory.
+pub struct Relative {
+  base: PathBuf,
+}
+
+impl Relative {
+  pub fn new(base: &Path) -> std::io::Result<Self> {
+    Ok(Relative {
+      base: base.canonicalize()?,
+    })
+  }
+
+  /// `path` resolved and taken relative to the base, with `..` for a path
+  /// outside it.
+  pub fn report(&self, path: &Path) -> PathBuf {
+    let path = path
+      .canonicalize()
+      .ok()
+      .or_else(|| resolved(path))
+      .unwrap_or_else(|| path.to_path_buf());
+    let mut base = self.base.components().peekable();
+    let mut rest = path.components().peekable();
+    while base.peek().is_some() && base.peek() == rest.peek() {
+      base.next();
+      rest.next();
+    }
+    let relative: PathBuf = base.map(|_| Component::ParentDir).chain(rest).collect();
+    match relative.as_os_str().is_empty() {
+      true => PathBuf::from("."),
+      false => relative,
+    }
+  }
+
+  pub fn report_all(&self, paths: &[PathBuf]) -> Vec<PathBuf> {
+    paths.iter().map(|p| self.report(p)).collect()
+  }
+}
+
+impl TriggerFiles {
+  pub fn new(names: &[PathBuf], roots: &[Root]) -> Self {
+    let files = names
+      .iter()
+      .flat_map(|name| match name.is_absolute() {
+        true => vec![name.clone()],
+        false => roots.iter().map(|r| r.path.join(name)).collect(),
+      })
+      .filter_map(|f| resolved(&f))
+      .collect();
+    TriggerFiles { files }
+  }
+
+  /// Directories that must be watched for the trigger files to be seen.
+  pub fn dirs(&self) -> impl Iterator<Item = &Path> {
+    self.files.iter().filter_map(|f| f.parent())
+  }
+
+  /// The trigger file among `paths`, if any.
+  pub fn find(&self, paths: &[PathBuf]) -> Option<PathBuf> {
+    paths
+      .iter()
+      .filter(|p| self.files.iter().any(|f| f.file_name() == p.file_name()))
+      .filter_map(|p| resolved(p))
+      .find(|p| self.files.contains(p))
+  }
+}
+
+/// First wait before watching a broken root again; it doubles with each
+/// failed attempt, up to [`MAX_BACKOFF`].
+const BACKOFF: Duration = Duration::from_secs(1);
+const MAX_BACKOFF: Duration = Duration::from_secs(60);
+/// Failed attempts after which a broken root is reported as such.
+const PERSISTENT: u32 = 5;
+
+/// What a backend error says about the roots it concerns.
+fn describe_error(e: &notify::Error) -> (&'static str, bool) {
+  match &e.kind {
+    notify::ErrorKind::MaxFilesWatch => {
+      ("out of watches (raise fs.inotify.max_user_watches)", true)
+    }
+    notify::ErrorKind::PathNotFound | notify::ErrorKind::WatchNotFound => {
+      ("the watched path is gone", true)
+    }
+    notify::ErrorKind::Io(_) | notify::ErrorKind::Generic(_) => ("backend failure", true),
+    notify::ErrorKind::InvalidConfig(_) => ("invalid watcher configuration", false),
+  }
+}
+
+pub struct WatchSet {
+  roots: Vec<Root>,
+  /// Directories watched individually for depth-limited roots.
+  dirs: HashSet<PathBuf>,
+  /// Roots to be watched again, with the attempts that failed so far.
+  broken: BTreeMap<usize, u32>,
+  /// Roots skipped as missing (`--allow-missing`), until they appear.
+  missing: BTreeSet<usize>,
+  /// Sharded roots, canonical, and the shard watching each directory
+  /// at their top.
+  sharded: HashSet<PathBuf>,
+  shard_of: HashMap<PathBuf, usize>,
+}
+
+/// `roots` without those another recursive root on the same backend
+/// covers, warning about the ones given separately.
+fn merge(roots: Vec<Root>) -> Vec<Root> {
+  let canonical: Vec<PathBuf> = roots
+    .iter()
+    .map(|r| fs::canonicalize(&r.path).unwrap_or_else(|_| r.path.clone()))
+    .collect();
+  let covering = |i: usize| {
+    (0..roots.len()).find(|&j| {
+      let same = canonical[i] == canonical[j];
+      j != i
+        && roots[j].depth.is_none()
+        && roots[j].backend == roots[i].backend
+        && canonical[i].starts_with(&canonical[j])
+        && (!same || roots[i].depth.is_some() || j < i)
+    })
+  };
+  let mut kept = Vec::new();
+  for (i, root) in roots.iter().enumerate() {
+    match covering(i) {
+      Some(j) if canonical[i] != canonical[j] => warn!(
+        "{:?} is inside {:?}, watching it only once",
+        root.path, roots[j].path
+      ),
+      Some(_) => {}
+      None => kept.push(root.clone()),
+    }
+  }
+  kept
+}
+
+impl WatchSet {
+  pub fn new(roots: Vec<Root>) -> Self {
+    WatchSet {
+      roots: merge(roots),
+      dirs: HashSet::new(),
+      broken: BTreeMap::new(),
+      missing: BTreeSet::new(),
+      sharded: HashSet::new(),
+      shard_of: HashMap::new(),
+    }
+  }
+
+  /// Logs a backend error and marks the roots it concerns (all of them if
+  /// it names no path) to be watched again. Returns when to try, unless
+  /// the error is not one that watching again can mend.
+  pub fn on_error(&mut self, e: &notify::Error) -> Option<Duration> {
+    let (what, recoverable) = describe_error(e);
+    let affected: Vec<usize> = (0..self.roots.len())
+      .filter(|&i| {
+        let root = &self.roots[i].path;
+        let root = fs::canonicalize(root).unwrap_or_else(|_| root.clone());
+        e.paths.is_empty() || e.paths.iter().any(|p| p.starts_with(&root))
+      })
+      .collect();
+    let fresh = affected.iter().any(|i| !self.broken.contains_key(i));
+    // One failure often comes as an error for each directory below a root.
+    match fresh || !recoverable {
+      true => warn!("Watcher error ({what}): {e}"),
+      false => debug!("watcher error ({what}): {e}"),
+    }
+    if !recoverable || !fresh {
+      return None;
+    }
+    for i in affected {
+      self.broken.entry(i).or_insert(0);
+    }
+    fresh.then_some(BACKOFF)
+  }
+
+  /// Watches the broken roots afresh. Returns when to try again for those
+  /// that still fail, and the ones that just turned out to fail
+  /// persistently.
+  pub fn retry(&mut self, watchers: &mut Watchers) -> (Option<Duration>, Vec<PathBuf>) {
+    let mut persistent = Vec::new();
+    for (i, attempts) in std::mem::take(&mut self.broken) {
+      let root = self.roots[i].clone();
+      self.unwatch_root(watchers, i);
+      match self.watch_root(watchers, i) {
+        Ok(()) if attempts >= PERSISTENT => info!("Watching {:?} again", root.path),
+        Ok(()) => debug!(root = ?root.path, "watched again"),
+        Err(e) => {
+          let attempts = attempts + 1;
+          debug!(root = ?root.path, attempts, "could not watch again: {e}");
+          if attempts == PERSISTENT {
+            error!(
+              "Cannot watch {:?} after {attempts} attempts, changes there are missed: {e}",
+              root.path
+            );
+            persistent.push(root.path.clone());
+          }
+          self.broken.insert(i, attempts);
+        }
+      }
+    }
+    let next = self.broken.values().min().map(|&attempts| {
+      BACKOFF
+        .saturating_mul(1 << attempts.min(16))
+        .min(MAX_BACKOFF)
+    });
+    (next, persistent)
+  }
+
+  /// Watches every root. With `allow_missing`, roots that don't exist are
+  /// skipped and left to [`WatchSet::recheck`].
+  pub fn setup(&mut self, watchers: &mut Watchers, allow_missing: bool) -> notify::Result<()> {
+    let mut announced = HashSet::new();
+    for i in 0..self.roots.len() {
+      let path = self.roots[i].path.clone();
+      if allow_missing && !path.is_dir() {
+        if self.missing.insert(i) {
+          warn!("Skipping {:?}: not a directory (yet)", path);
+        }
+        continue;
+      }
+      let backend = self.roots[i].backend;
+      self.watch_root(watchers, i)?;
+      if announced.insert(path.clone()) {
+        match backend {
+          Backend::Native => info!("Watching {:?}", path),
+          Backend::Poll => info!("Watching {:?} by polling", path),
+        }
+      }
+      if backend == Backend::Native
+        && let Some(fs) = network_filesystem(&path)
+      {
+        warn!(
+          "{:?} is on {fs}, where changes made from other machines may go unnoticed; --path-backend {}=poll would poll it",
+          path,
+          path.display()
+        );
+      }
+    }
+    Ok(())
+  }
+
+  /// Drops whatever watches root `i` has, on any watcher.
+  fn unwatch_root(&mut self, watchers: &mut Watchers, i: usize) {
+    let root = self.roots[i].clone();
+    let watcher = watchers.get(root.backend);
+    let _ = watcher.unwatch(&root.path);
+    let dirs: Vec<PathBuf> = self
+      .dirs
+      .iter()
+      .filter(|d| d.starts_with(&root.path))
+      .cloned()
+      .collect();
+    for dir in dirs {
+      let _ = watcher.unwatch(&dir);
+      self.dirs.remove(&dir);
+    }
+    let root_path = fs::canonicalize(&root.path).unwrap_or_else(|_| root.path.clone());
+    self.shard_of.retain(|dir, &mut k| {
+      let below = dir.starts_with(&root_path);
+      if below {
+        let _ = watchers.shards[k].unwatch(dir);
+      }
+      !below
+    });
+    self.sharded.remove(&root_path);
+  }
+
+  /// Watches `root` from now on, besides the others (`ctl watch add`).
+  /// Roots don't nest here: an added one may not lie inside another, nor
+  /// hold one.
+  pub fn add(&mut self, watchers: &mut Watchers, root: Root) -> Result<(), String> {
+    let path = fs::canonicalize(&root.path).map_err(|e| format!("{:?}: {e}", root.path))?;
+    for other in &self.roots {
+      let other_path = fs::canonicalize(&other.path).unwrap_or_else(|_| other.path.clone());
+      if path == other_path {
+        return Err(format!("{:?} is watched already", root.path));
+      }
+      if path.starts_with(&other_path) {
+        return Err(format!(
+          "{:?} is watched already, as part of {:?}",
+          root.path, other.path
+        ));
+      }
+      if other_path.starts_with(&path) {
+        return Err(format!(
+          "{:?} holds {:?}, which is watched already; remove that first",
+          root.path, other.path
+        ));
+      }
+    }
+    self.roots.push(root);
+    let i = self.roots.len() - 1;
+    if let Err(e) = self.watch_root(watchers, i) {
+      self.unwatch_root(watchers, i);
+      self.roots.pop();
+      return Err(e.to_string());
+    }
+    Ok(())
+  }
+
+  /// Stops watching the root at `path` (`ctl watch remove`) and returns it.
+  pub fn remove(&mut self, watchers: &mut Watchers, path: &Path) -> Result<Root, String> {
+    let canonical = fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf());
+    let i = self
+      .roots
+      .iter()
+      .position(|r| r.path == path || fs::canonicalize(&r.path).is_ok_and(|p| p == canonical))
+      .ok_or_else(|| format!("{path:?} is not one of the watched paths"))?;
+    self.unwatch_root(watchers, i);
+    let after = |k: usize| if k > i { k - 1 } else { k };
+    self.broken = std::mem::take(&mut self.broken)
+      .into_iter()
+      .filter(|&(k, _)| k != i)
+      .map(|(k, attempts)| (after(k), attempts))
+      .collect();
+    self.missing = std::mem::take(&mut self.missing)
+      .into_iter()
+      .filter(|&k| k != i)
+      .map(after)
+      .collect();
+    Ok(self.roots.remove(i))
+  }
+
+  fn watch_root(&mut self, watchers: &mut Watchers, i: usize) -> notify::Result<()> {
+    let root = self.roots[i].clone();
+    match root.depth {
+      None if root.backend == Backend::Native && !watchers.shards.is_empty() => {
+        self.shard(watchers, &root.path)
+      }
+      None => watchers
+        .get(root.backend)
+        .watch(&root.path, RecursiveMode::Recursive),
+      Some(depth) => self.add_tree(watchers, &root.path, depth, root.backend),
+    }
+  }
+
+  /// Watches `root` itself natively, and the trees below it from one
+  /// thread per shard, each taking the next directory once done with one.
+  fn shard(&mut self, watchers: &mut Watchers, root: &Path) -> notify::Result<()> {
+    watchers.native.watch(root, RecursiveMode::NonRecursive)?;
+    let root = fs::canonicalize(root)?;
+    let queue: Vec<PathBuf> = fs::read_dir(&root)?
+      .flatten()
+      .filter(|e| e.file_type().is_ok_and(|t| t.is_dir()))
+      .map(|e| e.path())
+      .collect();
+    let queue = Mutex::new(queue);
+    let results: Vec<notify::Result<Vec<PathBuf>>> = thread::scope(|scope| {
+      let threads: Vec<_> = watchers
+        .shards
+        .iter_mut()
+        .map(|watcher| {
+          let queue = &queue;
+          scope.spawn(move || {
+            let mut taken = Vec::new();
+            loop {
+              let Some(dir) = queue.lock().unwrap().pop() else {
+                return Ok(taken);
+              };
+              watcher.watch(&dir, RecursiveMode::Recursive)?;
+              taken.push(dir);
+            }
+          })
+        })
+        .collect();
+      threads
+        .into_iter()
+        .map(|t| t.join().expect("watch thread panicked"))
+        .collect()
+    });
+    for (k, taken) in results.into_iter().enumerate() {
+      for dir in taken? {
+        self.shard_of.insert(dir, k);
+      }
+    }
+    self.sharded.insert(root);
+    Ok(())
+  }
+
+  /// Starts watching the skipped roots that have appeared, and returns
+  /// whether any are still missing.
+  pub fn recheck(&mut self, watchers: &mut Watchers) -> bool {
+    for i in std::mem::take(&mut self.missing) {
+      let path = self.roots[i].path.clone();
+      let result = match path.is_dir() {
+        true => self.watch_root(watchers, i),
+        false => Err(notify::Error::path_not_found()),
+      };
+      match result {
+        Ok(()) => info!("Watching {:?}, which has appeared", path),
+        Err(e) => {
+          debug!(root = ?path, "still missing: {e}");
+          self.missing.insert(i);
+        }
+      }
+    }
+    !self.missing.is_empty()
+  }
+
+  /// Whether some path is watched twice, by a shard and the native
+  /// watcher, or natively and by polling.
+  pub fn overlaps(&self, watchers: &Watchers) -> bool {
+    let canonical: Vec<PathBuf> = self
+      .roots
+      .iter()
+      .map(|r| fs::canonicalize(&r.path).unwrap_or_else(|_| r.path.clone()))
+      .collect();
+    let nested = (0..self.roots.len()).any(|i| {
+      (0..self.roots.len()).any(|j| {
+        i != j
+          && self.roots[i].backend != self.roots[j].backend
+          && canonical[i].starts_with(&canonical[j])
+      })
+    });
+    nested || !watchers.shards.is_empty()
+  }
+
+  /// Number of directories watched for each root. Recursive roots are
+  /// left to the backend, which watches every directory below them.
+  pub fn dir_counts(&self) -> Vec<(PathBuf, usize)> {
+    fn count(dir: &Path) -> usize {
+      let below: usize = fs::read_dir(dir)
+        .into_iter()
+        .flatten()
+        .flatten()
+        .filter(|e| e.file_type().is_ok_and(|t| t.is_dir()))
+        .map(|e| count(&e.path()))
+        .sum();
+      1 + below
+    }
+    self
+      .roots
+      .iter()
+      .map(|root| {
+        let n = match root.depth {
+          None => count(&root.path),
+          Some(_) => self
+            .dirs
+            .iter()
+            .filter(|d| d.starts_with(&root.path))
+            .count(),
+        };
+        (root.path.clone(), n)
+      })
+      .collect()
+  }
+
+  /// Watches `dir` and its subdirectories down to `levels` further levels.
+  fn add_tree(
+    &mut self,
+    watchers: &mut Watchers,
+    dir: &Path,
+    levels: usize,
+    backend: Backend,
+  ) -> notify::Result<()> {
+    if self.dirs.contains(dir) {
+      return Ok(());
+    }
+    watchers
+      .get(backend)
+      .watch(dir, RecursiveMode::NonRecursive)?;
+    self.dirs.insert(dir.to_path_buf());
+    debug!(?dir, "watch added");
+    if levels == 0 {
+      return Ok(());
+    }
+    if let Ok(entries) = fs::read_dir(dir) {
+      for entry in entries.flatten() {
+        if entry.file_type().is_ok_and(|t| t.is_dir()) {
+          self.add_tree(watchers, &entry.path(), levels - 1, backend)?;
+        }
+      }
+    }
+    Ok(())
+  }
+
+  /// How many more levels below `dir` a depth-limited root wants watched,
+  /// and on which backend, or `None` if no such root covers it.
+  fn remaining_depth(&self, dir: &Path) -> Option<(usize, Backend)> {
+    self
+      .roots
+      .iter()
+      .filter_map(|root| {
+        let depth = root.depth?;
+        let level = dir.strip_prefix(&root.path).ok()?.components().count();
+        Some((depth.checked_sub(level)?, root.backend))
+      })
+      .max_by_key(|(levels, _)| *levels)
+  }
+
+  /// Adds watches for directories that appeared under depth-limited roots
+  /// or at the top of sharded ones, and forgets removed ones.
+  pub fn on_event(&mut self, watchers: &mut Watchers, event: &Event) {
+    match event.kind {
+      EventKind::Create(CreateKind::Folder | CreateKind::Any)
+      | EventKind::Modify(ModifyKind::Name(RenameMode::To | RenameMode::Both | RenameMode::Any)) => {
+        for path in &event.paths {
+          if !path.is_dir() || self.dirs.contains(path) {
+            continue;
+          }
+          if path.parent().is_some_and(|p| self.sharded.contains(p))
+            && !self.shard_of.contains_key(path)
+          {
+            let k = self.shard_of.len() % watchers.shards.len();
+            match watchers.shards[k].watch(path, RecursiveMode::Recursive) {
+              Ok(()) => {
+                debug!(?path, shard = k, "new directory sharded");
+                self.shard_of.insert(path.clone(), k);
+              }
+              Err(e) => warn!("Could not watch new directory {:?}: {e}", path),
+            }
+            continue;
+          }
+          if let Some((levels, backend)) = self.remaining_depth(path) {
+            match self.add_tree(watchers, path, levels, backend) {
+              Ok(()) => info!("Watching new directory {:?}", path),
+              Err(e) => warn!("Could not watch new directory {:?}: {e}", path),
+            }
+          }
+        }
+      }
+      EventKind::Remove(_) | EventKind::Modify(ModifyKind::Name(RenameMode::From)) => {
+        for path in &event.paths {
+          self.dirs.retain(|d| !d.starts_with(path));
+          self.shard_of.retain(|d, _| !d.starts_with(path));
+        }
+      }
+      _ => {}
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use notify::event::DataChange;
+
+  fn root(path: &str, depth: Option<usize>, backend: Backend) -> Root {
+    Root {
+      path: PathBuf::from(path),
+      depth,
+      backend,
+    }
+  }
+
+  fn merged(roots: Vec<Root>) -> Vec<String> {
+    merge(roots)
+      .iter()
+      .map(|r| r.path.display().to_string())
+      .collect()
+  }
+
+  #[test]
+  fn merges_nested_roots() {
+    let native = Backend::Native;
+    assert_eq!(
+      merged(vec![root("/w", None, native), root("/w/src", None, native)]),
+      ["/w"]
+    );
+    assert_eq!(
+      merged(vec![root("/w/src", None, native), root("/w", None, native)]),
+      ["/w"]
+    );
+    assert_eq!(
+      merged(vec![root("/w", None, native), root("/w", None, native)]),
+      ["/w"]
+    );
+    // Only a recursive root covers another.
+    assert_eq!(
+      merged(vec![
+        root("/w", Some(1), native),
+        root("/w/src", None, native)
+      ]),
+      ["/w", "/w/src"]
+    );
+    assert_eq!(
+      merged(vec![root("/w", Some(1), native), root("/w", None, native)]),
+      ["/w"]
+    );
+    // Nor one on another backend.
+    assert_eq!(
+      merged(vec![
+        root("/w", None, native),
+        root("/w/nfs", None, Backend::Poll)
+      ]),
+      ["/w", "/w/nfs"]
+    );
+    // A shared prefix isn't nesting.
+    assert_eq!(
+      merged(vec![root("/w", None, native), root("/work", None, native)]),
+      ["/w", "/work"]
+    );
+  }
+
+  #[test]
+  fn drops_repeated_events() {
+    let event = |kind, path: &str| Event::new(kind).add_path(PathBuf::from(path));
+    let modify = EventKind::Modify(ModifyKind::Data(DataChange::Content));
+    let mut duplicates = Duplicates::new(Duration::from_secs(60));
+    assert!(!duplicates.seen(&event(modify, "/w/a")));
+    assert!(duplicates.seen(&event(modify, "/w/a")));
+    assert!(!duplicates.seen(&event(modify, "/w/b")));
+    assert!(!duplicates.seen(&event(EventKind::Create(CreateKind::File), "/w/a")));
+    let access = EventKind::Access(notify::event::AccessKind::Any);
+    assert!(!duplicates.seen(&event(access, "/w/a")));
+    assert!(!duplicates.seen(&event(access, "/w/a")));
+
+    let mut duplicates = Duplicates::new(Duration::ZERO);
+    assert!(!duplicates.seen(&event(modify, "/w/a")));
+    assert!(!duplicates.seen(&event(modify, "/w/a")));
+  }
+}
@@ -0,0 +1,55 @@
+// Killing runs that use too much memory (`--kill-if-rss-exceeds`), before
+// the OOM killer picks something of the developer's instead.
+//
+// The resident memory of every process in the run's group is summed from
+// /proc on a thread of its own, which reports through the event loop's
+// channel and ends with the group.
+
+use crate::Msg;
+use nix::libc;
+use std::{fs, sync::mpsc::Sender, thread, time::Duration};
+
+const INTERVAL: Duration = Duration::from_millis(250);
+
+/// Resident memory of the processes in group `pgid`, in KiB, or `None` if
+/// the group has no processes left.
+pub fn group_rss_kb(pgid: u32) -> Option<u64> {
+  // SAFETY: sysconf has no preconditions
+  let page_kb = (unsafe { libc::sysconf(libc::_SC_PAGESIZE) }.max(4096) / 1024) as u64;
+  let entries = fs::read_dir("/proc").ok()?;
+  let mut members = 0;
+  let mut pages = 0;
+  for entry in entries.flatten() {
+    let Ok(stat) = fs::read_to_string(entry.path().join("stat")) else {
+      continue; // not a process, or already gone
+    };
+    // Fields after the parenthesised command name, which may contain
+    // spaces: state, ppid, pgrp, ..., rss (the 22nd).
+    let Some((_, rest)) = stat.rsplit_once(')') else {
+      continue;
+    };
+    let fields: Vec<&str> = rest.split_whitespace().collect();
+    if fields.get(2).and_then(|g| g.parse::<u32>().ok()) == Some(pgid) {
+      members += 1;
+      pages += fields
+        .get(21)
+        .and_then(|r| r.parse::<u64>().ok())
+        .unwrap_or(0);
+    }
+  }
+  (members > 0).then_some(pages * page_kb)
+}
+
+/// Watches group `pgid` until it is gone, sending [`Msg::MemoryExceeded`]
+/// for `run_id` if it uses more than `limit_kb`.
+pub fn start(pgid: u32, limit_kb: u64, run_id: u64, tx: Sender<Msg>) {
+  thread::spawn(move || {
+    while let Some(rss_kb) = group_rss_kb(pgid) {
+      if rss_kb > limit_kb {
+        let _ = tx.send(Msg::MemoryExceeded(run_id, rss_kb));
+        return;
+      }
+      thread::sleep(INTERVAL);
+    }
+  });
+}
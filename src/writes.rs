@@ -0,0 +1,146 @@
+// What each run wrote inside the watched trees (`--report-writes`), to
+// find the files that make the command trigger itself:
+//
+//   run_on_file_change --report-writes "make" .
+//
+// While a run goes on, and shortly after, the changes that pass its
+// job's filters are noted. Once it is over they are reported along with
+// the -i patterns that would exclude them: the highest directory all of
+// whose files the run wrote (a build directory made afresh), otherwise
+// `dir/*.ext` for three files or more of a kind in one place with no
+// others of the kind, otherwise the files themselves.
+
+use std::{
+  collections::{BTreeMap, BTreeSet},
+  fs,
+  path::{Path, PathBuf},
+  time::SystemTime,
+};
+
+/// Files looked at below a directory before it is taken not to be the
+/// run's own.
+const WALK_LIMIT: usize = 10_000;
+
+/// Files of one extension in one directory that make it a `*.ext`.
+const SAME_KIND: usize = 3;
+
+pub struct Writes {
+  pub run_id: u64,
+  pub command: String,
+  started: SystemTime,
+  paths: BTreeSet<PathBuf>,
+}
+
+impl Writes {
+  pub fn new(run_id: u64, command: &str) -> Self {
+    Writes {
+      run_id,
+      command: command.to_string(),
+      started: SystemTime::now(),
+      paths: BTreeSet::new(),
+    }
+  }
+
+  pub fn record(&mut self, path: &Path) {
+    self.paths.insert(path.to_path_buf());
+  }
+
+  pub fn len(&self) -> usize {
+    self.paths.len()
+  }
+
+  /// Whether the files of `dir` with extension `ext` were all written
+  /// since the run started.
+  fn fresh_kind(&self, dir: &Path, ext: &str) -> bool {
+    fs::read_dir(dir).is_ok_and(|entries| {
+      entries
+        .flatten()
+        .filter(|e| e.path().extension().is_some_and(|x| x == ext))
+        .all(|e| {
+          e.metadata()
+            .and_then(|m| m.modified())
+            .is_ok_and(|t| t >= self.started)
+        })
+    })
+  }
+
+  /// Whether every file below `dir` was written since the run started.
+  fn fresh(&self, dir: &Path) -> bool {
+    let mut pending = vec![dir.to_path_buf()];
+    let mut seen = 0;
+    while let Some(dir) = pending.pop() {
+      let Ok(entries) = fs::read_dir(&dir) else {
+        return false;
+      };
+      for entry in entries.flatten() {
+        seen += 1;
+        let Ok(meta) = entry.metadata() else {
+          return false;
+        };
+        if seen > WALK_LIMIT {
+          return false;
+        }
+        if meta.is_dir() {
+          pending.push(entry.path());
+        } else if meta.modified().is_ok_and(|t| t < self.started) {
+          return false;
+        }
+      }
+    }
+    seen > 0
+  }
+
+  /// The -i patterns, relative to the watched `roots`, that would have
+  /// kept the run's writes from triggering.
+  pub fn suggestions(&self, roots: &[PathBuf]) -> Vec<String> {
+    let mut dirs = BTreeSet::new();
+    let mut files = Vec::new();
+    for path in &self.paths {
+      let Some(root) = roots
+        .iter()
+        .filter(|r| path.starts_with(r))
+        .max_by_key(|r| r.as_os_str().len())
+      else {
+        continue;
+      };
+      let relative = path.strip_prefix(root).unwrap_or(path);
+      let fresh = relative
+        .ancestors()
+        .skip(1)
+        .filter(|a| !a.as_os_str().is_empty())
+        .collect::<Vec<_>>()
+        .into_iter()
+        .rev()
+        .find(|a| dirs.contains(*a) || self.fresh(&root.join(a)));
+      match fresh {
+        Some(dir) => {
+          dirs.insert(dir.to_path_buf());
+        }
+        None => files.push((root, relative.to_path_buf())),
+      }
+    }
+
+    type Kind<'a> = (&'a PathBuf, PathBuf, String);
+    let mut kinds: BTreeMap<Kind, Vec<PathBuf>> = BTreeMap::new();
+    let mut patterns: Vec<String> = dirs.iter().map(|d| d.display().to_string()).collect();
+    for (root, file) in files {
+      match file.extension() {
+        Some(ext) => {
+          let dir = file.parent().unwrap_or(Path::new("")).to_path_buf();
+          let key = (root, dir, ext.to_string_lossy().into_owned());
+          kinds.entry(key).or_default().push(file);
+        }
+        None => patterns.push(file.display().to_string()),
+      }
+    }
+    for ((root, dir, ext), files) in kinds {
+      match files.len() >= SAME_KIND && self.fresh_kind(&root.join(&dir), &ext) {
+        true => patterns.push(dir.join(format!("*.{ext}")).display().to_string()),
+        false => patterns.extend(files.iter().map(|f| f.display().to_string())),
+      }
+    }
+    patterns.sort();
+    patterns.dedup();
+    patterns
+  }
+}